@@ -16,13 +16,17 @@ class TableDrawer(object):
     LINE_COLOR = (100, 80, 20)
     BG_COLOR = (30, 30, 100)
     TEXT_COLOR = (255, 238, 173)
+    HIGHLIGHT_COLOR = (255, 200, 60)
 
     def __init__(self):
         self._topics = None
         self._scores = None
         self._rows = None
+        self._current_player = None
+        self._columns = None
         self._width = None
         self._height = None
+        self._table_height = None
         self._font = None
         self._image = None
         self._draw_proxy = None
@@ -31,8 +35,13 @@ class TableDrawer(object):
 
     def _parse_data(self, data):
         self._scores = [str(x).strip() for x in data['scores']]
+        # optional caption with the player who chooses the next question
+        self._current_player = data.get('current_player')
         self._topics = []
         self._rows = []
+        # optional trailing column with number of played questions
+        has_played = any('played' in row for row in data['data'])
+        self._columns = len(self._scores) + (1 if has_played else 0)
         for row in data['data']:
             self._topics.append(row['name'])
             self._rows.append([row['name']])
@@ -55,10 +64,15 @@ class TableDrawer(object):
                     self._rows[-1].append(score)
                 else:
                     self._rows[-1].append('')
+            if has_played:
+                self._rows[-1].append(str(row.get('played', '')))
 
     def _define_geometry(self):
-        self._width = self.TOPIC_WIDTH + self.SCORE_WIDTH * len(self._scores) + self.LINE_WIDTH
+        self._width = self.TOPIC_WIDTH + self.SCORE_WIDTH * self._columns + self.LINE_WIDTH
         self._height = self.ROW_HEIGHT * len(self._topics) + self.LINE_WIDTH
+        self._table_height = self._height
+        if self._current_player:
+            self._height += self.ROW_HEIGHT
 
     def _define_image(self):
         self._image = Image.new('RGB', (self._width, self._height), self.BG_COLOR)
@@ -107,7 +121,7 @@ class TableDrawer(object):
 
     def _define_coordinates(self):
         self._xs = [0, self.TOPIC_WIDTH]
-        for _ in self._scores:
+        for _ in range(self._columns):
             self._xs.append(self._xs[-1] + self.SCORE_WIDTH)
         self._ys = [0]
         for _ in self._topics:
@@ -116,7 +130,7 @@ class TableDrawer(object):
     def _draw_grid(self):
         for x in self._xs:
             self._draw_proxy.line(
-                (x, 0, x, self._height),
+                (x, 0, x, self._table_height),
                 fill=self.LINE_COLOR,
                 width=self.LINE_WIDTH
             )
@@ -140,6 +154,14 @@ class TableDrawer(object):
                 offy = self._ys[y] + (self._ys[y + 1] - self._ys[y] - text_size[1]) // 2
                 self._draw_proxy.text((offx, offy), col, font=self._font, fill=self.TEXT_COLOR)
 
+    def _draw_current_player(self):
+        if not self._current_player:
+            return
+        text = 'Выбирает: {}'.format(self._current_player)
+        text_size = self._draw_proxy.textsize(text, font=self._font)
+        offy = self._table_height + (self.ROW_HEIGHT - text_size[1]) // 2
+        self._draw_proxy.text((self.CELL_OFFSET, offy), text, font=self._font, fill=self.HIGHLIGHT_COLOR)
+
 
 
     def draw(self, data, result):
@@ -152,6 +174,7 @@ class TableDrawer(object):
 
         self._draw_grid()
         self._draw_texts()
+        self._draw_current_player()
 
         offset = int(min(self._width, self._height) * 0.05)
         image = Image.new('RGB', (self._width + offset * 2, self._height + offset * 2), self.BG_COLOR)
@@ -160,16 +183,81 @@ class TableDrawer(object):
 
 
 
+class SummaryDrawer(object):
+    """Draws final ranking of the players, winners are highlighted."""
+    FONT_NAME = TableDrawer.FONT_NAME
+    NAME_WIDTH = 700
+    NUMBER_WIDTH = 200
+    ROW_HEIGHT = TableDrawer.ROW_HEIGHT
+    CELL_OFFSET = TableDrawer.CELL_OFFSET
+    BG_COLOR = TableDrawer.BG_COLOR
+    TEXT_COLOR = TableDrawer.TEXT_COLOR
+    HIGHLIGHT_COLOR = TableDrawer.HIGHLIGHT_COLOR
+
+    def draw(self, data, result):
+        rows = [('', 'Очки', 'Ответы', False)]
+        for player in data['players']:
+            rows.append((
+                '{}. {}'.format(player['rank'], player['name']),
+                str(player['score']),
+                str(player['answered']),
+                player['winner'],
+            ))
+
+        width = self.NAME_WIDTH + 2 * self.NUMBER_WIDTH
+        height = self.ROW_HEIGHT * len(rows)
+        image = Image.new('RGB', (width, height), self.BG_COLOR)
+        draw_proxy = ImageDraw.Draw(image)
+
+        size = self.ROW_HEIGHT
+        for row in rows:
+            size = min(
+                size,
+                TableDrawer.get_font_size(
+                    draw_proxy,
+                    self.FONT_NAME,
+                    row[0],
+                    self.NAME_WIDTH - 2 * self.CELL_OFFSET,
+                    self.ROW_HEIGHT
+                )
+            )
+        font = ImageFont.truetype(self.FONT_NAME, size)
+
+        for y, (name, score, answered, winner) in enumerate(rows):
+            color = self.HIGHLIGHT_COLOR if winner else self.TEXT_COLOR
+            cells = [
+                (name, 0, self.NAME_WIDTH),
+                (score, self.NAME_WIDTH, self.NUMBER_WIDTH),
+                (answered, self.NAME_WIDTH + self.NUMBER_WIDTH, self.NUMBER_WIDTH),
+            ]
+            for x, (text, left, cell_width) in enumerate(cells):
+                text_size = draw_proxy.textsize(text, font=font)
+                if x == 0:
+                    offx = left + self.CELL_OFFSET
+                else:
+                    offx = left + (cell_width - text_size[0]) // 2
+                offy = y * self.ROW_HEIGHT + (self.ROW_HEIGHT - text_size[1]) // 2
+                draw_proxy.text((offx, offy), text, font=font, fill=color)
+
+        offset = int(min(width, height) * 0.05)
+        framed = Image.new('RGB', (width + offset * 2, height + offset * 2), self.BG_COLOR)
+        framed.paste(image, (offset, offset))
+        framed.save(result, format='png')
+
+
 def _parse_args():
-    parser = argparse.ArgumentParser(description="Draw score table in png")
-    parser.add_argument('data', help='json file with score table')
+    parser = argparse.ArgumentParser(description="Draw score table or game summary in png")
+    parser.add_argument('data', help='json file with score table or game summary')
     parser.add_argument('output', help='where to put result image')
     return parser.parse_args()
 
 def _main(args):
     with open(args.data, encoding='utf-8') as fin:
         data = json.load(fin)
-    TableDrawer().draw(data, args.output)
+    if 'players' in data:
+        SummaryDrawer().draw(data, args.output)
+    else:
+        TableDrawer().draw(data, args.output)
 
 if __name__ == '__main__':
     _main(_parse_args())
@@ -0,0 +1,68 @@
+use failure::Error;
+use serde_derive::Serialize;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::time::{SystemTime, UNIX_EPOCH};
+use telegram_bot::UserId;
+
+#[derive(Serialize)]
+struct AuditRecord<'a> {
+    timestamp: u64,
+    user: i64,
+    action: &'a str,
+}
+
+// Append-only log of admin actions, one json object per line
+pub struct AuditLog {
+    file: File,
+}
+
+impl AuditLog {
+    pub fn open(path: &str) -> Result<Self, Error> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self { file })
+    }
+
+    pub fn record(&mut self, user: UserId, action: &str) -> Result<(), Error> {
+        let record = AuditRecord {
+            timestamp: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+            user: user.into(),
+            action,
+        };
+        let mut line = serde_json::to_string(&record)?;
+        line.push('\n');
+        self.file.write_all(line.as_bytes())?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_audit_log() {
+        let path = std::env::temp_dir().join("svoyak_bot_test_audit_log.jsonl");
+        let _ = std::fs::remove_file(&path);
+        let path = path.to_str().unwrap().to_string();
+
+        let mut audit_log = AuditLog::open(&path).unwrap();
+        audit_log.record(UserId::from(1), "StartGame").unwrap();
+        // Reopening appends to the existing log
+        let mut audit_log = AuditLog::open(&path).unwrap();
+        audit_log.record(UserId::from(1), "UpdateScore(\"new_1\", 100)").unwrap();
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        let records: Vec<serde_json::Value> = content
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0]["user"], 1);
+        assert_eq!(records[0]["action"], "StartGame");
+        assert_eq!(records[1]["action"], "UpdateScore(\"new_1\", 100)");
+        assert!(records[1]["timestamp"].as_u64().unwrap() > 0);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,76 @@
+use failure::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+// Directory with downloaded questions and attachments
+#[derive(Clone)]
+pub struct Cache {
+    dir: PathBuf,
+}
+
+impl Cache {
+    pub fn new<P: Into<PathBuf>>(dir: P) -> Result<Self, Error> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        Ok(Self { dir })
+    }
+
+    pub fn path<P: AsRef<Path>>(&self, name: P) -> PathBuf {
+        self.dir.join(name)
+    }
+
+    // Returns number of removed files
+    pub fn clear(&self) -> Result<usize, Error> {
+        self.remove_older_than(Duration::from_secs(0))
+    }
+
+    // Returns number of removed files
+    pub fn remove_older_than(&self, max_age: Duration) -> Result<usize, Error> {
+        remove_files_older_than(&self.dir, SystemTime::now(), max_age)
+    }
+}
+
+fn remove_files_older_than(dir: &Path, now: SystemTime, max_age: Duration) -> Result<usize, Error> {
+    let mut removed = 0;
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if metadata.is_dir() {
+            removed += remove_files_older_than(&entry.path(), now, max_age)?;
+            continue;
+        }
+
+        // Files from the future (e.g. clock skew) are treated as new ones
+        let age = now.duration_since(metadata.modified()?).unwrap_or_default();
+        if age >= max_age {
+            fs::remove_file(entry.path())?;
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_cache() {
+        let dir = std::env::temp_dir().join("svoyak_bot_test_cache");
+        let _ = fs::remove_dir_all(&dir);
+        let cache = Cache::new(&dir).unwrap();
+        fs::write(cache.path("attachment"), "data").unwrap();
+        fs::create_dir_all(cache.path("downloaded_questions")).unwrap();
+        fs::write(cache.path("downloaded_questions").join("tour1.csv"), "data").unwrap();
+
+        assert_eq!(cache.remove_older_than(Duration::from_secs(3600)).unwrap(), 0);
+        assert!(cache.path("attachment").exists());
+
+        assert_eq!(cache.clear().unwrap(), 2);
+        assert!(!cache.path("attachment").exists());
+        assert!(!cache.path("downloaded_questions").join("tour1.csv").exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,651 @@
+use itertools::Itertools;
+use telegram_bot::{types::MessageId, Message, MessageOrChannelPost};
+
+use crate::gamestate::TopicIdx;
+use crate::messages::BEGIN_CMD;
+
+pub const ANSWER_YES: &str = "AnswerYes";
+pub const ANSWER_NO: &str = "AnswerNo";
+pub const START_YES: &str = "StartYes";
+pub const START_NO: &str = "StartNo";
+pub const MANUAL_SCORING_DONE: &str = "/manual_score_done";
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum TextMessage {
+    Join(String),
+    JustMessage(String),
+    // Reply to the message with the question text
+    ReplyToQuestion(String),
+    NextQuestion,
+    GetScore,
+    StartGame,
+    CurrentPlayer,
+    ChangePlayer(String),
+    // Sets the player who chooses and waits for the next question
+    NextChooser(String),
+    NextTour,
+    UpdateScore(String, i64),
+    // Player name and whether the answer was correct
+    Regrade(String, bool),
+    HideQuestion(String, usize),
+    // topic, cost and whether played question can be returned
+    UnhideQuestion(String, usize, bool),
+    UpdateAuctionCost(String, usize),
+    AuctionPass,
+    ChooseTopic(String),
+    ChooseQuestion(usize),
+    Appeal,
+    PauseAnswers,
+    ResumeAnswers,
+    ExportBoard,
+    Transcript,
+    // One of the recently played questions is posted again, 1 is the last one
+    Last(usize),
+    Finish,
+    Board,
+    // Score table image is sent to the main chat again
+    BoardImage,
+    Leaderboard,
+    Rating,
+    Stats,
+    Practice,
+    ClearPlayers,
+    ClearCache,
+    Help,
+}
+
+impl TextMessage {
+    // Admin commands which change the game, they are written to the audit log
+    pub fn is_admin_action(&self) -> bool {
+        use TextMessage::*;
+
+        matches!(
+            self,
+            NextQuestion
+                | StartGame
+                | Practice
+                | ChangePlayer(_)
+                | NextChooser(_)
+                | NextTour
+                | UpdateScore(..)
+                | Regrade(..)
+                | Appeal
+                | HideQuestion(..)
+                | UnhideQuestion(..)
+                | UpdateAuctionCost(..)
+                | PauseAnswers
+                | ResumeAnswers
+                | Finish
+                | ClearPlayers
+                | ClearCache
+        )
+    }
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum CallbackMessage {
+    SelectedTopic(TopicIdx),
+    SelectedQuestion(TopicIdx, usize),
+    AnswerYes,
+    AnswerNo,
+    StartConfirmed,
+    StartCancelled,
+    Unknown,
+    CatInBagPlayerChosen(String),
+    CatInBagCostChosen(usize),
+    ScoringPlayerChosen(String),
+    ScoringDone,
+}
+
+impl CallbackMessage {
+    pub fn is_admin_action(&self) -> bool {
+        use CallbackMessage::*;
+
+        matches!(
+            self,
+            AnswerYes | AnswerNo | StartConfirmed | StartCancelled | ScoringPlayerChosen(_) | ScoringDone
+        )
+    }
+}
+
+pub struct Command {
+    // Canonical name, i.e. with '/' prefix
+    pub name: &'static str,
+    // Shortcuts which are parsed the same way as the name
+    pub aliases: &'static [&'static str],
+    // Shown in /help
+    pub args: &'static str,
+    pub description: &'static str,
+    // Gets everything after the name and the following space. Returns None
+    // if arguments are malformed, then the text is treated as a usual message
+    parse: fn(&str) -> Option<TextMessage>,
+}
+
+fn no_args(args: &str, message: TextMessage) -> Option<TextMessage> {
+    if args.is_empty() {
+        Some(message)
+    } else {
+        None
+    }
+}
+
+fn non_empty(args: &str) -> Option<String> {
+    if args.is_empty() {
+        None
+    } else {
+        Some(args.to_string())
+    }
+}
+
+pub const COMMANDS: &[Command] = &[
+    Command {
+        name: "/join",
+        aliases: &["/играю"],
+        args: "ИМЯ",
+        description: "зарегистрироваться в игре",
+        parse: |args| non_empty(args).map(TextMessage::Join),
+    },
+    Command {
+        name: "/question",
+        aliases: &["/next", "/q", "/вопрос"],
+        args: "",
+        description: "следующий вопрос",
+        parse: |args| no_args(args, TextMessage::NextQuestion),
+    },
+    Command {
+        name: "/score",
+        aliases: &["/s", "/счет", "/счёт"],
+        args: "",
+        description: "текущий счёт",
+        parse: |args| no_args(args, TextMessage::GetScore),
+    },
+    Command {
+        name: "/currentplayer",
+        aliases: &["/cp", "/игрок"],
+        args: "",
+        description: "кто сейчас выбирает вопрос",
+        parse: |args| no_args(args, TextMessage::CurrentPlayer),
+    },
+    Command {
+        name: "/changeplayer",
+        aliases: &["/сменаигрока"],
+        args: "ИМЯ",
+        description: "передать выбор вопроса игроку",
+        parse: |args| non_empty(args).map(TextMessage::ChangePlayer),
+    },
+    Command {
+        name: "/nextchooser",
+        aliases: &["/выбирает"],
+        args: "ИМЯ",
+        description: "следующий вопрос выбирает игрок, игра ставится на паузу",
+        parse: |args| non_empty(args).map(TextMessage::NextChooser),
+    },
+    Command {
+        name: "/auction",
+        aliases: &["/аукцион"],
+        args: "СТАВКА ИМЯ",
+        description: "закрыть аукцион со ставкой игрока",
+        parse: |args| {
+            let (cost, name) = args.split_once(' ')?;
+            Some(TextMessage::UpdateAuctionCost(name.to_string(), cost.parse().ok()?))
+        },
+    },
+    Command {
+        name: "/pass",
+        aliases: &["/пас"],
+        args: "",
+        description: "выйти из торгов в аукционе",
+        parse: |args| no_args(args, TextMessage::AuctionPass),
+    },
+    Command {
+        name: "/hidequestion",
+        aliases: &[],
+        args: "СТОИМОСТЬ ТЕМА",
+        description: "убрать вопрос с табло",
+        parse: |args| {
+            let (cost, topic) = args.split_once(' ')?;
+            Some(TextMessage::HideQuestion(topic.to_string(), cost.parse().ok()?))
+        },
+    },
+    Command {
+        name: "/unhide",
+        aliases: &[],
+        args: "[force] СТОИМОСТЬ ТЕМА",
+        description: "вернуть вопрос на табло",
+        parse: |args| {
+            let (force, args) = match args.strip_prefix("force ") {
+                Some(args) => (true, args),
+                None => (false, args),
+            };
+            let (cost, topic) = args.split_once(' ')?;
+            Some(TextMessage::UnhideQuestion(topic.to_string(), cost.parse().ok()?, force))
+        },
+    },
+    Command {
+        name: "/nexttour",
+        aliases: &["/nt", "/тур"],
+        args: "",
+        description: "перейти к следующему туру",
+        parse: |args| no_args(args, TextMessage::NextTour),
+    },
+    Command {
+        name: "/appeal",
+        aliases: &["/апелляция"],
+        args: "",
+        description: "отменить последнее решение",
+        parse: |args| no_args(args, TextMessage::Appeal),
+    },
+    Command {
+        name: "/pauseanswers",
+        aliases: &["/стоп"],
+        args: "",
+        description: "временно не принимать ответы",
+        parse: |args| no_args(args, TextMessage::PauseAnswers),
+    },
+    Command {
+        name: "/resumeanswers",
+        aliases: &["/продолжить"],
+        args: "",
+        description: "снова принимать ответы",
+        parse: |args| no_args(args, TextMessage::ResumeAnswers),
+    },
+    Command {
+        name: "/exportboard",
+        aliases: &[],
+        args: "",
+        description: "сохранить табло в html",
+        parse: |args| no_args(args, TextMessage::ExportBoard),
+    },
+    Command {
+        name: "/transcript",
+        aliases: &["/стенограмма"],
+        args: "",
+        description: "сохранить стенограмму игры в файл",
+        parse: |args| no_args(args, TextMessage::Transcript),
+    },
+    Command {
+        name: "/last",
+        aliases: &["/повтор"],
+        args: "[N]",
+        description: "показать ещё раз последний (или N-й с конца) сыгранный вопрос",
+        parse: |args| {
+            if args.is_empty() {
+                return Some(TextMessage::Last(1));
+            }
+            match args.parse() {
+                Ok(ago) if ago > 0 => Some(TextMessage::Last(ago)),
+                _ => None,
+            }
+        },
+    },
+    Command {
+        name: "/finish",
+        aliases: &["/конец"],
+        args: "",
+        description: "закончить игру",
+        parse: |args| no_args(args, TextMessage::Finish),
+    },
+    Command {
+        name: "/board",
+        aliases: &["/b", "/табло"],
+        args: "[image]",
+        description: "оставшиеся вопросы, с image - показать табло в чате",
+        parse: |args| match args {
+            "image" | "картинка" => Some(TextMessage::BoardImage),
+            _ => no_args(args, TextMessage::Board),
+        },
+    },
+    Command {
+        name: "/leaderboard",
+        aliases: &["/лидеры"],
+        args: "",
+        description: "таблица лидеров",
+        parse: |args| no_args(args, TextMessage::Leaderboard),
+    },
+    Command {
+        name: "/rating",
+        aliases: &["/рейтинг"],
+        args: "",
+        description: "рейтинг игроков",
+        parse: |args| no_args(args, TextMessage::Rating),
+    },
+    Command {
+        name: "/stats",
+        aliases: &["/статистика"],
+        args: "",
+        description: "среднее время нажатия на кнопку",
+        parse: |args| no_args(args, TextMessage::Stats),
+    },
+    Command {
+        name: "/practice",
+        aliases: &["/тренировка"],
+        args: "",
+        description: "тренировка для одного игрока без табло",
+        parse: |args| no_args(args, TextMessage::Practice),
+    },
+    Command {
+        name: "/clearplayers",
+        aliases: &[],
+        args: "",
+        description: "очистить список игроков",
+        parse: |args| no_args(args, TextMessage::ClearPlayers),
+    },
+    Command {
+        name: "/clearcache",
+        aliases: &[],
+        args: "",
+        description: "удалить скачанные файлы",
+        parse: |args| no_args(args, TextMessage::ClearCache),
+    },
+    Command {
+        name: "/help",
+        aliases: &["/h", "/помощь"],
+        args: "",
+        description: "список команд",
+        parse: |args| no_args(args, TextMessage::Help),
+    },
+    Command {
+        name: "/updatescore",
+        aliases: &["/us", "/исправитьсчет"],
+        args: "ИМЯ СЧЁТ",
+        description: "исправить счёт игрока",
+        parse: |args| {
+            // Name may contain spaces, so the score is the last word
+            let (name, score) = args.rsplit_once(' ')?;
+            Some(TextMessage::UpdateScore(name.to_string(), score.parse().ok()?))
+        },
+    },
+    Command {
+        name: "/regrade",
+        aliases: &["/переоценка"],
+        args: "ИМЯ +/-",
+        description: "исправить оценку последнего ответа на стоимость вопроса",
+        parse: |args| {
+            let (name, sign) = args.rsplit_once(' ')?;
+            let correct = match sign {
+                "+" => true,
+                "-" => false,
+                _ => return None,
+            };
+            Some(TextMessage::Regrade(name.to_string(), correct))
+        },
+    },
+];
+
+// Commands are shown with the configured prefix
+pub fn help_text(command_prefix: &str) -> String {
+    let with_prefix = |name: &str| format!("{}{}", command_prefix, name.trim_start_matches('/'));
+    let mut res = "Команды:".to_string();
+    for command in COMMANDS {
+        res += &format!("\n{}", with_prefix(command.name));
+        if !command.aliases.is_empty() {
+            res += &format!(" ({})", command.aliases.iter().map(|alias| with_prefix(alias)).join(", "));
+        }
+        if !command.args.is_empty() {
+            res += &format!(" {}", command.args);
+        }
+        res += &format!(" - {}", command.description);
+    }
+    res
+}
+
+// Commands are parsed in the canonical form i.e. with '/' prefix
+pub fn canonical_command(data: &str, command_prefix: &str) -> Option<String> {
+    if command_prefix.is_empty() {
+        return None;
+    }
+    data.strip_prefix(command_prefix)
+        .map(|command| format!("/{}", command))
+}
+
+// In group chats telegram adds the bot name to the command, e.g. /thischat@bot
+pub fn is_this_chat_command(data: &str, command_prefix: &str) -> bool {
+    let command = data.trim().split('@').next().unwrap_or_default();
+    canonical_command(command, command_prefix).as_deref() == Some("/thischat")
+}
+
+pub fn parse_text_message(
+    message: &Message,
+    data: &str,
+    command_prefix: &str,
+    choose_topic_message_id: Option<MessageId>,
+    choose_question_message_id: Option<MessageId>,
+    question_message_id: Option<MessageId>,
+) -> TextMessage {
+    if let Some(MessageOrChannelPost::Message(ref msg)) = message.reply_to_message.as_deref() {
+        if Some(msg.id) == question_message_id && canonical_command(data, command_prefix).is_none() {
+            return TextMessage::ReplyToQuestion(data.to_string());
+        }
+
+        if Some(msg.id) == choose_topic_message_id {
+            return TextMessage::ChooseTopic(data.to_string());
+        }
+
+        if Some(msg.id) == choose_question_message_id {
+            if let Ok(cost) = data.parse::<usize>() {
+                return TextMessage::ChooseQuestion(cost);
+            }
+        }
+    }
+
+    parse_command(data, command_prefix)
+}
+
+pub fn parse_command(data: &str, command_prefix: &str) -> TextMessage {
+    if data == BEGIN_CMD {
+        return TextMessage::StartGame;
+    }
+
+    let data = match canonical_command(data, command_prefix) {
+        Some(command) => command,
+        None => {
+            return TextMessage::JustMessage(data.to_string());
+        }
+    };
+
+    let (name, args) = data.split_once(' ').unwrap_or((&data, ""));
+    // In group chats telegram adds the bot name to the command, e.g. /join@bot Вася
+    let name = name.split('@').next().unwrap_or_default();
+    COMMANDS
+        .iter()
+        .find(|command| command.name == name || command.aliases.contains(&name))
+        .and_then(|command| (command.parse)(args))
+        .unwrap_or(TextMessage::JustMessage(data))
+}
+
+type CallbackParser = fn(&str) -> Option<CallbackMessage>;
+
+// Callback data is a prefix followed by the arguments
+const CALLBACKS: &[(&str, CallbackParser)] = &[
+    ("/question", |args| {
+        let (topic_idx, cost) = args.rsplit_once('_')?;
+        Some(CallbackMessage::SelectedQuestion(
+            TopicIdx(topic_idx.parse().ok()?),
+            cost.parse().ok()?,
+        ))
+    }),
+    ("/topic", |args| Some(CallbackMessage::SelectedTopic(TopicIdx(args.parse().ok()?)))),
+    ("/cat_in_bag_choose_player_", |args| {
+        Some(CallbackMessage::CatInBagPlayerChosen(args.to_string()))
+    }),
+    ("/cat_in_bag_choose_cost_", |args| {
+        Some(CallbackMessage::CatInBagCostChosen(args.parse().ok()?))
+    }),
+    ("/manual_score_player_", |args| {
+        Some(CallbackMessage::ScoringPlayerChosen(args.to_string()))
+    }),
+];
+
+pub fn parse_callback(data: &Option<String>) -> CallbackMessage {
+    let data = match data {
+        Some(data) => data,
+        None => {
+            return CallbackMessage::Unknown;
+        }
+    };
+
+    match data.as_str() {
+        ANSWER_YES => return CallbackMessage::AnswerYes,
+        ANSWER_NO => return CallbackMessage::AnswerNo,
+        START_YES => return CallbackMessage::StartConfirmed,
+        START_NO => return CallbackMessage::StartCancelled,
+        MANUAL_SCORING_DONE => return CallbackMessage::ScoringDone,
+        _ => {}
+    }
+
+    CALLBACKS
+        .iter()
+        .find_map(|(prefix, parse)| data.strip_prefix(prefix).map(parse))
+        .flatten()
+        .unwrap_or(CallbackMessage::Unknown)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_command() {
+        assert_eq!(parse_command("/join Вася Пупкин", "/"), TextMessage::Join("Вася Пупкин".to_string()));
+        assert_eq!(parse_command("/join", "/"), TextMessage::JustMessage("/join".to_string()));
+        assert_eq!(parse_command("/next", "/"), TextMessage::NextQuestion);
+        assert_eq!(parse_command("/score now", "/"), TextMessage::JustMessage("/score now".to_string()));
+        assert_eq!(parse_command(BEGIN_CMD, "/"), TextMessage::StartGame);
+        assert_eq!(parse_command("/тренировка", "/"), TextMessage::Practice);
+        assert_eq!(parse_command("/pauseanswers", "/"), TextMessage::PauseAnswers);
+        assert_eq!(parse_command("просто текст", "/"), TextMessage::JustMessage("просто текст".to_string()));
+        assert_eq!(parse_command("/unknown", "/"), TextMessage::JustMessage("/unknown".to_string()));
+    }
+
+    #[test]
+    fn test_parse_command_with_args() {
+        assert_eq!(
+            parse_command("/updatescore имя с пробелом 100", "/"),
+            TextMessage::UpdateScore("имя с пробелом".to_string(), 100)
+        );
+        assert_eq!(
+            parse_command("/updatescore Вася -200", "/"),
+            TextMessage::UpdateScore("Вася".to_string(), -200)
+        );
+        assert_eq!(
+            parse_command("/updatescore Вася", "/"),
+            TextMessage::JustMessage("/updatescore Вася".to_string())
+        );
+        assert_eq!(
+            parse_command("/auction 500 Вася Пупкин", "/"),
+            TextMessage::UpdateAuctionCost("Вася Пупкин".to_string(), 500)
+        );
+        assert_eq!(
+            parse_command("/auction Вася 500", "/"),
+            TextMessage::JustMessage("/auction Вася 500".to_string())
+        );
+        assert_eq!(
+            parse_command("/hidequestion 200 Кино и театр", "/"),
+            TextMessage::HideQuestion("Кино и театр".to_string(), 200)
+        );
+        assert_eq!(
+            parse_command("/unhide 200 Кино и театр", "/"),
+            TextMessage::UnhideQuestion("Кино и театр".to_string(), 200, false)
+        );
+        assert_eq!(
+            parse_command("/unhide force 200 Кино", "/"),
+            TextMessage::UnhideQuestion("Кино".to_string(), 200, true)
+        );
+        assert_eq!(parse_command("/board", "/"), TextMessage::Board);
+        assert_eq!(parse_command("/board image", "/"), TextMessage::BoardImage);
+        assert_eq!(parse_command("/табло картинка", "/"), TextMessage::BoardImage);
+        assert_eq!(
+            parse_command("/board now", "/"),
+            TextMessage::JustMessage("/board now".to_string())
+        );
+    }
+
+    #[test]
+    fn test_aliases() {
+        assert_eq!(parse_command("/q", "/"), TextMessage::NextQuestion);
+        assert_eq!(parse_command("/next", "/"), TextMessage::NextQuestion);
+        assert_eq!(parse_command("/s", "/"), TextMessage::GetScore);
+        assert_eq!(parse_command("/us Вася 100", "/"), TextMessage::UpdateScore("Вася".to_string(), 100));
+        assert_eq!(parse_command("/regrade Вася +", "/"), TextMessage::Regrade("Вася".to_string(), true));
+        assert_eq!(parse_command("/last", "/"), TextMessage::Last(1));
+        assert_eq!(parse_command("/повтор 3", "/"), TextMessage::Last(3));
+        assert_eq!(parse_command("/last 0", "/"), TextMessage::JustMessage("/last 0".to_string()));
+        assert_eq!(
+            parse_command("/переоценка имя с пробелом -", "/"),
+            TextMessage::Regrade("имя с пробелом".to_string(), false)
+        );
+        assert_eq!(
+            parse_command("/regrade Вася 100", "/"),
+            TextMessage::JustMessage("/regrade Вася 100".to_string())
+        );
+        assert_eq!(parse_command("!q", "!"), TextMessage::NextQuestion);
+        assert_eq!(parse_command("/вопрос", "/"), TextMessage::NextQuestion);
+        assert_eq!(parse_command("/счёт", "/"), TextMessage::GetScore);
+        assert_eq!(parse_command("/счет", "/"), TextMessage::GetScore);
+        assert_eq!(parse_command("/игрок", "/"), TextMessage::CurrentPlayer);
+        assert_eq!(parse_command("/играю Вася", "/"), TextMessage::Join("Вася".to_string()));
+        assert_eq!(parse_command("/пас", "/"), TextMessage::AuctionPass);
+        assert_eq!(parse_command("/выбирает Вася", "/"), TextMessage::NextChooser("Вася".to_string()));
+        assert_eq!(parse_command("/nextchooser", "/"), TextMessage::JustMessage("/nextchooser".to_string()));
+    }
+
+    #[test]
+    fn test_help() {
+        let help = help_text("!");
+        assert!(help.starts_with("Команды:\n!join (!играю) ИМЯ - зарегистрироваться в игре\n"));
+        assert!(help.contains("\n!question (!next, !q, !вопрос) - следующий вопрос\n"));
+        assert!(help.contains("\n!updatescore (!us, !исправитьсчет) ИМЯ СЧЁТ - исправить счёт игрока"));
+    }
+
+    #[test]
+    fn test_command_with_bot_name() {
+        assert_eq!(parse_command("/join@svoyak_bot Вася", "/"), TextMessage::Join("Вася".to_string()));
+        assert_eq!(parse_command("/score@svoyak_bot", "/"), TextMessage::GetScore);
+        assert_eq!(parse_command("!score@svoyak_bot", "!"), TextMessage::GetScore);
+    }
+
+    #[test]
+    fn test_command_prefix() {
+        assert_eq!(parse_command("!score", "!"), TextMessage::GetScore);
+        assert_eq!(parse_command("/score", "!"), TextMessage::JustMessage("/score".to_string()));
+        assert_eq!(parse_command("/score", ""), TextMessage::JustMessage("/score".to_string()));
+    }
+
+    #[test]
+    fn test_this_chat_command() {
+        assert!(is_this_chat_command("/thischat", "/"));
+        assert!(is_this_chat_command("/thischat@svoyak_bot", "/"));
+        assert!(is_this_chat_command("!thischat", "!"));
+        assert!(!is_this_chat_command("/thischat", "!"));
+        assert!(!is_this_chat_command("/thischatnow", "/"));
+        assert!(!is_this_chat_command("/score@svoyak_bot", "/"));
+    }
+
+    #[test]
+    fn test_admin_action() {
+        assert!(TextMessage::Appeal.is_admin_action());
+        assert!(TextMessage::Regrade("Вася".to_string(), true).is_admin_action());
+        assert!(TextMessage::NextChooser("Вася".to_string()).is_admin_action());
+        assert!(!TextMessage::Board.is_admin_action());
+        assert!(CallbackMessage::ScoringDone.is_admin_action());
+        assert!(!CallbackMessage::CatInBagCostChosen(100).is_admin_action());
+    }
+
+    #[test]
+    fn test_parse_callback() {
+        let parse = |data: &str| parse_callback(&Some(data.to_string()));
+        assert_eq!(parse("/question1_200"), CallbackMessage::SelectedQuestion(TopicIdx(1), 200));
+        assert_eq!(parse("/questionx_200"), CallbackMessage::Unknown);
+        assert_eq!(parse("/topic3"), CallbackMessage::SelectedTopic(TopicIdx(3)));
+        assert_eq!(parse(ANSWER_YES), CallbackMessage::AnswerYes);
+        assert_eq!(parse(START_NO), CallbackMessage::StartCancelled);
+        assert_eq!(parse(MANUAL_SCORING_DONE), CallbackMessage::ScoringDone);
+        assert_eq!(
+            parse("/cat_in_bag_choose_player_Вася"),
+            CallbackMessage::CatInBagPlayerChosen("Вася".to_string())
+        );
+        assert_eq!(parse("/cat_in_bag_choose_cost_300"), CallbackMessage::CatInBagCostChosen(300));
+        assert_eq!(parse("/manual_score_player_Петя"), CallbackMessage::ScoringPlayerChosen("Петя".to_string()));
+        assert_eq!(parse_callback(&None), CallbackMessage::Unknown);
+    }
+}
@@ -1,19 +1,22 @@
 use std::collections::HashMap;
 use std::collections::HashSet;
-use std::convert::TryInto;
-use std::path::PathBuf;
+use std::collections::VecDeque;
+use std::fmt;
+use std::time::{Duration, Instant};
 
 use itertools::Itertools;
-use serde_derive::Serialize;
-use telegram_bot::UserId;
+use rand::{seq::SliceRandom, thread_rng};
+use serde_derive::{Deserialize, Serialize};
+use telegram_bot::{MessageId, UserId};
 
 use failure::{err_msg, Error};
 
 use crate::messages::*;
 use crate::player::Player;
-use crate::stickers::get_rand_sticker;
-use crate::question::Question;
+use crate::stickers::Stickers;
+use crate::question::{Media, Question};
 use crate::questionsstorage::{CatInBag, TourDescription, QuestionsStorage};
+use crate::timeout_stream::TimerId;
 
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
@@ -22,75 +25,521 @@ pub struct TopicIdx(pub usize);
 #[derive(Clone, Debug, Eq, PartialEq)]
 enum State {
     WaitingForPlayersToJoin,
+    WaitingForStartConfirmation,
     WaitingForTopic,
     WaitingForQuestion(TopicIdx),
     BeforeQuestionAsked(Question, i64),
     Falsestart(Question, i64),
     CanAnswer(Question, i64),
+    // Question of a blitz tour, anyone can answer until the timer fires
+    Blitz(Question, i64),
     WaitingForAuction(String, Question),
     // question, cost, anyone can answer
     Answering(Question, i64, bool),
 
     CatInBagChoosingPlayer(String, Question),
-    CatInBagChoosingCost(Question),
+    // Costs which can be chosen, derived from the topic of the cat in bag
+    CatInBagChoosingCost(Question, Vec<usize>),
+    // Current player sets the stake for the question of this nominal
+    StakeChoosing(Question, i64),
 
     Pause,
+    Finished,
+}
+
+impl State {
+    // Short name for logs, without the question
+    fn name(&self) -> &'static str {
+        match self {
+            State::WaitingForPlayersToJoin => "WaitingForPlayersToJoin",
+            State::WaitingForStartConfirmation => "WaitingForStartConfirmation",
+            State::WaitingForTopic => "WaitingForTopic",
+            State::WaitingForQuestion(..) => "WaitingForQuestion",
+            State::BeforeQuestionAsked(..) => "BeforeQuestionAsked",
+            State::Falsestart(..) => "Falsestart",
+            State::CanAnswer(..) => "CanAnswer",
+            State::Blitz(..) => "Blitz",
+            State::WaitingForAuction(..) => "WaitingForAuction",
+            State::Answering(..) => "Answering",
+            State::CatInBagChoosingPlayer(..) => "CatInBagChoosingPlayer",
+            State::CatInBagChoosingCost(..) => "CatInBagChoosingCost",
+            State::StakeChoosing(..) => "StakeChoosing",
+            State::Pause => "Pause",
+            State::Finished => "Finished",
+        }
+    }
+}
+
+// Opening of the game, media is sent before the text. The default greeting
+// is used if there is no text
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Intro {
+    pub text: Option<String>,
+    // Telegram file id
+    pub sticker: Option<String>,
+    // Path or url
+    pub image: Option<String>,
+}
+
+impl Intro {
+    fn requests(&self) -> Vec<UiRequest> {
+        let mut res = vec![];
+        if let Some(ref sticker) = self.sticker {
+            res.push(UiRequest::SendSticker(sticker.clone()));
+        }
+        if let Some(ref image) = self.image {
+            let image = if image.starts_with("http") {
+                Media::Url(image.clone())
+            } else {
+                Media::File(image.into())
+            };
+            res.push(UiRequest::SendImage(image));
+        }
+        let text = match self.text {
+            Some(ref text) => text.clone(),
+            None => "Здравствуйте, здравствуйте, добрый день! Это своя игра!".to_string(),
+        };
+        res.push(UiRequest::SendTextToMainChat(text));
+        res
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct GameConfig {
+    // How many players can be credited for a single manual question
+    pub manual_question_answerers: usize,
+    // Accept the answer automatically if the answering player typed it
+    pub auto_judge: bool,
+    // Game is finished when it elapses
+    pub game_duration: Option<Duration>,
+    // How many players have to join before the game can be started
+    pub min_players: usize,
+    // Show the score table and the ranking at the end of every tour
+    pub tour_recap: bool,
+    // Auction bidding is closed if nobody raises the bid during this time
+    pub auction_bid_timeout: Duration,
+    // Send cat in bag question privately to the player who plays it
+    pub cat_in_bag_dm: bool,
+    // Show who chooses the next question under the score table
+    pub highlight_current_player: bool,
+    // Added to the duration of question audio before the answers are allowed
+    pub audio_answer_buffer: Duration,
+    pub stickers: Stickers,
+    pub phrases: Phrases,
+    // Registration is closed when it elapses, None means unlimited
+    pub registration_window: Option<Duration>,
+    // Repeated /join of the same user within this time are ignored
+    pub join_cooldown: Duration,
+    // Remind the player in the private chat that it's their turn to choose
+    pub turn_dm: bool,
+    // Admin may join the game as a player
+    pub admin_can_play: bool,
+    // None means any number of players can join
+    pub max_players: Option<usize>,
+    // Show who chose the question and who answered it when it's closed
+    pub question_attribution: bool,
+    pub delays: Delays,
+    // Interval of the "alive" log line, no heartbeat if it's not set
+    pub heartbeat: Option<Duration>,
+    // Heartbeat is sent to the admin as well
+    pub heartbeat_to_admin: bool,
+    // Message with the remaining answer time which is edited on every tick
+    pub answer_countdown: bool,
+    pub intro: Intro,
+    // Yes/no taps on the same prompt which come sooner than this after the
+    // previous one are ignored, e.g. double taps from laggy clients
+    pub judgment_debounce: Duration,
+    // Number of played questions which are kept for /last
+    pub last_questions: usize,
+    // Text questions up to the first length get a short delay before the
+    // answers are allowed, up to the second one - a medium delay
+    pub length_thresholds: (usize, usize),
+    // Player names can't start with it, so that they don't look like commands
+    pub command_prefix: String,
+}
+
+impl Default for GameConfig {
+    fn default() -> Self {
+        Self {
+            manual_question_answerers: 1,
+            auto_judge: false,
+            game_duration: None,
+            min_players: 2,
+            tour_recap: false,
+            auction_bid_timeout: Duration::from_secs(15),
+            cat_in_bag_dm: false,
+            highlight_current_player: false,
+            audio_answer_buffer: Duration::from_secs(3),
+            stickers: Stickers::default(),
+            phrases: Phrases::default(),
+            registration_window: None,
+            join_cooldown: Duration::from_secs(0),
+            turn_dm: false,
+            admin_can_play: true,
+            max_players: None,
+            question_attribution: false,
+            delays: Delays::default(),
+            heartbeat: None,
+            heartbeat_to_admin: false,
+            answer_countdown: false,
+            intro: Intro::default(),
+            judgment_debounce: Duration::from_secs(0),
+            last_questions: 5,
+            length_thresholds: (100, 230),
+            command_prefix: String::from("/"),
+        }
+    }
+}
+
+// Question which was played to the end, kept for /last
+struct PlayedQuestion {
+    question: Question,
+    answered: Option<Player>,
+}
+
+// Last judgment of the admin, kept to be able to appeal it
+struct Judgment {
+    question: Question,
+    cost: i64,
+    player: Player,
+    score_delta: i64,
+    player_which_chose_question: Option<Player>,
+}
+
+// Bids of the auction which is being played right now
+struct AuctionBidding {
+    nominal: i64,
+    // Every bid has to be at least that much higher than the previous one
+    step: i64,
+    high_bid: Option<(Player, i64)>,
+    // Players who dropped out of the bidding
+    passed: HashSet<Player>,
+}
+
+// Solo drill with random questions instead of the board
+struct Practice {
+    // Topic, difficulty and cost of the questions which weren't asked yet
+    unused: Vec<(String, usize, usize)>,
+    // Topics get asked more often the more mistakes were made in them
+    mistakes: HashMap<String, usize>,
+}
+
+struct ManualScoring {
+    question: Question,
+    cost: i64,
+    credited: Vec<Player>,
 }
 
 pub struct GameState {
     admin_user: UserId,
+    config: GameConfig,
     state: State,
     players: HashMap<Player, i64>,
+    // Users who started a private chat with the bot
+    private_chats: HashSet<UserId>,
     current_player: Option<Player>,
     player_which_chose_question: Option<Player>,
     questions: Vec<(String, Vec<usize>)>,
+    // Questions of the current tour which were asked, as opposed to hidden ones
+    played_questions: HashSet<(String, usize)>,
+    // Topic of the question that is being played and points that
+    // questions of each topic brought to players during the tour
+    current_topic: Option<String>,
+    topic_points: HashMap<String, i64>,
     players_falsestarted: HashSet<Player>,
     players_answered_current_question: HashSet<Player>,
-    questions_per_topic: usize,
+    // Number of questions in every topic of every tour
+    topic_sizes: Vec<Vec<usize>>,
     tours: Vec<TourDescription>,
     current_tour: usize,
+    tour_recap_shown: bool,
     current_multiplier: usize,
     manual_questions: Vec<(String, usize)>,
     cats_in_bags: Vec<CatInBag>,
     auctions: Vec<(String, usize)>,
+    stakes: Vec<(String, usize)>,
+    single_answer_questions: Vec<(String, usize)>,
+    // Costs set in the pack, other questions cost difficulty * multiplier
+    custom_costs: HashMap<(String, usize), usize>,
+    // Difficulty of the question by topic and cost in the current tour
+    question_difficulties: HashMap<(String, usize), usize>,
+    auction_bidding: Option<AuctionBidding>,
+    manual_scoring: Option<ManualScoring>,
+    last_judgment: Option<Judgment>,
+    // Cost of the question which was just closed, for /regrade
+    last_question_cost: Option<i64>,
+    // Each player is regraded at most once for the closed question
+    regraded_players: HashSet<Player>,
+    practice: Option<Practice>,
+    // When the current question became open for answers
+    can_answer_since: Option<Instant>,
+    // When the time to answer the last question ran out
+    answers_closed_at: Option<Instant>,
+    // Prompt of the last admin judgment and when it was made
+    last_judged: Option<(MessageId, Instant)>,
+    // Answer of the closed question which is sent when the reveal timer fires
+    pending_reveal: Option<String>,
+    // Most recent questions are at the back
+    last_questions: VecDeque<PlayedQuestion>,
+    // Admin temporarily stopped accepting buzzes for the open question
+    answers_paused: bool,
+    // Blocks left in the countdown message and time between the ticks
+    countdown: Option<(u32, Duration)>,
+    registration_closed: bool,
+    last_join_attempts: HashMap<UserId, Instant>,
+    // How long it took players to press the button
+    response_times: HashMap<Player, Vec<Duration>>,
+    // How many questions every player answered correctly
+    correct_answers: HashMap<Player, usize>,
+    // Notable game events in the order they happened
+    history: Vec<String>,
 }
 
 pub enum UiRequest {
     SendTextToMainChat(String),
+    // Text of the question, replies to it are buzzes
+    SendQuestionText(String),
     RightBeforeAskingQuestion(String),
     SendSticker(String),
-    SendImage(PathBuf),
-    SendAudio(PathBuf),
-    Timeout(Option<String>, Delay),
-    // 3rd parameter is telegram's username
-    ChooseTopic(String, Vec<(TopicIdx, String)>, Option<String>),
+    SendImage(Media),
+    // Several images shown together as an album
+    SendMediaGroup(Vec<Media>),
+    SendAudio(Media),
+    SendAnimation(Media),
+    SendDocument(Media),
+    // Text and attachments sent to the private chat of the user
+    SendPrivately(UserId, Vec<UiRequest>),
+    Timeout(Option<String>, Duration),
+    // Timer which isn't related to the current question
+    ScheduleTimer(TimerId, Duration),
+    ChooseTopic(Player, Vec<(TopicIdx, String)>),
     // 3rd parameter is telegram's username
     ChooseQuestion(TopicIdx, String, Vec<usize>, Option<String>),
     AskAdminYesNo(String),
+    // Admin has to confirm the list of players before the game starts
+    AskAdminConfirmStart(String),
     SendToAdmin(String),
     SendScoreTable(ScoreTable),
+    // Save score table as html file
+    ExportScoreTable(ScoreTable),
+    // Save the transcript of the game to a text file
+    ExportTranscript(String),
+    // Final scores of all players
+    GameFinished(Vec<(Player, i64)>),
+    // Final ranking shown at the end of the game
+    SendGameSummary(GameSummary),
+    // Remove downloaded questions and attachments
+    ClearCache,
     StopTimer,
     CatInBagChoosePlayer(Vec<Player>),
     CatInBagChooseCost(Vec<usize>),
+    // Sent to admin to choose who answered a manual question correctly
+    ChooseScoringPlayer(Vec<Player>),
+    // New countdown message in the main chat
+    StartCountdown(String),
+    // Edits the countdown message
+    UpdateCountdown(String),
+    // Last edit of the countdown message, it isn't updated anymore
+    FinishCountdown(String),
 }
 
+// Step of the question the timer is waiting for, durations come from `Delays`,
+// which only game state looks up
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum Delay {
-    Short,
-    Medium,
-    Long,
-    ExtraLong,
+    // Between the announcement of the topic and the question
+    PreQuestion,
+    // Falsestart window, it depends on the length of the question
+    ShortQuestion,
+    MediumQuestion,
+    LongQuestion,
+    // Falsestart window of a question with images, animation or a document
+    MediaQuestion,
+    // Falsestart window of an audio question with unknown length
+    AudioQuestion,
+    // Time to press the button
+    AnswerWindow,
+    // Time to press the button after somebody answered incorrectly
+    RetryWindow,
+    // Players who falsestarted can't answer during this time
+    FalsestartPenalty,
+    // Time to answer a blitz question
+    Blitz,
+    // Between a closed blitz question and the next one
+    BlitzPause,
+    // Between the end of the question and the message with the answer, the
+    // answer is shown right away if it's 0
+    PostAnswer,
+}
+
+#[derive(Clone, Debug)]
+pub struct Delays(HashMap<Delay, Duration>);
+
+impl Default for Delays {
+    fn default() -> Self {
+        let delays = vec![
+            (Delay::PreQuestion, 5),
+            (Delay::ShortQuestion, 3),
+            (Delay::MediumQuestion, 5),
+            (Delay::LongQuestion, 10),
+            (Delay::MediaQuestion, 10),
+            (Delay::AudioQuestion, 15),
+            (Delay::AnswerWindow, 15),
+            (Delay::RetryWindow, 10),
+            (Delay::FalsestartPenalty, 3),
+            (Delay::Blitz, 5),
+            (Delay::BlitzPause, 3),
+            (Delay::PostAnswer, 0),
+        ];
+        Self(
+            delays
+                .into_iter()
+                .map(|(delay, secs)| (delay, Duration::from_secs(secs)))
+                .collect(),
+        )
+    }
+}
+
+impl Delays {
+    // Delays which aren't overridden keep their default durations
+    pub fn new(overrides: HashMap<Delay, Duration>) -> Self {
+        let mut delays = Self::default();
+        delays.0.extend(overrides);
+        delays
+    }
+
+    pub fn get(&self, delay: Delay) -> Duration {
+        self.0.get(&delay).cloned().unwrap_or_default()
+    }
 }
 
 #[derive(Serialize)]
-struct ScoreTableItem {
-    name: String,
-    questions: Vec<usize>,
+pub(crate) struct ScoreTableItem {
+    pub(crate) name: String,
+    pub(crate) questions: Vec<usize>,
+    // Number of questions already played in the topic
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) played: Option<usize>,
 }
 
 #[derive(Serialize)]
 pub struct ScoreTable {
-    scores: Vec<usize>,
-    data: Vec<ScoreTableItem>,
+    pub(crate) scores: Vec<usize>,
+    pub(crate) data: Vec<ScoreTableItem>,
+    // Player who chooses the next question, shown under the table
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) current_player: Option<String>,
+    // Current totals of the players, best first
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub(crate) standings: Vec<PublicScore>,
+}
+
+#[derive(Serialize)]
+pub(crate) struct GameSummaryRow {
+    pub(crate) rank: u32,
+    pub(crate) name: String,
+    pub(crate) score: i64,
+    // Number of correctly answered questions
+    pub(crate) answered: usize,
+    pub(crate) winner: bool,
+}
+
+#[derive(Serialize)]
+pub struct GameSummary {
+    pub(crate) players: Vec<GameSummaryRow>,
+}
+
+#[derive(Serialize)]
+pub(crate) struct PublicScore {
+    pub(crate) name: String,
+    pub(crate) score: i64,
+}
+
+#[derive(Serialize)]
+struct PublicTopic {
+    name: String,
+    costs: Vec<usize>,
+}
+
+// Read-only view of the game for external dashboards
+#[derive(Serialize)]
+pub struct PublicState {
+    current_player: Option<String>,
+    scores: Vec<PublicScore>,
+    // Starts from 1, 0 means the game hasn't started
+    current_tour: usize,
+    remaining_questions: Vec<PublicTopic>,
+}
+
+impl fmt::Display for GameSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Итоги игры:")?;
+        for row in &self.players {
+            write!(
+                f,
+                "\n{}. {}{}: {} (ответов: {})",
+                row.rank,
+                row.name,
+                if row.winner { " 🏆" } else { "" },
+                row.score,
+                row.answered
+            )?;
+        }
+        Ok(())
+    }
+}
+
+const MAX_PLAYER_NAME_LEN: usize = 32;
+
+// Buzzes within this time after the answers were closed get a reply that
+// they were too late
+const LATE_BUZZ_WINDOW: Duration = Duration::from_secs(3);
+
+// Number of blocks in the countdown message, one disappears on every tick
+const COUNTDOWN_STEPS: u32 = 5;
+
+fn countdown_text(left: u32) -> String {
+    format!(
+        "⏱ {}{}",
+        "🟩".repeat(left as usize),
+        "⬜".repeat((COUNTDOWN_STEPS - left) as usize)
+    )
+}
+
+// Trims the name and collapses internal whitespace. Returns the message
+// for the main chat if the name can't be used.
+fn normalize_player_name(name: &str, command_prefix: &str) -> Result<String, String> {
+    let name = name.split_whitespace().join(" ");
+    if name.is_empty() {
+        return Err(String::from("Имя игрока не может быть пустым"));
+    }
+    if name.chars().count() > MAX_PLAYER_NAME_LEN {
+        return Err(String::from("Слишком длинное имя игрока"));
+    }
+    if !command_prefix.is_empty() && name.starts_with(command_prefix) {
+        return Err(format!("Имя игрока не может начинаться с {}", command_prefix));
+    }
+    Ok(name)
+}
+
+// Shown in the main chat whenever a question is closed
+fn format_answer_reveal(question: &Question) -> String {
+    let mut msg = format!("Правильный ответ: {}", question.answer());
+    if let Some(comments) = question.comments() {
+        if !comments.is_empty() {
+            msg.push_str(&format!("\nКомментарий: {}", comments));
+        }
+    }
+    msg
+}
+
+fn format_admin_question(question: &Question) -> String {
+    let mut msg = format!("question: {}\nanswer: {}", question.question(), question.answer());
+    if !question.alt_answers().is_empty() {
+        msg.push_str(&format!("\nalso accepted: {}", question.alt_answers().join(", ")));
+    }
+    msg
 }
 
 impl ScoreTable {
@@ -105,15 +554,37 @@ impl ScoreTable {
             }
         }
 
+        let scores: Vec<String> = self.scores.iter().map(|score| score.to_string()).collect();
+
+        let mut header = String::from("|");
+        header.push_str(&pad_to_width(String::new(), topic_length));
+        header.push('|');
+        for score in scores.iter() {
+            header.push_str(score);
+            header.push('|');
+        }
+
+        let has_played = self.data.iter().any(|item| item.played.is_some());
+        let played_width = self
+            .data
+            .iter()
+            .filter_map(|item| item.played)
+            .map(|played| played.to_string().chars().count())
+            .chain(std::iter::once(PLAYED_HEADER.chars().count()))
+            .max()
+            .unwrap_or_default();
+        if has_played {
+            header.push_str(&pad_to_width(PLAYED_HEADER.to_string(), played_width));
+            header.push('|');
+        }
+        rows.push(header);
+
         for ref item in self.data.iter() {
             let mut row = String::from("|");
-            row.push_str(&item.name);
-            while row.chars().count() < topic_length + 1 {
-                row.push_str(" ");
-            }
+            row.push_str(&pad_to_width(item.name.clone(), topic_length));
             row.push_str("|");
 
-            for score in self.scores.iter() {
+            for (score, score_str) in self.scores.iter().zip(scores.iter()) {
                 let mut found = false;
                 for this_score in item.questions.iter() {
                     if this_score == score {
@@ -121,69 +592,208 @@ impl ScoreTable {
                         break;
                     }
                 }
-                if found {
-                    row.push_str("x");
+                let cell = if found {
+                    String::from("x")
                 } else {
-                    row.push_str(" ");
-                }
+                    String::new()
+                };
+                row.push_str(&pad_to_width(cell, score_str.chars().count()));
                 row.push_str("|");
             }
 
+            if has_played {
+                let played = item.played.map(|played| played.to_string()).unwrap_or_default();
+                row.push_str(&pad_to_width(played, played_width));
+                row.push('|');
+            }
+
             rows.push(row);
         }
 
+        if let Some(ref current_player) = self.current_player {
+            rows.push(format!("Выбирает: {}", current_player));
+        }
+
         rows.join("\n")
     }
+
+    pub fn to_html(&self) -> String {
+        let has_played = self.data.iter().any(|item| item.played.is_some());
+
+        let mut html = String::from(SCORE_TABLE_HTML_HEADER);
+        html.push_str("<table class=\"score-table\">\n<tr><th></th>");
+        for score in self.scores.iter() {
+            html.push_str(&format!("<th>{}</th>", score));
+        }
+        if has_played {
+            html.push_str(&format!("<th>{}</th>", PLAYED_HEADER));
+        }
+        html.push_str("</tr>\n");
+
+        for item in self.data.iter() {
+            html.push_str(&format!("<tr><td class=\"topic\">{}</td>", escape_html(&item.name)));
+            for score in self.scores.iter() {
+                if item.questions.contains(score) {
+                    html.push_str(&format!("<td class=\"available\">{}</td>", score));
+                } else {
+                    html.push_str("<td class=\"played\"></td>");
+                }
+            }
+            if has_played {
+                let played = item.played.map(|played| played.to_string()).unwrap_or_default();
+                html.push_str(&format!("<td class=\"total\">{}</td>", played));
+            }
+            html.push_str("</tr>\n");
+        }
+        html.push_str("</table>\n</body>\n</html>\n");
+        html
+    }
+}
+
+const SCORE_TABLE_HTML_HEADER: &str = "<!DOCTYPE html>
+<html>
+<head>
+<meta charset=\"utf-8\">
+<style>
+body { background: #1e1e64; font-family: Arial, sans-serif; }
+.score-table { border-collapse: collapse; color: #ffeead; font-size: 28px; }
+.score-table th, .score-table td { border: 3px solid #645014; padding: 8px 16px; text-align: center; }
+.score-table td.topic { text-align: left; }
+.score-table td.total { color: #a0a0c8; }
+</style>
+</head>
+<body>
+";
+
+const PLAYED_HEADER: &str = "Σ";
+
+fn pad_to_width(mut s: String, width: usize) -> String {
+    while s.chars().count() < width {
+        s.push(' ');
+    }
+    s
 }
 
 impl GameState {
     pub fn new(
         admin_user: UserId,
         questions_storage: &Box<dyn QuestionsStorage>,
-        questions_per_topic: usize,
+        // None means that topics can have different number of questions,
+        // it's detected from the pack
+        questions_per_topic: Option<usize>,
+        config: GameConfig,
     ) -> Result<Self, Error> {
-        if questions_per_topic == 0 {
+        if questions_per_topic == Some(0) {
             return Err(err_msg(String::from("questions per topic can't be zero")));
         }
         let tours = questions_storage.get_tours();
+        // Otherwise the game would stall right after the start
+        if tours.iter().all(|tour| tour.topics.is_empty()) {
+            return Err(err_msg(format!(
+                "questions pack is empty: {} tours and no topics were loaded",
+                tours.len()
+            )));
+        }
+        let mut topic_sizes = vec![];
         for tour in tours.iter() {
+            let mut sizes = vec![];
             for topic in tour.topics.iter() {
-                for i in 0..questions_per_topic {
-                    let question_num = i + 1;
-                    let topic_name = &topic.name;
-                    if questions_storage.get(topic_name.clone(), i + 1).is_none() {
-                        return Err(err_msg(format!(
-                            "{} is not found in {}",
-                            topic_name, question_num
-                        )));
+                let topic_name = &topic.name;
+                let size = match questions_per_topic {
+                    Some(questions_per_topic) => {
+                        for i in 0..questions_per_topic {
+                            let question_num = i + 1;
+                            if !questions_storage.contains(topic_name.clone(), i + 1) {
+                                return Err(err_msg(format!(
+                                    "{} is not found in {}",
+                                    topic_name, question_num
+                                )));
+                            }
+                        }
+                        questions_per_topic
                     }
-                }
+                    None => {
+                        let size = (1..)
+                            .take_while(|i| questions_storage.contains(topic_name.clone(), *i))
+                            .count();
+                        if size == 0 {
+                            return Err(err_msg(format!("{} has no questions", topic_name)));
+                        }
+                        size
+                    }
+                };
+                sizes.push(size);
             }
+            topic_sizes.push(sizes);
         }
 
         let manual_questions = questions_storage.get_manual_questions();
 
         Ok(Self {
             admin_user,
+            config,
             state: State::WaitingForPlayersToJoin,
             players: HashMap::new(),
+            private_chats: HashSet::new(),
             player_which_chose_question: None,
             current_player: None,
             questions: Vec::new(),
+            played_questions: HashSet::new(),
+            current_topic: None,
+            topic_points: HashMap::new(),
             players_falsestarted: HashSet::new(),
             players_answered_current_question: HashSet::new(),
-            questions_per_topic,
+            topic_sizes,
             tours,
             current_tour: 0,
+            tour_recap_shown: false,
             current_multiplier: 0,
             manual_questions,
             cats_in_bags: questions_storage.get_cats_in_bags(),
             auctions: questions_storage.get_auctions(),
+            stakes: questions_storage.get_stakes(),
+            single_answer_questions: questions_storage.get_single_answer_questions(),
+            custom_costs: questions_storage.get_custom_costs(),
+            question_difficulties: HashMap::new(),
+            auction_bidding: None,
+            manual_scoring: None,
+            last_judgment: None,
+            last_question_cost: None,
+            regraded_players: HashSet::new(),
+            can_answer_since: None,
+            registration_closed: false,
+            last_join_attempts: HashMap::new(),
+            practice: None,
+            answers_closed_at: None,
+            last_judged: None,
+            pending_reveal: None,
+            last_questions: VecDeque::new(),
+            answers_paused: false,
+            countdown: None,
+            response_times: HashMap::new(),
+            correct_answers: HashMap::new(),
+            history: vec![],
         })
     }
 
     fn set_state(&mut self, state: State) {
+        let cost = match self.state {
+            State::Falsestart(_, cost)
+            | State::CanAnswer(_, cost)
+            | State::Blitz(_, cost)
+            | State::Answering(_, cost, _) => Some(cost),
+            _ => None,
+        };
         self.state = state;
+        // Regrade is possible only until anything else happens in the game
+        self.last_question_cost = match self.state {
+            State::Pause => cost,
+            _ => None,
+        };
+        self.regraded_players.clear();
+        if !matches!(self.state, State::CanAnswer(..)) {
+            self.answers_paused = false;
+        }
         match self.state {
             State::WaitingForQuestion(_) => {
                 eprintln!("/question command was executed");
@@ -194,6 +804,7 @@ impl GameState {
 
                 self.players_falsestarted.clear();
                 self.players_answered_current_question.clear();
+                self.can_answer_since = None;
             }
             State::Answering(_, _, _) => {
                 eprintln!(
@@ -212,9 +823,14 @@ impl GameState {
             }
             State::CanAnswer(_, _) => {
                 eprintln!("Now it is ok to answer the question");
+                self.can_answer_since = Some(Instant::now());
+            }
+            State::Blitz(_, _) => {
+                eprintln!("Blitz question is asked");
+                self.can_answer_since = Some(Instant::now());
             }
             State::WaitingForAuction(..) => {
-                eprintln!("Waiting for an auction cost to be decided");
+                eprintln!("Waiting for players to bid in the auction");
             }
             State::Pause => {
                 eprintln!("The game is paused");
@@ -228,6 +844,15 @@ impl GameState {
             State::CatInBagChoosingCost(..) => {
                 eprintln!("Waiting while cat in bag cost is chosen");
             }
+            State::StakeChoosing(..) => {
+                eprintln!("Waiting for the stake of the current player");
+            }
+            State::WaitingForStartConfirmation => {
+                eprintln!("Waiting for the admin to confirm the list of players");
+            }
+            State::Finished => {
+                eprintln!("The game is finished");
+            }
         }
     }
 
@@ -247,93 +872,503 @@ impl GameState {
             }
         };
 
-        if let Some(player) = self.find_player_by_name(&name) {
-            self.current_player = Some(player.clone());
+        let player = match self.find_player_by_name(&name) {
+            Some(player) => player.clone(),
+            None => {
+                eprintln!("user {} not found", name);
+                return vec![];
+            }
+        };
+
+        // Same limits as for the bids, a player who has less than the nominal
+        // plays for the nominal
+        let nominal = self
+            .auction_bidding
+            .as_ref()
+            .map(|bidding| bidding.nominal)
+            .unwrap_or(self.cost_step() as i64);
+        let score = self.players.get(&player).cloned().unwrap_or_default();
+        let max_cost = score.max(nominal);
+        let cost = cost as i64;
+        if cost < nominal || cost > max_cost {
+            return vec![UiRequest::SendToAdmin(format!(
+                "Invalid auction cost {} for {}, it should be from {} to {}",
+                cost,
+                player.name(),
+                nominal,
+                max_cost
+            ))];
+        }
+
+        self.play_auction(topic, question, player, cost)
+    }
+
+    // Player sent a message while the auction is open. Either a number or "ва-банк"
+    // is a bid, everything else is ignored
+    fn auction_bid(&mut self, user: UserId, message: &str) -> Vec<UiRequest> {
+        let player = match self.find_player(user) {
+            Some(player) => player.clone(),
+            None => {
+                return vec![];
+            }
+        };
+        let score = self.players.get(&player).cloned().unwrap_or_default();
+        let bidding = match self.auction_bidding.as_mut() {
+            Some(bidding) => bidding,
+            None => {
+                return vec![];
+            }
+        };
+
+        let message = message.trim().to_lowercase();
+        if message == "пас" {
+            return self.auction_pass(user);
+        }
+        if bidding.passed.contains(&player) {
+            return vec![UiRequest::SendTextToMainChat(format!("{}, вы уже спасовали", player.name()))];
+        }
+        let all_in = message == "ва-банк" || message == "вабанк" || message == "ва банк";
+        let bid = if all_in {
+            score
+        } else {
+            match message.parse::<i64>() {
+                Ok(bid) => bid,
+                Err(_) => {
+                    return vec![];
+                }
+            }
+        };
+
+        let high_bid = bidding.high_bid.as_ref().map(|(_, bid)| *bid);
+        let error = if bid < bidding.nominal {
+            Some(format!("Ставка должна быть не меньше {}", bidding.nominal))
+        } else if bid > score && (bid > bidding.nominal || high_bid.is_some()) {
+            // Player who has less than the nominal can only play for the nominal
+            Some(format!("{}, у вас только {} очков", player.name(), score))
+        } else {
+            match high_bid {
+                // Going all-in is allowed with any raise
+                Some(high_bid) if bid == score && bid > high_bid => None,
+                Some(high_bid) if bid < high_bid + bidding.step => {
+                    Some(format!("Ставка должна быть не меньше {}", high_bid + bidding.step))
+                }
+                _ => None,
+            }
+        };
+        if let Some(error) = error {
+            return vec![UiRequest::SendTextToMainChat(error)];
+        }
+
+        bidding.high_bid = Some((player.clone(), bid));
+        let text = if bid == score {
+            format!("{} идёт ва-банк: {}", player.name(), bid)
         } else {
-            eprintln!("user {} not found", name);
+            format!("Ставка {}: {}", player.name(), bid)
+        };
+        vec![
+            UiRequest::SendTextToMainChat(text),
+            UiRequest::ScheduleTimer(TimerId::Auction, self.config.auction_bid_timeout),
+        ]
+    }
+
+    // Player drops out of the bidding. Bidding is over once all but one player passed
+    pub fn auction_pass(&mut self, user: UserId) -> Vec<UiRequest> {
+        let player = match self.find_player(user) {
+            Some(player) => player.clone(),
+            None => {
+                return vec![];
+            }
+        };
+        let bidding = match (&self.state, self.auction_bidding.as_mut()) {
+            (State::WaitingForAuction(..), Some(bidding)) => bidding,
+            _ => {
+                eprintln!("{} tried to pass, but there is no auction", player.name());
+                return vec![];
+            }
+        };
+
+        if bidding.high_bid.as_ref().map(|(bidder, _)| bidder) == Some(&player) {
+            return vec![UiRequest::SendTextToMainChat(format!(
+                "{}, ваша ставка самая высокая",
+                player.name()
+            ))];
+        }
+        if !bidding.passed.insert(player.clone()) {
             return vec![];
         }
 
-        self.player_which_chose_question = self.current_player.clone();
+        let mut res = vec![UiRequest::SendTextToMainChat(format!("{} пасует", player.name()))];
+        let passed = bidding.passed.len();
+        if passed + 1 >= self.players.len() {
+            res.extend(self.close_auction());
+        }
+        res
+    }
+
+    // Nobody raised the bid in time or all but one player passed, so the highest
+    // bidder plays the question. If there were no bids the only player who didn't
+    // pass plays it for the nominal, otherwise it's the player who chose the
+    // auction unless they passed too. Then nobody plays and the question is closed
+    pub fn close_auction(&mut self) -> Vec<UiRequest> {
+        let (topic, question) = match &self.state {
+            State::WaitingForAuction(topic, question) => (topic.clone(), question.clone()),
+            _ => {
+                eprintln!("Auction is already closed");
+                return vec![];
+            }
+        };
+        let bidding = match self.auction_bidding.take() {
+            Some(bidding) => bidding,
+            None => {
+                return vec![];
+            }
+        };
+
+        let mut not_passed = self
+            .players
+            .keys()
+            .filter(|player| !bidding.passed.contains(player));
+        let last_not_passed = match (not_passed.next(), not_passed.next()) {
+            (Some(player), None) => Some(player.clone()),
+            _ => None,
+        };
+
+        let chooser = self
+            .current_player
+            .clone()
+            .filter(|player| !bidding.passed.contains(player));
+        let (player, cost) = match bidding.high_bid {
+            Some(high_bid) => high_bid,
+            None => match last_not_passed.or(chooser) {
+                Some(player) => (player, bidding.nominal),
+                None if self.current_player.is_some() => {
+                    self.log_event("Nobody plays the auction".to_string());
+                    return self.close_unanswered_question(question, Some("Никто не играет аукцион".to_string()));
+                }
+                None => {
+                    eprintln!("Nobody bid in the auction and there is no current player");
+                    return vec![UiRequest::SendToAdmin(format!(
+                        "Nobody bid in the auction, use {}auction to choose a player",
+                        self.config.command_prefix
+                    ))];
+                }
+            },
+        };
+
+        self.play_auction(topic, question, player, cost)
+    }
+
+    fn play_auction(&mut self, topic: String, question: Question, player: Player, cost: i64) -> Vec<UiRequest> {
+        self.auction_bidding = None;
+        self.log_event(format!("{} plays the auction for {}", player.name(), cost));
+        self.current_player = Some(player.clone());
 
         // Only this player can answer
-        self.set_state(State::Answering(question.clone(), cost.try_into().unwrap(), false));
+        self.set_state(State::Answering(question.clone(), cost, false));
 
         let mut res = vec![
-            UiRequest::SendTextToMainChat(format!("Играем аукцион с {}, тема {}, стоимость {}", name, topic, cost)),
+            UiRequest::SendTextToMainChat(format!(
+                "Играем аукцион с {}, тема {}, стоимость {}",
+                player.name(),
+                topic,
+                cost
+            )),
         ];
         res.extend(self.format_question(&question));
         res.push(UiRequest::AskAdminYesNo("Correct answer?".to_string()));
         res
     }
 
+    // Bot can write to the user only after they started a private chat with it
+    pub fn private_chat_started(&mut self, user: UserId) {
+        self.private_chats.insert(user);
+    }
+
     fn format_question(&self, question: &Question) -> Vec<UiRequest> {
         let mut res = vec![];
-        if let Some(image) = question.image() {
-            res.push(UiRequest::SendImage(image.to_path_buf()));
+        match question.images().as_slice() {
+            [] => {}
+            [image] => res.push(UiRequest::SendImage(image.clone())),
+            images => res.push(UiRequest::SendMediaGroup(images.to_vec())),
+        }
+        if let Some(animation) = question.animation() {
+            res.push(UiRequest::SendAnimation(animation.clone()));
         }
         if let Some(audio) = question.audio() {
-            res.push(UiRequest::SendAudio(audio.to_path_buf()));
+            res.push(UiRequest::SendAudio(audio.clone()));
         }
-        let question_msg = question.question();
-        res.push(UiRequest::SendTextToMainChat(question_msg));
+        if let Some(document) = question.document() {
+            res.push(UiRequest::SendDocument(document.clone()));
+        }
+        res.push(UiRequest::SendQuestionText(question.question().to_string()));
         res
     }
 
     pub fn add_player(&mut self, new_user: UserId, name: String, username: Option<String>) -> Vec<UiRequest> {
-        if self.state != State::WaitingForPlayersToJoin {
-            println!("{} tried to join, but the game has already started", name);
-            return vec![];
+        match self.state {
+            State::WaitingForPlayersToJoin if !self.registration_closed => {}
+            // Registration window has elapsed or the admin is starting the game
+            State::WaitingForPlayersToJoin | State::WaitingForStartConfirmation => {
+                println!("{} tried to join, but the registration is closed", name);
+                return vec![UiRequest::SendTextToMainChat(String::from("Регистрация закрыта"))];
+            }
+            _ => {
+                println!("{} tried to join, but the game has already started", name);
+                return vec![];
+            }
         }
 
-        if !self.find_player(new_user).is_none() {
-            vec![UiRequest::SendTextToMainChat(String::from(
-                "Такой игрок уже существует",
-            ))]
+        let now = Instant::now();
+        if let Some(last_attempt) = self.last_join_attempts.get(&new_user) {
+            if now.duration_since(*last_attempt) < self.config.join_cooldown {
+                eprintln!("{} tries to join too often", new_user);
+                return vec![];
+            }
+        }
+        self.last_join_attempts.insert(new_user, now);
+
+        if new_user == self.admin_user && !self.config.admin_can_play {
+            return vec![UiRequest::SendTextToMainChat(String::from("Ведущий не может играть"))];
+        }
+
+        let name = match normalize_player_name(&name, &self.config.command_prefix) {
+            Ok(name) => name,
+            Err(msg) => {
+                return vec![UiRequest::SendTextToMainChat(msg)];
+            }
+        };
+
+        if let Some(player) = self.find_player(new_user) {
+            // Client may have lost its state, joining again with the same name is fine
+            if player.name().to_lowercase() == name.to_lowercase() {
+                vec![UiRequest::SendTextToMainChat(format!(
+                    "{}, вы уже зарегистрированы",
+                    player.name()
+                ))]
+            } else {
+                vec![UiRequest::SendTextToMainChat(String::from(
+                    "Такой игрок уже существует",
+                ))]
+            }
         } else if !self.find_player_by_name(&name).is_none() {
             vec![UiRequest::SendTextToMainChat(String::from(
                 "Игрок с таким именем уже существует",
             ))]
+        } else if matches!(self.config.max_players, Some(max_players) if self.players.len() >= max_players) {
+            vec![UiRequest::SendTextToMainChat(String::from(
+                "Регистрация заполнена",
+            ))]
         } else {
             self.players.insert(Player::new(name.clone(), new_user, username), 0);
-            vec![UiRequest::SendTextToMainChat(format!("Привет {}", name))]
+            let mut res = vec![UiRequest::SendTextToMainChat(format!("Привет {}", name))];
+            if new_user == self.admin_user {
+                res.push(UiRequest::SendToAdmin(
+                    "You joined as a player, you will judge your own answers".to_string(),
+                ));
+            }
+            res
         }
     }
 
-    pub fn start(&mut self, user: UserId) -> Vec<UiRequest> {
+    pub fn clear_players(&mut self, user: UserId) -> Vec<UiRequest> {
         if user != self.admin_user {
-            println!("non admin user attempted to start a game");
+            eprintln!("non admin user tried to clear players");
             return vec![];
         }
 
         if self.state != State::WaitingForPlayersToJoin {
-            println!("attempt to start the game twice");
-            vec![]
-        } else {
-            self.current_player = self.players.keys().next().cloned();
-            if self.current_player.is_none() {
-                return vec![UiRequest::SendTextToMainChat(String::from(
-                    "Ни одного игрока не зарегистрировалось!",
-                ))];
-            }
-
-            self.current_tour = 0;
-            self.reload_available_questions();
-            self.set_state(State::Pause);
-
-            let topics: String = self
-                .questions
-                .iter()
-                .map(|(topic, _)| topic)
-                .join("\n");
-            vec![
-                UiRequest::SendTextToMainChat(format!("Здравствуйте, здравствуйте, добрый день! Это своя игра!")),
-                UiRequest::SendTextToMainChat(format!("Темы первого раунда:\n{}", topics)),
-                UiRequest::SendTextToMainChat(format!(
-                    "Игру начинает {}",
-                    self.current_player.clone().unwrap().name()
-                ))
-            ]
+            eprintln!("players can be cleared only before the game starts");
+            return vec![];
+        }
+
+        self.players.clear();
+        let mut res = vec![UiRequest::SendTextToMainChat(String::from(
+            "Список игроков очищен, регистрируйтесь заново",
+        ))];
+        res.extend(self.open_registration());
+        res
+    }
+
+    // Sent when the bot starts
+    pub fn registration_prompt(&mut self) -> Vec<UiRequest> {
+        let mut res = vec![UiRequest::SendTextToMainChat(format!(
+            "Для регистрации в игре введите '{}join ИМЯ' без кавычек",
+            self.config.command_prefix
+        ))];
+        res.extend(self.open_registration());
+        res
+    }
+
+    // Starts the recurring heartbeat timer
+    pub fn start_heartbeat(&self) -> Vec<UiRequest> {
+        match self.config.heartbeat {
+            Some(interval) => vec![UiRequest::ScheduleTimer(TimerId::Heartbeat, interval)],
+            None => vec![],
+        }
+    }
+
+    pub fn heartbeat(&mut self) -> Vec<UiRequest> {
+        let msg = format!("alive, state={}, players={}", self.state.name(), self.players.len());
+        eprintln!("{}", msg);
+        let mut res = self.start_heartbeat();
+        if self.config.heartbeat_to_admin {
+            res.push(UiRequest::SendToAdmin(msg));
+        }
+        res
+    }
+
+    fn open_registration(&mut self) -> Vec<UiRequest> {
+        self.registration_closed = false;
+        match self.config.registration_window {
+            Some(window) => vec![UiRequest::ScheduleTimer(TimerId::Registration, window)],
+            None => vec![],
+        }
+    }
+
+    pub fn registration_expired(&mut self) -> Vec<UiRequest> {
+        if self.state != State::WaitingForPlayersToJoin {
+            eprintln!("registration expired, but nobody is joining");
+            return vec![];
+        }
+
+        if let Some(err) = self.not_enough_players() {
+            let mut res = vec![err, UiRequest::SendTextToMainChat("Регистрация продлена".to_string())];
+            res.extend(self.open_registration());
+            return res;
+        }
+
+        self.registration_closed = true;
+        let mut res = vec![UiRequest::SendTextToMainChat("Регистрация закрыта".to_string())];
+        res.extend(self.ask_start_confirmation());
+        res
+    }
+
+    // Questions of the current game refer to the cached files, so they can
+    // be removed only when the game is over
+    pub fn clear_cache(&mut self, user: UserId) -> Vec<UiRequest> {
+        if user != self.admin_user {
+            eprintln!("non admin user tried to clear the cache");
+            return vec![];
+        }
+
+        if self.state != State::Finished {
+            return vec![UiRequest::SendToAdmin(
+                "Cache can be cleared only after the game is finished".to_string(),
+            )];
+        }
+
+        vec![UiRequest::ClearCache]
+    }
+
+    // Checks that enough players joined and asks admin to confirm the list of
+    // players, so that the game isn't started accidentally while somebody is missing
+    pub fn request_start(&mut self, user: UserId) -> Vec<UiRequest> {
+        if user != self.admin_user {
+            println!("non admin user attempted to start a game");
+            return vec![];
+        }
+
+        if self.state != State::WaitingForPlayersToJoin {
+            println!("attempt to start the game twice");
+            return vec![];
+        }
+        if let Some(err) = self.not_enough_players() {
+            return vec![err];
+        }
+
+        self.ask_start_confirmation()
+    }
+
+    fn ask_start_confirmation(&mut self) -> Vec<UiRequest> {
+        self.set_state(State::WaitingForStartConfirmation);
+        let players = self.players.keys().map(|player| player.name()).sorted().join("\n");
+        vec![UiRequest::AskAdminConfirmStart(format!(
+            "Start the game with {} players?\n{}",
+            self.players.len(),
+            players
+        ))]
+    }
+
+    pub fn cancel_start(&mut self, user: UserId) -> Vec<UiRequest> {
+        if user != self.admin_user {
+            println!("non admin user attempted to cancel the start of the game");
+            return vec![];
+        }
+        if self.state != State::WaitingForStartConfirmation {
+            println!("the game is not waiting for start confirmation");
+            return vec![];
+        }
+
+        self.set_state(State::WaitingForPlayersToJoin);
+        let mut res = vec![UiRequest::SendTextToMainChat(
+            "Регистрация игроков продолжается".to_string(),
+        )];
+        res.extend(self.open_registration());
+        res
+    }
+
+    fn not_enough_players(&self) -> Option<UiRequest> {
+        if self.players.is_empty() {
+            return Some(UiRequest::SendTextToMainChat(String::from(
+                "Ни одного игрока не зарегистрировалось!",
+            )));
+        }
+        if self.players.len() < self.config.min_players {
+            return Some(UiRequest::SendTextToMainChat(format!(
+                "Недостаточно игроков, нужно ещё {}",
+                self.config.min_players - self.players.len()
+            )));
+        }
+        None
+    }
+
+    pub fn start(&mut self, user: UserId) -> Vec<UiRequest> {
+        if user != self.admin_user {
+            println!("non admin user attempted to start a game");
+            return vec![];
+        }
+
+        if self.state != State::WaitingForPlayersToJoin && self.state != State::WaitingForStartConfirmation {
+            println!("attempt to start the game twice");
+            vec![]
+        } else {
+            if let Some(err) = self.not_enough_players() {
+                return vec![err];
+            }
+            self.current_player = self.players.keys().next().cloned();
+
+            let players = self.players.keys().map(|player| player.name()).sorted().join(", ");
+            self.log_event(format!("Game started, players: {}", players));
+            self.last_join_attempts.clear();
+            self.current_tour = 0;
+            let skipped_tours = self.skip_empty_tours();
+            self.reload_available_questions();
+            self.set_state(State::Pause);
+            self.log_event(format!("Tour {}{}", self.current_tour + 1, self.current_tour_title_suffix()));
+
+            let topics: String = self
+                .questions
+                .iter()
+                .map(|(topic, _)| topic)
+                .join("\n");
+            let mut res = skipped_tours;
+            res.extend(self.config.intro.requests());
+            res.extend(self.config.stickers.start().map(UiRequest::SendSticker));
+            res.extend(vec![
+                UiRequest::SendTextToMainChat(format!(
+                    "Темы первого раунда{}:\n{}",
+                    self.current_tour_title_suffix(),
+                    topics
+                )),
+                UiRequest::SendTextToMainChat(format!(
+                    "Игру начинает {}",
+                    self.current_player.clone().unwrap().name()
+                ))
+            ]);
+            if let Some(game_duration) = self.config.game_duration {
+                res.push(UiRequest::ScheduleTimer(TimerId::GameClock, game_duration));
+            }
+            res
         }
     }
 
@@ -349,25 +1384,306 @@ impl GameState {
             return vec![];
         }
 
+        let mut res = self.reveal_answer();
+        res.extend(self.tour_recap());
         self.current_tour += 1;
+        res.extend(self.skip_empty_tours());
+        if self.current_tour >= self.tours.len() {
+            res.push(UiRequest::SendToAdmin(
+                "There are no more tours with topics, the game is finished".to_string(),
+            ));
+            res.extend(self.finish_game());
+            return res;
+        }
         self.reload_available_questions();
+        self.log_event(format!("Tour {}{}", self.current_tour + 1, self.current_tour_title_suffix()));
 
         let topics: String = self
             .questions
             .iter()
             .map(|(topic, _)| topic)
             .join("\n");
-        vec![UiRequest::SendTextToMainChat(
-            format!("Переходим к следующему туру\n\nТемы:\n{}", topics),
-        )]
+        res.push(UiRequest::SendTextToMainChat(
+            format!(
+                "Переходим к следующему туру{}\n\nТемы:\n{}",
+                self.current_tour_title_suffix(),
+                topics
+            ),
+        ));
+        res
+    }
+
+    // Tour without topics would have an empty board and stall the game
+    fn skip_empty_tours(&mut self) -> Vec<UiRequest> {
+        let mut res = vec![];
+        while self.current_tour < self.tours.len() && self.tours[self.current_tour].topics.is_empty() {
+            res.push(UiRequest::SendToAdmin(format!(
+                "Tour {} has no topics, it is skipped",
+                self.current_tour + 1
+            )));
+            self.log_event(format!("Tour {} is skipped", self.current_tour + 1));
+            self.current_tour += 1;
+        }
+        res
+    }
+
+    // Shown once per tour, either when it runs out of questions or when
+    // admin moves to the next one
+    fn tour_recap(&mut self) -> Vec<UiRequest> {
+        let mut res = self.topic_points_summary();
+        if !self.config.tour_recap || self.tour_recap_shown {
+            return res;
+        }
+        self.tour_recap_shown = true;
+
+        let mut msg = format!("Итоги тура{}:", self.current_tour_title_suffix());
+        for (rank, player, score) in self.ranked_players() {
+            msg += &format!("\n{}. {}: {}", rank, player.name(), score);
+        }
+        res.push(UiRequest::SendScoreTable(self.make_score_table()));
+        res.push(UiRequest::SendTextToMainChat(msg));
+        res
+    }
+
+    // Every question begins here, so whoever chose it gets the turn back if
+    // nobody answers, whatever the type of the question is
+    fn begin_question(&mut self, topic: &str) {
+        self.player_which_chose_question = self.current_player.clone();
+        self.current_topic = Some(topic.to_string());
+        self.topic_points.entry(topic.to_string()).or_default();
+    }
+
+    fn add_topic_points(&mut self, delta: i64) {
+        if let Some(ref topic) = self.current_topic {
+            *self.topic_points.entry(topic.clone()).or_default() += delta;
+        }
+    }
+
+    // Sent to admin once per tour, so that authors can see which topics
+    // turned out to be too easy or too hard
+    fn topic_points_summary(&mut self) -> Vec<UiRequest> {
+        if self.topic_points.is_empty() {
+            return vec![];
+        }
+        let topic_points = std::mem::take(&mut self.topic_points);
+
+        let mut msg = format!("Points by topic in tour {}:", self.current_tour + 1);
+        for (topic, points) in topic_points
+            .iter()
+            .sorted_by(|(t1, p1), (t2, p2)| p2.cmp(p1).then_with(|| t1.cmp(t2)))
+        {
+            msg += &format!("\n{}: {:+}", topic, points);
+        }
+        vec![UiRequest::SendToAdmin(msg)]
+    }
+
+    // Players with equal scores share the rank and are ordered by name,
+    // e.g. 1, 2, 2, 4
+    fn ranked_players(&self) -> Vec<(u32, &Player, i64)> {
+        let sorted: Vec<_> = self
+            .players
+            .iter()
+            .map(|(player, score)| (player, *score))
+            .sorted_by(|(p1, s1), (p2, s2)| s2.cmp(s1).then_with(|| p1.name().cmp(p2.name())))
+            .collect();
+
+        let mut res: Vec<(u32, &Player, i64)> = Vec::with_capacity(sorted.len());
+        for (idx, (player, score)) in sorted.into_iter().enumerate() {
+            let rank = match res.last() {
+                Some((prev_rank, _, prev_score)) if *prev_score == score => *prev_rank,
+                _ => idx as u32 + 1,
+            };
+            res.push((rank, player, score));
+        }
+        res
+    }
+
+    // Results are final once the game is over, admin actions can't change them
+    fn is_finished(&self) -> bool {
+        if self.state == State::Finished {
+            eprintln!("the game is already finished");
+            return true;
+        }
+        false
+    }
+
+    pub fn finish(&mut self, user: UserId) -> Vec<UiRequest> {
+        if user != self.admin_user {
+            eprintln!("non admin user tried to finish the game");
+            return vec![];
+        }
+
+        if self.state == State::WaitingForPlayersToJoin || self.state == State::Finished {
+            eprintln!("incorrect state to finish the game");
+            return vec![];
+        }
+
+        self.finish_game()
+    }
+
+    pub fn game_clock_expired(&mut self) -> Vec<UiRequest> {
+        if self.state == State::WaitingForPlayersToJoin || self.state == State::Finished {
+            eprintln!("game clock expired, but the game is not running");
+            return vec![];
+        }
+
+        let mut res = vec![
+            UiRequest::StopTimer,
+            UiRequest::SendTextToMainChat("Время игры вышло!".to_string()),
+        ];
+        res.extend(self.finish_game());
+        res
+    }
+
+    fn finish_game(&mut self) -> Vec<UiRequest> {
+        let revealed = self.reveal_answer();
+        // Question which players have already seen can be interrupted by the
+        // end of the game
+        let open_question = match self.state {
+            State::Falsestart(ref question, _)
+            | State::CanAnswer(ref question, _)
+            | State::Answering(ref question, ..)
+            | State::Blitz(ref question, _) => Some(format_answer_reveal(question)),
+            _ => None,
+        };
+        let topic_points_summary = self.topic_points_summary();
+        self.set_state(State::Finished);
+        self.log_event("Game finished".to_string());
+        self.log_score();
+
+        let winners: Vec<_> = self
+            .ranked_players()
+            .into_iter()
+            .filter(|(rank, _, _)| *rank == 1)
+            .map(|(_, player, _)| player.name().clone())
+            .collect();
+
+        let winners_msg = if winners.len() == 1 {
+            format!("Победитель: {}", winners[0])
+        } else {
+            format!("Победители: {}", winners.join(", "))
+        };
+
+        let scores = self
+            .players
+            .iter()
+            .map(|(player, score)| (player.clone(), *score))
+            .collect();
+
+        let mut res = revealed;
+        res.extend(self.finish_countdown("⏱ Игра окончена".to_string()));
+        res.extend(topic_points_summary);
+        res.extend(open_question.map(UiRequest::SendTextToMainChat));
+        res.extend(vec![
+            UiRequest::SendTextToMainChat(format!(
+                "Игра окончена!\n{}\n{}",
+                winners_msg,
+                self.get_score_str()
+            )),
+            UiRequest::SendGameSummary(self.make_game_summary()),
+            UiRequest::GameFinished(scores),
+        ]);
+        res
+    }
+
+    fn make_game_summary(&self) -> GameSummary {
+        let players = self
+            .ranked_players()
+            .into_iter()
+            .map(|(rank, player, score)| GameSummaryRow {
+                rank,
+                name: player.name().clone(),
+                score,
+                answered: self.correct_answers.get(player).cloned().unwrap_or_default(),
+                winner: rank == 1,
+            })
+            .collect();
+        GameSummary { players }
     }
 
     pub fn message(&mut self, user: UserId, message: String) -> Vec<UiRequest> {
         eprintln!("User {} sent a message '{}'", user, message);
 
-        // Only messages of up to 3 symbols are considered a "press of a button"
-        if message.chars().count() > 3 {
-            return vec![];
+        if let State::WaitingForAuction(..) = self.state {
+            return self.auction_bid(user, &message);
+        }
+        if let State::StakeChoosing(..) = self.state {
+            return self.choose_stake(user, &message);
+        }
+
+        if self.is_current_player(user) {
+            if let Some(res) = self.auto_judge(&message) {
+                return res;
+            }
+        }
+
+        // Buzz was already accepted, the question waits for the admin. What
+        // the answering player types is a hint for the judge
+        if let State::Answering(..) = self.state {
+            return match self.current_player {
+                Some(ref player) if player.id() == user => vec![UiRequest::SendToAdmin(
+                    format!("{} says: {}", player.name(), message),
+                )],
+                _ => vec![],
+            };
+        }
+
+        // Only messages of up to 3 symbols are considered a "press of a button".
+        // The button can be followed by the answer, e.g. "+ Пушкин". Words are
+        // not buttons, otherwise "ну и вопрос" would be a press
+        let typed_answer = match message.split_once(' ') {
+            Some((button, answer))
+                if button.chars().count() <= 3
+                    && !button.chars().any(char::is_alphabetic)
+                    && !answer.trim().is_empty() =>
+            {
+                Some(answer.trim().to_string())
+            }
+            _ if message.chars().count() <= 3 => None,
+            _ => {
+                return vec![];
+            }
+        };
+        self.buzz(user, typed_answer)
+    }
+
+    // Players who reply to the question message answer with the whole text,
+    // even if it's longer than a button press
+    pub fn reply_to_question(&mut self, user: UserId, message: String) -> Vec<UiRequest> {
+        match self.state {
+            State::Falsestart(..) | State::CanAnswer(..) | State::Blitz(..) => {
+                eprintln!("User {} replied to the question with '{}'", user, message);
+                let answer = message.trim().to_string();
+                self.buzz(user, if answer.is_empty() { None } else { Some(answer) })
+            }
+            _ => self.message(user, message),
+        }
+    }
+
+    // Answer of the answering player is accepted right away if it's correct,
+    // admin judges answers that don't match
+    fn auto_judge(&mut self, answer: &str) -> Option<Vec<UiRequest>> {
+        if !self.config.auto_judge {
+            return None;
+        }
+        match self.state {
+            State::Answering(ref question, ..) if question.matches_answer(answer) => {
+                let mut res = vec![UiRequest::SendToAdmin(format!("'{}' was automatically accepted", answer))];
+                res.extend(self.accept_answer());
+                Some(res)
+            }
+            _ => None,
+        }
+    }
+
+    fn buzz(&mut self, user: UserId, typed_answer: Option<String>) -> Vec<UiRequest> {
+        // Player who already tried to answer the question can't buzz again
+        // until the next question, even after the question is reopened
+        if let Some(player) = self.find_player(user) {
+            if self.players_answered_current_question.contains(player) {
+                eprintln!("Player '{:?}' already answered this question", player);
+                return vec![];
+            }
         }
 
         if let State::Falsestart(_, _) = self.state.clone() {
@@ -386,55 +1702,121 @@ impl GameState {
             }
         }
 
-        if let State::CanAnswer(question, cost) = self.state.clone() {
+        if let State::CanAnswer(question, cost) | State::Blitz(question, cost) = self.state.clone() {
+            if self.answers_paused {
+                eprintln!("answers are paused, buzz of {} is ignored", user);
+                return vec![];
+            }
             let player = self.find_player(user).cloned();
             match player {
                 Some(player) => {
-                    if self.players_answered_current_question.contains(&player) {
-                        eprintln!("Player '{:?}' already answered this question", player);
-                        return vec![];
-                    } else if self.players_falsestarted.contains(&player) {
+                    if self.players_falsestarted.contains(&player) {
                         eprintln!("Player {} falsestarted", player.name());
                         return vec![];
-                    } else {
-                        eprintln!("{:?}", self.players_answered_current_question);
                     }
                     self.current_player = Some(player.clone());
                     self.players_answered_current_question
                         .insert(player.clone());
+                    if let Some(since) = self.can_answer_since.take() {
+                        let response_time = since.elapsed();
+                        self.log_event(format!(
+                            "{} pressed the button in {:.2}s",
+                            player.name(),
+                            response_time.as_secs_f64()
+                        ));
+                        self.response_times
+                            .entry(player.clone())
+                            .or_default()
+                            .push(response_time);
+                    } else {
+                        self.log_event(format!("{} pressed the button", player.name()));
+                    }
                     // Anyone can answer
                     self.set_state(State::Answering(question, cost, true));
-                    vec![
-                        UiRequest::StopTimer,
-                        UiRequest::SendTextToMainChat(format!("Отвечает {}", player.name())),
-                        UiRequest::AskAdminYesNo("Correct answer?".to_string()),
-                    ]
+                    let prompt = match typed_answer {
+                        Some(ref answer) => format!("{} says: {}\nCorrect answer?", player.name(), answer),
+                        None => "Correct answer?".to_string(),
+                    };
+                    let mut res = vec![UiRequest::StopTimer];
+                    res.extend(self.finish_countdown(format!("⏱ Отвечает {}", player.name())));
+                    res.push(UiRequest::SendTextToMainChat(format!("Отвечает {}", player.name())));
+                    match typed_answer.as_deref().and_then(|answer| self.auto_judge(answer)) {
+                        Some(judged) => res.extend(judged),
+                        None => res.push(UiRequest::AskAdminYesNo(prompt)),
+                    }
+                    res
                 }
                 None => vec![],
             }
+        } else if let Some(late) = self.late_buzz(user) {
+            vec![UiRequest::SendTextToMainChat(late)]
         } else {
             println!("bad state");
             vec![]
         }
     }
 
-    fn make_score_table(&self) -> ScoreTable {
-        let mut scores = Vec::new();
-        for i in 1..self.questions_per_topic + 1 {
-            scores.push(i * self.current_multiplier);
+    // Players don't see whether their buzz was registered if it came right
+    // after the time ran out, so they are told about it once
+    fn late_buzz(&mut self, user: UserId) -> Option<String> {
+        if self.state != State::Pause {
+            return None;
+        }
+        let late_by = self.answers_closed_at?.elapsed();
+        if late_by > LATE_BUZZ_WINDOW {
+            return None;
         }
+        let player = self.find_player(user).cloned()?;
+        self.players_answered_current_question.insert(player.clone());
+        Some(format!(
+            "{}, время на ответ уже вышло (опоздание {:.1} с)",
+            player.name(),
+            late_by.as_secs_f64()
+        ))
+    }
+
+    fn make_score_table(&self) -> ScoreTable {
+        // Topics can have different costs, the table has a column for each of them
+        let mut scores: Vec<usize> = self
+            .questions
+            .iter()
+            .flat_map(|(topic, _)| self.topic_costs(topic))
+            .collect();
+        scores.sort_unstable();
+        scores.dedup();
         let mut data = Vec::new();
         for (topic, scores) in self.questions.iter() {
             let topic_name = topic.clone();
             let question_scores = scores.clone();
 
             data.push(ScoreTableItem {
+                played: Some(self.topic_size(topic).saturating_sub(question_scores.len())),
                 name: topic_name,
                 questions: question_scores,
             })
         }
 
-        ScoreTable { scores, data }
+        let current_player = if self.config.highlight_current_player {
+            self.current_player.as_ref().map(|player| player.name().clone())
+        } else {
+            None
+        };
+
+        let standings = self
+            .ranked_players()
+            .into_iter()
+            .map(|(_, player, score)| PublicScore {
+                name: player.name().clone(),
+                score,
+            })
+            .collect();
+
+        ScoreTable {
+            scores,
+            data,
+            current_player,
+            standings,
+        }
     }
 
     pub fn next_question(&mut self, user: UserId) -> Vec<UiRequest> {
@@ -442,6 +1824,9 @@ impl GameState {
             println!("non-admin user tried to select next question");
             return vec![];
         }
+        if self.is_finished() {
+            return vec![];
+        }
         let current_player = match self.current_player {
             Some(ref player) => player.clone(),
             None => {
@@ -457,102 +1842,451 @@ impl GameState {
             .filter(|&(_, (_, costs))| !costs.is_empty())
             .map(|(idx, (topic, _))| (TopicIdx(idx), topic.clone()))
             .collect();
+        let mut res = self.reveal_answer();
         if topics.is_empty() {
-            vec![
-                UiRequest::SendTextToMainChat("Нет больше вопросов в туре".to_string()),
-            ]
+            res.push(UiRequest::SendTextToMainChat("Нет больше вопросов в туре".to_string()));
+            res.extend(self.tour_recap());
+            res
         } else {
+            self.manual_scoring = None;
+            self.last_judgment = None;
             self.set_state(State::WaitingForTopic);
-            vec![
-                UiRequest::SendScoreTable(self.make_score_table()),
-                UiRequest::ChooseTopic(current_player.name().to_string(), topics, current_player.username().clone()),
-            ]
+            res.push(UiRequest::SendScoreTable(self.make_score_table()));
+            if self.config.turn_dm && self.private_chats.contains(&current_player.id()) {
+                res.push(UiRequest::SendPrivately(
+                    current_player.id(),
+                    vec![UiRequest::SendTextToMainChat("Ваша очередь выбирать вопрос".to_string())],
+                ));
+            }
+            res.push(UiRequest::ChooseTopic(current_player, topics));
+            res
         }
     }
 
-    fn close_unanswered_question(
+    pub fn is_blitz_tour(&self) -> bool {
+        self.tours
+            .get(self.current_tour)
+            .map(|tour| tour.blitz)
+            .unwrap_or(false)
+    }
+
+    // In a blitz tour questions go in a fixed order, topic by topic from the
+    // cheapest one, and there is a single short timer to answer
+    pub fn next_blitz_question(
         &mut self,
-        question: Question,
-        reason: Option<String>,
+        user: UserId,
+        questions_storage: &dyn QuestionsStorage,
     ) -> Vec<UiRequest> {
-        self.set_state(State::Pause);
-        // Haven't received correct answer, so current player is which
-        // asked the question (http://vladimirkhil.com/tv/game/10)
-        self.current_player = self.player_which_chose_question.clone();
+        if user != self.admin_user {
+            println!("non-admin user tried to select next question");
+            return vec![];
+        }
+        if self.state != State::Pause {
+            println!("blitz question can be asked only during the pause");
+            return vec![];
+        }
 
-        let score_msg = self.get_score_str();
-        let current_player_name = match self.current_player {
-            Some(ref player) => player.name(),
-            None => panic!("Trying to process question, but no current player set"),
+        let next = self.questions.iter_mut().find_map(|(topic, costs)| {
+            let cost = costs.iter().min().cloned()?;
+            costs.retain(|elem| *elem != cost);
+            Some((topic.clone(), cost))
+        });
+        let (topic, cost) = match next {
+            Some(next) => next,
+            None => {
+                let mut res = self.reveal_answer();
+                res.push(UiRequest::SendTextToMainChat("Нет больше вопросов в туре".to_string()));
+                res.extend(self.tour_recap());
+                return res;
+            }
         };
 
-        let mut msg = format!("Правильный ответ: {}\n", question.answer());
-        if let Some(comments) = question.comments() {
-            if comments.len() > 0 {
-                msg.push_str(&format!("Комментарий:{}\n", comments));
+        let difficulty = self.question_difficulty(&topic, cost);
+        let question = match difficulty.and_then(|difficulty| questions_storage.get(topic.clone(), difficulty)) {
+            Some(question) => question,
+            None => {
+                println!("internal error: question is not found");
+                return vec![];
             }
-        }
-
-        msg.push_str(&format!("{}\nСледующий вопрос выбирает {}", score_msg, current_player_name));
+        };
 
-        if let Some(reason_message) = reason {
-            vec![
-                UiRequest::SendTextToMainChat(reason_message),
-                UiRequest::SendTextToMainChat(msg),
-            ]
+        self.manual_scoring = None;
+        self.last_judgment = None;
+        self.players_falsestarted.clear();
+        self.players_answered_current_question.clear();
+        self.set_state(State::Blitz(question.clone(), cost as i64));
+        self.played_questions.insert((topic.clone(), cost));
+        self.begin_question(&topic);
+        self.log_event(format!("Blitz question '{}' for {}", topic, cost));
+        self.log_question(&question);
+
+        let mut res = self.reveal_answer();
+        res.push(UiRequest::SendToAdmin(format_admin_question(&question)));
+        res.push(UiRequest::RightBeforeAskingQuestion(format!("Блиц! Тема {}, вопрос за {}", topic, cost)));
+        res.extend(self.format_question(&question));
+        res.push(UiRequest::Timeout(None, self.config.delays.get(Delay::Blitz)));
+        res
+    }
+
+    // Blitz tour goes on by itself once the admin asked the first question
+    fn schedule_next_blitz_question(&self) -> Vec<UiRequest> {
+        if self.is_blitz_tour() && !self.is_practice() {
+            vec![UiRequest::ScheduleTimer(TimerId::Blitz, self.config.delays.get(Delay::BlitzPause))]
         } else {
-            vec![UiRequest::SendTextToMainChat(msg)]
+            vec![]
+        }
+    }
+
+    pub fn blitz_timer(&mut self, questions_storage: &dyn QuestionsStorage) -> Vec<UiRequest> {
+        if !self.is_blitz_tour() || self.state != State::Pause {
+            eprintln!("blitz timer is ignored");
+            return vec![];
+        }
+        self.next_blitz_question(self.admin_user, questions_storage)
+    }
+
+    pub fn is_practice(&self) -> bool {
+        self.practice.is_some()
+    }
+
+    // Questions of the selected topic which are not played yet, as topic and
+    // difficulty in the questions storage
+    pub fn selected_topic_questions(&self) -> Vec<(String, usize)> {
+        let topic_idx = match self.state {
+            State::WaitingForQuestion(topic_idx) => topic_idx,
+            _ => return vec![],
+        };
+        match self.questions.get(topic_idx.0) {
+            Some((topic, costs)) => costs
+                .iter()
+                .filter_map(|cost| self.question_difficulty(topic, *cost))
+                .map(|difficulty| (topic.clone(), difficulty))
+                .collect(),
+            None => vec![],
+        }
+    }
+
+    // Practice is played by the only joined player with all non-special
+    // questions of all tours
+    pub fn start_practice(&mut self, user: UserId, questions_storage: &dyn QuestionsStorage) -> Vec<UiRequest> {
+        if user != self.admin_user {
+            println!("non admin user attempted to start a practice");
+            return vec![];
+        }
+        if self.state != State::WaitingForPlayersToJoin && self.state != State::WaitingForStartConfirmation {
+            println!("practice can be started only instead of the game");
+            return vec![];
+        }
+        if self.players.len() != 1 {
+            return vec![UiRequest::SendTextToMainChat(
+                "Тренировка только для одного игрока".to_string(),
+            )];
+        }
+
+        let mut unused = vec![];
+        for (tour, sizes) in self.tours.iter().zip(self.topic_sizes.iter()) {
+            if tour.blitz {
+                continue;
+            }
+            for (topic, size) in tour.topics.iter().zip(sizes.iter()) {
+                for difficulty in 1..=*size {
+                    let cost = self.question_cost(&topic.name, difficulty, tour.multiplier);
+                    if self.is_manual(&topic.name, &cost)
+                        || self.is_auction(&topic.name, &cost)
+                        || self.is_stake(&topic.name, &cost)
+                        || self.is_cat_in_bag(&topic.name, &cost).is_some()
+                    {
+                        continue;
+                    }
+                    unused.push((topic.name.clone(), difficulty, cost));
+                }
+            }
+        }
+
+        self.current_player = self.players.keys().next().cloned();
+        self.current_tour = 0;
+        self.reload_available_questions();
+        self.practice = Some(Practice {
+            unused,
+            mistakes: HashMap::new(),
+        });
+        self.set_state(State::Pause);
+        self.log_event("Practice started".to_string());
+
+        let mut res = vec![UiRequest::SendTextToMainChat("Тренировка! Вопросы без табло".to_string())];
+        res.extend(self.next_practice_question(user, questions_storage));
+        res
+    }
+
+    pub fn next_practice_question(&mut self, user: UserId, questions_storage: &dyn QuestionsStorage) -> Vec<UiRequest> {
+        if user != self.admin_user {
+            println!("non-admin user tried to select next question");
+            return vec![];
+        }
+        if self.state != State::Pause {
+            println!("practice question can be asked only during the pause");
+            return vec![];
+        }
+        let practice = match self.practice {
+            Some(ref mut practice) => practice,
+            None => {
+                println!("practice is not started");
+                return vec![];
+            }
+        };
+
+        let mut rng = thread_rng();
+        let mistakes = &practice.mistakes;
+        let next = practice
+            .unused
+            .choose_weighted(&mut rng, |(topic, _, _)| 1 + mistakes.get(topic).cloned().unwrap_or_default())
+            .ok()
+            .cloned();
+        let (topic, difficulty, cost) = match next {
+            Some(next) => next,
+            None => {
+                self.set_state(State::Finished);
+                let mut res = self.reveal_answer();
+                res.push(UiRequest::SendTextToMainChat(format!(
+                    "Тренировка окончена\n{}",
+                    self.get_score_str()
+                )));
+                return res;
+            }
+        };
+        practice.unused.retain(|(cur_topic, cur_difficulty, _)| {
+            cur_topic != &topic || *cur_difficulty != difficulty
+        });
+
+        let question = match questions_storage.get(topic.clone(), difficulty) {
+            Some(question) => question,
+            None => {
+                println!("internal error: question is not found");
+                return vec![];
+            }
+        };
+
+        self.manual_scoring = None;
+        self.last_judgment = None;
+        self.players_falsestarted.clear();
+        self.players_answered_current_question.clear();
+        self.set_state(State::BeforeQuestionAsked(question.clone(), cost as i64));
+        self.begin_question(&topic);
+        self.log_event(format!("Practice question '{}' for {}", topic, cost));
+        self.log_question(&question);
+
+        let mut res = self.reveal_answer();
+        res.extend(vec![
+            UiRequest::SendToAdmin(format_admin_question(&question)),
+            UiRequest::RightBeforeAskingQuestion(format!("Тема {}, вопрос за {}", topic, cost)),
+            UiRequest::Timeout(None, self.config.delays.get(Delay::PreQuestion)),
+        ]);
+        res
+    }
+
+    fn note_practice_mistake(&mut self) {
+        if let (Some(practice), Some(topic)) = (self.practice.as_mut(), self.current_topic.as_ref()) {
+            *practice.mistakes.entry(topic.clone()).or_default() += 1;
+        }
+    }
+
+    fn close_unanswered_question(
+        &mut self,
+        question: Question,
+        reason: Option<String>,
+    ) -> Vec<UiRequest> {
+        // Player who was judged or who pressed the button last
+        let answered = match self.state {
+            State::Answering(..) => self.current_player.clone(),
+            _ => self
+                .current_player
+                .clone()
+                .filter(|player| self.players_answered_current_question.contains(player)),
+        };
+        let attribution = self.attribution(answered.as_ref(), "Последним отвечал");
+        self.remember_played_question(question.clone(), None);
+        self.set_state(State::Pause);
+        self.log_event(format!("Nobody answered, correct answer: {}", question.answer()));
+        self.log_score();
+        // Haven't received correct answer, so current player is which
+        // asked the question (http://vladimirkhil.com/tv/game/10)
+        self.current_player = self.player_which_chose_question.clone();
+
+        let score_msg = self.get_score_str();
+        let current_player_name = match self.current_player {
+            Some(ref player) => player.name(),
+            None => panic!("Trying to process question, but no current player set"),
+        };
+
+        let mut msg = format_answer_reveal(&question);
+        if let Some(attribution) = attribution {
+            msg.push_str(&format!("\n{}", attribution));
+        }
+        msg.push_str(&format!("\n{}", score_msg));
+        if !self.is_practice() {
+            msg.push_str(&format!("\nСледующий вопрос выбирает {}", current_player_name));
         }
+
+        let mut res = self.finish_countdown("⏱ Время вышло".to_string());
+        if let Some(reason_message) = reason {
+            res.push(UiRequest::SendTextToMainChat(reason_message));
+        }
+        res.extend(self.reveal(msg));
+        res.extend(self.schedule_next_blitz_question());
+        res
     }
 
     fn close_answered_question(&mut self, reason: Option<String>, send_sticker: bool) -> Vec<UiRequest> {
+        let attribution = self.attribution(self.current_player.as_ref(), "Ответил");
         self.set_state(State::Pause);
+        self.log_score();
         self.player_which_chose_question = None;
 
-        let mut msg = self.get_score_str();
+        let mut msg = String::new();
+        if let Some(attribution) = attribution {
+            msg += &format!("{}\n", attribution);
+        }
+        msg += &self.get_score_str();
         let current_player_name = match self.current_player {
             Some(ref player) => player.name(),
             None => panic!("Trying to process question, but no current player set"),
         };
-        msg += "\n";
-        msg += &format!("Игру продолжает {}", current_player_name);
+        if !self.is_practice() {
+            msg += "\n";
+            msg += &format!("Игру продолжает {}", current_player_name);
+        }
 
         let mut res = vec![];
         if send_sticker {
-            res.extend(get_rand_sticker().map(UiRequest::SendSticker));
+            res.extend(self.config.stickers.correct().map(UiRequest::SendSticker));
         }
 
         if let Some(reason_message) = reason {
-            res.push(
-                UiRequest::SendTextToMainChat(format!("{}\n{}", reason_message, msg))
-            );
+            res.extend(self.reveal(format!("{}\n{}", reason_message, msg)));
         } else {
-            res.push(UiRequest::SendTextToMainChat(msg));
+            res.extend(self.reveal(msg));
+        }
+        res.extend(self.schedule_next_blitz_question());
+        res
+    }
+
+    // Message with the answer is either sent right away or after the post answer pause
+    fn reveal(&mut self, msg: String) -> Vec<UiRequest> {
+        let delay = self.config.delays.get(Delay::PostAnswer);
+        if delay == Duration::from_secs(0) {
+            return vec![UiRequest::SendTextToMainChat(msg)];
         }
+        let mut res = self.reveal_answer();
+        self.pending_reveal = Some(msg);
+        res.push(UiRequest::ScheduleTimer(TimerId::Reveal, delay));
         res
     }
 
+    // Called when the reveal timer fires. The answer is also revealed if the game
+    // goes on before that, so that it's never lost or shown after the next question
+    pub fn reveal_answer(&mut self) -> Vec<UiRequest> {
+        self.pending_reveal
+            .take()
+            .map(UiRequest::SendTextToMainChat)
+            .into_iter()
+            .collect()
+    }
+
+    // Who chose the question and who answered it, e.g. "Выбрал: Вася. Ответил: Петя"
+    fn attribution(&self, answered: Option<&Player>, answered_label: &str) -> Option<String> {
+        // The only player of the practice chooses nothing
+        if !self.config.question_attribution || self.is_practice() {
+            return None;
+        }
+        let mut parts = vec![];
+        if let Some(ref player) = self.player_which_chose_question {
+            parts.push(format!("Выбрал: {}", player.name()));
+        }
+        if let Some(player) = answered {
+            parts.push(format!("{}: {}", answered_label, player.name()));
+        }
+        if parts.is_empty() {
+            None
+        } else {
+            Some(parts.join(". "))
+        }
+    }
+
     pub fn yes_reply(&mut self, user: UserId) -> Vec<UiRequest> {
         if user != self.admin_user {
             println!("non-admin yes reply");
             return vec![];
         }
-        if let State::Answering(question, cost, _) = &self.state {
-            let cost = *cost;
-            let correct_answer = get_rand_correct_answer();
-            let message = match question.comments() {
-                Some(comments) if comments.len() > 0 => {
-                    format!("{}\nКомментарий: {}", correct_answer, comments)
-                }
-                _ => {
-                    String::from(correct_answer)
+        let mut res = self.self_judgment_notice();
+        res.extend(self.accept_answer());
+        res
+    }
+
+    // Yes/no tap on the prompt message of the admin, None if it's not known
+    pub fn judge(&mut self, user: UserId, prompt: Option<MessageId>, correct: bool) -> Vec<UiRequest> {
+        if user == self.admin_user {
+            if let Some(prompt) = prompt {
+                if self.is_repeated_judgment(prompt) {
+                    return vec![UiRequest::SendToAdmin(String::from(
+                        "Repeated tap on the same answer is ignored",
+                    ))];
                 }
-            };
+            }
+        }
+        if correct {
+            self.yes_reply(user)
+        } else {
+            self.no_reply(user)
+        }
+    }
+
+    fn is_repeated_judgment(&mut self, prompt: MessageId) -> bool {
+        let now = Instant::now();
+        if let Some((last_prompt, judged_at)) = self.last_judged {
+            if last_prompt == prompt && now.duration_since(judged_at) < self.config.judgment_debounce {
+                eprintln!("repeated judgment of the same prompt is ignored");
+                return true;
+            }
+        }
+        self.last_judged = Some((prompt, now));
+        false
+    }
+
+    // Admin who plays judges their own answers, players should know about it
+    fn self_judgment_notice(&mut self) -> Vec<UiRequest> {
+        let admin_answers = match (&self.state, &self.current_player) {
+            (State::Answering(..), Some(player)) => player.id() == self.admin_user,
+            _ => false,
+        };
+        if !admin_answers {
+            return vec![];
+        }
+        self.log_event("Admin judged their own answer".to_string());
+        vec![UiRequest::SendTextToMainChat("Ведущий сам оценил свой ответ".to_string())]
+    }
+
+    fn accept_answer(&mut self) -> Vec<UiRequest> {
+        if let State::Answering(question, cost, _) = self.state.clone() {
+            let message = format!(
+                "{}\n{}",
+                self.config.phrases.get_rand_correct_answer(),
+                format_answer_reveal(&question)
+            );
 
 
             let res = match self.update_current_player_score(cost) {
                 Ok(_) => {
-                    let send_sticker = (cost / self.current_multiplier as i64) == 5;
+                    self.remember_played_question(question.clone(), self.current_player.clone());
+                    self.remember_judgment(question, cost, cost);
+                    if let Some(player) = self.current_player.clone() {
+                        self.log_event(format!("{} answered correctly: +{}", player.name(), cost));
+                        *self.correct_answers.entry(player).or_default() += 1;
+                    }
+                    let difficulty = self
+                        .current_topic
+                        .as_ref()
+                        .and_then(|topic| self.question_difficulty(topic, cost as usize));
+                    let send_sticker = difficulty == Some(5);
                     self.close_answered_question(Some(message), send_sticker)
                 },
                 Err(err_msg) => {
@@ -580,22 +2314,33 @@ impl GameState {
             println!("non-admin no reply");
             return vec![];
         }
+        let notice = self.self_judgment_notice();
 
         if let State::Answering(question, cost, anyone_can_answer) = self.state.clone() {
 
             let res = match self.update_current_player_score(-cost) {
                 Ok(_) => {
-                    if anyone_can_answer {
+                    self.remember_judgment(question.clone(), cost, -cost);
+                    self.note_practice_mistake();
+                    if let Some(player) = self.current_player.clone() {
+                        self.log_event(format!("{} answered incorrectly: -{}", player.name(), cost));
+                    }
+                    let mut res: Vec<_> = self.config.stickers.wrong().map(UiRequest::SendSticker).into_iter().collect();
+                    res.extend(if anyone_can_answer {
                         if self.players_answered_current_question.len() != self.players.len() {
-                            self.set_state(State::CanAnswer(question, cost));
-                            self.players_falsestarted.clear();
-                            vec![
-                                UiRequest::SendTextToMainChat(INCORRECT_ANSWER.to_string()),
-                                UiRequest::Timeout(
-                                    None,
-                                    Delay::Long,
-                                )
-                            ]
+                            let mut res = vec![
+                                UiRequest::SendTextToMainChat(self.config.phrases.get_rand_incorrect_answer()),
+                            ];
+                            if self.is_blitz_tour() && !self.is_practice() {
+                                // Others answer within the same short blitz timer
+                                self.set_state(State::Blitz(question, cost));
+                                res.push(UiRequest::Timeout(None, self.config.delays.get(Delay::Blitz)));
+                            } else {
+                                self.set_state(State::CanAnswer(question, cost));
+                                self.players_falsestarted.clear();
+                                res.extend(self.answer_window(Delay::RetryWindow));
+                            }
+                            res
                         } else {
                             self.close_unanswered_question(
                                 question,
@@ -605,9 +2350,10 @@ impl GameState {
                     } else {
                         self.close_unanswered_question(
                             question,
-                            Some(String::from("Нет")),
+                            Some(self.config.phrases.get_rand_incorrect_answer()),
                         )
-                    }
+                    });
+                    res
                 }
                 Err(err_msg) => {
                     println!("{}", err_msg);
@@ -620,29 +2366,227 @@ impl GameState {
                 res_score += &format!("{}: {}\n", player.name(), score);
             }
             println!("score: {}", res_score);
-            res
+            notice.into_iter().chain(res).collect()
         } else {
             println!("unexpected yes answer");
             vec![]
         }
     }
 
+    fn remember_judgment(&mut self, question: Question, cost: i64, score_delta: i64) {
+        self.last_judgment = self.current_player.clone().map(|player| Judgment {
+            question,
+            cost,
+            player,
+            score_delta,
+            player_which_chose_question: self.player_which_chose_question.clone(),
+        });
+    }
+
+    // Question stays open, but nobody can press the button, e.g. while the
+    // admin clarifies it
+    pub fn pause_answers(&mut self, user: UserId) -> Vec<UiRequest> {
+        if user != self.admin_user {
+            eprintln!("non admin user tried to pause answers");
+            return vec![];
+        }
+        if !matches!(self.state, State::CanAnswer(..)) || self.answers_paused {
+            eprintln!("answers can be paused only while they are accepted");
+            return vec![];
+        }
+        self.answers_paused = true;
+        self.log_event("Answers paused".to_string());
+        let mut res = vec![UiRequest::StopTimer];
+        res.extend(self.finish_countdown("⏱ Пауза".to_string()));
+        res.push(UiRequest::SendTextToMainChat("Ответы приостановлены".to_string()));
+        res
+    }
+
+    // Time to answer starts over after the pause
+    pub fn resume_answers(&mut self, user: UserId) -> Vec<UiRequest> {
+        if user != self.admin_user {
+            eprintln!("non admin user tried to resume answers");
+            return vec![];
+        }
+        if !self.answers_paused {
+            eprintln!("answers are not paused");
+            return vec![];
+        }
+        self.answers_paused = false;
+        self.can_answer_since = Some(Instant::now());
+        self.log_event("Answers resumed".to_string());
+        let mut res = vec![UiRequest::SendTextToMainChat("Ответы снова принимаются".to_string())];
+        res.extend(self.answer_window(Delay::AnswerWindow));
+        res
+    }
+
+    // Timer for the whole answer window, with the countdown message if it
+    // is enabled
+    fn answer_window(&mut self, delay: Delay) -> Vec<UiRequest> {
+        let mut res = vec![];
+        let delay = self.config.delays.get(delay);
+        if self.config.answer_countdown {
+            let tick = delay / COUNTDOWN_STEPS;
+            self.countdown = Some((COUNTDOWN_STEPS, tick));
+            res.push(UiRequest::StartCountdown(countdown_text(COUNTDOWN_STEPS)));
+            res.push(UiRequest::ScheduleTimer(TimerId::Countdown, tick));
+        }
+        res.push(UiRequest::Timeout(None, delay));
+        res
+    }
+
+    // Ticks which come after the answers were closed are ignored
+    pub fn countdown_tick(&mut self) -> Vec<UiRequest> {
+        if !matches!(self.state, State::CanAnswer(..)) || self.answers_paused {
+            return vec![];
+        }
+        match self.countdown {
+            Some((left, tick)) if left > 1 => {
+                self.countdown = Some((left - 1, tick));
+                vec![
+                    UiRequest::UpdateCountdown(countdown_text(left - 1)),
+                    UiRequest::ScheduleTimer(TimerId::Countdown, tick),
+                ]
+            }
+            _ => vec![],
+        }
+    }
+
+    fn finish_countdown(&mut self, text: String) -> Vec<UiRequest> {
+        match self.countdown.take() {
+            Some(_) => vec![UiRequest::FinishCountdown(text)],
+            None => vec![],
+        }
+    }
+
+    pub fn appeal(&mut self, user: UserId) -> Vec<UiRequest> {
+        if user != self.admin_user {
+            eprintln!("non admin user tried to appeal");
+            return vec![];
+        }
+
+        if self.state != State::Pause {
+            eprintln!("can appeal only right after the question was closed");
+            return vec![];
+        }
+
+        let judgment = match self.last_judgment.take() {
+            Some(judgment) => judgment,
+            None => {
+                eprintln!("nothing to appeal");
+                return vec![];
+            }
+        };
+
+        // Revert the score change of the last judgment, new one will be
+        // applied after admin replies again
+        match self.players.get_mut(&judgment.player) {
+            Some(score) => {
+                *score -= judgment.score_delta;
+                self.add_topic_points(-judgment.score_delta);
+            }
+            None => {
+                eprintln!("internal error: {} not found", judgment.player.name());
+                return vec![];
+            }
+        }
+
+        self.log_event(format!("Appeal of the answer of {}", judgment.player.name()));
+        if judgment.score_delta > 0 {
+            if let Some(answered) = self.correct_answers.get_mut(&judgment.player) {
+                *answered = answered.saturating_sub(1);
+            }
+        }
+
+        self.current_player = Some(judgment.player.clone());
+        self.player_which_chose_question = judgment.player_which_chose_question;
+        // Only the appealing player answers
+        self.set_state(State::Answering(judgment.question, judgment.cost, false));
+
+        vec![
+            UiRequest::SendTextToMainChat(format!(
+                "Апелляция! Ответ {} пересматривается",
+                judgment.player.name()
+            )),
+            UiRequest::AskAdminYesNo("Correct answer?".to_string()),
+        ]
+    }
+
+    // Quick fix of the judgment without reopening the question
+    pub fn regrade(&mut self, name: String, correct: bool, user: UserId) -> Vec<UiRequest> {
+        if user != self.admin_user {
+            eprintln!("non admin user tried to regrade");
+            return vec![];
+        }
+
+        let cost = match (&self.state, self.last_question_cost) {
+            (State::Pause, Some(cost)) => cost,
+            _ => {
+                return vec![UiRequest::SendToAdmin(
+                    "Can regrade only right after the question was closed".to_string(),
+                )];
+            }
+        };
+
+        let player = match self.find_player_by_name(&name) {
+            Some(player) => player.clone(),
+            None => {
+                return vec![UiRequest::SendToAdmin(format!("Player {} not found", name))];
+            }
+        };
+        if self.regraded_players.contains(&player) {
+            return vec![UiRequest::SendToAdmin(format!(
+                "{} was already regraded for this question",
+                player.name()
+            ))];
+        }
+
+        let delta = if correct { cost } else { -cost };
+        match self.players.get_mut(&player) {
+            Some(score) => {
+                *score += delta;
+            }
+            None => {
+                eprintln!("internal error: {} not found", name);
+                return vec![];
+            }
+        }
+        self.add_topic_points(delta);
+        self.regraded_players.insert(player.clone());
+        self.log_event(format!("Admin regraded {} by {}", player.name(), delta));
+
+        vec![UiRequest::SendTextToMainChat(format!(
+            "Оценка исправлена: {} {:+}\n{}",
+            player.name(),
+            delta,
+            self.get_score_str()
+        ))]
+    }
+
     pub fn timeout(&mut self) -> Vec<UiRequest> {
         eprintln!("Scheduled timeout occurred");
         if let State::BeforeQuestionAsked(question, cost) = self.state.clone() {
             eprintln!("Falsestart section is about to start");
             self.set_state(State::Falsestart(question.clone(), cost));
 
-            let delay = if question.image().is_some() {
-                Delay::Long
+            let delays = &self.config.delays;
+            let delay = if !question.images().is_empty()
+                || question.animation().is_some()
+                || question.document().is_some()
+            {
+                delays.get(Delay::MediaQuestion)
             } else if question.audio().is_some() {
-                Delay::ExtraLong
-            } else if question.question().len() <= 100 {
-                Delay::Short
-            } else if question.question().len() <= 230 {
-                Delay::Medium
+                // Players shouldn't answer before the clip is over
+                match question.audio_duration() {
+                    Some(duration) => duration + self.config.audio_answer_buffer,
+                    None => delays.get(Delay::AudioQuestion),
+                }
+            } else if question.question().len() <= self.config.length_thresholds.0 {
+                delays.get(Delay::ShortQuestion)
+            } else if question.question().len() <= self.config.length_thresholds.1 {
+                delays.get(Delay::MediumQuestion)
             } else {
-                Delay::Long
+                delays.get(Delay::LongQuestion)
             };
 
             let mut res = vec![];
@@ -657,21 +2601,30 @@ impl GameState {
             if !self.players_falsestarted.is_empty() {
                 // If we have falsestarted players then first set a timer that clears
                 // False start for them.
-                return vec![UiRequest::Timeout(None, Delay::Short)];
+                return vec![UiRequest::Timeout(None, self.config.delays.get(Delay::FalsestartPenalty))];
             } else {
-                return vec![UiRequest::Timeout(None, Delay::ExtraLong)];
+                return self.answer_window(Delay::AnswerWindow);
             }
         };
 
+        if let State::Blitz(question, _) = self.state.clone() {
+            self.answers_closed_at = Some(Instant::now());
+            return self.close_unanswered_question(question, Some(String::from("Время вышло!")));
+        }
+
         if let State::CanAnswer(question, _) = self.state.clone() {
+            if self.answers_paused {
+                eprintln!("answers are paused, timeout is ignored");
+                return vec![];
+            }
             if !self.players_falsestarted.is_empty() {
                 // False started people can answer now
                 self.players_falsestarted.clear();
-                vec![
-                    UiRequest::SendTextToMainChat("Фальстарт окончен".to_string()),
-                    UiRequest::Timeout(None, Delay::ExtraLong)
-                ]
+                let mut res = vec![UiRequest::SendTextToMainChat("Фальстарт окончен".to_string())];
+                res.extend(self.answer_window(Delay::AnswerWindow));
+                res
             } else {
+                self.answers_closed_at = Some(Instant::now());
                 self.close_unanswered_question(question, Some(String::from("Время на ответ вышло!")))
             }
         } else {
@@ -764,68 +2717,118 @@ impl GameState {
             UiRequest::RightBeforeAskingQuestion(format!("Играем тему {}, вопрос за {}", topic, cost))
         );
 
-        let question = match questions_storage.get(topic.clone(), cost / self.current_multiplier) {
+        let difficulty = self.question_difficulty(&topic, cost);
+        let question = match difficulty.and_then(|difficulty| questions_storage.get(topic.clone(), difficulty)) {
             Some(question) => question,
             None => {
                 println!("internal error: question is not found");
                 return vec![];
             }
         };
+        self.played_questions.insert((topic.clone(), cost));
+        self.begin_question(&topic);
+        let chooser = self.current_player.as_ref().map(|player| player.name().clone()).unwrap_or_default();
+        self.log_event(format!("{} chose '{}' for {}", chooser, topic, cost));
+        self.log_question(&question);
 
         let maybe_cat_in_bag = self.is_cat_in_bag(&topic, &cost);
         if let Some(new_topic) = maybe_cat_in_bag {
-            self.set_state(State::CatInBagChoosingPlayer(new_topic, question.clone()));
-            reply.push(
-                UiRequest::SendToAdmin(format!(
-                    "question: {}\nanswer: {}",
-                    question.question(),
-                    question.answer(),
-                ))
-            );
+            reply.push(UiRequest::SendToAdmin(format_admin_question(&question)));
             let score = self.get_score_str() ;
             reply.push(UiRequest::SendTextToMainChat(format!("Кот в мешке!\n{}", score)));
-            reply.push(
-                UiRequest::CatInBagChoosePlayer(
-                    self.players
-                        .keys()
-                        .map(|player| player.clone())
-                        .filter(|player| Some(player) != self.current_player.as_ref())
-                        .collect::<Vec<_>>()
-                )
-            );
+            let candidates = self.players
+                .keys()
+                .filter(|player| Some(*player) != self.current_player.as_ref())
+                .cloned()
+                .collect::<Vec<_>>();
+            if !candidates.is_empty() {
+                self.set_state(State::CatInBagChoosingPlayer(new_topic, question.clone()));
+                reply.push(UiRequest::CatInBagChoosePlayer(candidates));
+                return reply;
+            }
+
+            // Nobody to give the cat to, so the player who chose it plays it
+            match self.current_player.clone() {
+                Some(player) => {
+                    self.log_event(format!("Nobody to give the cat in bag to, {} plays it", player.name()));
+                    let costs = self.cat_in_bag_costs();
+                    self.set_state(State::CatInBagChoosingCost(question, costs.clone()));
+                    reply.push(UiRequest::SendTextToMainChat(format!(
+                        "Отдать кота некому, играет {}. Тема: {}",
+                        player.name(),
+                        new_topic,
+                    )));
+                    reply.push(UiRequest::CatInBagChooseCost(costs));
+                }
+                None => {
+                    eprintln!("no players for the cat in bag, question is skipped");
+                    self.set_state(State::Pause);
+                    reply.push(UiRequest::SendTextToMainChat(
+                        "Отдать кота некому, вопрос пропускается".to_string(),
+                    ));
+                }
+            }
             return reply;
         }
 
-        reply.push(
-            UiRequest::SendToAdmin(format!(
-                "question: {}\nanswer: {}",
-                question.question(),
-                question.answer(),
-            ))
-        );
+        reply.push(UiRequest::SendToAdmin(format_admin_question(&question)));
 
         if self.is_manual(&topic, &cost) {
             eprintln!("manual question");
             self.set_state(State::Pause);
+            self.manual_scoring = Some(ManualScoring {
+                question: question.clone(),
+                cost: cost as i64,
+                credited: vec![],
+            });
             let score = self.get_score_str();
             reply.push(
                 UiRequest::SendTextToMainChat(format!("Вопрос играется вручную\n{}", score)),
             );
+            reply.push(UiRequest::ChooseScoringPlayer(self.players.keys().cloned().collect()));
+            reply
+        } else if self.is_stake(&topic, &cost) {
+            eprintln!("stake question");
+            self.set_state(State::StakeChoosing(question, cost as i64));
+            let (min_stake, max_stake) = self.stake_limits(cost as i64);
+            let player_name = self.current_player.as_ref().map(|player| player.name().clone()).unwrap_or_default();
+            reply.push(UiRequest::SendTextToMainChat(format!(
+                "Вопрос со ставкой! {}, напишите ставку от {} до {}",
+                player_name, min_stake, max_stake
+            )));
             reply
         } else if self.is_auction(&topic, &cost) {
             eprintln!("auction");
             self.set_state(State::WaitingForAuction(topic.clone(), question.clone()));
+            self.auction_bidding = Some(AuctionBidding {
+                nominal: cost as i64,
+                step: self.cost_step() as i64,
+                high_bid: None,
+                passed: HashSet::new(),
+            });
             let score = self.get_score_str();
             reply.push(
-               UiRequest::SendTextToMainChat(format!("Аукцион!\n{}", score))
+               UiRequest::SendTextToMainChat(format!(
+                   "Аукцион! Номинал {}, делайте ставки числом, «ва-банк» или «пас»\n{}",
+                   cost, score
+               ))
             );
+            reply.push(UiRequest::ScheduleTimer(TimerId::Auction, self.config.auction_bid_timeout));
+            reply
+        } else if self.is_single_answer(&topic, &cost) {
+            eprintln!("single answer question");
+            // Only the player who chose the question answers
+            self.set_state(State::Answering(question.clone(), cost as i64, false));
+            let player_name = self.current_player.as_ref().map(|player| player.name().clone()).unwrap_or_default();
+            reply.push(UiRequest::SendTextToMainChat(format!("Отвечает только {}", player_name)));
+            reply.extend(self.format_question(&question));
+            reply.push(UiRequest::AskAdminYesNo("Correct answer?".to_string()));
             reply
         } else {
             eprintln!("automatic question");
             self.set_state(State::BeforeQuestionAsked(question.clone(), cost as i64));
-            self.player_which_chose_question = self.current_player.clone();
             reply.push(
-                UiRequest::Timeout(None, Delay::Medium),
+                UiRequest::Timeout(None, self.config.delays.get(Delay::PreQuestion)),
             );
             reply
         }
@@ -848,15 +2851,13 @@ impl GameState {
                     }
                     if player.name() == &selected_player {
                         self.current_player = Some(player.clone());
-                        self.player_which_chose_question = Some(player.clone());
-                        self.set_state(State::CatInBagChoosingCost(question));
+                        let costs = self.cat_in_bag_costs();
+                        self.set_state(State::CatInBagChoosingCost(question, costs.clone()));
                         return vec![
                             UiRequest::SendTextToMainChat(format!(
                                 "Играем с {}. Тема: {}", player.name(), topic,
                             )),
-                            UiRequest::CatInBagChooseCost(vec![
-                                self.current_multiplier, self.current_multiplier * self.questions_per_topic
-                            ])
+                            UiRequest::CatInBagChooseCost(costs),
                         ];
                     }
                 }
@@ -875,23 +2876,40 @@ impl GameState {
     pub fn select_cat_in_bag_cost(&mut self, user: UserId, cost: usize) -> Vec<UiRequest> {
         let cur_state = self.state.clone();
         match cur_state {
-            State::CatInBagChoosingCost(question) => {
+            State::CatInBagChoosingCost(question, costs) => {
                 if Some(user) != self.current_player.clone().map(|x| x.id()) {
                     eprintln!("invalid user {} tried to select cat in bag cost", user);
                     return vec![];
                 }
-                if cost != self.current_multiplier && cost != self.current_multiplier * self.questions_per_topic {
+                if !costs.contains(&cost) {
                     eprintln!("invalid cost {}", cost);
-                    return vec![];
+                    let costs: Vec<_> = costs.iter().map(|cost| cost.to_string()).collect();
+                    return vec![UiRequest::SendTextToMainChat(format!(
+                        "Неверная стоимость {}, можно выбрать: {}",
+                        cost,
+                        costs.join(", ")
+                    ))];
                 }
 
                 // Only one person can answer
                 self.set_state(State::Answering(question.clone(), cost as i64, false));
+                if let Some(player) = self.current_player.clone() {
+                    self.log_event(format!("{} plays the cat in bag for {}", player.name(), cost));
+                }
 
                 let mut res = vec![
                     UiRequest::SendTextToMainChat(format!("Выбрана стоимость {}", cost)),
                 ];
-                res.extend(self.format_question(&question));
+                match self.current_player.clone() {
+                    Some(player) if self.config.cat_in_bag_dm && self.private_chats.contains(&player.id()) => {
+                        res.push(UiRequest::SendTextToMainChat(format!(
+                            "Вопрос отправлен {} в личные сообщения",
+                            player.name()
+                        )));
+                        res.push(UiRequest::SendPrivately(player.id(), self.format_question(&question)));
+                    }
+                    _ => res.extend(self.format_question(&question)),
+                }
                 res.push(UiRequest::AskAdminYesNo("Correct answer?".to_string()));
                 res
             }
@@ -902,21 +2920,379 @@ impl GameState {
         }
     }
 
-    pub fn get_score(&mut self, _user: UserId) -> Vec<UiRequest> {
-        vec![UiRequest::SendTextToMainChat(self.get_score_str())]
+    // Player can bet up to their score, but at least the nominal is always allowed
+    fn stake_limits(&self, nominal: i64) -> (i64, i64) {
+        let score = self
+            .current_player
+            .as_ref()
+            .and_then(|player| self.players.get(player))
+            .cloned()
+            .unwrap_or_default();
+        (self.cost_step() as i64, score.max(nominal))
     }
 
-    pub fn get_score_str(&self) -> String {
-        let mut res = String::from("Счет:\n");
-        for (player, score) in self.players.iter() {
-            res += &format!("{}: {}\n", player.name(), score);
-        }
-        res
+    // Cheapest question of the current topic, topics with custom costs can
+    // have it below the multiplier
+    fn cost_step(&self) -> usize {
+        self.current_topic
+            .as_ref()
+            .and_then(|topic| self.topic_costs(topic).into_iter().min())
+            .unwrap_or(self.current_multiplier)
     }
 
-    pub fn current_player(&mut self, _user: UserId) -> Vec<UiRequest> {
-        let mut res = String::new();
-        match self.current_player {
+    fn choose_stake(&mut self, user: UserId, message: &str) -> Vec<UiRequest> {
+        let (question, nominal) = match self.state.clone() {
+            State::StakeChoosing(question, nominal) => (question, nominal),
+            _ => {
+                return vec![];
+            }
+        };
+        let player = match self.current_player.clone() {
+            Some(player) if player.id() == user => player,
+            _ => {
+                return vec![];
+            }
+        };
+        let stake = match message.trim().parse::<i64>() {
+            Ok(stake) => stake,
+            Err(_) => {
+                return vec![];
+            }
+        };
+
+        let (min_stake, max_stake) = self.stake_limits(nominal);
+        if stake < min_stake || stake > max_stake {
+            return vec![UiRequest::SendTextToMainChat(format!(
+                "Ставка должна быть от {} до {}",
+                min_stake, max_stake
+            ))];
+        }
+
+        // Only the player who made the stake answers
+        self.set_state(State::Answering(question.clone(), stake, false));
+        self.log_event(format!("{} plays the stake question for {}", player.name(), stake));
+
+        let mut res = vec![UiRequest::SendTextToMainChat(format!("Ставка {}", stake))];
+        res.extend(self.format_question(&question));
+        res.push(UiRequest::AskAdminYesNo("Correct answer?".to_string()));
+        res
+    }
+
+    pub fn select_scoring_player(&mut self, user: UserId, selected_player: String) -> Vec<UiRequest> {
+        if user != self.admin_user {
+            eprintln!("non admin user tried to score manual question");
+            return vec![];
+        }
+        if self.is_finished() {
+            return vec![];
+        }
+
+        let player = match self.find_player_by_name(&selected_player) {
+            Some(player) => player.clone(),
+            None => {
+                eprintln!("unknown player {} for manual question", selected_player);
+                return vec![];
+            }
+        };
+
+        let manual_scoring = match self.manual_scoring {
+            Some(ref mut manual_scoring) => manual_scoring,
+            None => {
+                eprintln!("no manual question to score");
+                return vec![];
+            }
+        };
+
+        if manual_scoring.credited.contains(&player) {
+            eprintln!("{} was already credited for manual question", player.name());
+            return vec![];
+        }
+        manual_scoring.credited.push(player.clone());
+        let cost = manual_scoring.cost;
+        let credited = manual_scoring.credited.clone();
+
+        if let Some(score) = self.players.get_mut(&player) {
+            *score += cost;
+            self.add_topic_points(cost);
+        }
+        *self.correct_answers.entry(player.clone()).or_default() += 1;
+        self.log_event(format!("{} is credited for the manual question: +{}", player.name(), cost));
+
+        let mut res = vec![
+            UiRequest::SendTextToMainChat(format!("{} получает {}", player.name(), cost)),
+        ];
+        if credited.len() >= self.config.manual_question_answerers {
+            res.extend(self.close_manual_question());
+        } else {
+            res.push(UiRequest::ChooseScoringPlayer(
+                self.players
+                    .keys()
+                    .filter(|player| !credited.contains(player))
+                    .cloned()
+                    .collect(),
+            ));
+        }
+        res
+    }
+
+    pub fn finish_manual_scoring(&mut self, user: UserId) -> Vec<UiRequest> {
+        if user != self.admin_user {
+            eprintln!("non admin user tried to finish manual question scoring");
+            return vec![];
+        }
+        if self.is_finished() {
+            return vec![];
+        }
+
+        if self.manual_scoring.is_none() {
+            eprintln!("no manual question to finish");
+            return vec![];
+        }
+        self.close_manual_question()
+    }
+
+    fn close_manual_question(&mut self) -> Vec<UiRequest> {
+        let manual_scoring = match self.manual_scoring.take() {
+            Some(manual_scoring) => manual_scoring,
+            None => {
+                return vec![];
+            }
+        };
+
+        self.remember_played_question(manual_scoring.question.clone(), manual_scoring.credited.first().cloned());
+        // First player who answered correctly continues the game, otherwise
+        // the one who chose the question
+        if let Some(player) = manual_scoring.credited.first() {
+            self.current_player = Some(player.clone());
+        } else if let Some(ref chooser) = self.player_which_chose_question {
+            self.current_player = Some(chooser.clone());
+        }
+        self.log_score();
+
+        let answer = format_answer_reveal(&manual_scoring.question);
+        let current_player_name = match self.current_player {
+            Some(ref player) => player.name().clone(),
+            None => {
+                return vec![UiRequest::SendTextToMainChat(format!("{}\n{}", answer, self.get_score_str()))];
+            }
+        };
+        vec![UiRequest::SendTextToMainChat(format!(
+            "{}\n{}\nИгру продолжает {}",
+            answer,
+            self.get_score_str(),
+            current_player_name
+        ))]
+    }
+
+    pub fn export_board(&mut self, user: UserId) -> Vec<UiRequest> {
+        if user != self.admin_user {
+            eprintln!("non admin user tried to export the board");
+            return vec![];
+        }
+
+        vec![UiRequest::ExportScoreTable(self.make_score_table())]
+    }
+
+    pub fn board(&mut self, user: UserId) -> Vec<UiRequest> {
+        if user != self.admin_user {
+            eprintln!("non admin user tried to get the board");
+            return vec![];
+        }
+
+        vec![UiRequest::SendToAdmin(self.get_board_str())]
+    }
+
+    pub fn board_image(&mut self, user: UserId) -> Vec<UiRequest> {
+        if user != self.admin_user {
+            eprintln!("non admin user tried to resend the board");
+            return vec![];
+        }
+
+        if matches!(
+            self.state,
+            State::WaitingForPlayersToJoin | State::WaitingForStartConfirmation
+        ) {
+            return vec![UiRequest::SendToAdmin("The game hasn't started yet".to_string())];
+        }
+
+        vec![UiRequest::SendScoreTable(self.make_score_table())]
+    }
+
+    pub fn public_state(&self) -> PublicState {
+        let started = !matches!(
+            self.state,
+            State::WaitingForPlayersToJoin | State::WaitingForStartConfirmation
+        );
+        PublicState {
+            current_player: self.current_player.as_ref().map(|player| player.name().clone()),
+            scores: self
+                .ranked_players()
+                .into_iter()
+                .map(|(_, player, score)| PublicScore {
+                    name: player.name().clone(),
+                    score,
+                })
+                .collect(),
+            current_tour: if started { self.current_tour + 1 } else { 0 },
+            remaining_questions: self
+                .questions
+                .iter()
+                .map(|(topic, costs)| PublicTopic {
+                    name: topic.clone(),
+                    costs: costs.clone(),
+                })
+                .collect(),
+        }
+    }
+
+    // Detailed breakdown of the current tour for the admin
+    fn get_board_str(&self) -> String {
+        let mut lines = vec![format!(
+            "Tour {} (multiplier {})",
+            self.current_tour + 1,
+            self.current_multiplier
+        )];
+        for (topic, available_costs) in self.questions.iter() {
+            let mut available = vec![];
+            let mut used = vec![];
+            for cost in self.topic_costs(topic) {
+                let mut cell = cost.to_string();
+                if self.is_cat_in_bag(topic, &cost).is_some() {
+                    cell += " [cat]";
+                }
+                if self.is_auction(topic, &cost) {
+                    cell += " [auction]";
+                }
+                if self.is_stake(topic, &cost) {
+                    cell += " [stake]";
+                }
+                if self.is_manual(topic, &cost) {
+                    cell += " [manual]";
+                }
+
+                if available_costs.contains(&cost) {
+                    available.push(cell);
+                } else {
+                    used.push(cell);
+                }
+            }
+            lines.push(format!(
+                "{}\n  available: {}\n  used: {}",
+                topic,
+                available.join(", "),
+                used.join(", ")
+            ));
+        }
+        lines.join("\n")
+    }
+
+    pub fn stats(&mut self, _user: UserId) -> Vec<UiRequest> {
+        vec![UiRequest::SendTextToMainChat(self.get_stats_str())]
+    }
+
+    fn get_stats_str(&self) -> String {
+        let mut res = "Среднее время нажатия на кнопку:".to_string();
+        for player in self.players.keys().sorted_by_key(|player| player.name()) {
+            match self.response_times.get(player) {
+                Some(times) if !times.is_empty() => {
+                    let total: Duration = times.iter().sum();
+                    res += &format!(
+                        "\n{}: {:.2}с (нажатий: {})",
+                        player.name(),
+                        total.as_secs_f64() / times.len() as f64,
+                        times.len()
+                    );
+                }
+                _ => {
+                    res += &format!("\n{}: -", player.name());
+                }
+            }
+        }
+        res
+    }
+
+    fn log_event(&mut self, event: String) {
+        eprintln!("{}", event);
+        self.history.push(event);
+    }
+
+    fn log_question(&mut self, question: &Question) {
+        self.log_event(format!("Question: {}", question.question()));
+        self.log_event(format!("Answer: {}", question.answer()));
+    }
+
+    fn log_score(&mut self) {
+        let score = self
+            .ranked_players()
+            .into_iter()
+            .map(|(_, player, score)| format!("{}: {}", player.name(), score))
+            .join(", ");
+        self.log_event(format!("Score: {}", score));
+    }
+
+    fn remember_played_question(&mut self, question: Question, answered: Option<Player>) {
+        // Appealed question is played once again
+        if self.last_questions.back().map(|played| &played.question) == Some(&question) {
+            self.last_questions.pop_back();
+        }
+        self.last_questions.push_back(PlayedQuestion { question, answered });
+        while self.last_questions.len() > self.config.last_questions {
+            self.last_questions.pop_front();
+        }
+    }
+
+    pub fn last_question(&mut self, user: UserId, ago: usize) -> Vec<UiRequest> {
+        if user != self.admin_user {
+            eprintln!("non admin user tried to repeat the last question");
+            return vec![];
+        }
+
+        let played = match self.last_questions.len().checked_sub(ago) {
+            Some(idx) => &self.last_questions[idx],
+            None => {
+                return vec![UiRequest::SendToAdmin(format!(
+                    "Only {} played questions are kept",
+                    self.last_questions.len()
+                ))];
+            }
+        };
+        let answered = match played.answered {
+            Some(ref player) => format!("Ответил: {}", player.name()),
+            None => "Никто не ответил".to_string(),
+        };
+        vec![UiRequest::SendTextToMainChat(format!(
+            "Повтор вопроса:\n{}\n{}\n{}",
+            played.question.question(),
+            format_answer_reveal(&played.question),
+            answered
+        ))]
+    }
+
+    // Human readable record of everything that happened in the game
+    pub fn transcript(&mut self, user: UserId) -> Vec<UiRequest> {
+        if user != self.admin_user {
+            eprintln!("non admin user tried to get the transcript");
+            return vec![];
+        }
+
+        vec![UiRequest::ExportTranscript(self.history.join("\n"))]
+    }
+
+    pub fn get_score(&mut self, _user: UserId) -> Vec<UiRequest> {
+        vec![UiRequest::SendTextToMainChat(self.get_score_str())]
+    }
+
+    pub fn get_score_str(&self) -> String {
+        let mut res = String::from("Счет:\n");
+        for (_, player, score) in self.ranked_players() {
+            res += &format!("{}: {}\n", player.name(), score);
+        }
+        res
+    }
+
+    pub fn current_player(&mut self, _user: UserId) -> Vec<UiRequest> {
+        let mut res = String::new();
+        match self.current_player {
             Some(ref player) => res += &player.name(),
             None => {
                 res += "No current player!";
@@ -931,6 +3307,9 @@ impl GameState {
             eprintln!("non admin user tried to change player");
             return vec![];
         }
+        if self.is_finished() {
+            return vec![];
+        }
 
         if let Some(player) = self.find_player_by_name(&change_player) {
             self.current_player = Some(player.clone());
@@ -940,11 +3319,52 @@ impl GameState {
         }
     }
 
+    // Unlike change_player it also finishes a manual question and stops waiting
+    // for the choice of the previous player
+    pub fn next_chooser(&mut self, user: UserId, name: String) -> Vec<UiRequest> {
+        if user != self.admin_user {
+            eprintln!("non admin user tried to set the next chooser");
+            return vec![];
+        }
+
+        match self.state {
+            State::Pause | State::WaitingForTopic | State::WaitingForQuestion(_) => {}
+            State::WaitingForPlayersToJoin | State::WaitingForStartConfirmation | State::Finished => {
+                return vec![UiRequest::SendToAdmin("Game is not in progress".to_string())];
+            }
+            _ => {
+                return vec![UiRequest::SendToAdmin(
+                    "Question is being played, the chooser can be set after it".to_string(),
+                )];
+            }
+        }
+
+        let player = match self.find_player_by_name(&name) {
+            Some(player) => player.clone(),
+            None => {
+                return vec![UiRequest::SendTextToMainChat(format!("Игрок {} не найден", name))];
+            }
+        };
+
+        let mut res = self.reveal_answer();
+        if self.manual_scoring.is_some() {
+            res.extend(self.close_manual_question());
+        }
+        self.set_state(State::Pause);
+        self.current_player = Some(player.clone());
+        self.log_event(format!("Admin passed the choice of the next question to {}", player.name()));
+        res.push(UiRequest::SendTextToMainChat(format!("Следующий вопрос выбирает {}", player.name())));
+        res
+    }
+
     pub fn update_score(&mut self, name: String, newscore: i64, user: UserId) -> Vec<UiRequest> {
         if user != self.admin_user {
             eprintln!("non admin user tried to update the score");
             return vec![];
         }
+        if self.is_finished() {
+            return vec![];
+        }
 
         let player = match self.find_player_by_name(&name) {
             Some(player) => player.clone(),
@@ -957,6 +3377,7 @@ impl GameState {
         if let Some(score) = self.players.get_mut(&player) {
             eprintln!("{} score updated", name);
             *score = newscore;
+            self.log_event(format!("Admin set the score of {} to {}", player.name(), newscore));
         } else {
             eprintln!("internal error: {} not found", name);
         }
@@ -968,6 +3389,9 @@ impl GameState {
             eprintln!("non admin user tried to hide question");
             return vec![];
         }
+        if self.is_finished() {
+            return vec![];
+        }
 
         let mut found = false;
         for (cur_topic, costs) in &mut self.questions {
@@ -990,6 +3414,45 @@ impl GameState {
         vec![]
     }
 
+    // Returns hidden question back to the board. Played questions are returned
+    // only if forced, so that a question isn't asked twice by mistake
+    pub fn unhide_question(&mut self, topic: String, cost: usize, force: bool, user: UserId) -> Vec<UiRequest> {
+        if user != self.admin_user {
+            eprintln!("non admin user tried to unhide question");
+            return vec![];
+        }
+        if self.is_finished() {
+            return vec![];
+        }
+
+        if !self.topic_costs(&topic).contains(&cost) {
+            return vec![UiRequest::SendToAdmin(format!("There is no question for {} in this tour", cost))];
+        }
+
+        let played = self.played_questions.contains(&(topic.clone(), cost));
+        let costs = match self.questions.iter_mut().find(|(cur_topic, _)| cur_topic == &topic) {
+            Some((_, costs)) => costs,
+            None => {
+                return vec![UiRequest::SendToAdmin(format!("Topic '{}' not found", topic))];
+            }
+        };
+        if costs.contains(&cost) {
+            return vec![UiRequest::SendToAdmin(format!("'{}' for {} is already on the board", topic, cost))];
+        }
+        if played && !force {
+            return vec![UiRequest::SendToAdmin(format!(
+                "'{}' for {} was already played, use {}unhide force {} {} to return it anyway",
+                topic, cost, self.config.command_prefix, cost, topic
+            ))];
+        }
+
+        costs.push(cost);
+        costs.sort_unstable();
+        self.played_questions.remove(&(topic.clone(), cost));
+        self.log_event(format!("Admin returned '{}' for {} to the board", topic, cost));
+        vec![UiRequest::SendToAdmin(format!("'{}' for {} is back on the board", topic, cost))]
+    }
+
     pub fn get_topic_id(&self, topic_name: String) -> Option<TopicIdx> {
         for (idx, (name, _)) in self.questions.iter().enumerate() {
             if name == &topic_name {
@@ -1001,15 +3464,20 @@ impl GameState {
 
     fn reload_available_questions(&mut self) {
         self.questions.clear();
+        self.question_difficulties.clear();
+        self.played_questions.clear();
+        self.topic_points.clear();
+        self.tour_recap_shown = false;
         match self.tours.get(self.current_tour) {
-            Some(ref tour) => {
+            Some(tour) => {
                 self.current_multiplier = tour.multiplier;
-                for topic in &tour.topics {
-                    let mut costs = vec![];
-                    for i in 0..self.questions_per_topic {
-                        costs.push((i + 1) * self.current_multiplier);
+                let topics: Vec<_> = tour.topics.iter().map(|topic| topic.name.clone()).collect();
+                for topic in topics {
+                    let costs = self.topic_costs(&topic);
+                    for (idx, cost) in costs.iter().enumerate() {
+                        self.question_difficulties.insert((topic.clone(), *cost), idx + 1);
                     }
-                    self.questions.push((topic.name.clone(), costs));
+                    self.questions.push((topic, costs));
                 }
             }
             None => {
@@ -1018,12 +3486,77 @@ impl GameState {
         }
     }
 
+    // Number of questions in the topic of the current tour
+    fn topic_size(&self, topic: &str) -> usize {
+        let topics = match self.tours.get(self.current_tour) {
+            Some(tour) => &tour.topics,
+            None => return 0,
+        };
+        topics
+            .iter()
+            .position(|cur_topic| cur_topic.name == topic)
+            .and_then(|idx| self.topic_sizes[self.current_tour].get(idx).cloned())
+            .unwrap_or_default()
+    }
+
+    fn question_cost(&self, topic: &str, difficulty: usize, multiplier: usize) -> usize {
+        self.custom_costs
+            .get(&(topic.to_string(), difficulty))
+            .cloned()
+            .unwrap_or(difficulty * multiplier)
+    }
+
+    // Costs of all questions of the topic in the current tour, played or not
+    fn topic_costs(&self, topic: &str) -> Vec<usize> {
+        (1..=self.topic_size(topic))
+            .map(|difficulty| self.question_cost(topic, difficulty, self.current_multiplier))
+            .collect()
+    }
+
+    fn question_difficulty(&self, topic: &str, cost: usize) -> Option<usize> {
+        self.question_difficulties.get(&(topic.to_string(), cost)).cloned()
+    }
+
+    // Cheapest or the most expensive question of the topic the cat in bag
+    // was hidden in
+    fn cat_in_bag_costs(&self) -> Vec<usize> {
+        let costs = match self.current_topic {
+            Some(ref topic) if self.topic_size(topic) > 0 => self.topic_costs(topic),
+            _ => (1..=self.max_topic_size()).map(|i| i * self.current_multiplier).collect(),
+        };
+        let mut costs = vec![
+            costs.iter().min().cloned().unwrap_or(self.current_multiplier),
+            costs.iter().max().cloned().unwrap_or(self.current_multiplier),
+        ];
+        costs.dedup();
+        costs
+    }
+
+    fn max_topic_size(&self) -> usize {
+        self.topic_sizes
+            .get(self.current_tour)
+            .and_then(|sizes| sizes.iter().max().cloned())
+            .unwrap_or_default()
+    }
+
+    fn current_tour_title_suffix(&self) -> String {
+        match self.tours.get(self.current_tour).and_then(|tour| tour.title.as_ref()) {
+            Some(title) => format!(" «{}»", title),
+            None => String::new(),
+        }
+    }
+
     fn find_player(&self, id: UserId) -> Option<&Player> {
         self.players.keys().find(|player| player.id() == id)
     }
 
-    fn find_player_by_name(&mut self, name: &String) -> Option<&Player> {
-        self.players.keys().find(|player| player.name() == name)
+    // Names are compared case-insensitively, so that "Вася" and "вася"
+    // can't both join
+    fn find_player_by_name(&mut self, name: &str) -> Option<&Player> {
+        let name = name.to_lowercase();
+        self.players
+            .keys()
+            .find(|player| player.name().to_lowercase() == name)
     }
 
     fn update_current_player_score(&mut self, cost: i64) -> Result<(), String> {
@@ -1033,6 +3566,7 @@ impl GameState {
                 match val {
                     Some(val) => {
                         *val += cost;
+                        self.add_topic_points(cost);
                         Ok(())
                     }
                     None => Err("current player is not in list of players".to_string()),
@@ -1056,7 +3590,19 @@ impl GameState {
             .is_some()
     }
 
-    fn is_cat_in_bag(&mut self, cur_topic: &String, cur_cost: &usize) -> Option<String> {
+    fn is_single_answer(&self, cur_topic: &str, cur_cost: &usize) -> bool {
+        self.single_answer_questions
+            .iter()
+            .any(|(topic, cost)| cur_topic == topic && cur_cost == cost)
+    }
+
+    fn is_stake(&self, cur_topic: &str, cur_cost: &usize) -> bool {
+        self.stakes
+            .iter()
+            .any(|(topic, cost)| cur_topic == topic && cur_cost == cost)
+    }
+
+    fn is_cat_in_bag(&self, cur_topic: &String, cur_cost: &usize) -> Option<String> {
         for cat_in_bag in &self.cats_in_bags {
             if &cat_in_bag.old_topic == cur_topic && &cat_in_bag.cost == cur_cost {
                 return Some(
@@ -1114,6 +3660,7 @@ mod test {
     use super::*;
     use crate::questionsstorage::QuestionsStorage;
     use crate::questionsstorage::Topic;
+    use std::path::PathBuf;
 
     pub struct FakeQuestionsStorage {
         questions: HashMap<(String, usize), Question>,
@@ -1121,6 +3668,9 @@ mod test {
         cats_in_bags: Vec<CatInBag>,
         manual_questions: Vec<(String, usize)>,
         auctions: Vec<(String, usize)>,
+        stakes: Vec<(String, usize)>,
+        single_answer_questions: Vec<(String, usize)>,
+        custom_costs: HashMap<(String, usize), usize>,
     }
 
     impl FakeQuestionsStorage {
@@ -1150,6 +3700,9 @@ mod test {
                 cats_in_bags: vec![],
                 manual_questions: vec![],
                 auctions: vec![],
+                stakes: vec![],
+                single_answer_questions: vec![],
+                custom_costs: HashMap::new(),
             }
         }
     }
@@ -1159,6 +3712,10 @@ mod test {
             self.questions.get(&(topic_name, difficulty)).cloned()
         }
 
+        fn contains(&self, topic_name: String, difficulty: usize) -> bool {
+            self.questions.contains_key(&(topic_name, difficulty))
+        }
+
         fn get_tours(&self) -> Vec<TourDescription> {
             self.tours.clone()
         }
@@ -1174,25 +3731,75 @@ mod test {
         fn get_auctions(&self) -> Vec<(String, usize)> {
             self.auctions.clone()
         }
+
+        fn get_stakes(&self) -> Vec<(String, usize)> {
+            self.stakes.clone()
+        }
+
+        fn get_single_answer_questions(&self) -> Vec<(String, usize)> {
+            self.single_answer_questions.clone()
+        }
+
+        fn get_custom_costs(&self) -> HashMap<(String, usize), usize> {
+            self.custom_costs.clone()
+        }
+    }
+
+    // Most of the tests are played by a single player
+    fn solo_game_config() -> GameConfig {
+        GameConfig {
+            min_players: 1,
+            ..GameConfig::default()
+        }
     }
 
     fn create_game_state(user: UserId) -> (GameState, Box<dyn QuestionsStorage>) {
         let tours = vec![
             TourDescription {
                 multiplier: 100,
+                title: None,
+                blitz: false,
                 topics: vec![Topic {
                     name: "Sport".to_string(),
                 }],
             },
             TourDescription {
                 multiplier: 200,
+                title: None,
+                blitz: false,
                 topics: vec![Topic {
                     name: "Movies".to_string(),
                 }],
             },
         ];
         let questions_storage: Box<dyn QuestionsStorage> = Box::new(FakeQuestionsStorage::new(tours));
-        (GameState::new(user, &questions_storage, 5).unwrap(), questions_storage)
+        (GameState::new(user, &questions_storage, Some(5), solo_game_config()).unwrap(), questions_storage)
+    }
+
+    // Single tour of the given topics with the players named new_1, new_2 etc.
+    // Special questions are set up by the caller
+    fn start_game<F: FnOnce(&mut FakeQuestionsStorage)>(
+        admin: UserId,
+        topics: &[&str],
+        players: &[UserId],
+        setup: F,
+    ) -> (GameState, Box<dyn QuestionsStorage>) {
+        let tours = vec![TourDescription {
+            multiplier: 100,
+            title: None,
+            blitz: false,
+            topics: topics.iter().map(|name| Topic { name: name.to_string() }).collect(),
+        }];
+        let mut questions_storage = FakeQuestionsStorage::new(tours);
+        setup(&mut questions_storage);
+        let questions_storage: Box<dyn QuestionsStorage> = Box::new(questions_storage);
+
+        let mut game_state = GameState::new(admin, &questions_storage, Some(5), solo_game_config()).unwrap();
+        for (i, player) in players.iter().enumerate() {
+            game_state.add_player(*player, format!("new_{}", i + 1), None);
+        }
+        game_state.start(admin);
+        (game_state, questions_storage)
     }
 
     fn select_question<T: ToString>(
@@ -1221,10 +3828,166 @@ mod test {
     }
 
     #[test]
-    fn test_start_game() {
-        let (mut game_state, _) = create_game_state(UserId::from(1));
-        assert_eq!(game_state.get_state(), &State::WaitingForPlayersToJoin);
-
+    fn test_admin_as_player() {
+        let admin = UserId::from(1);
+        let p1 = UserId::from(2);
+        let (mut game_state, questions_storage) = create_game_state(admin);
+        game_state.config.admin_can_play = false;
+        match game_state.add_player(admin, String::from("admin"), None).as_slice() {
+            [UiRequest::SendTextToMainChat(msg)] => assert_eq!(msg, "Ведущий не может играть"),
+            _ => panic!("admin shouldn't be able to join"),
+        }
+        assert!(game_state.get_players().is_empty());
+
+        game_state.config.admin_can_play = true;
+        match game_state.add_player(admin, String::from("admin"), None).as_slice() {
+            [UiRequest::SendTextToMainChat(_), UiRequest::SendToAdmin(msg)] => {
+                assert_eq!(msg, "You joined as a player, you will judge your own answers")
+            }
+            _ => panic!("admin should be warned"),
+        }
+        game_state.add_player(p1, String::from("new_1"), None);
+        game_state.start(admin);
+        game_state.next_question(admin);
+
+        // Judging the answer of the admin is announced
+        select_question(&mut game_state, &questions_storage, "Sport", p1, 100);
+        game_state.message(admin, String::from("1"));
+        match game_state.yes_reply(admin).first() {
+            Some(UiRequest::SendTextToMainChat(msg)) => assert_eq!(msg, "Ведущий сам оценил свой ответ"),
+            _ => panic!("self judgment should be announced"),
+        }
+        assert_eq!(game_state.get_player_score(admin), Some(100));
+
+        // Judging other players isn't
+        game_state.next_question(admin);
+        select_question(&mut game_state, &questions_storage, "Sport", admin, 200);
+        game_state.message(p1, String::from("1"));
+        let res = game_state.no_reply(admin);
+        assert!(!res.iter().any(|r| matches!(r, UiRequest::SendTextToMainChat(msg) if msg == "Ведущий сам оценил свой ответ")));
+        game_state.message(admin, String::from("1"));
+        match game_state.no_reply(admin).first() {
+            Some(UiRequest::SendTextToMainChat(msg)) => assert_eq!(msg, "Ведущий сам оценил свой ответ"),
+            _ => panic!("self judgment should be announced"),
+        }
+        assert_eq!(game_state.get_player_score(admin), Some(-100));
+    }
+
+    #[test]
+    fn test_max_players() {
+        let (mut game_state, _) = create_game_state(UserId::from(1));
+        game_state.config.max_players = Some(2);
+        game_state.add_player(UserId::from(2), String::from("new_1"), None);
+        game_state.add_player(UserId::from(3), String::from("new_2"), None);
+        match game_state.add_player(UserId::from(4), String::from("new_3"), None).as_slice() {
+            [UiRequest::SendTextToMainChat(msg)] => assert_eq!(msg, "Регистрация заполнена"),
+            _ => panic!("registration should be full"),
+        }
+        assert_eq!(game_state.get_players().len(), 2);
+
+        // Joined players can still join again
+        match game_state.add_player(UserId::from(3), String::from("new_2"), None).as_slice() {
+            [UiRequest::SendTextToMainChat(msg)] => assert_eq!(msg, "new_2, вы уже зарегистрированы"),
+            _ => panic!("unexpected ui requests"),
+        }
+    }
+
+    #[test]
+    fn test_add_player_name_validation() {
+        let (mut game_state, _) = create_game_state(UserId::from(1));
+        game_state.add_player(UserId::from(2), String::from("   "), None);
+        game_state.add_player(UserId::from(3), "я".repeat(MAX_PLAYER_NAME_LEN + 1), None);
+        game_state.add_player(UserId::from(4), String::from("/score"), None);
+        assert_eq!(game_state.get_players().len(), 0);
+
+        // Only the configured command prefix is rejected
+        game_state.config.command_prefix = String::from("!");
+        match game_state.add_player(UserId::from(4), String::from("!score"), None).as_slice() {
+            [UiRequest::SendTextToMainChat(msg)] => {
+                assert_eq!(msg, "Имя игрока не может начинаться с !")
+            }
+            _ => panic!("unexpected ui requests"),
+        }
+        game_state.add_player(UserId::from(7), String::from("/score"), None);
+        assert_eq!(game_state.get_players().len(), 1);
+        game_state.config.command_prefix = String::from("/");
+
+        game_state.add_player(UserId::from(5), String::from("  Вася \t Пупкин "), None);
+        assert_eq!(game_state.get_players().len(), 2);
+        assert!(game_state.find_player_by_name(&String::from("Вася Пупкин")).is_some());
+
+        game_state.add_player(UserId::from(6), String::from("вася пупкин"), None);
+        assert_eq!(game_state.get_players().len(), 2);
+        assert_eq!(
+            game_state.find_player_by_name(&String::from("ВАСЯ ПУПКИН")).unwrap().name(),
+            "Вася Пупкин"
+        );
+    }
+
+    #[test]
+    fn test_rejoin() {
+        let (mut game_state, _) = create_game_state(UserId::from(1));
+        game_state.add_player(UserId::from(2), String::from("Вася"), None);
+
+        // Same user with the same name is just a confirmation
+        match game_state.add_player(UserId::from(2), String::from("вася"), None).as_slice() {
+            [UiRequest::SendTextToMainChat(msg)] => assert_eq!(msg, "Вася, вы уже зарегистрированы"),
+            _ => panic!("unexpected ui requests"),
+        }
+        assert_eq!(game_state.get_players().len(), 1);
+
+        // Same user can't take another name
+        match game_state.add_player(UserId::from(2), String::from("Петя"), None).as_slice() {
+            [UiRequest::SendTextToMainChat(msg)] => assert_eq!(msg, "Такой игрок уже существует"),
+            _ => panic!("unexpected ui requests"),
+        }
+
+        // Another user can't take the name
+        match game_state.add_player(UserId::from(3), String::from("Вася"), None).as_slice() {
+            [UiRequest::SendTextToMainChat(msg)] => assert_eq!(msg, "Игрок с таким именем уже существует"),
+            _ => panic!("unexpected ui requests"),
+        }
+        assert_eq!(game_state.get_players().len(), 1);
+        assert_eq!(game_state.find_player_by_name("Вася").unwrap().id(), UserId::from(2));
+    }
+
+    #[test]
+    fn test_intro() {
+        let admin = UserId::from(1);
+        let start = |intro: Intro| {
+            let (mut game_state, _) = create_game_state(admin);
+            game_state.config.intro = intro;
+            game_state.add_player(UserId::from(2), String::from("new_1"), None);
+            game_state.start(admin)
+        };
+
+        match start(Intro::default()).first() {
+            Some(UiRequest::SendTextToMainChat(text)) => {
+                assert_eq!(text, "Здравствуйте, здравствуйте, добрый день! Это своя игра!")
+            }
+            _ => panic!("default greeting is expected"),
+        }
+
+        let intro = Intro {
+            text: Some("Кубок бара".to_string()),
+            sticker: Some("sticker_id".to_string()),
+            image: Some("https://example.com/logo.png".to_string()),
+        };
+        match &start(intro)[..3] {
+            [UiRequest::SendSticker(sticker), UiRequest::SendImage(Media::Url(url)), UiRequest::SendTextToMainChat(text)] => {
+                assert_eq!(sticker, "sticker_id");
+                assert_eq!(url, "https://example.com/logo.png");
+                assert_eq!(text, "Кубок бара");
+            }
+            _ => panic!("intro is expected"),
+        }
+    }
+
+    #[test]
+    fn test_start_game() {
+        let (mut game_state, _) = create_game_state(UserId::from(1));
+        assert_eq!(game_state.get_state(), &State::WaitingForPlayersToJoin);
+
         game_state.start(UserId::from(2));
         assert_eq!(game_state.get_state(), &State::WaitingForPlayersToJoin);
 
@@ -1239,6 +4002,150 @@ mod test {
         assert_eq!(game_state.get_state(), &State::Pause);
     }
 
+    #[test]
+    fn test_clear_players() {
+        let admin = UserId::from(1);
+        let p1 = UserId::from(2);
+        let (mut game_state, _) = create_game_state(admin);
+        game_state.add_player(p1, String::from("new_1"), None);
+
+        // Only admin can clear players
+        assert!(game_state.clear_players(p1).is_empty());
+        assert!(game_state.find_player(p1).is_some());
+
+        assert_eq!(game_state.clear_players(admin).len(), 1);
+        assert!(game_state.find_player(p1).is_none());
+
+        // The same user can join again
+        game_state.add_player(p1, String::from("new_1"), None);
+        assert!(game_state.find_player(p1).is_some());
+
+        game_state.start(admin);
+        assert!(game_state.clear_players(admin).is_empty());
+        assert!(game_state.find_player(p1).is_some());
+    }
+
+    #[test]
+    fn test_clear_cache() {
+        let admin = UserId::from(1);
+        let (mut game_state, _) = create_game_state(admin);
+        game_state.add_player(UserId::from(2), String::from("new_1"), None);
+        game_state.start(admin);
+
+        match game_state.clear_cache(admin).as_slice() {
+            [UiRequest::SendToAdmin(_)] => {}
+            _ => panic!("cache can't be cleared during the game"),
+        }
+
+        game_state.finish(admin);
+        assert!(game_state.clear_cache(UserId::from(2)).is_empty());
+        match game_state.clear_cache(admin).as_slice() {
+            [UiRequest::ClearCache] => {}
+            _ => panic!("unexpected ui requests"),
+        }
+    }
+
+    #[test]
+    fn test_start_confirmation() {
+        let admin = UserId::from(1);
+        let (mut game_state, _) = create_game_state(admin);
+        game_state.add_player(UserId::from(2), String::from("Петя"), None);
+        game_state.add_player(UserId::from(3), String::from("Вася"), None);
+
+        assert!(game_state.request_start(UserId::from(2)).is_empty());
+        match game_state.request_start(admin).as_slice() {
+            [UiRequest::AskAdminConfirmStart(text)] => {
+                assert_eq!(text, "Start the game with 2 players?\nВася\nПетя");
+            }
+            _ => panic!("admin should be asked to confirm the start"),
+        }
+        assert_eq!(game_state.get_state(), &State::WaitingForStartConfirmation);
+        match game_state.add_player(UserId::from(4), String::from("Маша"), None).as_slice() {
+            [UiRequest::SendTextToMainChat(msg)] => assert_eq!(msg, "Регистрация закрыта"),
+            _ => panic!("unexpected ui requests"),
+        }
+
+        game_state.cancel_start(admin);
+        assert_eq!(game_state.get_state(), &State::WaitingForPlayersToJoin);
+        game_state.add_player(UserId::from(4), String::from("Маша"), None);
+        assert_eq!(game_state.players.len(), 3);
+
+        game_state.request_start(admin);
+        game_state.start(admin);
+        assert_eq!(game_state.get_state(), &State::Pause);
+    }
+
+    #[test]
+    fn test_registration_window() {
+        let admin = UserId::from(1);
+        let (_, questions_storage) = create_game_state(admin);
+        let config = GameConfig {
+            registration_window: Some(Duration::from_secs(60)),
+            join_cooldown: Duration::from_secs(60),
+            ..solo_game_config()
+        };
+        let mut game_state = GameState::new(admin, &questions_storage, Some(5), config).unwrap();
+        game_state.config.command_prefix = String::from("!");
+        match game_state.registration_prompt().as_slice() {
+            [UiRequest::SendTextToMainChat(text), UiRequest::ScheduleTimer(TimerId::Registration, window)] => {
+                assert_eq!(text, "Для регистрации в игре введите '!join ИМЯ' без кавычек");
+                assert_eq!(*window, Duration::from_secs(60));
+            }
+            _ => panic!("unexpected ui requests"),
+        }
+
+        // Nobody joined, registration continues
+        match game_state.registration_expired().as_slice() {
+            [UiRequest::SendTextToMainChat(_), UiRequest::SendTextToMainChat(_), UiRequest::ScheduleTimer(..)] => {}
+            _ => panic!("unexpected ui requests"),
+        }
+
+        assert_eq!(game_state.add_player(UserId::from(2), String::from("new_1"), None).len(), 1);
+        // Too often
+        assert!(game_state.add_player(UserId::from(2), String::from("new_1"), None).is_empty());
+
+        match game_state.registration_expired().as_slice() {
+            [UiRequest::SendTextToMainChat(msg), UiRequest::AskAdminConfirmStart(_)] => {
+                assert_eq!(msg, "Регистрация закрыта");
+            }
+            _ => panic!("unexpected ui requests"),
+        }
+        match game_state.add_player(UserId::from(3), String::from("new_2"), None).as_slice() {
+            [UiRequest::SendTextToMainChat(msg)] => assert_eq!(msg, "Регистрация закрыта"),
+            _ => panic!("unexpected ui requests"),
+        }
+        assert!(!game_state.last_join_attempts.contains_key(&UserId::from(3)));
+
+        // Admin wants to wait for more players
+        game_state.cancel_start(admin);
+        game_state.add_player(UserId::from(4), String::from("new_3"), None);
+        assert_eq!(game_state.get_players().len(), 2);
+
+        // Nothing is said to late players once the game has started
+        game_state.start(admin);
+        assert_eq!(game_state.get_state(), &State::Pause);
+        assert!(game_state.add_player(UserId::from(3), String::from("new_2"), None).is_empty());
+        assert!(game_state.last_join_attempts.is_empty());
+    }
+
+    #[test]
+    fn test_min_players() {
+        let (_, questions_storage) = create_game_state(UserId::from(1));
+        let admin = UserId::from(1);
+        let mut game_state = GameState::new(admin, &questions_storage, Some(5), GameConfig::default()).unwrap();
+
+        game_state.add_player(UserId::from(2), String::from("new_1"), None);
+        match game_state.start(admin).as_slice() {
+            [UiRequest::SendTextToMainChat(msg)] => assert_eq!(msg, "Недостаточно игроков, нужно ещё 1"),
+            _ => panic!("unexpected ui requests"),
+        }
+        assert_eq!(game_state.get_state(), &State::WaitingForPlayersToJoin);
+
+        game_state.add_player(UserId::from(3), String::from("new_2"), None);
+        game_state.start(admin);
+        assert_eq!(game_state.get_state(), &State::Pause);
+    }
+
     #[test]
     fn test_score_simple() {
         let admin = UserId::from(1);
@@ -1308,6 +4215,8 @@ mod test {
         let admin = UserId::from(1);
         let tours = vec![TourDescription {
             multiplier: 100,
+            title: None,
+            blitz: false,
             topics: vec![Topic {
                 name: "Nonexisting topic".to_string(),
             }],
@@ -1315,22 +4224,84 @@ mod test {
         let questions_storage: Box<dyn QuestionsStorage> = Box::new(FakeQuestionsStorage::new(tours.clone()));
 
         // 0 question number
-        assert!(GameState::new(admin, &questions_storage, 0).is_err());
+        assert!(GameState::new(admin, &questions_storage, Some(0), solo_game_config()).is_err());
+
+        // Empty pack
+        let empty_storage: Box<dyn QuestionsStorage> = Box::new(FakeQuestionsStorage::new(vec![]));
+        match GameState::new(admin, &empty_storage, Some(5), solo_game_config()) {
+            Err(err) => assert_eq!(err.to_string(), "questions pack is empty: 0 tours and no topics were loaded"),
+            Ok(_) => panic!("empty pack should be rejected"),
+        }
 
         // Non existing topic
         let questions_storage: Box<dyn QuestionsStorage> = Box::new(FakeQuestionsStorage::new(tours.clone()));
-        assert!(GameState::new(admin, &questions_storage, 5).is_err());
+        assert!(GameState::new(admin, &questions_storage, Some(5), solo_game_config()).is_err());
 
         // Incorrect question number
         let tours = vec![TourDescription {
             multiplier: 100,
+            title: None,
+            blitz: false,
             topics: vec![Topic {
                 name: "Sport".to_string(),
             }],
         }];
 
         let questions_storage: Box<dyn QuestionsStorage> = Box::new(FakeQuestionsStorage::new(tours.clone()));
-        assert!(GameState::new(admin, &questions_storage, 6).is_err());
+        assert!(GameState::new(admin, &questions_storage, Some(6), solo_game_config()).is_err());
+
+        // Non existing topic can't be detected either
+        let tours = vec![TourDescription {
+            multiplier: 100,
+            title: None,
+            blitz: false,
+            topics: vec![Topic {
+                name: "Nonexisting topic".to_string(),
+            }],
+        }];
+        let questions_storage: Box<dyn QuestionsStorage> = Box::new(FakeQuestionsStorage::new(tours));
+        assert!(GameState::new(admin, &questions_storage, None, solo_game_config()).is_err());
+    }
+
+    #[test]
+    fn test_detect_questions_per_topic() {
+        let admin = UserId::from(1);
+        let tours = vec![TourDescription {
+            multiplier: 100,
+            title: None,
+            blitz: false,
+            topics: vec![
+                Topic {
+                    name: "Sport".to_string(),
+                },
+                Topic {
+                    name: "Movies".to_string(),
+                },
+            ],
+        }];
+        let mut questions_storage = FakeQuestionsStorage::new(tours);
+        questions_storage.questions.remove(&("Movies".to_string(), 4));
+        questions_storage.questions.remove(&("Movies".to_string(), 5));
+        let questions_storage: Box<dyn QuestionsStorage> = Box::new(questions_storage);
+        assert!(GameState::new(admin, &questions_storage, Some(5), solo_game_config()).is_err());
+
+        let mut game_state = GameState::new(admin, &questions_storage, None, solo_game_config()).unwrap();
+        game_state.add_player(UserId::from(2), String::from("new_1"), None);
+        game_state.start(admin);
+        assert_eq!(
+            game_state.questions,
+            vec![
+                ("Sport".to_string(), vec![100, 200, 300, 400, 500]),
+                ("Movies".to_string(), vec![100, 200, 300]),
+            ]
+        );
+
+        let score_table = game_state.make_score_table();
+        assert_eq!(score_table.scores, vec![100, 200, 300, 400, 500]);
+        assert_eq!(score_table.data[1].played, Some(0));
+        assert_eq!(score_table.standings.len(), 1);
+        assert_eq!(score_table.standings[0].name, "new_1");
+        assert!(game_state.get_board_str().contains("Movies\n  available: 100, 200, 300\n"));
     }
 
     #[test]
@@ -1351,104 +4322,1251 @@ mod test {
     }
 
     #[test]
-    fn test_falsestarts_simple() {
+    fn test_tour_titles() {
+        let tours = vec![
+            TourDescription {
+                multiplier: 100,
+                title: Some("Разминка".to_string()),
+                blitz: false,
+                topics: vec![Topic {
+                    name: "Sport".to_string(),
+                }],
+            },
+            TourDescription {
+                multiplier: 200,
+                title: None,
+                blitz: false,
+                topics: vec![Topic {
+                    name: "Movies".to_string(),
+                }],
+            },
+        ];
+        let questions_storage: Box<dyn QuestionsStorage> = Box::new(FakeQuestionsStorage::new(tours));
+        let admin = UserId::from(1);
+        let mut game_state = GameState::new(admin, &questions_storage, Some(5), solo_game_config()).unwrap();
+        game_state.add_player(UserId::from(2), String::from("new_1"), None);
+
+        match game_state.start(admin).get(1) {
+            Some(UiRequest::SendTextToMainChat(msg)) => {
+                assert_eq!(msg, "Темы первого раунда «Разминка»:\nSport")
+            }
+            _ => panic!("unexpected ui requests"),
+        }
+        match game_state.next_tour(admin).as_slice() {
+            [UiRequest::SendTextToMainChat(msg)] => {
+                assert_eq!(msg, "Переходим к следующему туру\n\nТемы:\nMovies")
+            }
+            _ => panic!("unexpected ui requests"),
+        }
+    }
+
+    #[test]
+    fn test_blitz() {
+        let tours = vec![TourDescription {
+            multiplier: 100,
+            title: None,
+            blitz: true,
+            topics: vec![
+                Topic {
+                    name: "Sport".to_string(),
+                },
+                Topic {
+                    name: "Movies".to_string(),
+                },
+            ],
+        }];
+        let questions_storage: Box<dyn QuestionsStorage> = Box::new(FakeQuestionsStorage::new(tours));
         let admin = UserId::from(1);
         let p1 = UserId::from(2);
-        let (mut game_state, questions_storage) = create_game_state(admin);
+        let p2 = UserId::from(3);
+        let mut game_state = GameState::new(admin, &questions_storage, Some(2), solo_game_config()).unwrap();
         game_state.add_player(p1, String::from("new_1"), None);
+        game_state.add_player(p2, String::from("new_2"), None);
         game_state.start(admin);
-        game_state.next_question(admin);
+        assert!(game_state.is_blitz_tour());
 
-        let maybe_topic_id = game_state.get_topic_id("Sport".to_string());
-        let topic_id = maybe_topic_id.unwrap();
-        game_state.select_topic(topic_id, p1);
-        game_state.select_question(200, p1, &questions_storage);
-        game_state.timeout();
+        // Topic and cost are not chosen
+        assert!(game_state.next_blitz_question(p1, questions_storage.as_ref()).is_empty());
+        let res = game_state.next_blitz_question(admin, questions_storage.as_ref());
+        assert!(res.iter().any(|r| matches!(r, UiRequest::RightBeforeAskingQuestion(text) if text == "Блиц! Тема Sport, вопрос за 100")));
+        assert!(res.iter().any(|r| matches!(r, UiRequest::Timeout(None, d) if *d == Duration::from_secs(5))));
+        assert!(matches!(game_state.get_state(), State::Blitz(_, 100)));
+
+        // No falsestarts in blitz, anyone can answer right away
+        game_state.message(p2, String::from("1"));
+        assert!(matches!(game_state.get_state(), State::Answering(_, 100, true)));
+        let res = game_state.yes_reply(admin);
+        assert_eq!(game_state.get_player_score(p2), Some(100));
+        assert!(res.iter().any(|r| matches!(r, UiRequest::ScheduleTimer(TimerId::Blitz, d) if *d == Duration::from_secs(3))));
+
+        // Next question is asked by the timer
+        game_state.blitz_timer(questions_storage.as_ref());
+        assert!(matches!(game_state.get_state(), State::Blitz(_, 200)));
+        assert!(game_state.blitz_timer(questions_storage.as_ref()).is_empty());
+
+        // Others answer after a wrong answer within the blitz timer
         game_state.message(p1, String::from("1"));
+        let res = game_state.no_reply(admin);
+        assert!(matches!(game_state.get_state(), State::Blitz(_, 200)));
+        assert!(res.iter().any(|r| matches!(r, UiRequest::Timeout(None, d) if *d == Duration::from_secs(5))));
+        game_state.message(p2, String::from("1"));
+        assert!(matches!(game_state.get_state(), State::Answering(_, 200, true)));
+        game_state.no_reply(admin);
+        assert_eq!(game_state.get_state(), &State::Pause);
+        assert_eq!(game_state.get_player_score(p1), Some(-200));
+
+        let res = game_state.blitz_timer(questions_storage.as_ref());
+        assert!(res.iter().any(|r| matches!(r, UiRequest::RightBeforeAskingQuestion(text) if text == "Блиц! Тема Movies, вопрос за 100")));
+        let res = game_state.timeout();
+        assert!(res.iter().any(|r| matches!(r, UiRequest::ScheduleTimer(TimerId::Blitz, _))));
+        game_state.blitz_timer(questions_storage.as_ref());
         game_state.timeout();
+
+        match game_state.blitz_timer(questions_storage.as_ref()).first() {
+            Some(UiRequest::SendTextToMainChat(text)) => assert_eq!(text, "Нет больше вопросов в туре"),
+            _ => panic!("tour should be over"),
+        }
+    }
+
+    #[test]
+    fn test_tour_recap() {
+        let admin = UserId::from(1);
+        let (_, questions_storage) = create_game_state(admin);
+        let config = GameConfig {
+            tour_recap: true,
+            ..GameConfig::default()
+        };
+        let mut game_state = GameState::new(admin, &questions_storage, Some(5), config).unwrap();
+        game_state.add_player(UserId::from(2), String::from("Петя"), None);
+        game_state.add_player(UserId::from(3), String::from("Вася"), None);
+        game_state.add_player(UserId::from(4), String::from("Маша"), None);
+        game_state.start(admin);
+        game_state.update_score(String::from("Маша"), 300, admin);
+
+        match game_state.next_tour(admin).as_slice() {
+            [UiRequest::SendScoreTable(_), UiRequest::SendTextToMainChat(recap), UiRequest::SendTextToMainChat(_)] => {
+                assert_eq!(recap, "Итоги тура:\n1. Маша: 300\n2. Вася: 0\n2. Петя: 0")
+            }
+            _ => panic!("unexpected ui requests"),
+        }
+    }
+
+    #[test]
+    fn test_topic_points() {
+        let admin = UserId::from(1);
+        let p1 = UserId::from(2);
+        let p2 = UserId::from(3);
+        let (mut game_state, questions_storage) = create_game_state(admin);
+        game_state.add_player(p1, String::from("new_1"), None);
+        game_state.add_player(p2, String::from("new_2"), None);
+        game_state.start(admin);
+
+        game_state.next_question(admin);
+        game_state.set_current_player(p1).unwrap();
+        select_question(&mut game_state, &questions_storage, "Sport", p1, 100);
+        game_state.message(p2, String::from("1"));
+        game_state.no_reply(admin);
         game_state.message(p1, String::from("1"));
-        match game_state.get_state() {
-            &State::Answering(..) => {
-                assert!(false);
+        game_state.yes_reply(admin);
+
+        game_state.next_question(admin);
+        select_question(&mut game_state, &questions_storage, "Sport", p1, 200);
+        game_state.message(p1, String::from("1"));
+        game_state.yes_reply(admin);
+        game_state.appeal(admin);
+        game_state.no_reply(admin);
+
+        match game_state.next_tour(admin).as_slice() {
+            [UiRequest::SendToAdmin(summary), UiRequest::SendTextToMainChat(_)] => {
+                assert_eq!(summary, "Points by topic in tour 1:\nSport: -200");
             }
-            _ => {}
+            _ => panic!("unexpected ui requests"),
+        }
+        // Nothing was played in the second tour yet
+        match game_state.finish(admin).as_slice() {
+            [UiRequest::SendTextToMainChat(_), UiRequest::SendGameSummary(_), UiRequest::GameFinished(_)] => {}
+            _ => panic!("unexpected ui requests"),
         }
     }
 
     #[test]
-    fn test_falsestarts_reset() {
+    fn test_practice() {
+        let tours = vec![TourDescription {
+            multiplier: 100,
+            title: None,
+            blitz: false,
+            topics: vec![
+                Topic {
+                    name: "Sport".to_string(),
+                },
+                Topic {
+                    name: "Movies".to_string(),
+                },
+            ],
+        }];
+        let mut questions_storage = FakeQuestionsStorage::new(tours);
+        questions_storage.auctions = vec![("Movies".to_string(), 300)];
+        let questions_storage: Box<dyn QuestionsStorage> = Box::new(questions_storage);
+
+        let admin_id = UserId::from(1);
+        let p1_id = UserId::from(2);
+        let mut game_state = GameState::new(admin_id, &questions_storage, Some(5), solo_game_config()).unwrap();
+        game_state.add_player(p1_id, String::from("new_1"), None);
+        game_state.add_player(UserId::from(3), String::from("new_2"), None);
+        match game_state.start_practice(admin_id, questions_storage.as_ref()).as_slice() {
+            [UiRequest::SendTextToMainChat(text)] => assert_eq!(text, "Тренировка только для одного игрока"),
+            _ => panic!("practice is only for a single player"),
+        }
+        game_state.clear_players(admin_id);
+        game_state.add_player(p1_id, String::from("new_1"), None);
+
+        game_state.config.question_attribution = true;
+        let res = game_state.start_practice(admin_id, questions_storage.as_ref());
+        assert!(res.iter().any(|r| matches!(r, UiRequest::RightBeforeAskingQuestion(_))));
+        assert!(game_state.is_practice());
+
+        // Every question except the auction is asked exactly once
+        let mut asked = vec![];
+        loop {
+            assert!(matches!(game_state.get_state(), State::BeforeQuestionAsked(..)));
+            asked.push(game_state.current_topic.clone().unwrap());
+            game_state.timeout();
+            game_state.timeout();
+            game_state.message(p1_id, "1".to_string());
+            let res = if asked.len() % 2 == 0 {
+                game_state.yes_reply(admin_id)
+            } else {
+                game_state.no_reply(admin_id)
+            };
+            // Nobody else chooses or continues the game
+            for r in res.iter() {
+                if let UiRequest::SendTextToMainChat(text) = r {
+                    assert!(!text.contains("выбирает") && !text.contains("продолжает") && !text.contains("Выбрал"));
+                }
+            }
+            let res = game_state.next_practice_question(admin_id, questions_storage.as_ref());
+            if game_state.get_state() == &State::Finished {
+                match res.as_slice() {
+                    [UiRequest::SendTextToMainChat(text)] => assert!(text.starts_with("Тренировка окончена")),
+                    _ => panic!("practice should be finished"),
+                }
+                break;
+            }
+        }
+        assert_eq!(asked.iter().filter(|topic| *topic == "Sport").count(), 5);
+        assert_eq!(asked.iter().filter(|topic| *topic == "Movies").count(), 4);
+        let mistakes: usize = game_state.practice.as_ref().unwrap().mistakes.values().sum();
+        assert_eq!(mistakes, 5);
+    }
+
+    #[test]
+    fn test_audio_answer_window() {
+        let (mut game_state, _) = create_game_state(UserId::from(1));
+        let mut question = Question::new("question", "answer", None);
+        question.set_audio(Media::File(PathBuf::from("1.mp3")));
+
+        game_state.set_state(State::BeforeQuestionAsked(question.clone(), 100));
+        match game_state.timeout().last() {
+            Some(UiRequest::Timeout(_, duration)) => assert_eq!(*duration, Duration::from_secs(15)),
+            _ => panic!("unexpected ui requests"),
+        }
+
+        question.set_audio_duration(Duration::from_secs(40));
+        game_state.set_state(State::BeforeQuestionAsked(question, 100));
+        match game_state.timeout().last() {
+            Some(UiRequest::Timeout(_, duration)) => assert_eq!(*duration, Duration::from_secs(43)),
+            _ => panic!("unexpected ui requests"),
+        }
+    }
+
+    #[test]
+    fn test_delays() {
+        let overrides: HashMap<Delay, u64> =
+            serde_json::from_str(r#"{"answer_window": 20, "pre_question": 2, "post_answer": 1}"#).unwrap();
+        let delays = Delays::new(
+            overrides
+                .into_iter()
+                .map(|(delay, secs)| (delay, Duration::from_secs(secs)))
+                .collect(),
+        );
+        assert_eq!(delays.get(Delay::AnswerWindow), Duration::from_secs(20));
+        assert_eq!(delays.get(Delay::PreQuestion), Duration::from_secs(2));
+        assert_eq!(delays.get(Delay::RetryWindow), Duration::from_secs(10));
+        assert_eq!(delays.get(Delay::PostAnswer), Duration::from_secs(1));
+        assert_eq!(Delays::default().get(Delay::PostAnswer), Duration::from_secs(0));
+        assert!(serde_json::from_str::<HashMap<Delay, u64>>(r#"{"exact": 1}"#).is_err());
+    }
+
+    #[test]
+    fn test_length_thresholds() {
+        let (mut game_state, _) = create_game_state(UserId::from(1));
+        let delay_for = |game_state: &mut GameState, len: usize| {
+            let question = Question::new("a".repeat(len), "answer".to_string(), None);
+            game_state.set_state(State::BeforeQuestionAsked(question, 100));
+            let delays = Delays::default();
+            match game_state.timeout().last() {
+                Some(UiRequest::Timeout(_, d)) if *d == delays.get(Delay::ShortQuestion) => "short",
+                Some(UiRequest::Timeout(_, d)) if *d == delays.get(Delay::MediumQuestion) => "medium",
+                Some(UiRequest::Timeout(_, d)) if *d == delays.get(Delay::LongQuestion) => "long",
+                _ => panic!("unexpected ui requests"),
+            }
+        };
+        assert_eq!(delay_for(&mut game_state, 100), "short");
+        assert_eq!(delay_for(&mut game_state, 230), "medium");
+        assert_eq!(delay_for(&mut game_state, 231), "long");
+
+        game_state.config.length_thresholds = (200, 500);
+        assert_eq!(delay_for(&mut game_state, 150), "short");
+        assert_eq!(delay_for(&mut game_state, 300), "medium");
+        assert_eq!(delay_for(&mut game_state, 501), "long");
+    }
+
+    #[test]
+    fn test_format_question_attachments() {
+        let (game_state, _) = create_game_state(UserId::from(1));
+        let mut question = Question::new("question", "answer", None);
+        question.add_image(Media::File(PathBuf::from("1.png")));
+        match game_state.format_question(&question).as_slice() {
+            [UiRequest::SendImage(image), UiRequest::SendQuestionText(_)] => {
+                assert_eq!(image, &Media::File(PathBuf::from("1.png")))
+            }
+            _ => panic!("unexpected ui requests"),
+        }
+
+        question.add_image(Media::File(PathBuf::from("2.png")));
+        match game_state.format_question(&question).as_slice() {
+            [UiRequest::SendMediaGroup(images), UiRequest::SendQuestionText(_)] => {
+                assert_eq!(
+                    images,
+                    &vec![Media::File(PathBuf::from("1.png")), Media::File(PathBuf::from("2.png"))]
+                )
+            }
+            _ => panic!("unexpected ui requests"),
+        }
+
+        let mut question = Question::new("question", "answer", None);
+        question.set_animation(Media::File(PathBuf::from("1.gif")));
+        match game_state.format_question(&question).as_slice() {
+            [UiRequest::SendAnimation(animation), UiRequest::SendQuestionText(_)] => {
+                assert_eq!(animation, &Media::File(PathBuf::from("1.gif")))
+            }
+            _ => panic!("unexpected ui requests"),
+        }
+
+        let mut question = Question::new("question", "answer", None);
+        question.set_document(Media::Url("https://example.com/notes.pdf".to_string()));
+        match game_state.format_question(&question).as_slice() {
+            [UiRequest::SendDocument(document), UiRequest::SendQuestionText(_)] => {
+                assert_eq!(document, &Media::Url("https://example.com/notes.pdf".to_string()))
+            }
+            _ => panic!("unexpected ui requests"),
+        }
+    }
+
+    #[test]
+    fn test_transcript() {
+        let admin = UserId::from(1);
+        let p1 = UserId::from(2);
+        let p2 = UserId::from(3);
+        let (mut game_state, questions_storage) = create_game_state(admin);
+        game_state.add_player(p1, String::from("new_1"), None);
+        game_state.add_player(p2, String::from("new_2"), None);
+        game_state.start(admin);
+
+        game_state.next_question(admin);
+        game_state.set_current_player(p1).unwrap();
+        select_question(&mut game_state, &questions_storage, "Sport", p1, 100);
+        game_state.message(p2, String::from("1"));
+        game_state.no_reply(admin);
+        game_state.message(p1, String::from("1"));
+        game_state.yes_reply(admin);
+
+        assert!(game_state.transcript(p1).is_empty());
+        match game_state.transcript(admin).as_slice() {
+            [UiRequest::ExportTranscript(transcript)] => {
+                let lines: Vec<_> = transcript
+                    .lines()
+                    .filter(|line| !line.contains(" pressed the button"))
+                    .collect();
+                assert_eq!(
+                    lines,
+                    vec![
+                        "Game started, players: new_1, new_2",
+                        "Tour 1",
+                        "new_1 chose 'Sport' for 100",
+                        "Question: 2 * 2 = ?",
+                        "Answer: 4",
+                        "new_2 answered incorrectly: -100",
+                        "new_1 answered correctly: +100",
+                        "Score: new_1: 100, new_2: -100",
+                    ]
+                );
+            }
+            _ => panic!("unexpected ui requests"),
+        }
+    }
+
+    #[test]
+    fn test_response_times() {
+        let admin = UserId::from(1);
+        let p1 = UserId::from(2);
+        let p2 = UserId::from(3);
+        let (mut game_state, questions_storage) = create_game_state(admin);
+        game_state.add_player(p1, String::from("new_1"), None);
+        game_state.add_player(p2, String::from("new_2"), None);
+        game_state.start(admin);
+
+        game_state.next_question(admin);
+        select_question(&mut game_state, &questions_storage, "Sport", p1, 100);
+        game_state.message(p2, String::from("1"));
+        game_state.no_reply(admin);
+        game_state.message(p1, String::from("1"));
+        game_state.yes_reply(admin);
+
+        game_state.next_question(admin);
+        select_question(&mut game_state, &questions_storage, "Sport", p1, 200);
+        game_state.message(p1, String::from("1"));
+        game_state.yes_reply(admin);
+
+        assert_eq!(game_state.response_times.get(&game_state.find_player(p1).unwrap().clone()).unwrap().len(), 2);
+        let presses: Vec<_> = game_state
+            .history
+            .iter()
+            .filter(|event| event.contains(" pressed the button in "))
+            .collect();
+        assert_eq!(presses.len(), 3);
+        assert!(presses[0].starts_with("new_2 pressed the button in "));
+
+        let stats = game_state.get_stats_str();
+        assert!(stats.contains("new_1: "));
+        assert!(stats.contains("(нажатий: 2)"));
+        assert!(stats.contains("(нажатий: 1)"));
+    }
+
+    #[test]
+    fn test_falsestarts_simple() {
+        let admin = UserId::from(1);
+        let p1 = UserId::from(2);
+        let (mut game_state, questions_storage) = create_game_state(admin);
+        game_state.add_player(p1, String::from("new_1"), None);
+        game_state.start(admin);
+        game_state.next_question(admin);
+
+        let maybe_topic_id = game_state.get_topic_id("Sport".to_string());
+        let topic_id = maybe_topic_id.unwrap();
+        game_state.select_topic(topic_id, p1);
+        game_state.select_question(200, p1, &questions_storage);
+        game_state.timeout();
+        game_state.message(p1, String::from("1"));
+        game_state.timeout();
+        game_state.message(p1, String::from("1"));
+        match game_state.get_state() {
+            &State::Answering(..) => {
+                assert!(false);
+            }
+            _ => {}
+        }
+    }
+
+    #[test]
+    fn test_falsestarts_reset() {
+        let admin = UserId::from(1);
+        let p1 = UserId::from(2);
+        let (mut game_state, questions_storage) = create_game_state(admin);
+        game_state.add_player(p1, String::from("new_1"), None);
+        game_state.start(admin);
+        game_state.next_question(admin);
+
+        let maybe_topic_id = game_state.get_topic_id("Sport".to_string());
+        let topic_id = maybe_topic_id.unwrap();
+        game_state.select_topic(topic_id, p1);
+        game_state.select_question(200, p1, &questions_storage);
+        game_state.timeout();
+        game_state.message(p1, String::from("1"));
+        game_state.timeout();
+        // Falsestart is over, now can answer
+        game_state.timeout();
+        game_state.message(p1, String::from("1"));
+        matches!(game_state.get_state(), State::Answering(..));
+    }
+
+    #[test]
+    fn test_falsestarts_second_can_answer() {
+        let admin = UserId::from(1);
+        let p1 = UserId::from(2);
+        let p2 = UserId::from(3);
+        let (mut game_state, questions_storage) = create_game_state(admin);
+        game_state.add_player(p1, String::from("new_1"), None);
+        game_state.add_player(p2, String::from("new_2"), None);
+        game_state.start(admin);
+        game_state.next_question(admin);
+
+        game_state.set_current_player(p1).unwrap();
+        let maybe_topic_id = game_state.get_topic_id("Sport".to_string());
+        let topic_id = maybe_topic_id.unwrap();
+        game_state.select_topic(topic_id, p1);
+        game_state.select_question(100, p1, &questions_storage);
+        game_state.timeout();
+        game_state.message(p1, String::from("1"));
+        game_state.timeout();
+        game_state.message(p2, String::from("1"));
+        game_state.yes_reply(admin);
+
+        assert_eq!(game_state.get_player_score(p1), Some(0));
+        assert_eq!(game_state.get_player_score(p2), Some(100));
+    }
+
+    #[test]
+    fn test_falsestarts_can_answer_after_no() {
+        let admin = UserId::from(1);
+        let p1 = UserId::from(2);
+        let p2 = UserId::from(3);
+        let (mut game_state, questions_storage) = create_game_state(admin);
+        game_state.add_player(p1, String::from("new_1"), None);
+        game_state.add_player(p2, String::from("new_2"), None);
+        game_state.start(admin);
+        game_state.next_question(admin);
+
+        game_state.set_current_player(p1).unwrap();
+        let maybe_topic_id = game_state.get_topic_id("Sport".to_string());
+        let topic_id = maybe_topic_id.unwrap();
+        game_state.select_topic(topic_id, p1);
+        game_state.select_question(100, p1, &questions_storage);
+        game_state.timeout();
+        game_state.message(p1, String::from("1"));
+        game_state.timeout();
+        game_state.message(p2, String::from("1"));
+        game_state.no_reply(admin);
+        game_state.message(p1, String::from("1"));
+        game_state.yes_reply(admin);
+
+        assert_eq!(game_state.get_player_score(p1), Some(100));
+        assert_eq!(game_state.get_player_score(p2), Some(-100));
+    }
+
+    #[test]
+    fn test_no_second_buzz_after_reopen() {
+        let admin = UserId::from(1);
+        let p1 = UserId::from(2);
+        let p2 = UserId::from(3);
+        let (mut game_state, questions_storage) = create_game_state(admin);
+        game_state.add_player(p1, String::from("new_1"), None);
+        game_state.add_player(p2, String::from("new_2"), None);
+        game_state.start(admin);
+        game_state.next_question(admin);
+
+        select_question(&mut game_state, &questions_storage, "Sport", p1, 100);
+        game_state.message(p1, String::from("1"));
+        game_state.no_reply(admin);
+        assert!(matches!(game_state.get_state(), State::CanAnswer(..)));
+
+        // Question is reopened, but the first player already answered it
+        let res = game_state.message(p1, String::from("1"));
+        assert!(res.is_empty());
+        assert!(matches!(game_state.get_state(), State::CanAnswer(..)));
+
+        game_state.message(p2, String::from("1"));
+        assert!(matches!(game_state.get_state(), State::Answering(..)));
+        assert_eq!(game_state.get_current_player().map(|p| p.id()), Some(p2));
+
+        // Messages from the first player don't affect the second one answering
+        game_state.message(p1, String::from("1"));
+        assert_eq!(game_state.get_current_player().map(|p| p.id()), Some(p2));
+        game_state.yes_reply(admin);
+
+        assert_eq!(game_state.get_player_score(p1), Some(-100));
+        assert_eq!(game_state.get_player_score(p2), Some(100));
+    }
+
+    #[test]
+    fn test_answering_follow_up() {
+        let admin = UserId::from(1);
+        let p1 = UserId::from(2);
+        let p2 = UserId::from(3);
+        let (mut game_state, questions_storage) = create_game_state(admin);
+        game_state.add_player(p1, String::from("new_1"), None);
+        game_state.add_player(p2, String::from("new_2"), None);
+        game_state.start(admin);
+        game_state.next_question(admin);
+        game_state.set_current_player(p1).unwrap();
+        select_question(&mut game_state, &questions_storage, "Sport", p1, 100);
+        game_state.message(p1, String::from("1"));
+
+        match game_state.message(p1, String::from("четыре")).as_slice() {
+            [UiRequest::SendToAdmin(msg)] => assert_eq!(msg, "new_1 says: четыре"),
+            _ => panic!("unexpected ui requests"),
+        }
+        assert!(game_state.message(p2, String::from("1")).is_empty());
+        assert!(game_state.message(p1, String::from("1")).len() == 1);
+
+        // Still waiting for the admin
+        game_state.yes_reply(admin);
+        assert_eq!(game_state.get_player_score(p1), Some(100));
+        assert_eq!(game_state.get_player_score(p2), Some(0));
+    }
+
+    #[test]
+    fn test_late_buzz() {
+        let admin = UserId::from(1);
+        let p1 = UserId::from(2);
+        let p2 = UserId::from(3);
+        let (mut game_state, questions_storage) = create_game_state(admin);
+        game_state.add_player(p1, String::from("new_1"), None);
+        game_state.add_player(p2, String::from("new_2"), None);
+        game_state.start(admin);
+        game_state.next_question(admin);
+        game_state.set_current_player(p1).unwrap();
+        select_question(&mut game_state, &questions_storage, "Sport", p1, 100);
+        game_state.timeout();
+        assert_eq!(game_state.get_state(), &State::Pause);
+
+        match game_state.message(p2, String::from("1")).as_slice() {
+            [UiRequest::SendTextToMainChat(msg)] => {
+                assert!(msg.starts_with("new_2, время на ответ уже вышло"))
+            }
+            _ => panic!("late buzz should be replied"),
+        }
+        // Only once per player
+        assert!(game_state.message(p2, String::from("1")).is_empty());
+
+        game_state.answers_closed_at = Instant::now().checked_sub(LATE_BUZZ_WINDOW * 2);
+        assert!(game_state.message(p1, String::from("1")).is_empty());
+    }
+
+    fn reveals_answer(res: &[UiRequest], answer: &str) -> bool {
+        let expected = format!("Правильный ответ: {}", answer);
+        res.iter()
+            .any(|r| matches!(r, UiRequest::SendTextToMainChat(text) if text.contains(&expected)))
+    }
+
+    #[test]
+    fn test_answer_revealed_on_close() {
+        let admin = UserId::from(1);
+        let p1 = UserId::from(2);
+        let p2 = UserId::from(3);
+        let new_game = |questions_storage: Box<dyn QuestionsStorage>| {
+            let mut game_state = GameState::new(admin, &questions_storage, Some(5), solo_game_config()).unwrap();
+            game_state.add_player(p1, String::from("new_1"), None);
+            game_state.add_player(p2, String::from("new_2"), None);
+            game_state.start(admin);
+            game_state.next_question(admin);
+            select_question(&mut game_state, &questions_storage, "Sport", p1, 100);
+            game_state
+        };
+        let tours = || {
+            vec![TourDescription {
+                multiplier: 100,
+                title: None,
+                blitz: false,
+                topics: vec![Topic {
+                    name: "Sport".to_string(),
+                }],
+            }]
+        };
+        let storage = || -> Box<dyn QuestionsStorage> { Box::new(FakeQuestionsStorage::new(tours())) };
+
+        // Nobody pressed the button
+        let mut game_state = new_game(storage());
+        assert!(reveals_answer(&game_state.timeout(), "4"));
+
+        // Everyone tried
+        let mut game_state = new_game(storage());
+        game_state.message(p1, String::from("1"));
+        assert!(!reveals_answer(&game_state.no_reply(admin), "4"));
+        game_state.message(p2, String::from("1"));
+        assert!(reveals_answer(&game_state.no_reply(admin), "4"));
+
+        // Correct answer
+        let mut game_state = new_game(storage());
+        game_state.message(p1, String::from("1"));
+        assert!(reveals_answer(&game_state.yes_reply(admin), "4"));
+
+        // Only one player could answer, e.g. cat in bag or auction
+        let mut game_state = new_game(storage());
+        game_state.set_state(State::Answering(Question::new("2 * 2 = ?", "4", Some("чётное")), 100, false));
+        let res = game_state.no_reply(admin);
+        assert!(reveals_answer(&res, "4\nКомментарий: чётное"));
+
+        // Game was finished while the question was played
+        let mut game_state = new_game(storage());
+        assert!(reveals_answer(&game_state.finish(admin), "4"));
+
+        // Manual question
+        let mut questions_storage = FakeQuestionsStorage::new(tours());
+        questions_storage.manual_questions = vec![("Sport".to_string(), 100)];
+        let mut game_state = new_game(Box::new(questions_storage));
+        assert!(reveals_answer(&game_state.finish_manual_scoring(admin), "4"));
+    }
+
+    #[test]
+    fn test_question_attribution() {
+        let admin = UserId::from(1);
+        let p1 = UserId::from(2);
+        let p2 = UserId::from(3);
+        let (mut game_state, questions_storage) = create_game_state(admin);
+        game_state.config.question_attribution = true;
+        game_state.add_player(p1, String::from("new_1"), None);
+        game_state.add_player(p2, String::from("new_2"), None);
+        game_state.start(admin);
+        let says = |res: &[UiRequest], expected: &str| {
+            res.iter().any(|r| matches!(r, UiRequest::SendTextToMainChat(text) if text.contains(expected)))
+        };
+
+        game_state.next_question(admin);
+        select_question(&mut game_state, &questions_storage, "Sport", p1, 100);
+        game_state.message(p2, String::from("1"));
+        assert!(says(&game_state.yes_reply(admin), "\nВыбрал: new_1. Ответил: new_2\n"));
+
+        // Nobody pressed the button
+        game_state.next_question(admin);
+        select_question(&mut game_state, &questions_storage, "Sport", p2, 200);
+        let res = game_state.timeout();
+        assert!(says(&res, "\nВыбрал: new_2\n"));
+        assert!(!says(&res, "отвечал"));
+
+        game_state.next_question(admin);
+        select_question(&mut game_state, &questions_storage, "Sport", p2, 300);
+        game_state.message(p1, String::from("1"));
+        game_state.no_reply(admin);
+        assert!(says(&game_state.timeout(), "\nВыбрал: new_2. Последним отвечал: new_1\n"));
+
+        game_state.config.question_attribution = false;
+        game_state.next_question(admin);
+        select_question(&mut game_state, &questions_storage, "Sport", p2, 400);
+        assert!(!says(&game_state.timeout(), "Выбрал"));
+    }
+
+    #[test]
+    fn test_heartbeat() {
+        let admin = UserId::from(1);
+        let (mut game_state, _) = create_game_state(admin);
+        assert!(game_state.start_heartbeat().is_empty());
+
+        let interval = Duration::from_secs(600);
+        game_state.config.heartbeat = Some(interval);
+        game_state.add_player(UserId::from(2), String::from("new_1"), None);
+        match &game_state.heartbeat()[..] {
+            [UiRequest::ScheduleTimer(TimerId::Heartbeat, duration)] => assert_eq!(*duration, interval),
+            _ => panic!("next heartbeat is expected"),
+        }
+
+        game_state.config.heartbeat_to_admin = true;
+        match &game_state.heartbeat()[..] {
+            [UiRequest::ScheduleTimer(TimerId::Heartbeat, _), UiRequest::SendToAdmin(msg)] => {
+                assert_eq!(msg, "alive, state=WaitingForPlayersToJoin, players=1")
+            }
+            _ => panic!("heartbeat for admin is expected"),
+        }
+    }
+
+    #[test]
+    fn test_answer_countdown() {
+        let admin = UserId::from(1);
+        let p1 = UserId::from(2);
+        let (mut game_state, questions_storage) = create_game_state(admin);
+        game_state.config.answer_countdown = true;
+        game_state.add_player(p1, String::from("new_1"), None);
+        game_state.add_player(UserId::from(3), String::from("new_2"), None);
+        game_state.start(admin);
+        game_state.next_question(admin);
+
+        game_state.set_current_player(p1).unwrap();
+        let topic_id = game_state.get_topic_id("Sport".to_string()).unwrap();
+        game_state.select_topic(topic_id, p1);
+        game_state.select_question(100, p1, &questions_storage);
+        game_state.timeout();
+        match &game_state.timeout()[..] {
+            [UiRequest::StartCountdown(text), UiRequest::ScheduleTimer(TimerId::Countdown, tick), UiRequest::Timeout(None, window)] =>
+            {
+                assert_eq!(text, "⏱ 🟩🟩🟩🟩🟩");
+                assert_eq!(*tick, Duration::from_secs(3));
+                assert_eq!(*window, Duration::from_secs(15));
+            }
+            _ => panic!("countdown is expected"),
+        }
+
+        match &game_state.countdown_tick()[..] {
+            [UiRequest::UpdateCountdown(text), UiRequest::ScheduleTimer(TimerId::Countdown, _)] => {
+                assert_eq!(text, "⏱ 🟩🟩🟩🟩⬜")
+            }
+            _ => panic!("countdown update is expected"),
+        }
+
+        let res = game_state.message(p1, String::from("1"));
+        assert!(res.iter().any(|r| matches!(r, UiRequest::FinishCountdown(text) if text == "⏱ Отвечает new_1")));
+        // Countdown is stopped once somebody buzzed
+        assert!(game_state.countdown_tick().is_empty());
+
+        let res = game_state.no_reply(admin);
+        assert!(res.iter().any(|r| matches!(r, UiRequest::StartCountdown(_))));
+        let res = game_state.timeout();
+        assert!(res.iter().any(|r| matches!(r, UiRequest::FinishCountdown(text) if text == "⏱ Время вышло")));
+    }
+
+    #[test]
+    fn test_pause_answers() {
+        let admin = UserId::from(1);
+        let p1 = UserId::from(2);
+        let p2 = UserId::from(3);
+        let (mut game_state, questions_storage) = create_game_state(admin);
+        game_state.add_player(p1, String::from("new_1"), None);
+        game_state.add_player(p2, String::from("new_2"), None);
+        game_state.start(admin);
+        game_state.next_question(admin);
+        select_question(&mut game_state, &questions_storage, "Sport", p1, 100);
+
+        assert!(game_state.pause_answers(p1).is_empty());
+        match game_state.pause_answers(admin).as_slice() {
+            [UiRequest::StopTimer, UiRequest::SendTextToMainChat(msg)] => assert_eq!(msg, "Ответы приостановлены"),
+            _ => panic!("unexpected ui requests"),
+        }
+        assert!(game_state.message(p1, String::from("1")).is_empty());
+        assert!(game_state.timeout().is_empty());
+        assert!(matches!(game_state.get_state(), State::CanAnswer(..)));
+
+        match game_state.resume_answers(admin).as_slice() {
+            [UiRequest::SendTextToMainChat(msg), UiRequest::Timeout(None, window)] => {
+                assert_eq!(msg, "Ответы снова принимаются");
+                assert_eq!(*window, Duration::from_secs(15));
+            }
+            _ => panic!("unexpected ui requests"),
+        }
+        assert!(game_state.resume_answers(admin).is_empty());
+        game_state.message(p1, String::from("1"));
+        assert!(matches!(game_state.get_state(), State::Answering(..)));
+        assert!(game_state.pause_answers(admin).is_empty());
+    }
+
+    #[test]
+    fn test_typed_answer_with_buzz() {
+        let admin = UserId::from(1);
+        let p1 = UserId::from(2);
+        let p2 = UserId::from(3);
+        let (mut game_state, questions_storage) = create_game_state(admin);
+        game_state.add_player(p1, String::from("new_1"), None);
+        game_state.add_player(p2, String::from("new_2"), None);
+        game_state.start(admin);
+        game_state.next_question(admin);
+        game_state.set_current_player(p1).unwrap();
+        select_question(&mut game_state, &questions_storage, "Sport", p1, 100);
+
+        // Long messages without a button are just chatting
+        assert!(game_state.message(p2, String::from("какой сложный вопрос")).is_empty());
+        assert!(game_state.message(p2, String::from("ну и вопрос")).is_empty());
+        match game_state.message(p2, String::from("+ пять")).as_slice() {
+            [UiRequest::StopTimer, UiRequest::SendTextToMainChat(_), UiRequest::AskAdminYesNo(prompt)] => {
+                assert_eq!(prompt, "new_2 says: пять\nCorrect answer?");
+            }
+            _ => panic!("unexpected ui requests"),
+        }
+        game_state.no_reply(admin);
+
+        match game_state.message(p1, String::from("1")).as_slice() {
+            [UiRequest::StopTimer, UiRequest::SendTextToMainChat(_), UiRequest::AskAdminYesNo(prompt)] => {
+                assert_eq!(prompt, "Correct answer?");
+            }
+            _ => panic!("unexpected ui requests"),
+        }
+    }
+
+    #[test]
+    fn test_reply_to_question() {
+        let admin = UserId::from(1);
+        let p1 = UserId::from(2);
+        let p2 = UserId::from(3);
+        let (mut game_state, questions_storage) = create_game_state(admin);
+        game_state.add_player(p1, String::from("new_1"), None);
+        game_state.add_player(p2, String::from("new_2"), None);
+        game_state.start(admin);
+
+        // Outside of a question replies are just chatting
+        assert!(game_state.reply_to_question(p1, String::from("Пушкин")).is_empty());
+
+        game_state.next_question(admin);
+        game_state.set_current_player(p1).unwrap();
+        select_question(&mut game_state, &questions_storage, "Sport", p1, 100);
+        match game_state.reply_to_question(p2, String::from(" Александр Пушкин ")).as_slice() {
+            [UiRequest::StopTimer, UiRequest::SendTextToMainChat(_), UiRequest::AskAdminYesNo(prompt)] => {
+                assert_eq!(prompt, "new_2 says: Александр Пушкин\nCorrect answer?");
+            }
+            _ => panic!("unexpected ui requests"),
+        }
+        assert!(matches!(game_state.get_state(), State::Answering(..)));
+    }
+
+    #[test]
+    fn test_judgment_debounce() {
+        let admin = UserId::from(1);
+        let p1 = UserId::from(2);
+        let p2 = UserId::from(3);
+        let (mut game_state, questions_storage) = create_game_state(admin);
+        game_state.config.judgment_debounce = Duration::from_secs(60);
+        game_state.add_player(p1, String::from("new_1"), None);
+        game_state.add_player(p2, String::from("new_2"), None);
+        game_state.start(admin);
+        game_state.next_question(admin);
+        game_state.set_current_player(p1).unwrap();
+        select_question(&mut game_state, &questions_storage, "Sport", p1, 100);
+
+        // Second "no" of a double tap arrives after the next player pressed the button
+        game_state.message(p1, String::from("1"));
+        game_state.judge(admin, Some(MessageId::new(10)), false);
+        game_state.message(p2, String::from("1"));
+        match game_state.judge(admin, Some(MessageId::new(10)), false).as_slice() {
+            [UiRequest::SendToAdmin(text)] => assert_eq!(text, "Repeated tap on the same answer is ignored"),
+            _ => panic!("repeated tap should be reported"),
+        }
+        assert!(matches!(game_state.get_state(), State::Answering(..)));
+        assert_eq!(game_state.get_player_score(p1), Some(-100));
+        assert_eq!(game_state.get_player_score(p2), Some(0));
+
+        // Prompt of the next player is judged right away
+        assert!(!game_state.judge(admin, Some(MessageId::new(11)), true).is_empty());
+        assert_eq!(game_state.get_state(), &State::Pause);
+        assert_eq!(game_state.get_player_score(p2), Some(100));
+        assert!(matches!(
+            game_state.judge(admin, Some(MessageId::new(11)), true).as_slice(),
+            [UiRequest::SendToAdmin(_)]
+        ));
+        assert_eq!(game_state.get_player_score(p2), Some(100));
+    }
+
+    #[test]
+    fn test_reveal_delay() {
+        let admin = UserId::from(1);
+        let p1 = UserId::from(2);
+        let (mut game_state, questions_storage) = create_game_state(admin);
+        let delay = Duration::from_secs(2);
+        game_state.config.delays = Delays::new(vec![(Delay::PostAnswer, delay)].into_iter().collect());
+        game_state.add_player(p1, String::from("new_1"), None);
+        game_state.start(admin);
+        game_state.next_question(admin);
+        select_question(&mut game_state, &questions_storage, "Sport", p1, 100);
+
+        // Correct answer is shown when the reveal timer fires
+        game_state.message(p1, String::from("1"));
+        let res = game_state.yes_reply(admin);
+        assert!(res.iter().any(|r| matches!(r, UiRequest::ScheduleTimer(TimerId::Reveal, d) if *d == delay)));
+        assert!(!res.iter().any(|r| matches!(r, UiRequest::SendTextToMainChat(_))));
+        assert_eq!(game_state.get_state(), &State::Pause);
+        match game_state.reveal_answer().as_slice() {
+            [UiRequest::SendTextToMainChat(text)] => {
+                assert!(text.contains("Правильный ответ"));
+                assert!(text.contains("Игру продолжает new_1"));
+            }
+            _ => panic!("unexpected ui requests"),
+        }
+        assert!(game_state.reveal_answer().is_empty());
+
+        // Nobody answered and the admin goes on before the timer fires
+        game_state.next_question(admin);
+        select_question(&mut game_state, &questions_storage, "Sport", p1, 200);
+        game_state.timeout();
+        match game_state.next_question(admin).as_slice() {
+            [UiRequest::SendTextToMainChat(text), UiRequest::SendScoreTable(_), UiRequest::ChooseTopic(..)] => {
+                assert!(text.contains("Следующий вопрос выбирает new_1"));
+            }
+            _ => panic!("unexpected ui requests"),
+        }
+        assert!(game_state.reveal_answer().is_empty());
+
+        // Answer goes before the next tour
+        select_question(&mut game_state, &questions_storage, "Sport", p1, 300);
+        game_state.timeout();
+        match game_state.next_tour(admin).first() {
+            Some(UiRequest::SendTextToMainChat(text)) => assert!(text.starts_with("Правильный ответ")),
+            _ => panic!("unexpected ui requests"),
+        }
+        assert!(game_state.reveal_answer().is_empty());
+
+        // And before the results of the game
+        game_state.next_question(admin);
+        select_question(&mut game_state, &questions_storage, "Movies", p1, 200);
+        game_state.timeout();
+        match game_state.finish(admin).first() {
+            Some(UiRequest::SendTextToMainChat(text)) => assert!(text.starts_with("Правильный ответ")),
+            _ => panic!("unexpected ui requests"),
+        }
+        assert!(game_state.reveal_answer().is_empty());
+    }
+
+    #[test]
+    fn test_last_question() {
+        let admin = UserId::from(1);
+        let p1 = UserId::from(2);
+        let (mut game_state, questions_storage) = create_game_state(admin);
+        game_state.config.last_questions = 2;
+        game_state.add_player(p1, String::from("new_1"), None);
+        game_state.start(admin);
+        assert!(matches!(game_state.last_question(admin, 1).as_slice(), [UiRequest::SendToAdmin(_)]));
+
+        for cost in [100, 200, 300] {
+            game_state.next_question(admin);
+            select_question(&mut game_state, &questions_storage, "Sport", p1, cost);
+            game_state.message(p1, String::from("1"));
+            if cost == 300 {
+                game_state.no_reply(admin);
+            } else {
+                game_state.yes_reply(admin);
+            }
+        }
+
+        // Only admin can repeat questions
+        assert!(game_state.last_question(p1, 1).is_empty());
+        match game_state.last_question(admin, 1).as_slice() {
+            [UiRequest::SendTextToMainChat(text)] => {
+                assert_eq!(text, "Повтор вопроса:\n4 * 2 = ?\nПравильный ответ: 8\nНикто не ответил");
+            }
+            _ => panic!("unexpected ui requests"),
+        }
+        match game_state.last_question(admin, 2).as_slice() {
+            [UiRequest::SendTextToMainChat(text)] => {
+                assert_eq!(text, "Повтор вопроса:\n3 * 2 = ?\nПравильный ответ: 6\nОтветил: new_1");
+            }
+            _ => panic!("unexpected ui requests"),
+        }
+        // The oldest question is dropped
+        assert!(matches!(game_state.last_question(admin, 3).as_slice(), [UiRequest::SendToAdmin(_)]));
+    }
+
+    #[test]
+    fn test_regrade() {
+        let admin = UserId::from(1);
+        let p1 = UserId::from(2);
+        let p2 = UserId::from(3);
+        let (mut game_state, questions_storage) = create_game_state(admin);
+        game_state.add_player(p1, String::from("new_1"), None);
+        game_state.add_player(p2, String::from("new_2"), None);
+        game_state.start(admin);
+
+        // No question was closed yet
+        assert!(matches!(
+            game_state.regrade(String::from("new_1"), true, admin).as_slice(),
+            [UiRequest::SendToAdmin(_)]
+        ));
+        assert_eq!(game_state.get_player_score(p1), Some(0));
+
+        game_state.next_question(admin);
+        select_question(&mut game_state, &questions_storage, "Sport", p1, 100);
+        game_state.message(p1, String::from("1"));
+        game_state.no_reply(admin);
+        game_state.message(p2, String::from("1"));
+        game_state.yes_reply(admin);
+        assert_eq!(game_state.get_player_score(p1), Some(-100));
+        assert_eq!(game_state.get_player_score(p2), Some(100));
+
+        // Only admin can regrade
+        assert!(game_state.regrade(String::from("new_1"), true, p1).is_empty());
+        assert_eq!(game_state.get_player_score(p1), Some(-100));
+
+        match game_state.regrade(String::from("new_1"), true, admin).as_slice() {
+            [UiRequest::SendTextToMainChat(text)] => {
+                assert!(text.starts_with("Оценка исправлена: new_1 +100\n"));
+            }
+            _ => panic!("unexpected ui requests"),
+        }
+        assert_eq!(game_state.get_player_score(p1), Some(0));
+        // Repeated command doesn't change the score twice
+        match game_state.regrade(String::from("new_1"), true, admin).as_slice() {
+            [UiRequest::SendToAdmin(text)] => assert_eq!(text, "new_1 was already regraded for this question"),
+            _ => panic!("unexpected ui requests"),
+        }
+        assert_eq!(game_state.get_player_score(p1), Some(0));
+        game_state.regrade(String::from("new_2"), false, admin);
+        assert_eq!(game_state.get_player_score(p2), Some(0));
+        assert!(matches!(
+            game_state.regrade(String::from("new_3"), true, admin).as_slice(),
+            [UiRequest::SendToAdmin(_)]
+        ));
+
+        // Too late after the next question was requested
+        game_state.next_question(admin);
+        game_state.regrade(String::from("new_1"), true, admin);
+        assert_eq!(game_state.get_player_score(p1), Some(0));
+    }
+
+    #[test]
+    fn test_appeal() {
+        let admin = UserId::from(1);
+        let p1 = UserId::from(2);
+        let p2 = UserId::from(3);
+        let (mut game_state, questions_storage) = create_game_state(admin);
+        game_state.add_player(p1, String::from("new_1"), None);
+        game_state.add_player(p2, String::from("new_2"), None);
+        game_state.start(admin);
+
+        // Nothing to appeal yet
+        game_state.appeal(admin);
+        assert_eq!(game_state.get_state(), &State::Pause);
+
+        game_state.next_question(admin);
+        select_question(&mut game_state, &questions_storage, "Sport", p1, 100);
+        game_state.message(p1, String::from("1"));
+        game_state.no_reply(admin);
+
+        // Can't appeal while question is still open
+        game_state.appeal(admin);
+        assert!(matches!(game_state.get_state(), State::CanAnswer(..)));
+
+        game_state.message(p2, String::from("1"));
+        game_state.yes_reply(admin);
+        assert_eq!(game_state.get_player_score(p2), Some(100));
+        assert_eq!(game_state.get_current_player().map(|p| p.id()), Some(p2));
+
+        // Only admin can appeal
+        game_state.appeal(p1);
+        assert_eq!(game_state.get_state(), &State::Pause);
+
+        game_state.appeal(admin);
+        assert!(matches!(game_state.get_state(), State::Answering(_, _, false)));
+        assert_eq!(game_state.get_player_score(p2), Some(0));
+        assert_eq!(game_state.get_current_player().map(|p| p.id()), Some(p2));
+
+        game_state.no_reply(admin);
+        assert_eq!(game_state.get_state(), &State::Pause);
+        assert_eq!(game_state.get_player_score(p1), Some(-100));
+        assert_eq!(game_state.get_player_score(p2), Some(-100));
+        // Nobody answered correctly, so the chooser continues
+        assert_eq!(game_state.get_current_player().map(|p| p.id()), Some(p1));
+
+        // Appealing back to correct answer
+        game_state.appeal(admin);
+        game_state.yes_reply(admin);
+        assert_eq!(game_state.get_player_score(p2), Some(100));
+        assert_eq!(game_state.get_current_player().map(|p| p.id()), Some(p2));
+    }
+
+    #[test]
+    fn test_auto_judge() {
         let admin = UserId::from(1);
         let p1 = UserId::from(2);
-        let (mut game_state, questions_storage) = create_game_state(admin);
+        let p2 = UserId::from(3);
+        let (game_state, questions_storage) = create_game_state(admin);
+        let mut game_state = GameState {
+            config: GameConfig {
+                auto_judge: true,
+                ..solo_game_config()
+            },
+            ..game_state
+        };
         game_state.add_player(p1, String::from("new_1"), None);
+        game_state.add_player(p2, String::from("new_2"), None);
         game_state.start(admin);
-        game_state.next_question(admin);
 
-        let maybe_topic_id = game_state.get_topic_id("Sport".to_string());
-        let topic_id = maybe_topic_id.unwrap();
-        game_state.select_topic(topic_id, p1);
-        game_state.select_question(200, p1, &questions_storage);
-        game_state.timeout();
-        game_state.message(p1, String::from("1"));
-        game_state.timeout();
-        // Falsestart is over, now can answer
-        game_state.timeout();
+        game_state.next_question(admin);
+        select_question(&mut game_state, &questions_storage, "Sport", p1, 100);
         game_state.message(p1, String::from("1"));
-        matches!(game_state.get_state(), State::Answering(..));
+        // Other players can't answer instead of the answering one
+        game_state.message(p2, String::from("4"));
+        assert!(matches!(game_state.get_state(), State::Answering(..)));
+        // Wrong answer is left to admin
+        game_state.message(p1, String::from("5"));
+        assert!(matches!(game_state.get_state(), State::Answering(..)));
+        game_state.message(p1, String::from(" 4! "));
+        assert_eq!(game_state.get_state(), &State::Pause);
+        assert_eq!(game_state.get_player_score(p1), Some(100));
+
+        // Admin still can judge
+        game_state.next_question(admin);
+        select_question(&mut game_state, &questions_storage, "Sport", p1, 200);
+        game_state.message(p2, String::from("1"));
+        game_state.message(p2, String::from("7"));
+        game_state.no_reply(admin);
+        assert_eq!(game_state.get_player_score(p2), Some(-200));
+
+        // Answer typed together with the buzz or as a reply is judged as well
+        game_state.next_question(admin);
+        select_question(&mut game_state, &questions_storage, "Sport", p1, 300);
+        let res = game_state.message(p2, String::from("+ 8"));
+        assert!(res.iter().any(|r| matches!(r, UiRequest::SendToAdmin(text) if text == "'8' was automatically accepted")));
+        assert!(!res.iter().any(|r| matches!(r, UiRequest::AskAdminYesNo(_))));
+        assert_eq!(game_state.get_state(), &State::Pause);
+        assert_eq!(game_state.get_player_score(p2), Some(100));
+
+        game_state.next_question(admin);
+        select_question(&mut game_state, &questions_storage, "Sport", p2, 400);
+        game_state.reply_to_question(p1, String::from("девять"));
+        assert!(matches!(game_state.get_state(), State::Answering(..)));
+        game_state.no_reply(admin);
+        game_state.reply_to_question(p2, String::from("10"));
+        assert_eq!(game_state.get_state(), &State::Pause);
+        assert_eq!(game_state.get_player_score(p2), Some(500));
     }
 
     #[test]
-    fn test_falsestarts_second_can_answer() {
+    fn test_game_clock() {
         let admin = UserId::from(1);
         let p1 = UserId::from(2);
         let p2 = UserId::from(3);
-        let (mut game_state, questions_storage) = create_game_state(admin);
+        let (game_state, questions_storage) = create_game_state(admin);
+        let mut game_state = GameState {
+            config: GameConfig {
+                game_duration: Some(Duration::from_secs(60)),
+                ..solo_game_config()
+            },
+            ..game_state
+        };
+
+        // Game is not started yet
+        assert!(game_state.game_clock_expired().is_empty());
+
         game_state.add_player(p1, String::from("new_1"), None);
         game_state.add_player(p2, String::from("new_2"), None);
-        game_state.start(admin);
-        game_state.next_question(admin);
+        let res = game_state.start(admin);
+        assert!(res.iter().any(|r| matches!(r, UiRequest::ScheduleTimer(TimerId::GameClock, _))));
 
-        game_state.set_current_player(p1).unwrap();
-        let maybe_topic_id = game_state.get_topic_id("Sport".to_string());
-        let topic_id = maybe_topic_id.unwrap();
-        game_state.select_topic(topic_id, p1);
-        game_state.select_question(100, p1, &questions_storage);
-        game_state.timeout();
-        game_state.message(p1, String::from("1"));
-        game_state.timeout();
+        game_state.next_question(admin);
+        select_question(&mut game_state, &questions_storage, "Sport", p1, 100);
         game_state.message(p2, String::from("1"));
         game_state.yes_reply(admin);
 
-        assert_eq!(game_state.get_player_score(p1), Some(0));
-        assert_eq!(game_state.get_player_score(p2), Some(100));
+        game_state.next_question(admin);
+        select_question(&mut game_state, &questions_storage, "Sport", p2, 200);
+        let res = game_state.game_clock_expired();
+        assert_eq!(game_state.get_state(), &State::Finished);
+        assert!(res.iter().any(|r| matches!(r, UiRequest::StopTimer)));
+        assert!(res.iter().any(|r| matches!(
+            r,
+            UiRequest::SendTextToMainChat(msg) if msg.contains("Победитель: new_2")
+        )));
+
+        assert!(res.iter().any(|r| matches!(r, UiRequest::GameFinished(scores) if scores.len() == 2)));
+        match res.iter().find(|r| matches!(r, UiRequest::SendGameSummary(_))) {
+            Some(UiRequest::SendGameSummary(summary)) => assert_eq!(
+                summary.to_string(),
+                "Итоги игры:\n1. new_2 🏆: 100 (ответов: 1)\n2. new_1: 0 (ответов: 0)"
+            ),
+            _ => panic!("game summary should be sent"),
+        }
+
+        // Nothing happens after the game is finished
+        game_state.message(p1, String::from("1"));
+        assert_eq!(game_state.get_state(), &State::Finished);
+        assert!(game_state.finish(admin).is_empty());
     }
 
     #[test]
-    fn test_falsestarts_can_answer_after_no() {
+    fn test_finished_game_is_final() {
         let admin = UserId::from(1);
         let p1 = UserId::from(2);
-        let p2 = UserId::from(3);
         let (mut game_state, questions_storage) = create_game_state(admin);
         game_state.add_player(p1, String::from("new_1"), None);
-        game_state.add_player(p2, String::from("new_2"), None);
         game_state.start(admin);
         game_state.next_question(admin);
-
-        game_state.set_current_player(p1).unwrap();
-        let maybe_topic_id = game_state.get_topic_id("Sport".to_string());
-        let topic_id = maybe_topic_id.unwrap();
-        game_state.select_topic(topic_id, p1);
-        game_state.select_question(100, p1, &questions_storage);
-        game_state.timeout();
-        game_state.message(p1, String::from("1"));
-        game_state.timeout();
-        game_state.message(p2, String::from("1"));
-        game_state.no_reply(admin);
+        select_question(&mut game_state, &questions_storage, "Sport", p1, 100);
         game_state.message(p1, String::from("1"));
         game_state.yes_reply(admin);
-
+        assert!(game_state.finish(admin).iter().any(|r| matches!(r, UiRequest::GameFinished(_))));
+
+        // Otherwise the next /finish would record the same game again
+        assert!(game_state.next_question(admin).is_empty());
+        assert!(game_state.change_player(admin, "new_1".to_string()).is_empty());
+        game_state.update_score("new_1".to_string(), 1000, admin);
+        assert!(game_state.hide_question("Sport".to_string(), 200, admin).is_empty());
+        assert_eq!(game_state.get_state(), &State::Finished);
         assert_eq!(game_state.get_player_score(p1), Some(100));
-        assert_eq!(game_state.get_player_score(p2), Some(-100));
+        assert!(game_state.finish(admin).is_empty());
+        assert!(game_state.game_clock_expired().is_empty());
     }
 
     #[test]
@@ -1458,10 +5576,13 @@ mod test {
             data: vec![ScoreTableItem {
                 name: String::from("a"),
                 questions: vec![10, 20],
+                played: None,
             }],
+            current_player: None,
+            standings: vec![],
         };
 
-        assert_eq!(table.to_string(), "|a|x| |x|");
+        assert_eq!(table.to_string(), "| |10|30|20|\n|a|x |  |x |");
 
         let table = ScoreTable {
             scores: vec![10, 30, 20],
@@ -1469,15 +5590,101 @@ mod test {
                 ScoreTableItem {
                     name: String::from("a"),
                     questions: vec![10, 20],
+                    played: None,
                 },
                 ScoreTableItem {
                     name: String::from("привет"),
                     questions: vec![30],
+                    played: None,
+                },
+            ],
+            current_player: None,
+            standings: vec![],
+        };
+
+        assert_eq!(
+            table.to_string(),
+            "|      |10|30|20|\n|a     |x |  |x |\n|привет|  |x |  |"
+        );
+
+        let table = ScoreTable {
+            scores: vec![10, 30, 20],
+            data: vec![
+                ScoreTableItem {
+                    name: String::from("a"),
+                    questions: vec![10, 20],
+                    played: Some(1),
+                },
+                ScoreTableItem {
+                    name: String::from("b"),
+                    questions: vec![],
+                    played: Some(3),
                 },
             ],
+            current_player: None,
+            standings: vec![],
+        };
+
+        assert_eq!(
+            table.to_string(),
+            "| |10|30|20|Σ|\n|a|x |  |x |1|\n|b|  |  |  |3|"
+        );
+
+        let table = ScoreTable {
+            scores: vec![10],
+            data: vec![ScoreTableItem {
+                name: String::from("a"),
+                questions: vec![10],
+                played: None,
+            }],
+            current_player: Some(String::from("Вася")),
+            standings: vec![],
+        };
+
+        assert_eq!(table.to_string(), "| |10|\n|a|x |\nВыбирает: Вася");
+    }
+
+    #[test]
+    fn test_score_table_current_player() {
+        let admin = UserId::from(1);
+        let (_, questions_storage) = create_game_state(admin);
+        for highlight_current_player in [false, true] {
+            let config = GameConfig {
+                highlight_current_player,
+                ..solo_game_config()
+            };
+            let mut game_state = GameState::new(admin, &questions_storage, Some(5), config).unwrap();
+            game_state.add_player(UserId::from(2), String::from("Вася"), None);
+            game_state.start(admin);
+            match game_state.next_question(admin).first() {
+                Some(UiRequest::SendScoreTable(table)) => {
+                    let expected = if highlight_current_player { Some("Вася") } else { None };
+                    assert_eq!(table.current_player.as_deref(), expected);
+                }
+                _ => panic!("score table should be sent"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_score_table_to_html() {
+        let table = ScoreTable {
+            scores: vec![10, 20],
+            data: vec![ScoreTableItem {
+                name: String::from("<b>"),
+                questions: vec![20],
+                played: Some(1),
+            }],
+            current_player: None,
+            standings: vec![],
         };
 
-        assert_eq!(table.to_string(), "|a     |x| |x|\n|привет| |x| |");
+        let html = table.to_html();
+        assert!(html.contains("<tr><th></th><th>10</th><th>20</th><th>Σ</th></tr>"));
+        assert!(html.contains(
+            "<tr><td class=\"topic\">&lt;b&gt;</td><td class=\"played\"></td>\
+             <td class=\"available\">20</td><td class=\"total\">1</td></tr>"
+        ));
     }
 
     #[test]
@@ -1615,6 +5822,8 @@ mod test {
     fn test_manual_questions() {
         let tours = vec![TourDescription {
             multiplier: 100,
+            title: None,
+            blitz: false,
             topics: vec![Topic {
                 name: "Sport".to_string(),
             }],
@@ -1630,7 +5839,8 @@ mod test {
         let mut game_state = GameState::new(
             admin_id,
             &questions_storage,
-            5,
+            Some(5),
+            solo_game_config(),
         )
         .unwrap();
 
@@ -1652,10 +5862,295 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_next_chooser() {
+        let admin_id = UserId::from(1);
+        let p1_id = UserId::from(2);
+        let p2_id = UserId::from(3);
+        let (mut game_state, questions_storage) =
+            start_game(admin_id, &["Sport"], &[p1_id, p2_id], |storage| {
+                storage.manual_questions = vec![("Sport".to_string(), 100)];
+            });
+        game_state.next_question(admin_id);
+        game_state.set_current_player(p1_id).unwrap();
+        let topic_id = game_state.get_topic_id("Sport".to_string()).unwrap();
+        game_state.select_topic(topic_id, p1_id);
+        game_state.select_question(100, p1_id, &questions_storage);
+        assert!(game_state.manual_scoring.is_some());
+
+        assert!(game_state.next_chooser(p1_id, "new_2".to_string()).is_empty());
+        match game_state.next_chooser(admin_id, "Петя".to_string()).as_slice() {
+            [UiRequest::SendTextToMainChat(text)] => assert_eq!(text, "Игрок Петя не найден"),
+            _ => panic!("unexpected ui requests"),
+        }
+
+        // Manual question is finished and nobody got the turn automatically
+        match game_state.next_chooser(admin_id, "NEW_2".to_string()).as_slice() {
+            [UiRequest::SendTextToMainChat(answer), UiRequest::SendTextToMainChat(text)] => {
+                assert!(answer.starts_with("Правильный ответ"));
+                assert_eq!(text, "Следующий вопрос выбирает new_2");
+            }
+            _ => panic!("unexpected ui requests"),
+        }
+        assert!(game_state.manual_scoring.is_none());
+        assert_eq!(game_state.get_state(), &State::Pause);
+        assert_eq!(game_state.get_current_player().map(|p| p.id()), Some(p2_id));
+
+        // Previous player's choice of topic is cancelled
+        game_state.next_question(admin_id);
+        assert_eq!(game_state.get_state(), &State::WaitingForTopic);
+        game_state.next_chooser(admin_id, "new_1".to_string());
+        assert_eq!(game_state.get_state(), &State::Pause);
+        assert_eq!(game_state.get_current_player().map(|p| p.id()), Some(p1_id));
+
+        // Not while a question is played
+        game_state.next_question(admin_id);
+        select_question(&mut game_state, &questions_storage, "Sport", p1_id, 200);
+        assert!(matches!(game_state.get_state(), State::CanAnswer(..)));
+        assert!(matches!(
+            game_state.next_chooser(admin_id, "new_2".to_string()).as_slice(),
+            [UiRequest::SendToAdmin(_)]
+        ));
+        assert_eq!(game_state.get_current_player().map(|p| p.id()), Some(p1_id));
+    }
+
+    #[test]
+    fn test_chooser_regains_turn() {
+        let admin_id = UserId::from(1);
+        let p1_id = UserId::from(2);
+        let p2_id = UserId::from(3);
+        let (mut game_state, questions_storage) =
+            start_game(admin_id, &["Sport"], &[p1_id, p2_id], |storage| {
+                storage.cats_in_bags = vec![CatInBag {
+                    old_topic: "Sport".to_string(),
+                    cost: 100,
+                    new_topic: "CatInBag".to_string(),
+                    question: "question".to_string(),
+                    answer: "answer".to_string(),
+                }];
+                storage.auctions = vec![("Sport".to_string(), 200)];
+                storage.manual_questions = vec![("Sport".to_string(), 300)];
+            });
+        let topic_id = game_state.get_topic_id("Sport".to_string()).unwrap();
+        let current_player = |game_state: &GameState| game_state.get_current_player().map(|p| p.id());
+
+        // Cat in bag is given to the second player, who misses it
+        game_state.next_question(admin_id);
+        game_state.set_current_player(p1_id).unwrap();
+        game_state.select_topic(topic_id, p1_id);
+        game_state.select_question(100, p1_id, &questions_storage);
+        game_state.select_cat_in_bag_player(p1_id, "new_2".to_string());
+        game_state.select_cat_in_bag_cost(p2_id, 100);
+        game_state.no_reply(admin_id);
+        assert_eq!(game_state.get_state(), &State::Pause);
+        assert_eq!(current_player(&game_state), Some(p1_id));
+
+        // Auction is won by the second player, who misses it
+        game_state.next_question(admin_id);
+        game_state.select_topic(topic_id, p1_id);
+        game_state.select_question(200, p1_id, &questions_storage);
+        game_state.update_auction_cost(admin_id, "new_2".to_string(), 200);
+        assert_eq!(current_player(&game_state), Some(p2_id));
+        game_state.no_reply(admin_id);
+        assert_eq!(game_state.get_state(), &State::Pause);
+        assert_eq!(current_player(&game_state), Some(p1_id));
+
+        // Nobody is credited for the manual question
+        game_state.next_question(admin_id);
+        game_state.set_current_player(p2_id).unwrap();
+        game_state.select_topic(topic_id, p2_id);
+        game_state.select_question(300, p2_id, &questions_storage);
+        game_state.finish_manual_scoring(admin_id);
+        assert_eq!(current_player(&game_state), Some(p2_id));
+    }
+
+    #[test]
+    fn test_custom_costs() {
+        let admin_id = UserId::from(1);
+        let p1_id = UserId::from(2);
+        let (mut game_state, questions_storage) =
+            start_game(admin_id, &["Sport", "Movies"], &[p1_id], |storage| {
+                for (difficulty, cost) in [50, 150, 400, 700, 1000].iter().enumerate() {
+                    storage.custom_costs.insert(("Sport".to_string(), difficulty + 1), *cost);
+                }
+                // Same cost can mean different difficulties in different topics
+                storage.custom_costs.insert(("Movies".to_string(), 2), 150);
+            });
+        game_state.next_question(admin_id);
+
+        // Columns of the table are all costs of the tour
+        let score_table = game_state.make_score_table();
+        assert_eq!(score_table.scores, vec![50, 100, 150, 300, 400, 500, 700, 1000]);
+        assert_eq!(score_table.data[0].questions, vec![50, 150, 400, 700, 1000]);
+        assert_eq!(score_table.data[1].questions, vec![100, 150, 300, 400, 500]);
+
+        assert_eq!(game_state.question_difficulty("Sport", 400), Some(3));
+        assert_eq!(game_state.question_difficulty("Sport", 1000), Some(5));
+        assert_eq!(game_state.question_difficulty("Sport", 100), None);
+        assert_eq!(game_state.question_difficulty("Movies", 150), Some(2));
+        assert_eq!(game_state.question_difficulty("Movies", 300), Some(3));
+
+        let topic_id = game_state.get_topic_id("Sport".to_string()).unwrap();
+        game_state.select_topic(topic_id, p1_id);
+        match game_state.select_question(150, p1_id, &questions_storage).as_slice() {
+            [UiRequest::RightBeforeAskingQuestion(text), ..] => {
+                assert_eq!(text, "Играем тему Sport, вопрос за 150");
+            }
+            _ => panic!("unexpected ui requests"),
+        }
+        match game_state.get_state() {
+            State::BeforeQuestionAsked(question, 150) => assert_eq!(question.question(), "3 * 2 = ?"),
+            _ => panic!("unexpected state"),
+        }
+    }
+
+    #[test]
+    fn test_stake_and_auction_with_custom_costs() {
+        let admin_id = UserId::from(1);
+        let p1_id = UserId::from(2);
+        let (mut game_state, questions_storage) = start_game(admin_id, &["Sport"], &[p1_id], |storage| {
+            for (difficulty, cost) in [50, 150, 400, 700, 1000].iter().enumerate() {
+                storage.custom_costs.insert(("Sport".to_string(), difficulty + 1), *cost);
+            }
+            storage.stakes = vec![("Sport".to_string(), 50)];
+            storage.auctions = vec![("Sport".to_string(), 150)];
+        });
+        game_state.next_question(admin_id);
+
+        // Minimal stake is the cheapest question of the topic, not the multiplier
+        let topic_id = game_state.get_topic_id("Sport".to_string()).unwrap();
+        game_state.select_topic(topic_id, p1_id);
+        match game_state.select_question(50, p1_id, &questions_storage).last() {
+            Some(UiRequest::SendTextToMainChat(msg)) => {
+                assert_eq!(msg, "Вопрос со ставкой! new_1, напишите ставку от 50 до 50")
+            }
+            _ => panic!("unexpected ui requests"),
+        }
+        game_state.message(p1_id, "50".to_string());
+        assert!(matches!(game_state.get_state(), State::Answering(_, 50, false)));
+        game_state.yes_reply(admin_id);
+        assert_eq!(game_state.get_player_score(p1_id), Some(50));
+        game_state.next_question(admin_id);
+        game_state.select_topic(topic_id, p1_id);
+        game_state.select_question(150, p1_id, &questions_storage);
+        match game_state.auction_bidding {
+            Some(ref bidding) => assert_eq!(bidding.step, 50),
+            None => panic!("auction should be started"),
+        }
+    }
+
+    #[test]
+    fn test_single_answer_questions() {
+        let admin_id = UserId::from(1);
+        let p1_id = UserId::from(2);
+        let p2_id = UserId::from(3);
+        let (mut game_state, questions_storage) =
+            start_game(admin_id, &["Sport"], &[p1_id, p2_id], |storage| {
+                storage.single_answer_questions = vec![("Sport".to_string(), 100)];
+            });
+        game_state.next_question(admin_id);
+        game_state.set_current_player(p1_id).unwrap();
+        let topic_id = game_state.get_topic_id("Sport".to_string()).unwrap();
+        game_state.select_topic(topic_id, p1_id);
+        match game_state.select_question(100, p1_id, &questions_storage).as_slice() {
+            [UiRequest::RightBeforeAskingQuestion(_), UiRequest::SendToAdmin(_), UiRequest::SendTextToMainChat(text), UiRequest::SendQuestionText(_), UiRequest::AskAdminYesNo(_)] => {
+                assert_eq!(text, "Отвечает только new_1");
+            }
+            _ => panic!("unexpected ui requests"),
+        }
+        assert!(matches!(game_state.get_state(), State::Answering(_, 100, false)));
+
+        // Other players can't buzz, and a miss closes the question
+        assert!(game_state.message(p2_id, String::from("1")).is_empty());
+        game_state.no_reply(admin_id);
+        assert_eq!(game_state.get_state(), &State::Pause);
+        assert_eq!(game_state.get_player_score(p1_id), Some(-100));
+        assert_eq!(game_state.get_player_score(p2_id), Some(0));
+
+        // Regular questions are still open for everyone
+        game_state.next_question(admin_id);
+        game_state.select_topic(topic_id, p1_id);
+        game_state.select_question(200, p1_id, &questions_storage);
+        assert!(matches!(game_state.get_state(), State::BeforeQuestionAsked(..)));
+    }
+
+    #[test]
+    fn test_manual_questions_scoring() {
+        let tours = vec![TourDescription {
+            multiplier: 100,
+            title: None,
+            blitz: false,
+            topics: vec![Topic {
+                name: "Sport".to_string(),
+            }],
+        }];
+
+        let mut questions_storage = FakeQuestionsStorage::new(tours);
+        questions_storage.manual_questions = vec![("Sport".to_string(), 100), ("Sport".to_string(), 200)];
+        let questions_storage: Box<dyn QuestionsStorage> = Box::new(questions_storage);
+
+        let admin_id = UserId::from(1);
+        let p1_id = UserId::from(2);
+        let p2_id = UserId::from(3);
+        let p3_id = UserId::from(4);
+
+        let mut game_state = GameState::new(
+            admin_id,
+            &questions_storage,
+            Some(5),
+            GameConfig {
+                manual_question_answerers: 2,
+                ..solo_game_config()
+            },
+        )
+        .unwrap();
+
+        game_state.add_player(p1_id, String::from("new_1"), None);
+        game_state.add_player(p2_id, String::from("new_2"), None);
+        game_state.add_player(p3_id, String::from("new_3"), None);
+        game_state.start(admin_id);
+
+        game_state.next_question(admin_id);
+        game_state.set_current_player(p1_id).unwrap();
+        let topic_id = game_state.get_topic_id("Sport".to_string()).unwrap();
+        game_state.select_topic(topic_id, p1_id);
+        game_state.select_question(100, p1_id, &questions_storage);
+
+        // Only admin can score
+        game_state.select_scoring_player(p2_id, "new_2".to_string());
+        assert_eq!(game_state.get_player_score(p2_id), Some(0));
+
+        game_state.select_scoring_player(admin_id, "new_2".to_string());
+        // Can't credit the same player twice
+        game_state.select_scoring_player(admin_id, "new_2".to_string());
+        game_state.select_scoring_player(admin_id, "new_3".to_string());
+        // Limit is reached
+        game_state.select_scoring_player(admin_id, "new_1".to_string());
+
+        assert_eq!(game_state.get_player_score(p1_id), Some(0));
+        assert_eq!(game_state.get_player_score(p2_id), Some(100));
+        assert_eq!(game_state.get_player_score(p3_id), Some(100));
+        assert_eq!(game_state.get_current_player().map(|p| p.id()), Some(p2_id));
+
+        // Admin can end scoring before the limit is reached
+        game_state.next_question(admin_id);
+        game_state.select_topic(topic_id, p2_id);
+        game_state.select_question(200, p2_id, &questions_storage);
+        game_state.select_scoring_player(admin_id, "new_1".to_string());
+        game_state.finish_manual_scoring(admin_id);
+        game_state.select_scoring_player(admin_id, "new_3".to_string());
+
+        assert_eq!(game_state.get_player_score(p1_id), Some(200));
+        assert_eq!(game_state.get_player_score(p3_id), Some(100));
+        assert_eq!(game_state.get_current_player().map(|p| p.id()), Some(p1_id));
+    }
+
     #[test]
     fn test_cats_in_bags_questions() {
         let tours = vec![TourDescription {
             multiplier: 100,
+            title: None,
+            blitz: false,
             topics: vec![Topic {
                 name: "Sport".to_string(),
             }],
@@ -1678,7 +6173,8 @@ mod test {
         let mut game_state = GameState::new(
             admin_id,
             &questions_storage,
-            5,
+            Some(5),
+            solo_game_config(),
         )
         .unwrap();
 
@@ -1707,14 +6203,14 @@ mod test {
 
         // Right choice
         game_state.select_cat_in_bag_player(p1_id, "new_2".to_string());
-        assert!(matches!(game_state.get_state(), State::CatInBagChoosingCost(_)));
+        assert!(matches!(game_state.get_state(), State::CatInBagChoosingCost(..)));
 
         // Select cost - wrong cost
         game_state.select_cat_in_bag_cost(p2_id, 200);
-        assert!(matches!(game_state.get_state(), State::CatInBagChoosingCost(_)));
+        assert!(matches!(game_state.get_state(), State::CatInBagChoosingCost(..)));
         // Select cost - wrong user id
         game_state.select_cat_in_bag_cost(p1_id, 500);
-        assert!(matches!(game_state.get_state(), State::CatInBagChoosingCost(_)));
+        assert!(matches!(game_state.get_state(), State::CatInBagChoosingCost(..)));
 
         // Select cost - right choice
         game_state.select_cat_in_bag_cost(p2_id, 500);
@@ -1723,10 +6219,438 @@ mod test {
         assert_eq!(game_state.current_player.map(|x| x.id()), Some(p2_id));
     }
 
+    #[test]
+    fn test_stake_questions() {
+        let admin_id = UserId::from(1);
+        let p1_id = UserId::from(2);
+        let p2_id = UserId::from(3);
+        let (mut game_state, questions_storage) =
+            start_game(admin_id, &["Sport"], &[p1_id, p2_id], |storage| {
+                storage.stakes = vec![("Sport".to_string(), 200)];
+            });
+        game_state.update_score("new_1".to_string(), 700, admin_id);
+
+        game_state.next_question(admin_id);
+        game_state.set_current_player(p1_id).unwrap();
+        let topic_id = game_state.get_topic_id("Sport".to_string()).unwrap();
+        game_state.select_topic(topic_id, p1_id);
+        match game_state.select_question(200, p1_id, &questions_storage).last() {
+            Some(UiRequest::SendTextToMainChat(msg)) => {
+                assert_eq!(msg, "Вопрос со ставкой! new_1, напишите ставку от 100 до 700")
+            }
+            _ => panic!("unexpected ui requests"),
+        }
+        assert!(matches!(game_state.get_state(), State::StakeChoosing(_, 200)));
+
+        // Wrong user
+        assert!(game_state.message(p2_id, "300".to_string()).is_empty());
+        // More than the score
+        game_state.message(p1_id, "800".to_string());
+        assert!(matches!(game_state.get_state(), State::StakeChoosing(_, 200)));
+        // Less than the minimum
+        game_state.message(p1_id, "50".to_string());
+        assert!(matches!(game_state.get_state(), State::StakeChoosing(_, 200)));
+
+        game_state.message(p1_id, "600".to_string());
+        assert!(matches!(game_state.get_state(), State::Answering(_, 600, false)));
+        assert_eq!(game_state.current_player.as_ref().map(|x| x.id()), Some(p1_id));
+
+        // Other players can't answer the stake question
+        assert!(game_state.message(p2_id, "1".to_string()).is_empty());
+
+        game_state.no_reply(admin_id);
+        assert_eq!(game_state.get_player_score(p1_id), Some(100));
+    }
+
+    #[test]
+    fn test_cat_in_bag_costs_of_short_topic() {
+        let tours = vec![TourDescription {
+            multiplier: 100,
+            title: None,
+            blitz: false,
+            topics: vec![
+                Topic {
+                    name: "Sport".to_string(),
+                },
+                Topic {
+                    name: "Movies".to_string(),
+                },
+            ],
+        }];
+        let mut questions_storage = FakeQuestionsStorage::new(tours);
+        questions_storage.questions.remove(&("Movies".to_string(), 4));
+        questions_storage.questions.remove(&("Movies".to_string(), 5));
+        questions_storage.cats_in_bags = vec![CatInBag {
+            old_topic: "Movies".to_string(),
+            cost: 100,
+            new_topic: "CatInBag".to_string(),
+            question: "question".to_string(),
+            answer: "answer".to_string(),
+        }];
+        let questions_storage: Box<dyn QuestionsStorage> = Box::new(questions_storage);
+
+        let admin_id = UserId::from(1);
+        let p1_id = UserId::from(2);
+        let p2_id = UserId::from(3);
+        let mut game_state = GameState::new(admin_id, &questions_storage, None, solo_game_config()).unwrap();
+        game_state.add_player(p1_id, String::from("new_1"), None);
+        game_state.add_player(p2_id, String::from("new_2"), None);
+        game_state.start(admin_id);
+        game_state.next_question(admin_id);
+        game_state.set_current_player(p1_id).unwrap();
+        let topic_id = game_state.get_topic_id("Movies".to_string()).unwrap();
+        game_state.select_topic(topic_id, p1_id);
+        game_state.select_question(100, p1_id, &questions_storage);
+
+        let res = game_state.select_cat_in_bag_player(p1_id, "new_2".to_string());
+        match res.as_slice() {
+            [UiRequest::SendTextToMainChat(_), UiRequest::CatInBagChooseCost(costs)] => {
+                assert_eq!(costs, &vec![100, 300]);
+            }
+            _ => panic!("unexpected ui requests"),
+        }
+
+        // Most expensive question of the board isn't in this topic
+        match game_state.select_cat_in_bag_cost(p2_id, 500).as_slice() {
+            [UiRequest::SendTextToMainChat(text)] => {
+                assert_eq!(text, "Неверная стоимость 500, можно выбрать: 100, 300");
+            }
+            _ => panic!("invalid cost should be reported"),
+        }
+        assert!(matches!(game_state.get_state(), State::CatInBagChoosingCost(..)));
+
+        game_state.select_cat_in_bag_cost(p2_id, 300);
+        assert!(matches!(game_state.get_state(), State::Answering(_, 300, false)));
+    }
+
+    #[test]
+    fn test_cat_in_bag_small_roster() {
+        let admin_id = UserId::from(1);
+        let p1_id = UserId::from(2);
+        let p2_id = UserId::from(3);
+        let play_cat_in_bag = |players: &[UserId]| {
+            let (mut game_state, questions_storage) = start_game(admin_id, &["Sport"], players, |storage| {
+                storage.cats_in_bags = vec![CatInBag {
+                    old_topic: "Sport".to_string(),
+                    cost: 100,
+                    new_topic: "CatInBag".to_string(),
+                    question: "question".to_string(),
+                    answer: "answer".to_string(),
+                }];
+            });
+            game_state.next_question(admin_id);
+            game_state.set_current_player(p1_id).unwrap();
+            let topic_id = game_state.get_topic_id("Sport".to_string()).unwrap();
+            game_state.select_topic(topic_id, p1_id);
+            let res = game_state.select_question(100, p1_id, &questions_storage);
+            (game_state, res)
+        };
+
+        // Exactly one player to give the cat to
+        let (game_state, res) = play_cat_in_bag(&[p1_id, p2_id]);
+        match res.as_slice() {
+            [_, _, _, UiRequest::CatInBagChoosePlayer(players)] => {
+                assert_eq!(players.iter().map(|p| p.id()).collect::<Vec<_>>(), vec![p2_id]);
+            }
+            _ => panic!("unexpected ui requests"),
+        }
+        assert!(matches!(game_state.get_state(), State::CatInBagChoosingPlayer(..)));
+
+        // Nobody else, so the player keeps the question
+        let (mut game_state, res) = play_cat_in_bag(&[p1_id]);
+        match res.as_slice() {
+            [_, _, _, UiRequest::SendTextToMainChat(text), UiRequest::CatInBagChooseCost(costs)] => {
+                assert_eq!(text, "Отдать кота некому, играет new_1. Тема: CatInBag");
+                assert!(!costs.is_empty());
+            }
+            _ => panic!("unexpected ui requests"),
+        }
+        assert!(matches!(game_state.get_state(), State::CatInBagChoosingCost(..)));
+        game_state.select_cat_in_bag_cost(p1_id, 100);
+        assert!(matches!(game_state.get_state(), State::Answering(_, 100, false)));
+        game_state.yes_reply(admin_id);
+        assert_eq!(game_state.get_player_score(p1_id), Some(100));
+    }
+
+    #[test]
+    fn test_cat_in_bag_dm() {
+        let tours = vec![TourDescription {
+            multiplier: 100,
+            title: None,
+            blitz: false,
+            topics: vec![Topic {
+                name: "Sport".to_string(),
+            }],
+        }];
+        let mut questions_storage = FakeQuestionsStorage::new(tours);
+        questions_storage.cats_in_bags = vec![CatInBag {
+            old_topic: "Sport".to_string(),
+            cost: 100,
+            new_topic: "CatInBag".to_string(),
+            question: "question".to_string(),
+            answer: "answer".to_string(),
+        }];
+        let questions_storage: Box<dyn QuestionsStorage> = Box::new(questions_storage);
+
+        let admin_id = UserId::from(1);
+        let config = GameConfig {
+            cat_in_bag_dm: true,
+            ..solo_game_config()
+        };
+        let p1_id = UserId::from(2);
+        let p2_id = UserId::from(3);
+        let play_cat_in_bag = |private_chat: bool| {
+            let mut game_state = GameState::new(admin_id, &questions_storage, Some(5), config.clone()).unwrap();
+            game_state.add_player(p1_id, String::from("new_1"), None);
+            game_state.add_player(p2_id, String::from("new_2"), None);
+            if private_chat {
+                game_state.private_chat_started(p2_id);
+            }
+            game_state.start(admin_id);
+            game_state.next_question(admin_id);
+            game_state.set_current_player(p1_id).unwrap();
+            let topic_id = game_state.get_topic_id("Sport".to_string()).unwrap();
+            game_state.select_topic(topic_id, p1_id);
+            game_state.select_question(100, p1_id, &questions_storage);
+            game_state.select_cat_in_bag_player(p1_id, "new_2".to_string());
+            game_state.select_cat_in_bag_cost(p2_id, 100)
+        };
+
+        let res = play_cat_in_bag(true);
+        match res.as_slice() {
+            [UiRequest::SendTextToMainChat(_), UiRequest::SendTextToMainChat(text), UiRequest::SendPrivately(user, question), UiRequest::AskAdminYesNo(_)] => {
+                assert_eq!(text, "Вопрос отправлен new_2 в личные сообщения");
+                assert_eq!(*user, p2_id);
+                assert!(matches!(question.as_slice(), [UiRequest::SendQuestionText(_)]));
+            }
+            _ => panic!("question should be sent privately"),
+        }
+
+        // No private chat with the player
+        let res = play_cat_in_bag(false);
+        assert!(!res.iter().any(|r| matches!(r, UiRequest::SendPrivately(..))));
+        assert!(res.iter().any(|r| matches!(r, UiRequest::SendQuestionText(text) if text == "2 * 2 = ?")));
+    }
+
+    #[test]
+    fn test_turn_dm() {
+        let admin_id = UserId::from(1);
+        let p1_id = UserId::from(2);
+        let p2_id = UserId::from(3);
+        let (mut game_state, _) = create_game_state(admin_id);
+        game_state.config.turn_dm = true;
+        game_state.add_player(p1_id, String::from("new_1"), None);
+        game_state.add_player(p2_id, String::from("new_2"), None);
+        game_state.private_chat_started(p1_id);
+        game_state.start(admin_id);
+
+        game_state.set_current_player(p1_id).unwrap();
+        let res = game_state.next_question(admin_id);
+        match res.as_slice() {
+            [UiRequest::SendScoreTable(_), UiRequest::SendPrivately(user, msg), UiRequest::ChooseTopic(..)] => {
+                assert_eq!(*user, p1_id);
+                assert!(matches!(msg.as_slice(), [UiRequest::SendTextToMainChat(text)] if text == "Ваша очередь выбирать вопрос"));
+            }
+            _ => panic!("player should be reminded privately"),
+        }
+
+        // No private chat with the player, only the main chat prompt
+        game_state.set_current_player(p2_id).unwrap();
+        let res = game_state.next_question(admin_id);
+        assert!(matches!(res.as_slice(), [UiRequest::SendScoreTable(_), UiRequest::ChooseTopic(..)]));
+    }
+
+    #[test]
+    fn test_public_state() {
+        let admin = UserId::from(1);
+        let p1 = UserId::from(2);
+        let (mut game_state, questions_storage) = create_game_state(admin);
+        game_state.add_player(p1, String::from("new_1"), None);
+        let state = serde_json::to_value(game_state.public_state()).unwrap();
+        assert_eq!(state["current_tour"], 0);
+
+        game_state.start(admin);
+        game_state.next_question(admin);
+        select_question(&mut game_state, &questions_storage, "Sport", p1, 100);
+        game_state.message(p1, String::from("1"));
+        game_state.yes_reply(admin);
+
+        let state = serde_json::to_value(game_state.public_state()).unwrap();
+        assert_eq!(
+            state,
+            serde_json::json!({
+                "current_player": "new_1",
+                "scores": [{"name": "new_1", "score": 100}],
+                "current_tour": 1,
+                "remaining_questions": [{"name": "Sport", "costs": [200, 300, 400, 500]}],
+            })
+        );
+    }
+
+    #[test]
+    fn test_skip_empty_tours() {
+        let tour = |multiplier: usize, topics: &[&str]| TourDescription {
+            multiplier,
+            title: None,
+            blitz: false,
+            topics: topics
+                .iter()
+                .map(|name| Topic {
+                    name: name.to_string(),
+                })
+                .collect(),
+        };
+        let tours = vec![
+            tour(100, &[]),
+            tour(200, &["Sport"]),
+            tour(300, &[]),
+            tour(400, &["Movies"]),
+            tour(500, &[]),
+        ];
+        let questions_storage: Box<dyn QuestionsStorage> = Box::new(FakeQuestionsStorage::new(tours));
+        let admin = UserId::from(1);
+        let mut game_state = GameState::new(admin, &questions_storage, Some(5), solo_game_config()).unwrap();
+        game_state.add_player(UserId::from(2), String::from("new_1"), None);
+
+        match game_state.start(admin).first() {
+            Some(UiRequest::SendToAdmin(msg)) => assert_eq!(msg, "Tour 1 has no topics, it is skipped"),
+            _ => panic!("admin should be warned"),
+        }
+        assert_eq!(game_state.current_tour, 1);
+        assert_eq!(game_state.current_multiplier, 200);
+
+        let res = game_state.next_tour(admin);
+        assert!(res.iter().any(|r| matches!(r, UiRequest::SendToAdmin(msg) if msg == "Tour 3 has no topics, it is skipped")));
+        assert_eq!(game_state.current_tour, 3);
+        assert_eq!(game_state.questions[0].0, "Movies");
+
+        // Only an empty tour is left
+        let res = game_state.next_tour(admin);
+        assert!(res.iter().any(|r| matches!(r, UiRequest::SendToAdmin(msg) if msg == "Tour 5 has no topics, it is skipped")));
+        assert!(res.iter().any(|r| matches!(r, UiRequest::GameFinished(_))));
+        assert_eq!(game_state.get_state(), &State::Finished);
+    }
+
+    #[test]
+    fn test_board() {
+        let tours = vec![TourDescription {
+            multiplier: 100,
+            title: None,
+            blitz: false,
+            topics: vec![Topic {
+                name: "Sport".to_string(),
+            }],
+        }];
+        let mut questions_storage = FakeQuestionsStorage::new(tours);
+        questions_storage.cats_in_bags = vec![CatInBag {
+            old_topic: "Sport".to_string(),
+            cost: 200,
+            new_topic: "Movies".to_string(),
+            question: "question".to_string(),
+            answer: "answer".to_string(),
+        }];
+        questions_storage.auctions = vec![("Sport".to_string(), 300)];
+        let questions_storage: Box<dyn QuestionsStorage> = Box::new(questions_storage);
+
+        let admin = UserId::from(1);
+        let p1 = UserId::from(2);
+        let mut game_state = GameState::new(
+            admin,
+            &questions_storage,
+            Some(5),
+            solo_game_config(),
+        )
+        .unwrap();
+
+        game_state.add_player(p1, String::from("new_1"), None);
+        game_state.start(admin);
+        game_state.next_question(admin);
+        select_question(&mut game_state, &questions_storage, "Sport", p1, 100);
+
+        assert!(game_state.board(p1).is_empty());
+        let res = game_state.board(admin);
+        assert_eq!(res.len(), 1);
+        match &res[0] {
+            UiRequest::SendToAdmin(msg) => {
+                assert!(msg.starts_with("Tour 1 (multiplier 100)\n"));
+                assert!(msg.contains(
+                    "Sport\n  available: 200 [cat], 300 [auction], 400, 500\n  used: 100"
+                ));
+            }
+            _ => panic!("unexpected request"),
+        }
+    }
+
+    #[test]
+    fn test_board_image() {
+        let admin = UserId::from(1);
+        let p1 = UserId::from(2);
+        let (mut game_state, questions_storage) = create_game_state(admin);
+        game_state.add_player(p1, String::from("new_1"), None);
+        match &game_state.board_image(admin)[..] {
+            [UiRequest::SendToAdmin(msg)] => assert_eq!(msg, "The game hasn't started yet"),
+            _ => panic!("board isn't available before the start"),
+        }
+
+        game_state.start(admin);
+        game_state.next_question(admin);
+        select_question(&mut game_state, &questions_storage, "Sport", p1, 100);
+
+        assert!(game_state.board_image(p1).is_empty());
+        match &game_state.board_image(admin)[..] {
+            [UiRequest::SendScoreTable(_)] => {}
+            _ => panic!("score table is expected"),
+        }
+    }
+
+    #[test]
+    fn test_unhide_question() {
+        let admin = UserId::from(1);
+        let p1 = UserId::from(2);
+        let (mut game_state, questions_storage) = create_game_state(admin);
+        game_state.add_player(p1, String::from("new_1"), None);
+        game_state.start(admin);
+        game_state.next_question(admin);
+
+        game_state.hide_question("Sport".to_string(), 300, admin);
+        select_question(&mut game_state, &questions_storage, "Sport", p1, 100);
+        game_state.timeout();
+
+        assert!(game_state.unhide_question("Sport".to_string(), 300, false, p1).is_empty());
+        let expect_admin_reply = |res: Vec<UiRequest>, expected: &str| match res.as_slice() {
+            [UiRequest::SendToAdmin(msg)] => assert!(msg.contains(expected), "{}", msg),
+            _ => panic!("unexpected ui requests"),
+        };
+
+        expect_admin_reply(
+            game_state.unhide_question("Sport".to_string(), 200, false, admin),
+            "already on the board",
+        );
+        expect_admin_reply(
+            game_state.unhide_question("Sport".to_string(), 250, false, admin),
+            "There is no question",
+        );
+        expect_admin_reply(
+            game_state.unhide_question("Sport".to_string(), 100, false, admin),
+            "was already played",
+        );
+        expect_admin_reply(
+            game_state.unhide_question("Sport".to_string(), 300, false, admin),
+            "back on the board",
+        );
+        expect_admin_reply(
+            game_state.unhide_question("Sport".to_string(), 100, true, admin),
+            "back on the board",
+        );
+        assert_eq!(game_state.questions[0].1, vec![100, 200, 300, 400, 500]);
+    }
+
     #[test]
     fn test_auctions() {
         let tours = vec![TourDescription {
             multiplier: 100,
+            title: None,
+            blitz: false,
             topics: vec![Topic {
                 name: "Sport".to_string(),
             }],
@@ -1741,7 +6665,8 @@ mod test {
         let mut game_state = GameState::new(
             admin_id,
             &questions_storage,
-            5,
+            Some(5),
+            solo_game_config(),
         )
         .unwrap();
 
@@ -1764,8 +6689,160 @@ mod test {
         game_state.update_auction_cost(p1_id, "new_1".to_string(), 100);
         assert!(matches!(game_state.get_state(), State::WaitingForAuction(..)));
 
+        // Below the nominal, and above the nominal while the player has no points
+        for cost in [50, 200] {
+            match game_state.update_auction_cost(admin_id, "new_1".to_string(), cost).as_slice() {
+                [UiRequest::SendToAdmin(msg)] => {
+                    assert_eq!(msg, &format!("Invalid auction cost {} for new_1, it should be from 100 to 100", cost))
+                }
+                _ => panic!("unexpected ui requests"),
+            }
+        }
+        assert!(matches!(game_state.get_state(), State::WaitingForAuction(..)));
+
         game_state.update_auction_cost(admin_id, "new_1".to_string(), 100);
         assert!(matches!(game_state.get_state(), State::Answering(_, _, _)));
         assert_eq!(game_state.get_current_player().map(|p| p.id()), Some(p1_id));
     }
+
+    #[test]
+    fn test_auction_bidding() {
+        let admin_id = UserId::from(1);
+        let p1_id = UserId::from(2);
+        let p2_id = UserId::from(3);
+        let (mut game_state, questions_storage) =
+            start_game(admin_id, &["Sport"], &[p1_id, p2_id], |storage| {
+                storage.auctions = vec![("Sport".to_string(), 200)];
+            });
+        game_state.update_score("new_1".to_string(), 500, admin_id);
+        game_state.update_score("new_2".to_string(), 1000, admin_id);
+
+        game_state.next_question(admin_id);
+        game_state.set_current_player(p1_id).unwrap();
+        let topic_id = game_state.get_topic_id("Sport".to_string()).unwrap();
+        game_state.select_topic(topic_id, p1_id);
+        let res = game_state.select_question(200, p1_id, &questions_storage);
+        assert!(res.iter().any(|r| matches!(r, UiRequest::ScheduleTimer(TimerId::Auction, _))));
+        assert!(matches!(game_state.get_state(), State::WaitingForAuction(..)));
+
+        // Below the nominal
+        let res = game_state.message(p1_id, "100".to_string());
+        match res.as_slice() {
+            [UiRequest::SendTextToMainChat(text)] => assert_eq!(text, "Ставка должна быть не меньше 200"),
+            _ => panic!("unexpected reply"),
+        }
+        // More than the player has
+        let res = game_state.message(p1_id, "600".to_string());
+        match res.as_slice() {
+            [UiRequest::SendTextToMainChat(text)] => assert_eq!(text, "new_1, у вас только 500 очков"),
+            _ => panic!("unexpected reply"),
+        }
+        // Usual chat messages are ignored
+        assert!(game_state.message(p2_id, "ого".to_string()).is_empty());
+
+        let res = game_state.message(p1_id, "300".to_string());
+        assert!(res.iter().any(|r| matches!(r, UiRequest::ScheduleTimer(TimerId::Auction, _))));
+
+        // Raise is smaller than the step
+        let res = game_state.message(p2_id, "350".to_string());
+        match res.as_slice() {
+            [UiRequest::SendTextToMainChat(text)] => assert_eq!(text, "Ставка должна быть не меньше 400"),
+            _ => panic!("unexpected reply"),
+        }
+        game_state.message(p2_id, "400".to_string());
+
+        // Going all-in is allowed with any raise
+        let res = game_state.message(p1_id, "ва-банк".to_string());
+        match res.first() {
+            Some(UiRequest::SendTextToMainChat(text)) => assert_eq!(text, "new_1 идёт ва-банк: 500"),
+            _ => panic!("unexpected reply"),
+        }
+        game_state.message(p2_id, "ва-банк".to_string());
+
+        game_state.close_auction();
+        assert!(matches!(game_state.get_state(), State::Answering(_, 1000, false)));
+        assert_eq!(game_state.get_current_player().map(|p| p.id()), Some(p2_id));
+
+        // Timer of the closed auction does nothing
+        assert!(game_state.close_auction().is_empty());
+    }
+
+    #[test]
+    fn test_auction_without_bids() {
+        let admin_id = UserId::from(1);
+        let p1_id = UserId::from(2);
+        let (mut game_state, questions_storage) = start_game(admin_id, &["Sport"], &[p1_id], |storage| {
+            storage.auctions = vec![("Sport".to_string(), 200)];
+        });
+
+        game_state.next_question(admin_id);
+        game_state.set_current_player(p1_id).unwrap();
+        let topic_id = game_state.get_topic_id("Sport".to_string()).unwrap();
+        game_state.select_topic(topic_id, p1_id);
+        game_state.select_question(200, p1_id, &questions_storage);
+
+        // Player with less than the nominal can bid only the nominal
+        let res = game_state.message(p1_id, "300".to_string());
+        match res.as_slice() {
+            [UiRequest::SendTextToMainChat(text)] => assert_eq!(text, "new_1, у вас только 0 очков"),
+            _ => panic!("unexpected reply"),
+        }
+
+        game_state.close_auction();
+        assert!(matches!(game_state.get_state(), State::Answering(_, 200, false)));
+        assert_eq!(game_state.get_current_player().map(|p| p.id()), Some(p1_id));
+    }
+
+    #[test]
+    fn test_auction_pass() {
+        let admin_id = UserId::from(1);
+        let p1_id = UserId::from(2);
+        let p2_id = UserId::from(3);
+        let p3_id = UserId::from(4);
+        let (mut game_state, questions_storage) =
+            start_game(admin_id, &["Sport"], &[p1_id, p2_id, p3_id], |storage| {
+                storage.auctions = vec![("Sport".to_string(), 100), ("Sport".to_string(), 200)];
+            });
+        game_state.update_score("new_2".to_string(), 500, admin_id);
+
+        game_state.next_question(admin_id);
+        game_state.set_current_player(p1_id).unwrap();
+        let topic_id = game_state.get_topic_id("Sport".to_string()).unwrap();
+        game_state.select_topic(topic_id, p1_id);
+        game_state.select_question(200, p1_id, &questions_storage);
+
+        game_state.message(p2_id, "300".to_string());
+        // The highest bidder can't pass
+        let res = game_state.auction_pass(p2_id);
+        match res.as_slice() {
+            [UiRequest::SendTextToMainChat(text)] => assert_eq!(text, "new_2, ваша ставка самая высокая"),
+            _ => panic!("unexpected reply"),
+        }
+
+        game_state.auction_pass(p1_id);
+        let res = game_state.message(p1_id, "400".to_string());
+        match res.as_slice() {
+            [UiRequest::SendTextToMainChat(text)] => assert_eq!(text, "new_1, вы уже спасовали"),
+            _ => panic!("unexpected reply"),
+        }
+        assert!(matches!(game_state.get_state(), State::WaitingForAuction(..)));
+
+        // All but one player passed
+        game_state.message(p3_id, "пас".to_string());
+        assert!(matches!(game_state.get_state(), State::Answering(_, 300, false)));
+        assert_eq!(game_state.get_current_player().map(|p| p.id()), Some(p2_id));
+        game_state.yes_reply(admin_id);
+
+        // Player who chose the auction passed and nobody bid in time
+        game_state.next_question(admin_id);
+        game_state.set_current_player(p1_id).unwrap();
+        game_state.select_topic(topic_id, p1_id);
+        game_state.select_question(100, p1_id, &questions_storage);
+        assert!(matches!(game_state.get_state(), State::WaitingForAuction(..)));
+        game_state.auction_pass(p1_id);
+        let res = game_state.close_auction();
+        assert!(res.iter().any(|r| matches!(r, UiRequest::SendTextToMainChat(text) if text == "Никто не играет аукцион")));
+        assert_eq!(game_state.get_state(), &State::Pause);
+        assert_eq!(game_state.get_current_player().map(|p| p.id()), Some(p1_id));
+    }
 }
@@ -5,47 +5,67 @@ use futures::sync::mpsc;
 use futures::{Future, Sink, Stream};
 use futures_03::{
     compat::{Future01CompatExt, Stream01CompatExt},
+    future::{select, Either},
     FutureExt, StreamExt, TryFutureExt, TryStreamExt,
 };
+use std::collections::HashMap;
 use std::fs::File;
 use std::io::prelude::*;
+use std::path::PathBuf;
 use std::process::Command;
+use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 use structopt::StructOpt;
-use telegram_bot::{reply_markup, types::MessageId};
+use telegram_bot::UserId;
 use tokio as tokio_01;
 use tokio_compat::runtime::Runtime;
 
 use telegram_bot::{
-    Api, ChatId, KeyboardButton, ReplyKeyboardMarkup, InlineKeyboardButton, InlineKeyboardMarkup, MessageKind,
-    MessageOrChannelPost, Message, ReplyKeyboardRemove,
+    Api, ChatId, MessageChat, MessageId, SendMessage, MessageKind, MessageOrChannelPost,
 };
-use telegram_bot::{SendMessage, Update, UpdateKind, UpdatesStream};
+use telegram_bot::{Update, UpdateKind, UpdatesStream};
 
+mod audit_log;
+mod cache;
+mod commands;
 mod gamestate;
+mod message_sink;
 mod messages;
 mod player;
 mod question;
 mod questionsstorage;
+mod results_db;
+mod score_image;
+mod state_server;
 mod stickers;
 mod telegram_config;
 mod timeout_stream;
 
+use commands::{
+    is_this_chat_command, parse_callback, parse_command, parse_text_message, CallbackMessage,
+    TextMessage, ANSWER_NO, ANSWER_YES, MANUAL_SCORING_DONE, START_NO, START_YES,
+};
+use message_sink::{Chat, InlineButton, Keyboard, MessageSink, SendError, SendErrorKind, TextFormat};
 use gamestate::TopicIdx;
+use telegram_config::ScoreboardMode;
+use timeout_stream::TimerId;
 use messages::*;
-use questionsstorage::{CsvQuestionsStorage, QuestionsStorage};
+use question::Media;
+use questionsstorage::{AttachmentSettings, CsvQuestionsStorage, QuestionsStorage};
 
 const TOKEN_VAR: &str = "TELEGRAM_BOT_TOKEN";
 const GOOGLE_API_KEY: &str = "GOOGLE_API_KEY";
 const CONFIG_VAR: &str = "GAME_CONFIG";
 
-const ANSWER_YES: &str = "AnswerYes";
-const ANSWER_NO: &str = "AnswerNo";
 
 const SCORE_TABLE_JSON_FILE: &str = "score_table.json";
+const GAME_SUMMARY_JSON_FILE: &str = "game_summary.json";
+const GAME_SUMMARY_PNG_FILE: &str = "game_summary.png";
 const SCORE_TABLE_PNG_FILE: &str = "score_table.png";
+const SCORE_TABLE_HTML_FILE: &str = "score_table.html";
+const TRANSCRIPT_FILE: &str = "transcript.txt";
 
-fn dump_score_table_file(table: gamestate::ScoreTable, filename: &str) -> Result<(), Error> {
+fn dump_score_table_file<T: serde::Serialize>(table: &T, filename: &str) -> Result<(), Error> {
     let mut file = File::create(filename).map_err(|error| {
         err_msg(format!(
             "Can't create file to dump score table ({:?})",
@@ -66,35 +86,217 @@ fn dump_score_table_file(table: gamestate::ScoreTable, filename: &str) -> Result
     })
 }
 
-fn make_score_table_image(table_filename: &str, image_filename: &str) -> Result<(), Error> {
-    let status = Command::new("python3")
-        .arg("external/draw_table.py")
-        .arg(table_filename)
-        .arg(image_filename)
-        .status()
+fn dump_score_table_html(table: &gamestate::ScoreTable, filename: &str) -> Result<(), Error> {
+    let mut file = File::create(filename).map_err(|error| {
+        err_msg(format!(
+            "Can't create file to export score table ({:?})",
+            error
+        ))
+    })?;
+    file.write_all(table.to_html().as_bytes()).map_err(|error| {
+        err_msg(format!(
+            "Can't write to file while exporting score table ({:?})",
+            error
+        ))
+    })
+}
+
+// External script which draws the score table and the game summary images
+#[derive(Clone)]
+pub struct ScoreTableDrawer {
+    python: String,
+    script: String,
+    // Score table is drawn by score_image instead of the script
+    native: bool,
+    colors: score_image::Colors,
+}
+
+impl ScoreTableDrawer {
+    fn new(config: &telegram_config::Config) -> Self {
+        Self {
+            python: config.scoreboard_python.clone(),
+            script: config.scoreboard_script.clone(),
+            native: config.scoreboard_mode == ScoreboardMode::Native,
+            colors: config.scoreboard_colors,
+        }
+    }
+
+    // Checks that the interpreter and the script are available, so that
+    // problems are found before the first score table is shown
+    fn probe(&self) -> Result<(), Error> {
+        if !std::path::Path::new(&self.script).is_file() {
+            return Err(err_msg(format!("score table script {} doesn't exist", self.script)));
+        }
+        let output = Command::new(&self.python)
+            .arg("--version")
+            .output()
+            .map_err(|error| err_msg(format!("Can't execute {} ({:?})", self.python, error)))?;
+        if !output.status.success() {
+            return Err(err_msg(format!("{} --version finished unsucessfully", self.python)));
+        }
+        Ok(())
+    }
+
+    fn draw(&self, table_filename: &str, image_filename: &str) -> Result<(), Error> {
+        let status = Command::new(&self.python)
+            .arg(&self.script)
+            .arg(table_filename)
+            .arg(image_filename)
+            .status()
+            .map_err(|error| {
+                err_msg(format!(
+                    "Can't execute process to draw score table ({:?})",
+                    error
+                ))
+            })?;
+        if !status.success() {
+            Err(err_msg(
+                "Process drawing score table finished unsucessfully",
+            ))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+// Files are uploaded, urls are passed as is for telegram to download them
+fn curl_media_value(media: &Media) -> String {
+    match media {
+        Media::File(path) => format!("@{}", path.display()),
+        Media::Url(url) => url.clone(),
+    }
+}
+
+fn send_photo_via_curl(game_chat: ChatId, token: &str, photo: &Media) -> Result<(), Error> {
+    send_photo_value_via_curl(game_chat, token, &curl_media_value(photo))?;
+    Ok(())
+}
+
+// File ids of the already uploaded photos, telegram lets us send them again
+// without uploading the file
+pub type FileIdCache = Arc<Mutex<HashMap<PathBuf, String>>>;
+
+fn send_cached_photo_via_curl(
+    game_chat: ChatId,
+    token: &str,
+    photo: &Media,
+    file_ids: &FileIdCache,
+) -> Result<(), Error> {
+    let path = match photo {
+        Media::File(path) => path,
+        Media::Url(_) => {
+            return send_photo_via_curl(game_chat, token, photo);
+        }
+    };
+
+    let file_id = file_ids.lock().unwrap().get(path).cloned();
+    if let Some(file_id) = file_id {
+        match send_photo_value_via_curl(game_chat, token, &file_id) {
+            Ok(_) => return Ok(()),
+            // E.g. telegram forgot the file, then it's uploaded again
+            Err(err) => {
+                eprintln!("failed to send {} by file id: {}", path.display(), err);
+                file_ids.lock().unwrap().remove(path);
+            }
+        }
+    }
+    if let Some(file_id) = send_photo_value_via_curl(game_chat, token, &curl_media_value(photo))? {
+        file_ids.lock().unwrap().insert(path.clone(), file_id);
+    }
+    Ok(())
+}
+
+// Returns file id of the sent photo if telegram returned it
+fn send_photo_value_via_curl(game_chat: ChatId, token: &str, photo: &str) -> Result<Option<String>, Error> {
+    println!("send_photo_via_curl");
+    let output = Command::new("curl")
+        .arg("-s")
+        .arg("-F")
+        .arg(format!("chat_id={}", game_chat))
+        .arg("-F")
+        .arg(format!("photo={}", photo))
+        .arg(format!("https://api.telegram.org/bot{}/sendPhoto", token))
+        .output()
         .map_err(|error| {
             err_msg(format!(
-                "Can't execute process to draw score table ({:?})",
+                "Can't execute curl to send photo ({:?})",
                 error
             ))
         })?;
-    if !status.success() {
-        Err(err_msg(
-            "Process drawing score table finished unsucessfully",
-        ))
+    if !output.status.success() {
+        Err(err_msg("Curl sending photo finished unsucessfully"))
     } else {
-        Ok(())
+        photo_file_id(&output.stdout)
     }
 }
 
-fn send_photo_via_curl(game_chat: ChatId, token: &str, filename: &str) -> Result<(), Error> {
-    println!("send_photo_via_curl");
+// Telegram returns several sizes of the photo, the last one is the biggest
+fn photo_file_id(response: &[u8]) -> Result<Option<String>, Error> {
+    let response: serde_json::Value = serde_json::from_slice(response)
+        .map_err(|err| err_msg(format!("invalid response to sendPhoto: {}", err)))?;
+    if response["ok"].as_bool() != Some(true) {
+        let description = response["description"].as_str().unwrap_or("unknown error");
+        return Err(err_msg(format!("sending photo failed: {}", description)));
+    }
+    let file_id = response["result"]["photo"]
+        .as_array()
+        .and_then(|sizes| sizes.last())
+        .and_then(|size| size["file_id"].as_str())
+        .map(String::from);
+    Ok(file_id)
+}
+
+// Telegram accepts from 2 to 10 photos in a single media group
+const MAX_MEDIA_GROUP_SIZE: usize = 10;
+
+fn send_media_group_via_curl(game_chat: ChatId, token: &str, photos: &[Media]) -> Result<(), Error> {
+    for chunk in photos.chunks(MAX_MEDIA_GROUP_SIZE) {
+        if let [photo] = chunk {
+            send_photo_via_curl(game_chat, token, photo)?;
+            continue;
+        }
+
+        let mut command = Command::new("curl");
+        let mut media = vec![];
+        for (i, photo) in chunk.iter().enumerate() {
+            match photo {
+                Media::File(path) => {
+                    media.push(serde_json::json!({"type": "photo", "media": format!("attach://photo{}", i)}));
+                    command.arg("-F").arg(format!("photo{}=@{}", i, path.display()));
+                }
+                Media::Url(url) => {
+                    media.push(serde_json::json!({"type": "photo", "media": url}));
+                }
+            }
+        }
+        command
+            .arg("-F")
+            .arg(format!("chat_id={}", game_chat))
+            .arg("-F")
+            .arg(format!("media={}", serde_json::Value::from(media)));
+        let status = command
+            .arg(format!("https://api.telegram.org/bot{}/sendMediaGroup", token))
+            .status()
+            .map_err(|error| {
+                err_msg(format!(
+                    "Can't execute curl to send media group ({:?})",
+                    error
+                ))
+            })?;
+        if !status.success() {
+            return Err(err_msg("Curl sending media group finished unsucessfully"));
+        }
+    }
+    Ok(())
+}
+
+fn send_audio_via_curl(game_chat: ChatId, token: &str, audio: &Media) -> Result<(), Error> {
     let status = Command::new("curl")
         .arg("-F")
         .arg(format!("chat_id={}", game_chat))
         .arg("-F")
-        .arg(format!("photo=@{}", filename))
-        .arg(format!("https://api.telegram.org/bot{}/sendPhoto", token))
+        .arg(format!("audio={}", curl_media_value(audio)))
+        .arg(format!("https://api.telegram.org/bot{}/sendAudio", token))
         .status()
         .map_err(|error| {
             err_msg(format!(
@@ -109,22 +311,48 @@ fn send_photo_via_curl(game_chat: ChatId, token: &str, filename: &str) -> Result
     }
 }
 
-fn send_audio_via_curl(game_chat: ChatId, token: &str, filename: &str) -> Result<(), Error> {
+// Falls back to sending a document if telegram doesn't accept the file as
+// an animation
+fn send_animation_via_curl(game_chat: ChatId, token: &str, animation: &Media) -> Result<(), Error> {
+    for (method, field) in &[("sendAnimation", "animation"), ("sendDocument", "document")] {
+        let status = Command::new("curl")
+            .arg("--fail")
+            .arg("-F")
+            .arg(format!("chat_id={}", game_chat))
+            .arg("-F")
+            .arg(format!("{}={}", field, curl_media_value(animation)))
+            .arg(format!("https://api.telegram.org/bot{}/{}", token, method))
+            .status()
+            .map_err(|error| {
+                err_msg(format!(
+                    "Can't execute curl to send animation ({:?})",
+                    error
+                ))
+            })?;
+        if status.success() {
+            return Ok(());
+        }
+        eprintln!("{} failed for {}", method, animation);
+    }
+    Err(err_msg("Curl sending animation finished unsucessfully"))
+}
+
+fn send_document_via_curl(game_chat: ChatId, token: &str, document: &Media) -> Result<(), Error> {
     let status = Command::new("curl")
         .arg("-F")
         .arg(format!("chat_id={}", game_chat))
         .arg("-F")
-        .arg(format!("audio=@{}", filename))
-        .arg(format!("https://api.telegram.org/bot{}/sendAudio", token))
+        .arg(format!("document={}", curl_media_value(document)))
+        .arg(format!("https://api.telegram.org/bot{}/sendDocument", token))
         .status()
         .map_err(|error| {
             err_msg(format!(
-                "Can't execute curl to send score table ({:?})",
+                "Can't execute curl to send document ({:?})",
                 error
             ))
         })?;
     if !status.success() {
-        Err(err_msg("Curl sending score table finished unsucessfully"))
+        Err(err_msg("Curl sending document finished unsucessfully"))
     } else {
         Ok(())
     }
@@ -155,92 +383,159 @@ fn send_score_table(
     table: gamestate::ScoreTable,
     game_chat: ChatId,
     token: String,
+    drawer: &ScoreTableDrawer,
 ) -> Result<(), Error> {
-    dump_score_table_file(table, SCORE_TABLE_JSON_FILE)?;
-    make_score_table_image(SCORE_TABLE_JSON_FILE, SCORE_TABLE_PNG_FILE)?;
-    send_photo_via_curl(game_chat, &token, SCORE_TABLE_PNG_FILE)?;
+    if drawer.native {
+        score_image::save_score_table(&table, &drawer.colors, SCORE_TABLE_PNG_FILE)?;
+    } else {
+        dump_score_table_file(&table, SCORE_TABLE_JSON_FILE)?;
+        drawer.draw(SCORE_TABLE_JSON_FILE, SCORE_TABLE_PNG_FILE)?;
+    }
+    send_photo_via_curl(game_chat, &token, &Media::File(SCORE_TABLE_PNG_FILE.into()))?;
     Ok(())
 }
 
-fn topics_inline_keyboard(topics: Vec<(TopicIdx, String)>) -> InlineKeyboardMarkup {
-    let mut inline_markup = InlineKeyboardMarkup::new();
-    {
-        for (idx, topic) in topics {
-            let data = format!("/topic{}", idx.0);
-            let row = inline_markup.add_empty_row();
-        row.push(InlineKeyboardButton::callback(format!("{}", topic), data));
-        }
+// Summary is drawn by the same renderer as the score table
+fn send_game_summary(
+    summary: gamestate::GameSummary,
+    game_chat: ChatId,
+    token: String,
+    drawer: &ScoreTableDrawer,
+) -> Result<(), Error> {
+    if drawer.native {
+        score_image::save_game_summary(&summary, &drawer.colors, GAME_SUMMARY_PNG_FILE)?;
+    } else {
+        dump_score_table_file(&summary, GAME_SUMMARY_JSON_FILE)?;
+        drawer.draw(GAME_SUMMARY_JSON_FILE, GAME_SUMMARY_PNG_FILE)?;
     }
-    inline_markup
+    send_photo_via_curl(game_chat, &token, &Media::File(GAME_SUMMARY_PNG_FILE.into()))?;
+    Ok(())
 }
 
-fn topics_keyboard(topics: Vec<(TopicIdx, String)>, selective: bool) -> ReplyKeyboardMarkup {
-    let mut markup = ReplyKeyboardMarkup::new();
-    markup.one_time_keyboard();
-    if selective {
-        markup.selective();
-    }
-    {
-        for (_, topic) in topics {
-            let row = markup.add_empty_row();
-            row.push(KeyboardButton::new(format!("{}", topic)));
-        }
-    }
-    markup
+// Html mention which makes telegram notify the player even if they
+// don't have a username
+fn player_mention(player: &player::Player) -> String {
+    format!(
+        "<a href=\"tg://user?id={}\">{}</a>",
+        player.id(),
+        escape_html(player.name())
+    )
 }
 
-fn questioncosts_inline_keyboard(topic_idx: TopicIdx, costs: Vec<usize>) -> InlineKeyboardMarkup {
-    let mut inline_markup = InlineKeyboardMarkup::new();
-    {
-        for cost in costs {
-            let data = format!("/question{}_{}", topic_idx.0, cost);
-            let row = inline_markup.add_empty_row();
-            row.push(InlineKeyboardButton::callback(format!("{}", cost), data));
-        }
+fn topics_inline_keyboard(topics: Vec<(TopicIdx, String)>) -> Keyboard {
+    Keyboard::Inline(
+        topics
+            .into_iter()
+            .map(|(idx, topic)| vec![InlineButton::new(topic, format!("/topic{}", idx.0))])
+            .collect(),
+    )
+}
+
+fn topics_keyboard(topics: Vec<(TopicIdx, String)>, selective: bool) -> Keyboard {
+    Keyboard::Reply {
+        rows: topics.into_iter().map(|(_, topic)| vec![topic]).collect(),
+        selective,
     }
-    inline_markup
 }
-     
 
-fn questioncosts_keyboard(costs: Vec<usize>, selective: bool) -> ReplyKeyboardMarkup {
-    let mut markup = ReplyKeyboardMarkup::new();
-    markup.one_time_keyboard();
-    if selective {
-        markup.selective();
+// Telegram doesn't show more than 8 buttons in a row, so costs are put in
+// several columns only when they don't fit into this many rows
+const MAX_COST_ROWS: usize = 8;
+const MAX_BUTTONS_PER_ROW: usize = 8;
+const MAX_KEYBOARD_COSTS: usize = MAX_COST_ROWS * MAX_BUTTONS_PER_ROW;
+
+fn cost_rows<T>(costs: Vec<T>) -> Vec<Vec<T>> {
+    let per_row = costs.len().div_ceil(MAX_COST_ROWS).max(1);
+    let mut rows = vec![];
+    let mut costs = costs.into_iter().peekable();
+    while costs.peek().is_some() {
+        rows.push(costs.by_ref().take(per_row).collect());
     }
-    {
-        for cost in costs {
-            let row = markup.add_empty_row();
-            row.push(KeyboardButton::new(format!("{}", cost)));
+    rows
+}
+
+fn warn_about_cost_keyboard(questions_per_topic: Option<usize>) {
+    if let Some(questions_per_topic) = questions_per_topic {
+        if questions_per_topic > MAX_KEYBOARD_COSTS {
+            eprintln!(
+                "warning: {} questions per topic don't fit into the keyboard, at most {} are shown properly",
+                questions_per_topic, MAX_KEYBOARD_COSTS
+            );
         }
     }
-    markup
 }
 
-fn cat_in_bag_player_inline_keyboard(players: Vec<player::Player>) -> InlineKeyboardMarkup {
-    let mut inline_markup = InlineKeyboardMarkup::new();
-    for player in players {
-        let data = format!("/cat_in_bag_choose_player_{}", player.name());
-        let row = inline_markup.add_empty_row();
-        row.push(InlineKeyboardButton::callback(player.name().to_string(), data))
-    }
-    inline_markup
+fn questioncosts_inline_keyboard(topic_idx: TopicIdx, costs: Vec<usize>) -> Keyboard {
+    let buttons = costs
+        .into_iter()
+        .map(|cost| InlineButton::new(cost.to_string(), format!("/question{}_{}", topic_idx.0, cost)))
+        .collect();
+    Keyboard::Inline(cost_rows(buttons))
 }
 
-fn cat_in_bag_cost_inline_keyboard(costs: Vec<usize>) -> InlineKeyboardMarkup {
-    let mut inline_markup = InlineKeyboardMarkup::new();
-    for cost in costs {
-        let data = format!("/cat_in_bag_choose_cost_{}", cost);
-        let row = inline_markup.add_empty_row();
-        row.push(InlineKeyboardButton::callback(format!("{}", cost), data))
+fn questioncosts_keyboard(costs: Vec<usize>, selective: bool) -> Keyboard {
+    Keyboard::Reply {
+        rows: cost_rows(costs.into_iter().map(|cost| cost.to_string()).collect()),
+        selective,
     }
-    inline_markup
+}
+
+fn cat_in_bag_player_inline_keyboard(players: Vec<player::Player>) -> Keyboard {
+    Keyboard::Inline(
+        players
+            .into_iter()
+            .map(|player| {
+                let data = format!("/cat_in_bag_choose_player_{}", player.name());
+                vec![InlineButton::new(player.name().clone(), data)]
+            })
+            .collect(),
+    )
+}
+
+fn cat_in_bag_cost_inline_keyboard(costs: Vec<usize>) -> Keyboard {
+    Keyboard::Inline(
+        costs
+            .into_iter()
+            .map(|cost| {
+                vec![InlineButton::new(
+                    cost.to_string(),
+                    format!("/cat_in_bag_choose_cost_{}", cost),
+                )]
+            })
+            .collect(),
+    )
+}
+
+fn scoring_player_inline_keyboard(players: Vec<player::Player>) -> Keyboard {
+    let mut rows: Vec<_> = players
+        .into_iter()
+        .map(|player| {
+            let data = format!("/manual_score_player_{}", player.name());
+            vec![InlineButton::new(player.name().clone(), data)]
+        })
+        .collect();
+    rows.push(vec![InlineButton::new("Done", MANUAL_SCORING_DONE)]);
+    Keyboard::Inline(rows)
+}
+
+fn answer_yes_no_inline_keyboard() -> Keyboard {
+    Keyboard::Inline(vec![vec![
+        InlineButton::new("Yes", ANSWER_YES),
+        InlineButton::new("No", ANSWER_NO),
+    ]])
+}
+
+fn start_yes_no_inline_keyboard() -> Keyboard {
+    Keyboard::Inline(vec![vec![
+        InlineButton::new("Yes", START_YES),
+        InlineButton::new("No", START_NO),
+    ]])
 }
 
 fn merge_updates_and_timeouts(
     updates_stream: UpdatesStream,
     timeouts: timeout_stream::TimeoutStream,
-) -> Box<dyn Stream<Item = Result<Update, ()>, Error = Error>> {
+) -> Box<dyn Stream<Item = Result<Update, TimerId>, Error = Error>> {
     let updates_stream = Box::new(
         updates_stream
             .compat()
@@ -256,203 +551,922 @@ fn merge_updates_and_timeouts(
     Box::new(updates_stream.select(timeouts))
 }
 
-enum TextMessage {
-    Join(String),
-    JustMessage(String),
-    NextQuestion,
-    GetScore,
-    StartGame,
-    CurrentPlayer,
-    ChangePlayer(String),
-    NextTour,
-    UpdateScore(String, i64),
-    HideQuestion(String, usize),
-    UpdateAuctionCost(String, usize),
-    ChooseTopic(String),
-    ChooseQuestion(usize),
+// The bot can't go on with a wrong token or without the game or admin chat.
+// A player may block the bot, but the game goes on without their private chat
+fn is_fatal(err: &Error) -> bool {
+    match err.downcast_ref::<SendError>() {
+        Some(SendError { chat: Chat::Private(_), .. }) => false,
+        Some(err) => err.kind != SendErrorKind::Other,
+        None => false,
+    }
+}
+
+// A failed message shouldn't end a live game, so only fatal errors are
+// returned
+fn ignore_recoverable(res: Result<(), Error>) -> Result<(), Error> {
+    match res {
+        Err(err) if is_fatal(&err) => Err(err),
+        Err(err) => {
+            eprintln!("failed to handle ui request, the game goes on: {}", err);
+            Ok(())
+        }
+        Ok(()) => Ok(()),
+    }
 }
 
-enum CallbackMessage {
-    SelectedTopic(TopicIdx),
-    SelectedQuestion(TopicIdx, usize),
-    AnswerYes,
-    AnswerNo,
-    Unknown,
-    CatInBagPlayerChosen(String),
-    CatInBagCostChosen(usize),
+// Sends everything gamestate asks for to the game and admin chats
+struct UiContext<S: MessageSink> {
+    sink: S,
+    use_separate_keyboards: bool,
+    timer_sender: mpsc::Sender<timeout_stream::TimerRequest>,
+    // Timers are fired by hand in local mode, so they don't wait
+    manual_timers: bool,
+    results_db: Option<results_db::ResultsDb>,
+    cache: Option<cache::Cache>,
+    scoreboard_mode: ScoreboardMode,
+    choose_topic_message_id: Option<S::MessageId>,
+    choose_question_message_id: Option<S::MessageId>,
+    countdown_message_id: Option<S::MessageId>,
+    // Replies to this message are buzzes
+    question_message_id: Option<S::MessageId>,
 }
 
-fn parse_text_message(message: &Message, data: &String, choose_topic_message_id: Option<MessageId>, choose_question_message_id: Option<MessageId>) -> TextMessage {
-    if let Some(ref msg_or_post) = &message.reply_to_message {
-        if let MessageOrChannelPost::Message(ref msg) = **msg_or_post {
-            if Some(msg.id) == choose_topic_message_id {
-                return TextMessage::ChooseTopic(data.to_string());
-            }
+impl<S: MessageSink> UiContext<S> {
+    fn timer(&self, duration: Duration) -> Box<dyn Future<Item = (), Error = Error> + Send> {
+        if self.manual_timers {
+            return Box::new(futures::future::ok(()));
         }
+        let timer = tokio_01::timer::Delay::new(Instant::now() + duration);
+        Box::new(timer.map_err(|_err| err_msg("timer error happened")))
     }
 
-    if let Some(ref msg_or_post) = &message.reply_to_message {
-        if let MessageOrChannelPost::Message(ref msg) = **msg_or_post {
-            if Some(msg.id) == choose_question_message_id {
+    async fn send_timer(&self, id: TimerId, timer: Option<Box<dyn Future<Item = (), Error = Error>>>) {
+        // TODO(stash): handle?
+        let _ = self.timer_sender.clone().send((id, timer)).compat().map_err(|_| ()).await;
+    }
 
-                if let Ok(cost) = data.parse::<usize>() {
-                    return TextMessage::ChooseQuestion(cost);
+    // Question text and attachments, which can be sent either to the game chat or privately
+    // Returns id of the sent text message
+    async fn send_question_part(
+        &self,
+        chat: Chat,
+        request: &gamestate::UiRequest,
+    ) -> Result<Option<S::MessageId>, Error> {
+        match request {
+            gamestate::UiRequest::SendTextToMainChat(msg)
+            | gamestate::UiRequest::SendQuestionText(msg) => {
+                if !msg.is_empty() {
+                    return self.sink.send_plain(chat, msg.clone()).await;
                 }
             }
+            gamestate::UiRequest::SendImage(image) => {
+                let r = self.sink.send_photo(chat, image.clone()).await;
+                if let Err(e) = r {
+                    eprintln!("was not able to send image {}!", e);
+                }
+            }
+            gamestate::UiRequest::SendMediaGroup(images) => {
+                let r = self.sink.send_media_group(chat, images.clone()).await;
+                if let Err(e) = r {
+                    eprintln!("was not able to send images {}!", e);
+                }
+            }
+            gamestate::UiRequest::SendAnimation(animation) => {
+                let r = self.sink.send_animation(chat, animation.clone()).await;
+                if let Err(e) = r {
+                    eprintln!("was not able to send animation {}!", e);
+                }
+            }
+            gamestate::UiRequest::SendAudio(audio) => {
+                let r = self.sink.send_audio(chat, audio.clone()).await;
+                if let Err(e) = r {
+                    eprintln!("was not able to send audio {}!", e);
+                }
+            }
+            gamestate::UiRequest::SendDocument(document) => {
+                let r = self.sink.send_document(chat, document.clone()).await;
+                if let Err(e) = r {
+                    eprintln!("was not able to send document {}!", e);
+                }
+            }
+            _ => {
+                eprintln!("unexpected part of the question");
+            }
         }
+        Ok(None)
     }
 
-    if data.starts_with("/join") {
-        let split: Vec<_> = data.splitn(2, ' ').collect();
-        if split.len() == 2 {
-            return TextMessage::Join((*split.get(1).expect("should not happen")).to_string());
+    async fn handle_ui_request(&mut self, request: gamestate::UiRequest) -> Result<(), Error> {
+        match request {
+            request @ (gamestate::UiRequest::SendTextToMainChat(_)
+            | gamestate::UiRequest::SendImage(_)
+            | gamestate::UiRequest::SendMediaGroup(_)
+            | gamestate::UiRequest::SendAnimation(_)
+            | gamestate::UiRequest::SendAudio(_)
+            | gamestate::UiRequest::SendDocument(_)) => {
+                self.send_question_part(Chat::Game, &request).await?;
+            }
+            request @ gamestate::UiRequest::SendQuestionText(_) => {
+                self.question_message_id = None;
+                self.question_message_id = self.send_question_part(Chat::Game, &request).await?;
+            }
+            gamestate::UiRequest::SendPrivately(user, requests) => {
+                // Question isn't in the game chat, so there is nothing to reply to
+                self.question_message_id = None;
+                // E.g. the player blocked the bot, then everything goes to the
+                // game chat so that the question isn't lost
+                for request in requests.iter() {
+                    if let Err(err) = self.send_question_part(Chat::Private(user), request).await {
+                        eprintln!("failed to send private message to {}: {}", user, err);
+                        self.sink
+                            .send_plain(
+                                Chat::Admin,
+                                format!("Can't send a private message to {}, it's sent to the game chat: {}", user, err),
+                            )
+                            .await?;
+                        for request in requests.iter() {
+                            let msg_id = self.send_question_part(Chat::Game, request).await?;
+                            if let gamestate::UiRequest::SendQuestionText(_) = request {
+                                self.question_message_id = msg_id;
+                            }
+                        }
+                        break;
+                    }
+                }
+            }
+            gamestate::UiRequest::RightBeforeAskingQuestion(msg) => {
+                // Replies to the previous question aren't buzzes anymore
+                self.question_message_id = None;
+                if !msg.is_empty() {
+                    self.sink
+                        .send_text(Chat::Game, msg, TextFormat::Plain, Some(Keyboard::Remove))
+                        .await?;
+                }
+            }
+            gamestate::UiRequest::SendSticker(sticker) => {
+                let r = self.sink.send_sticker(Chat::Game, sticker).await;
+                if let Err(e) = r {
+                    eprintln!("was not able to send sticker {}!", e);
+                }
+            }
+            gamestate::UiRequest::Timeout(msg, delay) => {
+                let timer = self.timer(delay);
+                let timer_and_msg = match msg {
+                    Some(msg) => {
+                        let sendfut = self
+                            .sink
+                            .send_plain(Chat::Game, msg)
+                            .compat()
+                            .map_err(|err| {
+                                let msg =
+                                    format!("send msg after timeout failed {:?}", err);
+                                err_msg(msg)
+                            })
+                            .map(|_| ());
+                        let res: Box<dyn Future<Item = (), Error = Error> + Send> =
+                            Box::new(timer.and_then(|_| sendfut));
+                        res
+                    }
+                    None => {
+                        timer
+                    }
+                };
+
+                self.send_timer(TimerId::Question, Some(timer_and_msg)).await;
+            }
+            gamestate::UiRequest::ScheduleTimer(id, duration) => {
+                let timer = self.timer(duration);
+                self.send_timer(id, Some(timer)).await;
+            }
+            gamestate::UiRequest::ChooseTopic(current_player, topics) => {
+                if self.use_separate_keyboards {
+                    let (text, format, selective) = if let Some(username) = current_player.username() {
+                        (format!("@{}, выберите тему", username), TextFormat::Plain, true)
+                    } else {
+                        (
+                            format!("{}, выберите тему", player_mention(&current_player)),
+                            TextFormat::Html,
+                            false,
+                        )
+                    };
+                    let keyboard = topics_keyboard(topics, selective);
+                    let msg_id = self.sink.send_text(Chat::Game, text, format, Some(keyboard)).await?;
+                    if msg_id.is_some() {
+                        self.choose_topic_message_id = msg_id;
+                    }
+                } else {
+                    self.sink
+                        .send_text(
+                            Chat::Game,
+                            format!("{}, выберите тему", player_mention(&current_player)),
+                            TextFormat::Html,
+                            Some(topics_inline_keyboard(topics)),
+                        )
+                        .await?;
+                }
+            }
+            gamestate::UiRequest::ChooseQuestion(topic_idx, topic, costs, username) => {
+                if self.use_separate_keyboards {
+                    let (text, selective) = if let Some(username) = username {
+                        (format!("@{}, выбрана тема '{}', выберите цену", username, topic), true)
+                    } else {
+                        (format!("Выбрана тема '{}', выберите цену", topic), false)
+                    };
+
+                    let keyboard = questioncosts_keyboard(costs, selective);
+                    let msg_id = self
+                        .sink
+                        .send_text(Chat::Game, text, TextFormat::Plain, Some(keyboard))
+                        .await?;
+                    if msg_id.is_some() {
+                        self.choose_question_message_id = msg_id;
+                    }
+                } else {
+                    self.sink
+                        .send_text(
+                            Chat::Game,
+                            format!("Выбрана тема '{}', выберите цену", topic),
+                            TextFormat::Plain,
+                            Some(questioncosts_inline_keyboard(topic_idx, costs)),
+                        )
+                        .await?;
+                }
+            }
+            gamestate::UiRequest::AskAdminYesNo(question) => {
+                self.sink
+                    .send_text(
+                        Chat::Admin,
+                        question,
+                        TextFormat::Plain,
+                        Some(answer_yes_no_inline_keyboard()),
+                    )
+                    .await?;
+            }
+            gamestate::UiRequest::AskAdminConfirmStart(question) => {
+                self.sink
+                    .send_text(
+                        Chat::Admin,
+                        question,
+                        TextFormat::Plain,
+                        Some(start_yes_no_inline_keyboard()),
+                    )
+                    .await?;
+            }
+            gamestate::UiRequest::SendToAdmin(msg) => {
+                self.sink.send_plain(Chat::Admin, msg).await?;
+            }
+            gamestate::UiRequest::StopTimer => {
+                self.send_timer(TimerId::Question, None).await;
+            },
+            gamestate::UiRequest::GameFinished(scores) => {
+                if let Some(ref mut results_db) = self.results_db {
+                    if let Err(err) = results_db.record_game(&scores) {
+                        eprintln!("failed to save game results: {}", err);
+                        self.sink
+                            .send_plain(Chat::Admin, format!("Failed to save game results: {}", err))
+                            .await?;
+                    }
+                }
+            }
+            gamestate::UiRequest::ClearCache => {
+                let msg = match self.cache {
+                    Some(ref cache) => match cache.clear() {
+                        Ok(removed) => format!("Removed {} cached files", removed),
+                        Err(err) => format!("Failed to clear the cache: {}", err),
+                    },
+                    None => "No cache is used".to_string(),
+                };
+                eprintln!("{}", msg);
+                self.sink.send_plain(Chat::Admin, msg).await?;
+            }
+            gamestate::UiRequest::SendScoreTable(score_table) => {
+                let score_table_str = score_table.to_string();
+                let send_text = match self.scoreboard_mode {
+                    ScoreboardMode::Off => false,
+                    ScoreboardMode::Text => true,
+                    ScoreboardMode::Image | ScoreboardMode::Native => {
+                        match self.sink.send_score_table(Chat::Game, score_table).await {
+                            Ok(()) => false,
+                            Err(errmsg) => {
+                                eprintln!("Couldn't send score table image: '{:?}'", errmsg);
+                                true
+                            }
+                        }
+                    }
+                };
+                if send_text {
+                    let res = self
+                        .sink
+                        .send_text(
+                            Chat::Game,
+                            markdown_code_block(&score_table_str),
+                            TextFormat::Markdown,
+                            None,
+                        )
+                        .await;
+                    // Telegram may still fail to parse it, the table is sent as is then
+                    if let Err(err) = res {
+                        eprintln!("Couldn't send score table as markdown: '{:?}'", err);
+                        self.sink.send_plain(Chat::Game, score_table_str).await?;
+                    }
+                }
+            }
+            gamestate::UiRequest::SendGameSummary(summary) => {
+                let summary_str = summary.to_string();
+                let send_text = match self.scoreboard_mode {
+                    ScoreboardMode::Off => false,
+                    ScoreboardMode::Text => true,
+                    ScoreboardMode::Image | ScoreboardMode::Native => {
+                        match self.sink.send_game_summary(Chat::Game, summary).await {
+                            Ok(()) => false,
+                            Err(errmsg) => {
+                                eprintln!("Couldn't send game summary image: '{:?}'", errmsg);
+                                true
+                            }
+                        }
+                    }
+                };
+                if send_text {
+                    self.sink.send_plain(Chat::Game, summary_str).await?;
+                }
+            }
+            gamestate::UiRequest::ExportScoreTable(score_table) => {
+                let text = match dump_score_table_html(&score_table, SCORE_TABLE_HTML_FILE) {
+                    Ok(()) => format!("Score board is exported to {}", SCORE_TABLE_HTML_FILE),
+                    Err(err) => format!("Failed to export score board: {}", err),
+                };
+                self.sink.send_plain(Chat::Admin, text).await?;
+            }
+            gamestate::UiRequest::ExportTranscript(transcript) => {
+                let text = match std::fs::write(TRANSCRIPT_FILE, transcript + "\n") {
+                    Ok(()) => format!("Transcript is saved to {}", TRANSCRIPT_FILE),
+                    Err(err) => format!("Failed to save transcript: {}", err),
+                };
+                self.sink.send_plain(Chat::Admin, text).await?;
+            }
+            gamestate::UiRequest::CatInBagChoosePlayer(players) => {
+                self.sink
+                    .send_text(
+                        Chat::Game,
+                        "Кто играет?".to_string(),
+                        TextFormat::Plain,
+                        Some(cat_in_bag_player_inline_keyboard(players)),
+                    )
+                    .await?;
+            }
+            gamestate::UiRequest::CatInBagChooseCost(costs) => {
+                self.sink
+                    .send_text(
+                        Chat::Game,
+                        "Выберите ставку".to_string(),
+                        TextFormat::Plain,
+                        Some(cat_in_bag_cost_inline_keyboard(costs)),
+                    )
+                    .await?;
+            }
+            gamestate::UiRequest::StartCountdown(text) => {
+                self.countdown_message_id = self.sink.send_plain(Chat::Game, text).await?;
+            }
+            gamestate::UiRequest::UpdateCountdown(text) => {
+                if let Some(message) = self.countdown_message_id.clone() {
+                    if let Err(err) = self.sink.edit_text(Chat::Game, message, text).await {
+                        eprintln!("failed to update countdown: {}", err);
+                    }
+                }
+            }
+            gamestate::UiRequest::FinishCountdown(text) => {
+                if let Some(message) = self.countdown_message_id.take() {
+                    if let Err(err) = self.sink.edit_text(Chat::Game, message, text).await {
+                        eprintln!("failed to finish countdown: {}", err);
+                    }
+                }
+            }
+            gamestate::UiRequest::ChooseScoringPlayer(players) => {
+                self.sink
+                    .send_text(
+                        Chat::Admin,
+                        "Who answered correctly?".to_string(),
+                        TextFormat::Plain,
+                        Some(scoring_player_inline_keyboard(players)),
+                    )
+                    .await?;
+            }
         }
+        Ok(())
     }
+}
 
-    if data == "/question" || data == "/next" {
-        return TextMessage::NextQuestion;
+// Input of the game that doesn't come from telegram
+enum ScriptedEvent {
+    Text(UserId, String),
+    Callback(UserId, String),
+    Timer(TimerId),
+}
+
+// Line formats:
+//   <user id> <message>
+//   <user id> callback <callback data>
+//   timer
+//   gameclock
+//   auction
+//   countdown
+//   heartbeat
+//   reveal
+fn parse_scripted_event(line: &str) -> Option<ScriptedEvent> {
+    let line = line.trim();
+    match line {
+        "timer" => return Some(ScriptedEvent::Timer(TimerId::Question)),
+        "gameclock" => return Some(ScriptedEvent::Timer(TimerId::GameClock)),
+        "auction" => return Some(ScriptedEvent::Timer(TimerId::Auction)),
+        "registration" => return Some(ScriptedEvent::Timer(TimerId::Registration)),
+        "countdown" => return Some(ScriptedEvent::Timer(TimerId::Countdown)),
+        "heartbeat" => return Some(ScriptedEvent::Timer(TimerId::Heartbeat)),
+        "reveal" => return Some(ScriptedEvent::Timer(TimerId::Reveal)),
+        "blitz" => return Some(ScriptedEvent::Timer(TimerId::Blitz)),
+        _ => {}
     }
 
-    if data == "/score" {
-        return TextMessage::GetScore;
+    let split: Vec<_> = line.splitn(2, ' ').collect();
+    if split.len() != 2 {
+        return None;
+    }
+    let user = UserId::from(split[0].parse::<i64>().ok()?);
+    match split[1].strip_prefix("callback ") {
+        Some(data) => Some(ScriptedEvent::Callback(user, data.to_string())),
+        None => Some(ScriptedEvent::Text(user, split[1].to_string())),
     }
+}
+
+// Turns user input and timers into calls to the game state
+struct GameDriver {
+    gamestate: gamestate::GameState,
+    question_storage: Box<dyn QuestionsStorage>,
+    command_prefix: String,
+    admin_user: UserId,
+    audit_log: Option<audit_log::AuditLog>,
+    state_snapshot: Option<state_server::StateSnapshot>,
+}
 
-    if data == "/currentplayer" {
-        return TextMessage::CurrentPlayer;
+impl GameDriver {
+    fn publish_state(&self) {
+        if let Some(ref state_snapshot) = self.state_snapshot {
+            state_snapshot.update(&self.gamestate.public_state());
+        }
     }
 
-    if data.starts_with("/changeplayer") {
-        let split: Vec<_> = data.splitn(2, ' ').collect();
-        if split.len() == 2 {
-            return TextMessage::ChangePlayer((*split.get(1).expect("should not happen")).to_string());
+    fn audit(&mut self, user: UserId, action: &dyn std::fmt::Debug) {
+        if user != self.admin_user {
+            return;
+        }
+        if let Some(audit_log) = self.audit_log.as_mut() {
+            if let Err(err) = audit_log.record(user, &format!("{:?}", action)) {
+                eprintln!("failed to write audit log: {}", err);
+            }
         }
     }
 
-    if data.starts_with("/auction") {
-        let split: Vec<_> = data.splitn(3, ' ').collect();
-        if split.len() == 3 {
-            if let Ok(cost) = split[1].parse() {
-                return TextMessage::UpdateAuctionCost(split[2].to_string(), cost);
+    fn handle_text_message(
+        &mut self,
+        results_db: Option<&results_db::ResultsDb>,
+        user: UserId,
+        username: Option<String>,
+        text_message: TextMessage,
+    ) -> Vec<gamestate::UiRequest> {
+        if text_message.is_admin_action() {
+            self.audit(user, &text_message);
+        }
+        match text_message {
+            TextMessage::Join(name) => {
+                self.gamestate.add_player(user, name, username)
+            }
+            TextMessage::JustMessage(text_msg) => {
+                self.gamestate.message(user, text_msg)
+            }
+            TextMessage::ReplyToQuestion(text_msg) => {
+                self.gamestate.reply_to_question(user, text_msg)
+            }
+            TextMessage::NextQuestion => {
+                if self.gamestate.is_practice() {
+                    self.gamestate.next_practice_question(user, self.question_storage.as_ref())
+                } else if self.gamestate.is_blitz_tour() {
+                    self.gamestate.next_blitz_question(user, self.question_storage.as_ref())
+                } else {
+                    self.gamestate.next_question(user)
+                }
+            }
+            TextMessage::StartGame => self.gamestate.request_start(user),
+            TextMessage::Practice => self.gamestate.start_practice(user, self.question_storage.as_ref()),
+            TextMessage::GetScore => self.gamestate.get_score(user),
+            TextMessage::CurrentPlayer => {
+                self.gamestate.current_player(user)
+            }
+            TextMessage::ChangePlayer(player) => {
+                self.gamestate.change_player(user, player)
+            }
+            TextMessage::NextChooser(player) => {
+                self.gamestate.next_chooser(user, player)
+            }
+            TextMessage::NextTour => self.gamestate.next_tour(user),
+            TextMessage::UpdateScore(name, newscore) => {
+                self.gamestate.update_score(name, newscore, user)
+            }
+            TextMessage::HideQuestion(topic, cost) => {
+                self.gamestate.hide_question(topic, cost, user)
+            }
+            TextMessage::UnhideQuestion(topic, cost, force) => {
+                self.gamestate.unhide_question(topic, cost, force, user)
+            }
+            TextMessage::UpdateAuctionCost(name, cost) => {
+                self.gamestate.update_auction_cost(user, name, cost)
+            }
+            TextMessage::ChooseTopic(topic) => {
+                if let Some(topic_id) = self.gamestate.get_topic_id(topic.clone()) {
+                    self.gamestate.select_topic(topic_id, user)
+                } else {
+                    eprintln!("unknown topic when choosing topic from keyboard {}", topic);
+                    vec![]
+                }
+            }
+            TextMessage::ChooseQuestion(cost) => {
+                self.gamestate.select_question(cost, user, &self.question_storage)
+            }
+            TextMessage::AuctionPass => self.gamestate.auction_pass(user),
+            TextMessage::Appeal => self.gamestate.appeal(user),
+            TextMessage::Regrade(name, correct) => self.gamestate.regrade(name, correct, user),
+            TextMessage::PauseAnswers => self.gamestate.pause_answers(user),
+            TextMessage::ResumeAnswers => self.gamestate.resume_answers(user),
+            TextMessage::ExportBoard => self.gamestate.export_board(user),
+            TextMessage::Transcript => self.gamestate.transcript(user),
+            TextMessage::Last(ago) => self.gamestate.last_question(user, ago),
+            TextMessage::Finish => self.gamestate.finish(user),
+            TextMessage::Board => self.gamestate.board(user),
+            TextMessage::BoardImage => self.gamestate.board_image(user),
+            TextMessage::Stats => self.gamestate.stats(user),
+            TextMessage::ClearPlayers => self.gamestate.clear_players(user),
+            TextMessage::ClearCache => self.gamestate.clear_cache(user),
+            TextMessage::Help => {
+                vec![gamestate::UiRequest::SendTextToMainChat(commands::help_text(&self.command_prefix))]
+            }
+            TextMessage::Leaderboard => {
+                let text = match results_db {
+                    Some(results_db) => match results_db.leaderboard() {
+                        Ok(entries) => results_db::format_leaderboard(&entries),
+                        Err(err) => {
+                            eprintln!("failed to get leaderboard: {}", err);
+                            "Не удалось получить таблицу лидеров".to_string()
+                        }
+                    },
+                    None => "Результаты игр не сохраняются".to_string(),
+                };
+                vec![gamestate::UiRequest::SendTextToMainChat(text)]
+            }
+            TextMessage::Rating => {
+                let text = match results_db {
+                    Some(results_db) => match results_db.ratings() {
+                        Ok(ratings) => results_db::format_ratings(&ratings),
+                        Err(err) => {
+                            eprintln!("failed to get ratings: {}", err);
+                            "Не удалось получить рейтинг".to_string()
+                        }
+                    },
+                    None => "Результаты игр не сохраняются".to_string(),
+                };
+                vec![gamestate::UiRequest::SendTextToMainChat(text)]
             }
         }
     }
 
-    if data.starts_with("/hidequestion") {
-        let data = data.trim_start_matches("/hidequestion ");
-        let split: Vec<_> = data.splitn(2, ' ').collect();
+    fn handle_callback(
+        &mut self,
+        user: UserId,
+        // Message with the buttons, if it's known
+        prompt: Option<MessageId>,
+        callback: CallbackMessage,
+    ) -> Vec<gamestate::UiRequest> {
+        if callback.is_admin_action() {
+            self.audit(user, &callback);
+        }
+        match callback {
+            CallbackMessage::SelectedTopic(topic_id) => {
+                let res = self.gamestate.select_topic(topic_id, user);
+                self.question_storage.prefetch(self.gamestate.selected_topic_questions());
+                res
+            }
+            CallbackMessage::SelectedQuestion(_topic_idx, cost) => {
+                self.gamestate.select_question(cost, user, &self.question_storage)
+            }
+            CallbackMessage::AnswerYes => self.gamestate.judge(user, prompt, true),
+            CallbackMessage::AnswerNo => self.gamestate.judge(user, prompt, false),
+            CallbackMessage::StartConfirmed => self.gamestate.start(user),
+            CallbackMessage::StartCancelled => self.gamestate.cancel_start(user),
+            CallbackMessage::CatInBagPlayerChosen(player) => {
+                self.gamestate.select_cat_in_bag_player(user, player)
+            }
+            CallbackMessage::CatInBagCostChosen(cost) => {
+                self.gamestate.select_cat_in_bag_cost(user, cost)
+            }
+            CallbackMessage::ScoringPlayerChosen(player) => {
+                self.gamestate.select_scoring_player(user, player)
+            }
+            CallbackMessage::ScoringDone => {
+                self.gamestate.finish_manual_scoring(user)
+            }
+            CallbackMessage::Unknown => vec![],
+        }
+    }
 
-        if split.len() == 2 {
-            let cost = split.get(0).unwrap();
-            let topic = split.get(1).unwrap();
-            let cost = cost.parse();
-            if let Ok(cost) = cost {
-                return TextMessage::HideQuestion(topic.to_string(), cost);
+    fn handle_scripted_event(
+        &mut self,
+        results_db: Option<&results_db::ResultsDb>,
+        event: ScriptedEvent,
+    ) -> Vec<gamestate::UiRequest> {
+        match event {
+            ScriptedEvent::Text(user, text) => {
+                let text_message = parse_command(&text, &self.command_prefix);
+                self.handle_text_message(results_db, user, None, text_message)
+            }
+            ScriptedEvent::Callback(user, data) => {
+                self.handle_callback(user, None, parse_callback(&Some(data)))
             }
+            ScriptedEvent::Timer(id) => self.handle_timer(id),
         }
     }
 
-    if data == "/nexttour" {
-        return TextMessage::NextTour;
+    // The game goes on without attachments which failed to download
+    fn attachment_errors(&self) -> Vec<gamestate::UiRequest> {
+        self.question_storage
+            .take_attachment_errors()
+            .into_iter()
+            .map(gamestate::UiRequest::SendToAdmin)
+            .collect()
     }
 
-    if data.starts_with("/updatescore ") {
-        let data = data.trim_start_matches("/updatescore ");
-        let split: Vec<_> = data.rsplitn(2, ' ').collect();
-        if split.len() == 2 {
-            let name = split.get(1).unwrap();
-            let newscore = split.get(0).unwrap();
-            let score = newscore.parse();
-            if let Ok(score) = score {
-                return TextMessage::UpdateScore((*name).into(), score);
-            }
+    fn handle_timer(&mut self, id: TimerId) -> Vec<gamestate::UiRequest> {
+        match id {
+            TimerId::Question => self.gamestate.timeout(),
+            TimerId::GameClock => self.gamestate.game_clock_expired(),
+            TimerId::Auction => self.gamestate.close_auction(),
+            TimerId::Registration => self.gamestate.registration_expired(),
+            TimerId::Countdown => self.gamestate.countdown_tick(),
+            TimerId::Heartbeat => self.gamestate.heartbeat(),
+            TimerId::Reveal => self.gamestate.reveal_answer(),
+            TimerId::Blitz => self.gamestate.blitz_timer(self.question_storage.as_ref()),
         }
     }
+}
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "svoyak_bot")]
+struct Opt {
+    /// Do not download questions from google drive.
+    #[structopt(long)]   
+    use_cached_questions: bool,
+
+    /// Experimental option to not use inline keyboards
+    #[structopt(long)]
+    use_separate_keyboards: bool,
 
-    if data == BEGIN_CMD {
-        return TextMessage::StartGame;
+    /// Play without telegram: read '<user id> <message>' or
+    /// '<user id> callback <data>' lines from stdin and print what the bot sends.
+    /// Timers don't fire by themselves, use 'timer', 'gameclock', 'auction',
+    /// 'registration', 'countdown', 'heartbeat', 'reveal' and 'blitz' lines instead.
+    #[structopt(long)]
+    local: bool,
+}
+
+fn open_cache(config: &telegram_config::Config, opt: &Opt) -> Result<cache::Cache, Error> {
+    let cache = cache::Cache::new(&config.cache_dir)?;
+    if opt.use_cached_questions {
+        eprintln!("using cached questions, cache is not cleaned");
+    } else if config.clean_cache_on_start {
+        eprintln!("removed {} cached files", cache.clear()?);
+    } else if let Some(days) = config.cache_max_age_days {
+        let removed = cache.remove_older_than(Duration::from_secs(days * 24 * 60 * 60))?;
+        eprintln!("removed {} cached files older than {} days", removed, days);
     }
+    Ok(cache)
+}
 
-    return TextMessage::JustMessage(data.clone());
+fn open_audit_log(config: &telegram_config::Config) -> Result<Option<audit_log::AuditLog>, Error> {
+    match config.audit_log_path {
+        Some(ref path) => Ok(Some(audit_log::AuditLog::open(path)?)),
+        None => Ok(None),
+    }
 }
 
-fn parse_callback(data: &Option<String>) -> CallbackMessage {
-    let data = match data {
-        Some(data) => data,
-        None => {
-            return CallbackMessage::Unknown;
+fn start_state_server(
+    config: &telegram_config::Config,
+    runtime: &mut Runtime,
+) -> Result<Option<state_server::StateSnapshot>, Error> {
+    let addr = match config.state_server_addr {
+        Some(ref addr) => addr
+            .parse()
+            .map_err(|err| err_msg(format!("invalid state server address '{}': {}", addr, err)))?,
+        None => return Ok(None),
+    };
+    let state_snapshot = state_server::StateSnapshot::default();
+    let server_snapshot = state_snapshot.clone();
+    runtime.spawn_std(async move {
+        if let Err(err) = state_server::serve(addr, server_snapshot).await {
+            eprintln!("state server failed: {}", err);
         }
+    });
+    Ok(Some(state_snapshot))
+}
+
+fn run_local(opt: Opt, runtime: &mut Runtime) -> Result<(), Error> {
+    let config = telegram_config::Config::new(env::var(CONFIG_VAR).ok(), String::new());
+    let cache = open_cache(&config, &opt)?;
+    let question_storage = runtime.block_on_std(
+        CsvQuestionsStorage::new(
+            config.questions_storage_path.clone(),
+            opt.use_cached_questions,
+            config.tts.clone(),
+            AttachmentSettings {
+                google_api_key: env::var(GOOGLE_API_KEY).ok(),
+                image_compression: config.image_compression.clone(),
+                direct_urls: config.direct_attachment_urls,
+                prefetch: config.prefetch_attachments,
+            },
+            config.custom_costs,
+            &cache,
+        )
+    )?;
+    let question_storage: Box<dyn QuestionsStorage> = Box::new(question_storage);
+    warn_about_cost_keyboard(config.questions_per_topic);
+    let gamestate = gamestate::GameState::new(
+        config.admin_user,
+        &question_storage,
+        config.questions_per_topic,
+        game_config(&config)?,
+    )?;
+    let mut driver = GameDriver {
+        gamestate,
+        question_storage,
+        command_prefix: config.command_prefix.clone(),
+        admin_user: config.admin_user,
+        audit_log: open_audit_log(&config)?,
+        state_snapshot: None,
+    };
+
+    // Timers are fired manually, scheduled ones are kept until the
+    // corresponding scripted line, e.g. delayed messages are printed then
+    let (timer_sender, timer_receiver) = mpsc::channel(1);
+    let mut timer_requests = timer_receiver.compat();
+    let mut pending_timers = HashMap::new();
+    let mut ui = UiContext {
+        sink: message_sink::ConsoleSink,
+        use_separate_keyboards: opt.use_separate_keyboards,
+        timer_sender,
+        manual_timers: true,
+        results_db: None,
+        cache: Some(cache),
+        scoreboard_mode: config.scoreboard_mode,
+        choose_topic_message_id: None,
+        choose_question_message_id: None,
+        countdown_message_id: None,
+        question_message_id: None,
     };
-    if data.starts_with("/question") {
-        let data = data.trim_start_matches("/question");
-        let split: Vec<_> = data.rsplitn(2, '_').collect();
-        if split.len() == 2 {
-            let cost = split.get(0).expect("should not happen");
-            let topic_idx = split.get(1).expect("should not happen");
-            let topic_idx = match topic_idx.parse::<usize>() {
-                Ok(topic_idx) => topic_idx,
-                Err(_) => {
-                    return CallbackMessage::Unknown;
+
+    println!("Admin user id is {}", config.admin_user);
+    runtime.block_on_std(async {
+        let stdin = std::io::stdin();
+        for line in stdin.lock().lines() {
+            let line = line?;
+            let event = match parse_scripted_event(&line) {
+                Some(event) => event,
+                None => {
+                    eprintln!("can't parse '{}'", line);
+                    continue;
                 }
             };
-            if let Ok(cost) = cost.parse::<usize>() {
-                return CallbackMessage::SelectedQuestion(TopicIdx(topic_idx), cost);
-            } else {
-                return CallbackMessage::Unknown;
+            if let ScriptedEvent::Timer(id) = event {
+                if let Some(timer) = pending_timers.remove(&id) {
+                    Future01CompatExt::compat(timer).await?;
+                }
+            }
+            let mut res = driver.handle_scripted_event(None, event);
+            res.extend(driver.attachment_errors());
+            for r in res {
+                ui.handle_ui_request(r).await?;
+            }
+            while let Some(Some(Ok((id, timer)))) = timer_requests.next().now_or_never() {
+                match timer {
+                    Some(timer) => pending_timers.insert(id, timer),
+                    None => pending_timers.remove(&id),
+                };
             }
-        } else {
-            return CallbackMessage::Unknown;
-        }
-    }
-
-    if data.starts_with("/topic") {
-        let data = data.trim_start_matches("/topic");
-        let maybe_topic_idx = data.parse::<usize>();
-        if let Ok(idx) = maybe_topic_idx {
-            return CallbackMessage::SelectedTopic(TopicIdx(idx));
-        } else {
-            return CallbackMessage::Unknown;
         }
-    }
-
-    if data == ANSWER_YES {
-        return CallbackMessage::AnswerYes;
-    }
+        Result::<_, Error>::Ok(())
+    })
+}
 
-    if data == ANSWER_NO {
-        return CallbackMessage::AnswerNo;
-    }
+fn game_config(config: &telegram_config::Config) -> Result<gamestate::GameConfig, Error> {
+    Ok(gamestate::GameConfig {
+        manual_question_answerers: config.manual_question_answerers,
+        auto_judge: config.auto_judge,
+        game_duration: config.game_duration_minutes.map(|minutes| Duration::from_secs(minutes * 60)),
+        min_players: config.min_players,
+        tour_recap: config.tour_recap,
+        auction_bid_timeout: Duration::from_secs(config.auction_bid_timeout_secs),
+        cat_in_bag_dm: config.cat_in_bag_dm,
+        highlight_current_player: config.highlight_current_player,
+        audio_answer_buffer: Duration::from_secs(config.audio_answer_buffer_secs),
+        stickers: stickers::Stickers::load(config.stickers_path.as_deref())?,
+        phrases: Phrases::load(config.phrases_path.as_deref())?,
+        registration_window: match config.registration_window_secs {
+            0 => None,
+            secs => Some(Duration::from_secs(secs)),
+        },
+        join_cooldown: Duration::from_secs(config.join_cooldown_secs),
+        turn_dm: config.turn_dm,
+        length_thresholds: config.length_thresholds,
+        command_prefix: config.command_prefix.clone(),
+        admin_can_play: config.admin_can_play,
+        max_players: config.max_players,
+        question_attribution: config.question_attribution,
+        answer_countdown: config.answer_countdown,
+        delays: config.delays.clone(),
+        intro: config.intro.clone(),
+        judgment_debounce: Duration::from_millis(config.judgment_debounce_ms),
+        last_questions: config.last_questions_count,
+        heartbeat: config.heartbeat_interval_secs.map(Duration::from_secs),
+        heartbeat_to_admin: config.heartbeat_to_admin,
+    })
+}
 
-    if data.starts_with("/cat_in_bag_choose_player_") {
-        let data = data.trim_start_matches("/cat_in_bag_choose_player_");
-        return CallbackMessage::CatInBagPlayerChosen(data.to_string());
-    }
+// Admin selects the game chat by sending /thischat to it. Until then the admin
+// is reminded about it periodically
+async fn wait_for_game_chat(api: &Api, config: &telegram_config::Config) -> Result<ChatId, Error> {
+    let started = Instant::now();
+    let timeout = match config.game_chat_timeout_secs {
+        0 => None,
+        secs => Some(Duration::from_secs(secs)),
+    };
+    let reminder_interval = Duration::from_secs(config.game_chat_reminder_secs.max(1));
+    let reminder = format!(
+        "Game chat is not selected, send {}thischat to the chat where the game will be played",
+        config.command_prefix
+    );
+    api.send(SendMessage::new(config.admin_chat, reminder.clone())).await?;
 
-    if data.starts_with("/cat_in_bag_choose_cost_") {
-        let data = data.trim_start_matches("/cat_in_bag_choose_cost_");
-        let maybe_cost = data.parse::<usize>();
-        match maybe_cost {
-            Ok(cost) => {
-                return CallbackMessage::CatInBagCostChosen(cost);
+    let mut updates = api.stream();
+    loop {
+        let mut next_reminder = Instant::now() + reminder_interval;
+        if let Some(timeout) = timeout {
+            next_reminder = next_reminder.min(started + timeout);
+        }
+        let timer = tokio_01::timer::Delay::new(next_reminder).compat();
+        match select(updates.try_next(), timer).await {
+            Either::Left((update, _)) => {
+                let update = match update? {
+                    Some(update) => update,
+                    None => return Err(err_msg("updates stream ended before the game chat was selected")),
+                };
+                if let UpdateKind::Message(message) = update.kind {
+                    if let MessageKind::Text { ref data, .. } = message.kind {
+                        if is_this_chat_command(data, &config.command_prefix) && message.from.id == config.admin_user {
+                            let chat = message.chat.id();
+                            eprintln!("game chat is selected: {}", chat);
+                            api.send(SendMessage::new(chat, "Игра будет проходить в этом чате")).await?;
+                            return Ok(chat);
+                        }
+                    }
+                }
             }
-            Err(_) => {
-                return CallbackMessage::Unknown;
+            Either::Right((timer, _)) => {
+                timer.map_err(|err| err_msg(format!("reminder timer failed: {}", err)))?;
+                if let Some(timeout) = timeout {
+                    if started.elapsed() >= timeout {
+                        return Err(err_msg(format!(
+                            "game chat wasn't selected in {} seconds, set game_chat_id in the config \
+                             or send {}thischat to the game chat next time",
+                            timeout.as_secs(),
+                            config.command_prefix
+                        )));
+                    }
+                }
+                eprintln!("still waiting to select a game chat");
+                api.send(SendMessage::new(config.admin_chat, reminder.clone())).await?;
             }
         }
     }
-
-    CallbackMessage::Unknown
 }
 
-#[derive(Debug, StructOpt)]
-#[structopt(name = "svoyak_bot")]
-struct Opt {
-    /// Do not download questions from google drive.
-    #[structopt(long)]   
-    use_cached_questions: bool,
-
-    /// Experimental option to not use inline keyboards
-    #[structopt(long)]
-    use_separate_keyboards: bool,
+// Fails if the bot can't write to the chat e.g. the id is wrong or the bot
+// was removed from the chat
+async fn probe_game_chat(api: &Api, game_chat: ChatId) -> Result<(), Error> {
+    api.send(SendMessage::new(game_chat, "Бот подключён к игровому чату"))
+        .await
+        .map_err(|err| {
+            err_msg(format!(
+                "can't send messages to the game chat {}: {}. Check game_chat_id in the config \
+                 and that the bot is a member of the chat",
+                game_chat, err
+            ))
+        })?;
+    eprintln!("game chat {} is available", game_chat);
+    Ok(())
 }
 
 fn main() -> Result<(), Error> {
@@ -460,16 +1474,29 @@ fn main() -> Result<(), Error> {
     let google_api_key = env::var(GOOGLE_API_KEY);
 
     let mut runtime = Runtime::new()?;
+    if opt.local {
+        return run_local(opt, &mut runtime);
+    }
+
     let token = env::var(TOKEN_VAR).unwrap();
     let config = telegram_config::Config::new(env::var(CONFIG_VAR).ok(), token);
     let api = Api::new(&config.token);
 
+    let cache = open_cache(&config, &opt)?;
     eprintln!("loading questions");
     let question_storage = runtime.block_on_std(
         CsvQuestionsStorage::new(
             config.questions_storage_path.clone(),
-            google_api_key.ok().map(|x| x.to_string()),
             opt.use_cached_questions,
+            config.tts.clone(),
+            AttachmentSettings {
+                google_api_key: google_api_key.ok().map(|x| x.to_string()),
+                image_compression: config.image_compression.clone(),
+                direct_urls: config.direct_attachment_urls,
+                prefetch: config.prefetch_attachments,
+            },
+            config.custom_costs,
+            &cache,
         )
     )?;
     let question_storage: Box<dyn QuestionsStorage> = Box::new(question_storage);
@@ -478,55 +1505,97 @@ fn main() -> Result<(), Error> {
 
     let game_chat = match config.game_chat {
         Some(game_chat) => {
+            if config.probe_game_chat {
+                runtime.block_on_std(probe_game_chat(&api, game_chat))?;
+            }
             game_chat
         }
         None => {
             eprintln!("waiting to select a game chat");
-            let mut s = api.stream();
-            runtime.block_on_std(
-                async {
-                    while let Some(telegram_update) = s.try_next().await? {
-                        if let UpdateKind::Message(message) = telegram_update.kind {
-                            if let MessageKind::Text { ref data, .. } = message.kind {
-                                if data == "/thischat" && message.from.id == config.admin_user {
-                                    return Ok(message.chat.id());
-                                }
-                            }
-                        }
-                    }
-                    Err(err_msg("unexpected exit"))
-                }
-            )?
+            runtime.block_on_std(wait_for_game_chat(&api, &config))?
         }
     };
 
-    runtime.block_on_std(
-        async {
-            let msg = SendMessage::new(game_chat, "Для регистрации в игре введите '/join ИМЯ' без кавычек".to_string());
-            api.send(msg).await?;
-            Result::<_, Error>::Ok(())
-        }
-    )?;
+    let drawer = ScoreTableDrawer::new(&config);
+    let drawer_problem = if config.scoreboard_mode == ScoreboardMode::Image {
+        drawer.probe().err()
+    } else {
+        None
+    };
+    let sink = message_sink::TelegramSink::new(
+        api.clone(),
+        config.token.clone(),
+        game_chat,
+        config.admin_chat,
+        drawer,
+        FileIdCache::default(),
+    );
+    // Drawing isn't even attempted if the script can't run
+    let scoreboard_mode = match drawer_problem {
+        Some(_) => ScoreboardMode::Text,
+        None => config.scoreboard_mode,
+    };
+    if let Some(err) = drawer_problem {
+        eprintln!("score table image is not available: {}", err);
+        runtime.block_on_std(sink.send_plain(
+            Chat::Admin,
+            format!("Score table will be sent as text, image can't be drawn: {}", err),
+        ))?;
+    }
 
     // Fetch new updates via long poll method
-    let (sender, receiver) = mpsc::channel::<Option<Box<dyn Future<Item = (), Error = Error>>>>(1);
+    let (sender, receiver) = mpsc::channel::<timeout_stream::TimerRequest>(1);
 
     let timeout_stream = timeout_stream::TimeoutStream::new(receiver);
     let updates_stream = api.stream();
     let requests_stream = merge_updates_and_timeouts(updates_stream, timeout_stream);
 
     eprintln!("Game is ready to start!");
-    let mut gamestate = gamestate::GameState::new(
+    warn_about_cost_keyboard(config.questions_per_topic);
+    let gamestate = gamestate::GameState::new(
         config.admin_user,
         &question_storage,
         config.questions_per_topic,
+        game_config(&config)?,
     )?;
     eprintln!("created gamestate");
+    let mut driver = GameDriver {
+        gamestate,
+        question_storage,
+        command_prefix: config.command_prefix.clone(),
+        admin_user: config.admin_user,
+        audit_log: open_audit_log(&config)?,
+        state_snapshot: start_state_server(&config, &mut runtime)?,
+    };
+
+    let results_db = match config.results_db_path {
+        Some(ref path) => Some(results_db::ResultsDb::open(path)?),
+        None => None,
+    };
+
+    let mut ui = UiContext {
+        sink,
+        use_separate_keyboards: opt.use_separate_keyboards,
+        timer_sender: sender,
+        manual_timers: false,
+        results_db,
+        cache: Some(cache),
+        scoreboard_mode,
+        choose_topic_message_id: None,
+        choose_question_message_id: None,
+        countdown_message_id: None,
+        question_message_id: None,
+    };
 
     let fut = async move {
+        let mut requests = driver.gamestate.registration_prompt();
+        requests.extend(driver.gamestate.start_heartbeat());
+        for r in requests {
+            ignore_recoverable(ui.handle_ui_request(r).await)?;
+        }
+        driver.publish_state();
+
         let mut s = requests_stream.compat();
-        let mut choose_topic_message_id: Option<MessageId> = None;
-        let mut choose_question_message_id: Option<MessageId> = None;
 
         while let Some(request) = s.next().await {
             let request = match request {
@@ -536,52 +1605,29 @@ fn main() -> Result<(), Error> {
                     continue;
                 }
             };
-            let res = match request {
+            let mut res = match request {
                 Ok(telegram_update) => {
                     match telegram_update.kind {
                         UpdateKind::Message(message) => {
                             println!("message chat id {}", message.chat.id());
+                            if let MessageChat::Private(_) = message.chat {
+                                driver.gamestate.private_chat_started(message.from.id);
+                            }
                             if let MessageKind::Text { ref data, .. } = message.kind {
-                                match parse_text_message(&message, data, choose_topic_message_id, choose_question_message_id) {
-                                    TextMessage::Join(name) => {
-                                        gamestate.add_player(message.from.id, name, message.from.username)
-                                    }
-                                    TextMessage::JustMessage(text_msg) => {
-                                        gamestate.message(message.from.id, text_msg)
-                                    }
-                                    TextMessage::NextQuestion => {
-                                        gamestate.next_question(message.from.id)
-                                    }
-                                    TextMessage::StartGame => gamestate.start(message.from.id),
-                                    TextMessage::GetScore => gamestate.get_score(message.from.id),
-                                    TextMessage::CurrentPlayer => {
-                                        gamestate.current_player(message.from.id)
-                                    }
-                                    TextMessage::ChangePlayer(player) => {
-                                        gamestate.change_player(message.from.id, player)
-                                    }
-                                    TextMessage::NextTour => gamestate.next_tour(message.from.id),
-                                    TextMessage::UpdateScore(name, newscore) => {
-                                        gamestate.update_score(name, newscore, message.from.id)
-                                    }
-                                    TextMessage::HideQuestion(topic, cost) => {
-                                        gamestate.hide_question(topic, cost, message.from.id)
-                                    }
-                                    TextMessage::UpdateAuctionCost(user, cost) => {
-                                        gamestate.update_auction_cost(message.from.id, user, cost)
-                                    }
-                                    TextMessage::ChooseTopic(topic) => {
-                                        if let Some(topic_id) = gamestate.get_topic_id(topic.clone()) {
-                                            gamestate.select_topic(topic_id, message.from.id)
-                                        } else {
-                                            eprintln!("unknown topic when choosing topic from keyboard {}", topic);
-                                            vec![]
-                                        }
-                                    }
-                                    TextMessage::ChooseQuestion(cost) => {
-                                        gamestate.select_question(cost, message.from.id, &question_storage)
-                                    }
-                                }
+                                let text_message = parse_text_message(
+                                    &message,
+                                    data,
+                                    &config.command_prefix,
+                                    ui.choose_topic_message_id,
+                                    ui.choose_question_message_id,
+                                    ui.question_message_id,
+                                );
+                                driver.handle_text_message(
+                                    ui.results_db.as_ref(),
+                                    message.from.id,
+                                    message.from.username.clone(),
+                                    text_message,
+                                )
                             } else if let  MessageKind::Sticker { ref data } = message.kind {
                                 eprintln!("sticker: {}", data.file_id);
                                 vec![]
@@ -591,210 +1637,39 @@ fn main() -> Result<(), Error> {
                         }
                         // TODO(stash): better matching
                         UpdateKind::CallbackQuery(callback) => {
-                            let data = callback.data;
-                            match parse_callback(&data) {
-                                CallbackMessage::SelectedTopic(topic_id) => {
-                                    gamestate.select_topic(topic_id, callback.from.id)
-                                }
-                                CallbackMessage::SelectedQuestion(_topic_idx, cost) => {
-                                    gamestate.select_question(cost, callback.from.id, &question_storage)
-                                }
-                                CallbackMessage::AnswerYes => gamestate.yes_reply(callback.from.id),
-                                CallbackMessage::AnswerNo => gamestate.no_reply(callback.from.id),
-                                CallbackMessage::CatInBagPlayerChosen(player) => {
-                                    gamestate.select_cat_in_bag_player(callback.from.id, player)
-                                }
-                                CallbackMessage::CatInBagCostChosen(cost) => {
-                                    gamestate.select_cat_in_bag_cost(callback.from.id, cost)
+                            if let Err(err) = ui.sink.answer_callback(callback.id.clone()).await {
+                                eprintln!("failed to answer callback: {}", err);
+                            }
+                            let callback_message = parse_callback(&callback.data);
+                            // Yes/no buttons are pressed once, they are not needed anymore
+                            if let CallbackMessage::AnswerYes
+                            | CallbackMessage::AnswerNo
+                            | CallbackMessage::StartConfirmed
+                            | CallbackMessage::StartCancelled = callback_message
+                            {
+                                if let Some(MessageOrChannelPost::Message(ref msg)) = callback.message {
+                                    if let Err(err) = ui.sink.edit_keyboard(Chat::Admin, msg.id, None).await {
+                                        eprintln!("failed to remove yes/no keyboard: {}", err);
+                                    }
                                 }
-                                CallbackMessage::Unknown => vec![],
                             }
+                            let prompt = match callback.message {
+                                Some(MessageOrChannelPost::Message(ref msg)) => Some(msg.id),
+                                _ => None,
+                            };
+                            driver.handle_callback(callback.from.id, prompt, callback_message)
                         }
                         _ => vec![],
                     }
                 }
-                Err(_timeout) => gamestate.timeout(),
+                Err(timer_id) => driver.handle_timer(timer_id),
             };
+            res.extend(driver.attachment_errors());
 
             for r in res {
-                match r {
-                    gamestate::UiRequest::SendTextToMainChat(msg) => {
-                        if !msg.is_empty() {
-                            let msg = SendMessage::new(game_chat, msg);
-                            api.send(msg).await?;
-                        }
-                    }
-                    gamestate::UiRequest::RightBeforeAskingQuestion(msg) => {
-                        if !msg.is_empty() {
-                            let mut msg = SendMessage::new(game_chat, msg);
-                            msg.reply_markup(ReplyKeyboardRemove::new());
-                            api.send(msg).await?;
-                        }
-                    }
-                    gamestate::UiRequest::SendSticker(sticker) => {
-                        let r = send_sticker_via_curl(game_chat, &config.token, &sticker);
-                        if let Err(e) = r {
-                            eprintln!("was not able to send sticker {}!", e);
-                        }
-                    }
-                    gamestate::UiRequest::SendImage(image) => {
-                        let r = send_photo_via_curl(game_chat, &config.token, &image.to_string_lossy());
-                        if let Err(e) = r {
-                            eprintln!("was not able to send image {}!", e);
-                        }
-                    }
-                    gamestate::UiRequest::SendAudio(audio) => {
-                        let r = send_audio_via_curl(game_chat, &config.token, &audio.to_string_lossy());
-                        if let Err(e) = r {
-                            eprintln!("was not able to send audio {}!", e);
-                        }
-                    }
-                    gamestate::UiRequest::Timeout(msg, delay) => {
-                        let duration = match delay {
-                            gamestate::Delay::Short => Duration::new(3, 0),
-                            gamestate::Delay::Medium => Duration::new(5, 0),
-                            gamestate::Delay::Long => Duration::new(10, 0),
-                            gamestate::Delay::ExtraLong => Duration::new(15, 0),
-                        };
-
-                        let when = Instant::now() + duration;
-                        let timer = tokio_01::timer::Delay::new(when);
-                        let timer = timer.map_err(|_err| err_msg("timer error happened"));
-                        let timer_and_msg = match msg {
-                            Some(msg) => {
-                                let msg = SendMessage::new(game_chat, msg);
-                                let sendfut = api
-                                    .send(msg)
-                                    .boxed()
-                                    .compat()
-                                    .map_err(|err| {
-                                        let msg =
-                                            format!("send msg after timeout failed {:?}", err);
-                                        err_msg(msg)
-                                    })
-                                    .map(|_| ());
-                                let res: Box<dyn Future<Item = (), Error = Error> + Send> =
-                                    Box::new(timer.and_then(|_| sendfut));
-                                res
-                            }
-                            None => {
-                                let res: Box<dyn Future<Item = (), Error = Error> + Send> =
-                                    Box::new(timer);
-                                res
-                            }
-                        };
-
-                        // TODO(stash): handle?
-                        let _ = sender.clone().send(Some(timer_and_msg)).compat().map_err(|_|()).await;
-                    }
-                    gamestate::UiRequest::ChooseTopic(current_player_name, topics, username) => {
-                        if opt.use_separate_keyboards {
-                            let (mut msg, selective) = if let Some(username) = username {
-                                (SendMessage::new(
-                                    game_chat,
-                                    format!("@{}, выберите тему", username),
-                                ), true)
-                            } else {
-                                (SendMessage::new(
-                                    game_chat,
-                                    format!("{}, выберите тему", current_player_name),
-                                ), false)
-                            };
-                            let keyboard = topics_keyboard(topics, selective);
-                            msg.reply_markup(keyboard);
-                            let r = api.send(msg).await?;
-                            if let MessageOrChannelPost::Message(msg) = r {
-                                choose_topic_message_id = Some(msg.id);
-                            }
-                        } else {
-                            let mut msg = SendMessage::new(
-                                game_chat,
-                                format!("{}, выберите тему", current_player_name),
-                            );
-                            let inline_keyboard = topics_inline_keyboard(topics);
-                            msg.reply_markup(inline_keyboard);
-                            api.send(msg).await?;
-                        }
-                    }
-                    gamestate::UiRequest::ChooseQuestion(topic_idx, topic, costs, username) => {
-                        if opt.use_separate_keyboards {
-                            let (mut msg, selective) = if let Some(username) = username {
-                                (SendMessage::new(
-                                    game_chat,
-                                    format!("@{}, выбрана тема '{}', выберите цену", username, topic)
-                                ), true)
-                            } else {
-                                (SendMessage::new(
-                                    game_chat,
-                                    format!("Выбрана тема '{}', выберите цену", topic),
-                                ), false)
-                            };
-
-                            let inline_keyboard = questioncosts_keyboard(costs, selective);
-                            msg.reply_markup(inline_keyboard);
-                            let r = api.send(msg).await?;
-                            if let MessageOrChannelPost::Message(msg) = r {
-                                choose_question_message_id = Some(msg.id);
-                            }
-                        } else {
-                            let mut msg = SendMessage::new(
-                                game_chat,
-                                format!("Выбрана тема '{}', выберите цену", topic),
-                            );
-                            let inline_keyboard = questioncosts_inline_keyboard(topic_idx, costs);
-                            msg.reply_markup(inline_keyboard);
-                            api.send(msg).await?;
-                        }
-                    }
-                    gamestate::UiRequest::AskAdminYesNo(question) => {
-                        let inline_keyboard = reply_markup!(inline_keyboard,
-                            ["Yes" callback ANSWER_YES, "No" callback ANSWER_NO]
-                        );
-                        let mut msg = SendMessage::new(config.admin_chat, question);
-                        msg.reply_markup(inline_keyboard);
-                        api.send(msg).await?;
-                    }
-                    gamestate::UiRequest::SendToAdmin(msg) => {
-                        let msg = SendMessage::new(config.admin_chat, msg);
-                        api.send(msg).await?;
-                    }
-                    gamestate::UiRequest::StopTimer => {
-                        // TODO(stash): handle?
-                        let _ = sender.clone().send(None).compat().map_err(|_| ()).await;
-                    },
-                    gamestate::UiRequest::SendScoreTable(score_table) => {
-                        let score_table_str = score_table.to_string();
-                        let res = match send_score_table(score_table, game_chat, config.token.clone())
-                        {
-                            Ok(_) => (),
-                            Err(errmsg) => {
-                                eprintln!("Couldn't send score table image: '{:?}'", errmsg);
-
-                                let mut msg = SendMessage::new(
-                                    game_chat,
-                                    String::from("```\n") + &score_table_str + "```",
-                                );
-                                msg.parse_mode(telegram_bot::ParseMode::Markdown);
-                                api.send(msg).await?;
-                            }
-                        };
-
-                        res
-                    }
-                    gamestate::UiRequest::CatInBagChoosePlayer(players) => {
-                        let inline_keyboard = cat_in_bag_player_inline_keyboard(players);
-                        let mut msg = SendMessage::new(game_chat, "Кто играет?".to_string());
-                        msg.reply_markup(inline_keyboard);
-                        api.send(msg).await?;
-                    }
-                    gamestate::UiRequest::CatInBagChooseCost(costs) => {
-                        let inline_keyboard = cat_in_bag_cost_inline_keyboard(costs);
-                        let mut msg = SendMessage::new(game_chat, "Выберите ставку".to_string());
-                        msg.reply_markup(inline_keyboard);
-                        api.send(msg).await?;
-                    }
-                }
+                ignore_recoverable(ui.handle_ui_request(r).await)?;
             }
+            driver.publish_state();
         }
         Result::<_, Error>::Ok(())
     };
@@ -803,3 +1678,343 @@ fn main() -> Result<(), Error> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use futures_03::future::{BoxFuture, FutureExt};
+
+    use crate::question::Question;
+    use crate::questionsstorage::{CatInBag, Topic, TourDescription};
+
+    #[derive(Debug, PartialEq)]
+    enum Sent {
+        Text(Chat, String),
+        Photo(Chat, Media),
+        MediaGroup(Chat, Vec<Media>),
+        Audio(Chat, Media),
+        Animation(Chat, Media),
+        Document(Chat, Media),
+        Sticker(Chat),
+        ScoreTable(Chat),
+        GameSummary(Chat),
+        KeyboardEdit(Chat, usize),
+        TextEdit(Chat, String),
+    }
+
+    // Test double which records everything that is sent. Messages are
+    // recorded only when futures are polled, the same way they are sent by
+    // telegram
+    #[derive(Clone, Default)]
+    struct FakeSink {
+        sent: Arc<Mutex<Vec<Sent>>>,
+    }
+
+    impl FakeSink {
+        fn record(&self, sent: Sent) -> BoxFuture<'static, Result<(), Error>> {
+            let all_sent = self.sent.clone();
+            async move {
+                all_sent.lock().unwrap().push(sent);
+                Ok(())
+            }
+            .boxed()
+        }
+    }
+
+    impl MessageSink for FakeSink {
+        // Index of the message in the list of sent messages
+        type MessageId = usize;
+        type CallbackId = ();
+
+        fn send_text(
+            &self,
+            chat: Chat,
+            text: String,
+            _format: TextFormat,
+            _keyboard: Option<Keyboard>,
+        ) -> BoxFuture<'static, Result<Option<usize>, Error>> {
+            let all_sent = self.sent.clone();
+            async move {
+                let mut all_sent = all_sent.lock().unwrap();
+                all_sent.push(Sent::Text(chat, text));
+                Ok(Some(all_sent.len() - 1))
+            }
+            .boxed()
+        }
+
+        fn send_photo(&self, chat: Chat, photo: Media) -> BoxFuture<'static, Result<(), Error>> {
+            self.record(Sent::Photo(chat, photo))
+        }
+
+        fn send_media_group(&self, chat: Chat, photos: Vec<Media>) -> BoxFuture<'static, Result<(), Error>> {
+            self.record(Sent::MediaGroup(chat, photos))
+        }
+
+        fn send_audio(&self, chat: Chat, audio: Media) -> BoxFuture<'static, Result<(), Error>> {
+            self.record(Sent::Audio(chat, audio))
+        }
+
+        fn send_animation(&self, chat: Chat, animation: Media) -> BoxFuture<'static, Result<(), Error>> {
+            self.record(Sent::Animation(chat, animation))
+        }
+
+        fn send_document(&self, chat: Chat, document: Media) -> BoxFuture<'static, Result<(), Error>> {
+            self.record(Sent::Document(chat, document))
+        }
+
+        fn send_sticker(&self, chat: Chat, _file_id: String) -> BoxFuture<'static, Result<(), Error>> {
+            self.record(Sent::Sticker(chat))
+        }
+
+        fn send_score_table(&self, chat: Chat, _table: gamestate::ScoreTable) -> BoxFuture<'static, Result<(), Error>> {
+            self.record(Sent::ScoreTable(chat))
+        }
+
+        fn send_game_summary(&self, chat: Chat, _summary: gamestate::GameSummary) -> BoxFuture<'static, Result<(), Error>> {
+            self.record(Sent::GameSummary(chat))
+        }
+
+        fn edit_keyboard(
+            &self,
+            chat: Chat,
+            message: usize,
+            _keyboard: Option<Keyboard>,
+        ) -> BoxFuture<'static, Result<(), Error>> {
+            self.record(Sent::KeyboardEdit(chat, message))
+        }
+
+        fn edit_text(&self, chat: Chat, _message: usize, text: String) -> BoxFuture<'static, Result<(), Error>> {
+            self.record(Sent::TextEdit(chat, text))
+        }
+
+        fn answer_callback(&self, _callback: ()) -> BoxFuture<'static, Result<(), Error>> {
+            async { Ok(()) }.boxed()
+        }
+    }
+
+    struct FakeQuestionsStorage {
+        questions: HashMap<(String, usize), Question>,
+    }
+
+    impl QuestionsStorage for FakeQuestionsStorage {
+        fn get(&self, topic_name: String, difficulty: usize) -> Option<Question> {
+            self.questions.get(&(topic_name, difficulty)).cloned()
+        }
+
+        fn contains(&self, topic_name: String, difficulty: usize) -> bool {
+            self.questions.contains_key(&(topic_name, difficulty))
+        }
+
+        fn get_tours(&self) -> Vec<TourDescription> {
+            vec![TourDescription {
+                multiplier: 100,
+                title: None,
+                blitz: false,
+                topics: vec![Topic {
+                    name: "Sport".to_string(),
+                }],
+            }]
+        }
+
+        fn get_cats_in_bags(&self) -> Vec<CatInBag> {
+            vec![]
+        }
+
+        fn get_manual_questions(&self) -> Vec<(String, usize)> {
+            vec![]
+        }
+
+        fn get_auctions(&self) -> Vec<(String, usize)> {
+            vec![]
+        }
+
+        fn get_stakes(&self) -> Vec<(String, usize)> {
+            vec![]
+        }
+
+        fn get_single_answer_questions(&self) -> Vec<(String, usize)> {
+            vec![]
+        }
+
+        fn get_custom_costs(&self) -> HashMap<(String, usize), usize> {
+            HashMap::new()
+        }
+    }
+
+    // Timers are not fired in the tests
+    fn fake_ui(sink: &FakeSink) -> UiContext<FakeSink> {
+        let (timer_sender, _) = mpsc::channel(1);
+        UiContext {
+            sink: sink.clone(),
+            use_separate_keyboards: false,
+            timer_sender,
+            manual_timers: false,
+            results_db: None,
+            cache: None,
+            scoreboard_mode: ScoreboardMode::Image,
+            choose_topic_message_id: None,
+            choose_question_message_id: None,
+            countdown_message_id: None,
+            question_message_id: None,
+        }
+    }
+
+    #[test]
+    fn test_question_message_id() {
+        let sink = FakeSink::default();
+        let mut ui = fake_ui(&sink);
+        let handle = |ui: &mut UiContext<FakeSink>, request| {
+            futures_03::executor::block_on(ui.handle_ui_request(request)).unwrap()
+        };
+
+        handle(&mut ui, gamestate::UiRequest::SendQuestionText("question 1".to_string()));
+        assert_eq!(ui.question_message_id, Some(0));
+        // The next question is asked privately, replies to the old one are ignored
+        handle(&mut ui, gamestate::UiRequest::RightBeforeAskingQuestion(String::new()));
+        assert_eq!(ui.question_message_id, None);
+        handle(&mut ui, gamestate::UiRequest::SendQuestionText("question 2".to_string()));
+        handle(
+            &mut ui,
+            gamestate::UiRequest::SendPrivately(
+                UserId::from(2),
+                vec![gamestate::UiRequest::SendQuestionText("question 3".to_string())],
+            ),
+        );
+        assert_eq!(ui.question_message_id, None);
+    }
+
+    #[test]
+    fn test_scripted_game() {
+        let admin = UserId::from(1);
+        let p1 = UserId::from(2);
+        let game_chat = Chat::Game;
+        let admin_chat = Chat::Admin;
+
+        let mut questions = HashMap::new();
+        for i in 1..3 {
+            questions.insert(
+                ("Sport".to_string(), i),
+                Question::new(&format!("question {}", i), &format!("answer {}", i), None),
+            );
+        }
+        let question_storage: Box<dyn QuestionsStorage> = Box::new(FakeQuestionsStorage { questions });
+        let gamestate = gamestate::GameState::new(
+            admin,
+            &question_storage,
+            Some(2),
+            gamestate::GameConfig {
+                min_players: 1,
+                ..gamestate::GameConfig::default()
+            },
+        )
+        .unwrap();
+        let mut driver = GameDriver {
+            gamestate,
+            question_storage,
+            command_prefix: "/".to_string(),
+            admin_user: admin,
+            audit_log: None,
+            state_snapshot: None,
+        };
+
+        let sink = FakeSink::default();
+        let mut ui = fake_ui(&sink);
+
+        let script = vec![
+            ScriptedEvent::Text(p1, "/join first".to_string()),
+            ScriptedEvent::Text(admin, BEGIN_CMD.to_string()),
+            ScriptedEvent::Callback(admin, START_YES.to_string()),
+            ScriptedEvent::Text(admin, "/question".to_string()),
+            ScriptedEvent::Callback(p1, "/topic0".to_string()),
+            ScriptedEvent::Callback(p1, "/question0_100".to_string()),
+            ScriptedEvent::Timer(TimerId::Question),
+            ScriptedEvent::Timer(TimerId::Question),
+            ScriptedEvent::Text(p1, "1".to_string()),
+            ScriptedEvent::Callback(admin, ANSWER_YES.to_string()),
+            ScriptedEvent::Text(admin, "/finish".to_string()),
+        ];
+
+        for event in script {
+            let res = driver.handle_scripted_event(None, event);
+            for r in res {
+                futures_03::executor::block_on(ui.handle_ui_request(r)).unwrap();
+            }
+        }
+
+        let mut sent = sink.sent.lock().unwrap();
+        // Praise for the correct answer is random
+        match sent.remove(13) {
+            Sent::Text(chat, text) => {
+                assert_eq!(chat, game_chat);
+                assert!(text.ends_with("\nСчет:\nfirst: 100\n\nИгру продолжает first"));
+            }
+            sent => panic!("unexpected {:?}", sent),
+        }
+        assert_eq!(
+            *sent,
+            vec![
+                Sent::Text(game_chat, "Привет first".to_string()),
+                Sent::Text(admin_chat, "Start the game with 1 players?\nfirst".to_string()),
+                Sent::Text(game_chat, "Здравствуйте, здравствуйте, добрый день! Это своя игра!".to_string()),
+                Sent::Text(game_chat, "Темы первого раунда:\nSport".to_string()),
+                Sent::Text(game_chat, "Игру начинает first".to_string()),
+                Sent::ScoreTable(game_chat),
+                Sent::Text(game_chat, "<a href=\"tg://user?id=2\">first</a>, выберите тему".to_string()),
+                Sent::Text(game_chat, "Выбрана тема 'Sport', выберите цену".to_string()),
+                Sent::Text(game_chat, "Играем тему Sport, вопрос за 100".to_string()),
+                Sent::Text(admin_chat, "question: question 1\nanswer: answer 1".to_string()),
+                Sent::Text(game_chat, "question 1".to_string()),
+                Sent::Text(game_chat, "Отвечает first".to_string()),
+                Sent::Text(admin_chat, "Correct answer?".to_string()),
+                Sent::Text(admin_chat, "Points by topic in tour 1:\nSport: +100".to_string()),
+                Sent::Text(game_chat, "Игра окончена!\nПобедитель: first\nСчет:\nfirst: 100\n".to_string()),
+                Sent::GameSummary(game_chat),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_cost_rows() {
+        assert!(cost_rows::<usize>(vec![]).is_empty());
+        // Usual boards keep one cost per row
+        assert_eq!(cost_rows(vec![100, 200, 300]), vec![vec![100], vec![200], vec![300]]);
+        let costs: Vec<_> = (1..=20).collect();
+        let rows = cost_rows(costs.clone());
+        assert_eq!(rows.len(), 7);
+        assert!(rows.iter().all(|row| row.len() <= 3));
+        assert_eq!(rows.concat(), costs);
+
+        let rows = cost_rows((1..=MAX_KEYBOARD_COSTS).collect());
+        assert_eq!(rows.len(), MAX_COST_ROWS);
+        assert!(rows.iter().all(|row| row.len() == MAX_BUTTONS_PER_ROW));
+    }
+
+    #[test]
+    fn test_photo_file_id() {
+        let response = br#"{"ok": true, "result": {"message_id": 1, "photo": [
+            {"file_id": "small", "width": 90, "height": 90},
+            {"file_id": "big", "width": 800, "height": 800}
+        ]}}"#;
+        assert_eq!(photo_file_id(response).unwrap(), Some("big".to_string()));
+        assert_eq!(photo_file_id(br#"{"ok": true, "result": {"message_id": 1}}"#).unwrap(), None);
+        let err = photo_file_id(br#"{"ok": false, "description": "Bad Request: wrong file identifier"}"#);
+        assert!(err.unwrap_err().to_string().contains("wrong file identifier"));
+        assert!(photo_file_id(b"not json").is_err());
+    }
+
+    #[test]
+    fn test_ignore_recoverable() {
+        let send_error = |chat, msg| Err(SendError::new(chat, err_msg(msg)).into());
+        assert!(ignore_recoverable(Ok(())).is_ok());
+        assert!(ignore_recoverable(send_error(Chat::Admin, "Bad Request: message to edit not found")).is_ok());
+        assert!(ignore_recoverable(send_error(Chat::Game, "Curl sending photo finished unsucessfully")).is_ok());
+        assert!(ignore_recoverable(send_error(Chat::Game, "Unauthorized")).is_err());
+        assert!(ignore_recoverable(send_error(Chat::Admin, "Bad Request: chat not found")).is_err());
+        assert!(ignore_recoverable(send_error(Chat::Game, "Forbidden: bot was kicked from the group chat")).is_err());
+        assert!(ignore_recoverable(send_error(Chat::Private(UserId::new(1)), "Bad Request: chat not found")).is_ok());
+        assert!(ignore_recoverable(send_error(Chat::Private(UserId::new(1)), "Forbidden: bot was blocked by the user")).is_ok());
+        // Errors which are not about sending don't stop the game
+        assert!(ignore_recoverable(Err(err_msg("Bad Request: chat not found"))).is_ok());
+    }
+}
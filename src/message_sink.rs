@@ -0,0 +1,483 @@
+use failure::Error;
+use futures_03::future::{BoxFuture, FutureExt};
+use telegram_bot::{
+    types::MessageId, Api, CallbackQueryId, CanAnswerCallbackQuery, ChatId, EditMessageReplyMarkup,
+    EditMessageText, InlineKeyboardButton, InlineKeyboardMarkup, KeyboardButton, MessageOrChannelPost, ParseMode,
+    ReplyKeyboardMarkup, ReplyKeyboardRemove, ReplyMarkup, SendMessage, UserId,
+};
+
+use crate::gamestate::{GameSummary, ScoreTable};
+use crate::question::Media;
+use crate::{FileIdCache, ScoreTableDrawer};
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Chat {
+    Game,
+    Admin,
+    // Private chat of the user with the bot
+    Private(UserId),
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SendErrorKind {
+    // Wrong bot token
+    Unauthorized,
+    // The chat was removed or the bot can't write there anymore
+    ChatUnavailable,
+    Other,
+}
+
+impl SendErrorKind {
+    fn from_description(description: &str) -> Self {
+        const CHAT_UNAVAILABLE: &[&str] = &[
+            "chat not found",
+            "bot was kicked",
+            "bot is not a member",
+            "bot was blocked",
+        ];
+        if description.contains("Unauthorized") {
+            SendErrorKind::Unauthorized
+        } else if CHAT_UNAVAILABLE.iter().any(|error| description.contains(error)) {
+            SendErrorKind::ChatUnavailable
+        } else {
+            SendErrorKind::Other
+        }
+    }
+}
+
+// Telegram error together with the chat the message was sent to, so that
+// the main loop can decide whether the game can go on
+#[derive(Debug)]
+pub struct SendError {
+    pub chat: Chat,
+    pub kind: SendErrorKind,
+    description: String,
+}
+
+impl SendError {
+    pub fn new(chat: Chat, err: Error) -> Self {
+        let description = err.to_string();
+        Self {
+            chat,
+            kind: SendErrorKind::from_description(&description),
+            description,
+        }
+    }
+}
+
+impl std::fmt::Display for SendError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "failed to send to {} chat: {}", chat_name(self.chat), self.description)
+    }
+}
+
+impl std::error::Error for SendError {}
+
+fn in_chat<T>(chat: Chat, res: Result<T, Error>) -> Result<T, Error> {
+    res.map_err(|err| SendError::new(chat, err).into())
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TextFormat {
+    Plain,
+    Html,
+    Markdown,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct InlineButton {
+    pub text: String,
+    pub data: String,
+}
+
+impl InlineButton {
+    pub fn new<T: Into<String>, D: Into<String>>(text: T, data: D) -> Self {
+        Self {
+            text: text.into(),
+            data: data.into(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Keyboard {
+    // Buttons attached to the message
+    Inline(Vec<Vec<InlineButton>>),
+    // One time keyboard which replaces the usual one. Selective keyboard is
+    // shown only to the mentioned user
+    Reply { rows: Vec<Vec<String>>, selective: bool },
+    // Removes reply keyboard
+    Remove,
+}
+
+// Everything the bot shows to the users goes through this trait, so that
+// game logic doesn't depend on telegram and tests can replace it with a fake
+pub trait MessageSink: Clone + Send + Sync + 'static {
+    type MessageId: Clone + Send;
+    type CallbackId: Send;
+
+    // Returns id of the sent message if the backend knows it
+    fn send_text(
+        &self,
+        chat: Chat,
+        text: String,
+        format: TextFormat,
+        keyboard: Option<Keyboard>,
+    ) -> BoxFuture<'static, Result<Option<Self::MessageId>, Error>>;
+
+    fn send_photo(&self, chat: Chat, photo: Media) -> BoxFuture<'static, Result<(), Error>>;
+
+    // Photos grouped into a single album
+    fn send_media_group(&self, chat: Chat, photos: Vec<Media>) -> BoxFuture<'static, Result<(), Error>>;
+
+    fn send_audio(&self, chat: Chat, audio: Media) -> BoxFuture<'static, Result<(), Error>>;
+
+    fn send_animation(&self, chat: Chat, animation: Media) -> BoxFuture<'static, Result<(), Error>>;
+
+    fn send_document(&self, chat: Chat, document: Media) -> BoxFuture<'static, Result<(), Error>>;
+
+    fn send_sticker(&self, chat: Chat, file_id: String) -> BoxFuture<'static, Result<(), Error>>;
+
+    // Draws the score table and sends it as an image
+    fn send_score_table(&self, chat: Chat, table: ScoreTable) -> BoxFuture<'static, Result<(), Error>>;
+
+    // Draws the final ranking and sends it as an image
+    fn send_game_summary(&self, chat: Chat, summary: GameSummary) -> BoxFuture<'static, Result<(), Error>>;
+
+    // None removes the keyboard
+    fn edit_keyboard(
+        &self,
+        chat: Chat,
+        message: Self::MessageId,
+        keyboard: Option<Keyboard>,
+    ) -> BoxFuture<'static, Result<(), Error>>;
+
+    fn edit_text(&self, chat: Chat, message: Self::MessageId, text: String) -> BoxFuture<'static, Result<(), Error>>;
+
+    fn answer_callback(&self, callback: Self::CallbackId) -> BoxFuture<'static, Result<(), Error>>;
+
+    fn send_plain(&self, chat: Chat, text: String) -> BoxFuture<'static, Result<Option<Self::MessageId>, Error>> {
+        self.send_text(chat, text, TextFormat::Plain, None)
+    }
+}
+
+#[derive(Clone)]
+pub struct TelegramSink {
+    api: Api,
+    token: String,
+    game_chat: ChatId,
+    admin_chat: ChatId,
+    drawer: ScoreTableDrawer,
+    file_ids: FileIdCache,
+}
+
+impl TelegramSink {
+    pub fn new(
+        api: Api,
+        token: String,
+        game_chat: ChatId,
+        admin_chat: ChatId,
+        drawer: ScoreTableDrawer,
+        file_ids: FileIdCache,
+    ) -> Self {
+        Self {
+            api,
+            token,
+            game_chat,
+            admin_chat,
+            drawer,
+            file_ids,
+        }
+    }
+
+    fn chat_id(&self, chat: Chat) -> ChatId {
+        match chat {
+            Chat::Game => self.game_chat,
+            Chat::Admin => self.admin_chat,
+            Chat::Private(user) => ChatId::from(user),
+        }
+    }
+}
+
+fn to_reply_markup(keyboard: Keyboard) -> ReplyMarkup {
+    match keyboard {
+        Keyboard::Inline(rows) => {
+            let mut inline_markup = InlineKeyboardMarkup::new();
+            for buttons in rows {
+                let row = inline_markup.add_empty_row();
+                for button in buttons {
+                    row.push(InlineKeyboardButton::callback(button.text, button.data));
+                }
+            }
+            inline_markup.into()
+        }
+        Keyboard::Reply { rows, selective } => {
+            let mut markup = ReplyKeyboardMarkup::new();
+            markup.one_time_keyboard();
+            if selective {
+                markup.selective();
+            }
+            for buttons in rows {
+                let row = markup.add_empty_row();
+                for button in buttons {
+                    row.push(KeyboardButton::new(button));
+                }
+            }
+            markup.into()
+        }
+        Keyboard::Remove => ReplyKeyboardRemove::new().into(),
+    }
+}
+
+impl MessageSink for TelegramSink {
+    type MessageId = MessageId;
+    type CallbackId = CallbackQueryId;
+
+    fn send_text(
+        &self,
+        chat: Chat,
+        text: String,
+        format: TextFormat,
+        keyboard: Option<Keyboard>,
+    ) -> BoxFuture<'static, Result<Option<MessageId>, Error>> {
+        let mut request = SendMessage::new(self.chat_id(chat), text);
+        match format {
+            TextFormat::Plain => {}
+            TextFormat::Html => {
+                request.parse_mode(ParseMode::Html);
+            }
+            TextFormat::Markdown => {
+                request.parse_mode(ParseMode::Markdown);
+            }
+        }
+        if let Some(keyboard) = keyboard {
+            request.reply_markup(to_reply_markup(keyboard));
+        }
+
+        let api = self.api.clone();
+        async move {
+            match in_chat(chat, api.send(request).await.map_err(Error::from))? {
+                MessageOrChannelPost::Message(msg) => Ok(Some(msg.id)),
+                _ => Ok(None),
+            }
+        }
+        .boxed()
+    }
+
+    fn send_photo(&self, chat: Chat, photo: Media) -> BoxFuture<'static, Result<(), Error>> {
+        let token = self.token.clone();
+        let chat_id = self.chat_id(chat);
+        let file_ids = self.file_ids.clone();
+        async move { in_chat(chat, crate::send_cached_photo_via_curl(chat_id, &token, &photo, &file_ids)) }.boxed()
+    }
+
+    fn send_media_group(&self, chat: Chat, photos: Vec<Media>) -> BoxFuture<'static, Result<(), Error>> {
+        let token = self.token.clone();
+        let chat_id = self.chat_id(chat);
+        async move { in_chat(chat, crate::send_media_group_via_curl(chat_id, &token, &photos)) }.boxed()
+    }
+
+    fn send_audio(&self, chat: Chat, audio: Media) -> BoxFuture<'static, Result<(), Error>> {
+        let token = self.token.clone();
+        let chat_id = self.chat_id(chat);
+        async move { in_chat(chat, crate::send_audio_via_curl(chat_id, &token, &audio)) }.boxed()
+    }
+
+    fn send_animation(&self, chat: Chat, animation: Media) -> BoxFuture<'static, Result<(), Error>> {
+        let token = self.token.clone();
+        let chat_id = self.chat_id(chat);
+        async move { in_chat(chat, crate::send_animation_via_curl(chat_id, &token, &animation)) }.boxed()
+    }
+
+    fn send_document(&self, chat: Chat, document: Media) -> BoxFuture<'static, Result<(), Error>> {
+        let token = self.token.clone();
+        let chat_id = self.chat_id(chat);
+        async move { in_chat(chat, crate::send_document_via_curl(chat_id, &token, &document)) }.boxed()
+    }
+
+    fn send_sticker(&self, chat: Chat, file_id: String) -> BoxFuture<'static, Result<(), Error>> {
+        let token = self.token.clone();
+        let chat_id = self.chat_id(chat);
+        async move { in_chat(chat, crate::send_sticker_via_curl(chat_id, &token, &file_id)) }.boxed()
+    }
+
+    fn send_score_table(&self, chat: Chat, table: ScoreTable) -> BoxFuture<'static, Result<(), Error>> {
+        let token = self.token.clone();
+        let chat_id = self.chat_id(chat);
+        let drawer = self.drawer.clone();
+        async move { in_chat(chat, crate::send_score_table(table, chat_id, token, &drawer)) }.boxed()
+    }
+
+    fn send_game_summary(&self, chat: Chat, summary: GameSummary) -> BoxFuture<'static, Result<(), Error>> {
+        let token = self.token.clone();
+        let chat_id = self.chat_id(chat);
+        let drawer = self.drawer.clone();
+        async move { in_chat(chat, crate::send_game_summary(summary, chat_id, token, &drawer)) }.boxed()
+    }
+
+    fn edit_keyboard(
+        &self,
+        chat: Chat,
+        message: MessageId,
+        keyboard: Option<Keyboard>,
+    ) -> BoxFuture<'static, Result<(), Error>> {
+        let request = EditMessageReplyMarkup::new(
+            self.chat_id(chat),
+            message,
+            keyboard.map(to_reply_markup),
+        );
+        let api = self.api.clone();
+        async move {
+            in_chat(chat, api.send(request).await.map_err(Error::from))?;
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn edit_text(&self, chat: Chat, message: MessageId, text: String) -> BoxFuture<'static, Result<(), Error>> {
+        let request = EditMessageText::new(self.chat_id(chat), message, text);
+        let api = self.api.clone();
+        async move {
+            in_chat(chat, api.send(request).await.map_err(Error::from))?;
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn answer_callback(&self, callback: CallbackQueryId) -> BoxFuture<'static, Result<(), Error>> {
+        let request = callback.acknowledge();
+        let api = self.api.clone();
+        async move {
+            api.send(request).await?;
+            Ok(())
+        }
+        .boxed()
+    }
+}
+
+// Prints everything to stdout instead of sending it, used to play locally.
+// Printing happens when futures are polled, so that delayed messages are
+// printed only when their timer fires
+#[derive(Clone)]
+pub struct ConsoleSink;
+
+fn chat_name(chat: Chat) -> String {
+    match chat {
+        Chat::Game => "game".to_string(),
+        Chat::Admin => "admin".to_string(),
+        Chat::Private(user) => format!("private {}", user),
+    }
+}
+
+impl MessageSink for ConsoleSink {
+    type MessageId = ();
+    type CallbackId = ();
+
+    fn send_text(
+        &self,
+        chat: Chat,
+        text: String,
+        _format: TextFormat,
+        keyboard: Option<Keyboard>,
+    ) -> BoxFuture<'static, Result<Option<()>, Error>> {
+        async move {
+            println!("[{}] {}", chat_name(chat), text);
+            if let Some(keyboard) = keyboard {
+                println!("[{}] keyboard: {:?}", chat_name(chat), keyboard);
+            }
+            Ok(None)
+        }
+        .boxed()
+    }
+
+    fn send_photo(&self, chat: Chat, photo: Media) -> BoxFuture<'static, Result<(), Error>> {
+        async move {
+            println!("[{}] photo {}", chat_name(chat), photo);
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn send_media_group(&self, chat: Chat, photos: Vec<Media>) -> BoxFuture<'static, Result<(), Error>> {
+        async move {
+            for photo in photos {
+                println!("[{}] album photo {}", chat_name(chat), photo);
+            }
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn send_audio(&self, chat: Chat, audio: Media) -> BoxFuture<'static, Result<(), Error>> {
+        async move {
+            println!("[{}] audio {}", chat_name(chat), audio);
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn send_animation(&self, chat: Chat, animation: Media) -> BoxFuture<'static, Result<(), Error>> {
+        async move {
+            println!("[{}] animation {}", chat_name(chat), animation);
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn send_document(&self, chat: Chat, document: Media) -> BoxFuture<'static, Result<(), Error>> {
+        async move {
+            println!("[{}] document {}", chat_name(chat), document);
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn send_sticker(&self, chat: Chat, file_id: String) -> BoxFuture<'static, Result<(), Error>> {
+        async move {
+            println!("[{}] sticker {}", chat_name(chat), file_id);
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn send_score_table(&self, chat: Chat, table: ScoreTable) -> BoxFuture<'static, Result<(), Error>> {
+        async move {
+            println!("[{}] score table:\n{}", chat_name(chat), table.to_string());
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn send_game_summary(&self, chat: Chat, summary: GameSummary) -> BoxFuture<'static, Result<(), Error>> {
+        async move {
+            println!("[{}] {}", chat_name(chat), summary);
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn edit_keyboard(
+        &self,
+        chat: Chat,
+        _message: (),
+        keyboard: Option<Keyboard>,
+    ) -> BoxFuture<'static, Result<(), Error>> {
+        async move {
+            println!("[{}] keyboard is changed to {:?}", chat_name(chat), keyboard);
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn edit_text(&self, chat: Chat, _message: (), text: String) -> BoxFuture<'static, Result<(), Error>> {
+        async move {
+            println!("[{}] message is changed to: {}", chat_name(chat), text);
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn answer_callback(&self, _callback: ()) -> BoxFuture<'static, Result<(), Error>> {
+        async { Ok(()) }.boxed()
+    }
+}
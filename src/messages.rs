@@ -1,11 +1,25 @@
+use failure::{err_msg, Error};
 use rand::{seq::SliceRandom, thread_rng};
+use serde_derive::Deserialize;
+use std::fs::File;
+
 pub const BEGIN_CMD: &str = "Начинаем";
 
-pub const INCORRECT_ANSWER: &str = "Нет";
+pub fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
 
+// Backticks can't be escaped inside of a markdown code block, they would
+// close it and break the parsing of the whole message
+pub fn markdown_code_block(text: &str) -> String {
+    format!("```\n{}```", text.replace('`', "'"))
+}
 
-pub fn get_rand_correct_answer() -> String {
-    let answers = vec![
+fn default_correct_answers() -> Vec<String> {
+    vec![
         "Правильно!".to_string(),
         "Верно!".to_string(),
         "В точку!".to_string(),
@@ -13,8 +27,89 @@ pub fn get_rand_correct_answer() -> String {
         "Блестящий ответ!".to_string(),
         "Отлично!".to_string(),
         "Замечательно, продолжаем".to_string(),
-    ];
+    ]
+}
+
+fn default_incorrect_answers() -> Vec<String> {
+    vec!["Нет".to_string()]
+}
+
+// Phrases the bot reacts with to the answers, a random one is chosen every time
+#[derive(Clone, Debug, Deserialize)]
+pub struct Phrases {
+    #[serde(default = "default_correct_answers")]
+    correct: Vec<String>,
+    #[serde(default = "default_incorrect_answers")]
+    incorrect: Vec<String>,
+}
+
+impl Default for Phrases {
+    fn default() -> Self {
+        Self {
+            correct: default_correct_answers(),
+            incorrect: default_incorrect_answers(),
+        }
+    }
+}
 
+impl Phrases {
+    // Built-in phrases are used if there is no file
+    pub fn load(path: Option<&str>) -> Result<Self, Error> {
+        let path = match path {
+            Some(path) => path,
+            None => {
+                return Ok(Self::default());
+            }
+        };
+        let file = File::open(path)
+            .map_err(|err| err_msg(format!("can't open phrases file '{}': {}", path, err)))?;
+        let phrases: Self = serde_json::from_reader(file)
+            .map_err(|err| err_msg(format!("invalid phrases file '{}': {}", path, err)))?;
+        if phrases.correct.is_empty() || phrases.incorrect.is_empty() {
+            return Err(err_msg(format!("phrases file '{}' has an empty list", path)));
+        }
+        Ok(phrases)
+    }
+
+    pub fn get_rand_correct_answer(&self) -> String {
+        choose(&self.correct)
+    }
+
+    pub fn get_rand_incorrect_answer(&self) -> String {
+        choose(&self.incorrect)
+    }
+}
+
+fn choose(phrases: &[String]) -> String {
     let mut rng = thread_rng();
-    answers.choose(&mut rng).cloned().unwrap()
+    phrases.choose(&mut rng).cloned().unwrap_or_default()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_escaping() {
+        assert_eq!(escape_html("<b>Tom & \"Jerry\"</b>"), "&lt;b&gt;Tom &amp; &quot;Jerry&quot;&lt;/b&gt;");
+        assert_eq!(markdown_code_block("Topic `1`\n"), "```\nTopic '1'\n```");
+    }
+
+    #[test]
+    fn test_load_phrases() {
+        let phrases = Phrases::load(None).unwrap();
+        assert!(default_correct_answers().contains(&phrases.get_rand_correct_answer()));
+        assert_eq!(phrases.get_rand_incorrect_answer(), "Нет");
+
+        let path = std::env::temp_dir().join("svoyak_bot_test_phrases.json");
+        let path = path.to_str().unwrap();
+        std::fs::write(path, r#"{"correct": ["Да!"]}"#).unwrap();
+        let phrases = Phrases::load(Some(path)).unwrap();
+        assert_eq!(phrases.get_rand_correct_answer(), "Да!");
+        assert_eq!(phrases.get_rand_incorrect_answer(), "Нет");
+
+        std::fs::write(path, r#"{"incorrect": []}"#).unwrap();
+        assert!(Phrases::load(Some(path)).is_err());
+        std::fs::remove_file(path).unwrap();
+    }
 }
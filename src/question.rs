@@ -1,50 +1,170 @@
+use std::fmt;
 use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
 
+// Question attachment: either a cached file which has to be uploaded or a
+// public url which telegram fetches by itself
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Media {
+    File(PathBuf),
+    Url(String),
+}
+
+impl fmt::Display for Media {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Media::File(path) => write!(f, "{}", path.display()),
+            Media::Url(url) => write!(f, "{}", url),
+        }
+    }
+}
+
+// Questions are cloned on every state transition, so the texts are shared
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct Question {
-    question: String,
-    answer: String,
+    question: Arc<str>,
+    answer: Arc<str>,
+    alt_answers: Vec<String>,
     comments: Option<String>,
-    image: Option<PathBuf>,
-    audio: Option<PathBuf>,
+    images: Vec<Media>,
+    audio: Option<Media>,
+    // Known only for downloaded audio files
+    audio_duration: Option<Duration>,
+    animation: Option<Media>,
+    // Sent as a file, e.g. pdf with a scanned page
+    document: Option<Media>,
 }
 
 impl Question {
     pub fn new<T: ToString>(question: T, answer: T, comments: Option<T>) -> Self {
         Self {
-            question: question.to_string(),
-            answer: answer.to_string(),
+            question: question.to_string().into(),
+            answer: answer.to_string().into(),
+            alt_answers: vec![],
             comments: comments.map(|s| s.to_string()),
-            image: None,
+            images: vec![],
             audio: None,
+            audio_duration: None,
+            animation: None,
+            document: None,
         }
     }
 
-    pub fn question(&self) -> String {
-        self.question.clone()
+    pub fn question(&self) -> &str {
+        &self.question
+    }
+
+    pub fn answer(&self) -> &str {
+        &self.answer
     }
 
-    pub fn answer(&self) -> String {
-        self.answer.clone()
+    pub fn alt_answers(&self) -> &Vec<String> {
+        &self.alt_answers
     }
 
     pub fn comments(&self) -> &Option<String> {
         &self.comments
     }
 
-    pub fn image(&self) -> &Option<PathBuf> {
-        &self.image
+    pub fn images(&self) -> &Vec<Media> {
+        &self.images
     }
 
-    pub fn audio(&self) -> &Option<PathBuf> {
+    pub fn audio(&self) -> &Option<Media> {
         &self.audio
     }
 
-    pub fn set_image(&mut self, path: PathBuf) {
-        self.image = Some(path);
+    pub fn audio_duration(&self) -> Option<Duration> {
+        self.audio_duration
+    }
+
+    pub fn animation(&self) -> &Option<Media> {
+        &self.animation
+    }
+
+    pub fn document(&self) -> &Option<Media> {
+        &self.document
+    }
+
+    // Whether the text matches the answer or one of the alternative answers
+    // up to case, punctuation and whitespaces
+    pub fn matches_answer(&self, text: &str) -> bool {
+        let text = normalize_answer(text);
+        if text.is_empty() {
+            return false;
+        }
+        std::iter::once(self.answer())
+            .chain(self.alt_answers.iter().map(String::as_str))
+            .any(|answer| normalize_answer(answer) == text)
+    }
+
+    pub fn set_alt_answers(&mut self, alt_answers: Vec<String>) {
+        self.alt_answers = alt_answers;
     }
 
-    pub fn set_audio(&mut self, path: PathBuf) {
-        self.audio = Some(path);
+    pub fn add_image(&mut self, image: Media) {
+        self.images.push(image);
+    }
+
+    pub fn set_audio(&mut self, audio: Media) {
+        self.audio = Some(audio);
+    }
+
+    pub fn set_audio_duration(&mut self, duration: Duration) {
+        self.audio_duration = Some(duration);
+    }
+
+    pub fn set_animation(&mut self, animation: Media) {
+        self.animation = Some(animation);
+    }
+
+    pub fn set_document(&mut self, document: Media) {
+        self.document = Some(document);
+    }
+}
+
+pub fn normalize_answer(text: &str) -> String {
+    text.to_lowercase()
+        .replace('ё', "е")
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { ' ' })
+        .collect::<String>()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_normalize_answer() {
+        assert_eq!(normalize_answer("  Пушкин "), "пушкин");
+        assert_eq!(normalize_answer("ЁЖИК"), "ежик");
+        assert_eq!(normalize_answer("Ёлка-палка!"), "елка палка");
+        assert_eq!(normalize_answer("«Война  и мир»"), "война и мир");
+        assert_eq!(normalize_answer("Dr. Who?"), "dr who");
+        assert_eq!(normalize_answer("1812"), "1812");
+        assert_eq!(normalize_answer("?!..."), "");
+        assert_eq!(normalize_answer(""), "");
+    }
+
+    #[test]
+    fn test_matches_answer() {
+        let mut question = Question::new("question", "Лев Толстой", None);
+        question.set_alt_answers(vec!["Толстой".to_string()]);
+
+        assert!(question.matches_answer("лев толстой"));
+        assert!(question.matches_answer("Лев  Толстой!"));
+        assert!(question.matches_answer("толстой"));
+        assert!(!question.matches_answer("Алексей Толстой"));
+        assert!(!question.matches_answer("!!!"));
+
+        let question = Question::new("question", "Ёж", None);
+        assert!(question.matches_answer("еж"));
+        let question = Question::new("question", "...", None);
+        assert!(!question.matches_answer(""));
     }
 }
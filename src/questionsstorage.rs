@@ -2,18 +2,27 @@ use csv;
 use failure::{err_msg, Error};
 use hyper::Client;
 use hyper_tls::HttpsConnector;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::collections::hash_map::DefaultHasher;
 use std::fs::File;
 use std::hash::{Hash, Hasher};
 use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Mutex;
+use std::thread::JoinHandle;
+use std::time::Duration;
 use regex::Regex;
+use serde_derive::{Deserialize, Serialize};
 
-use crate::question::Question;
+use crate::cache::Cache;
+use crate::question::{Media, Question};
 
 pub trait QuestionsStorage {
     fn get(&self, topic_name: String, difficulty: usize) -> Option<Question>;
 
+    // Unlike get() it doesn't download attachments of the question
+    fn contains(&self, topic_name: String, difficulty: usize) -> bool;
+
     fn get_tours(&self) -> Vec<TourDescription>;
 
     fn get_cats_in_bags(&self) -> Vec<CatInBag>;
@@ -21,6 +30,24 @@ pub trait QuestionsStorage {
     fn get_manual_questions(&self) -> Vec<(String, usize)>;
 
     fn get_auctions(&self) -> Vec<(String, usize)>;
+
+    // Questions for which the player sets the stake
+    fn get_stakes(&self) -> Vec<(String, usize)>;
+
+    // Regular questions which only the player who chose them can answer
+    fn get_single_answer_questions(&self) -> Vec<(String, usize)>;
+
+    // Costs set explicitly in the pack by topic and difficulty, other
+    // questions cost difficulty * multiplier
+    fn get_custom_costs(&self) -> HashMap<(String, usize), usize>;
+
+    // Starts downloading attachments of the questions in the background
+    fn prefetch(&self, _questions: Vec<(String, usize)>) {}
+
+    // Attachments which failed to download since the last call
+    fn take_attachment_errors(&self) -> Vec<String> {
+        vec![]
+    }
 }
 
 #[derive(Clone)]
@@ -31,6 +58,9 @@ pub struct Topic {
 #[derive(Clone)]
 pub struct TourDescription {
     pub multiplier: usize,
+    pub title: Option<String>,
+    // Questions are asked one by one without choosing topic and cost
+    pub blitz: bool,
     pub topics: Vec<Topic>,
 }
 
@@ -43,40 +73,106 @@ pub struct CatInBag {
     pub answer: String,
 }
 
+// External text-to-speech service used for questions that have to be read aloud.
+// Text is POSTed as json, response body is expected to be an audio file
+#[derive(Clone, Serialize, Deserialize)]
+pub struct TtsConfig {
+    pub url: String,
+    pub api_key: Option<String>,
+}
+
+// Images which are larger than these limits are downscaled and re-encoded
+// before they are cached, so that they are uploaded faster
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ImageCompressionConfig {
+    #[serde(default = "default_max_image_dimension")]
+    pub max_dimension: u32,
+    #[serde(default = "default_max_image_bytes")]
+    pub max_bytes: u64,
+}
+
+fn default_max_image_dimension() -> u32 {
+    1280
+}
+
+fn default_max_image_bytes() -> u64 {
+    1024 * 1024
+}
+
+impl Default for ImageCompressionConfig {
+    fn default() -> Self {
+        Self {
+            max_dimension: default_max_image_dimension(),
+            max_bytes: default_max_image_bytes(),
+        }
+    }
+}
+
+// How attachments of the questions are downloaded
+#[derive(Clone, Default)]
+pub struct AttachmentSettings {
+    pub google_api_key: Option<String>,
+    pub image_compression: ImageCompressionConfig,
+    // Public urls are sent to telegram without downloading
+    pub direct_urls: bool,
+    // Download everything on startup instead of when the question is asked
+    pub prefetch: bool,
+}
+
 // Questions for the same topic have to go one after another
 // Row: question,answer,optional comment,topic
+// Optional row with "НАЗВАНИЕ ТУРА: <title>" in the topic column sets the tour title
 pub struct CsvQuestionsStorage {
-    questions: HashMap<(String, usize), Question>,
+    questions: Mutex<HashMap<(String, usize), Question>>,
+    // Attachments which are downloaded the first time the question is asked
+    pending_attachments: Mutex<HashMap<(String, usize), Vec<String>>>,
+    // Questions with attachments which are being downloaded right now
+    loading: Mutex<HashMap<(String, usize), AttachmentsLoader>>,
+    attachment_errors: Mutex<Vec<String>>,
+    attachment_settings: AttachmentSettings,
+    cache: Cache,
     tours: Vec<TourDescription>,
     cats_in_bags: Vec<CatInBag>,
     manual_questions: Vec<(String, usize)>,
     auctions: Vec<(String, usize)>,
+    stakes: Vec<(String, usize)>,
+    single_answer_questions: Vec<(String, usize)>,
+    custom_costs: HashMap<(String, usize), usize>,
 }
 
 impl CsvQuestionsStorage {
     // TODO(stash): skip header
-    pub async fn new(p: String, google_api_key: Option<String>, use_cached_questions: bool) -> Result<Self, Error> {
+    pub async fn new(
+        p: String,
+        use_cached_questions: bool,
+        tts: Option<TtsConfig>,
+        attachment_settings: AttachmentSettings,
+        // Take costs from the second column instead of difficulty * multiplier
+        use_custom_costs: bool,
+        cache: &Cache,
+    ) -> Result<Self, Error> {
         let dir = if p.starts_with("http") {
             eprintln!("downloading questions from google drive");
-            downloading_questions_from_gdrive(p, use_cached_questions).await?
+            downloading_questions_from_gdrive(p, use_cached_questions, cache).await?
         } else {
             PathBuf::from(&p)
         };
 
         eprintln!("{:?}", dir);
+        if !ffprobe_installed() {
+            eprintln!("ffprobe is not installed, audio questions use the fixed audio delay");
+        }
         let mut questions_storage = HashMap::new();
+        let mut pending_attachments = HashMap::new();
 
         let mut tours = vec![];
         let mut cats_in_bags = vec![];
         let mut manual_questions = vec![];
         let mut auctions = vec![];
-        let mut i = 1;
-        loop {
-            let multiplier = 100 * i;
-            let file = dir.join(format!("tour{}.csv", i));
-            if !file.exists() {
-                break;
-            }
+        let mut stakes = vec![];
+        let mut single_answer_questions = vec![];
+        let mut custom_costs = HashMap::new();
+        for (file, multiplier) in tour_files(&dir)? {
             eprintln!("opening {:?}", file);
 
             let mut topics = vec![];
@@ -87,23 +183,60 @@ impl CsvQuestionsStorage {
                     .from_reader(file);
             let mut current_topic: Option<String> = None;
             let mut current_difficulty = 0;
+            // Costs of the current topic, a cost identifies the question on the board
+            let mut current_costs = HashSet::new();
+            let mut title = None;
+            let mut blitz = false;
+            // All regular questions of the tour are single answer
+            let mut single_answer_tour = false;
 
             for r in reader.records() {
                 let record = r?;
+                if let Some(tour_title) = record.get(0).and_then(check_if_tour_title) {
+                    eprintln!("Tour title {}", tour_title);
+                    title = Some(tour_title);
+                    continue;
+                }
+                if record.get(0).map(check_if_blitz) == Some(true) {
+                    eprintln!("Blitz tour");
+                    blitz = true;
+                    continue;
+                }
+                if record.get(0).map(check_if_single_answer_tour) == Some(true) {
+                    eprintln!("Single answer tour");
+                    single_answer_tour = true;
+                    continue;
+                }
                 if record.len() < 5 {
                     let msg = format!("incorrect number of field: {} < 4", record.len());
                     return Err(err_msg(msg));
                 }
                 let topic = record.get(0).unwrap().to_string();
-                // second field is cost, we ignore it here
-                let attachment = record.get(2).unwrap();
-                let (image, audio) = if !attachment.is_empty() {
-                    parse_attachment(attachment, google_api_key.clone()).await?
+                let custom_cost = if use_custom_costs {
+                    parse_cost(record.get(1).unwrap())?
                 } else {
-                    (None, None)
+                    None
                 };
+                // Several attachments can be put on separate lines of the cell
+                let attachments: Vec<String> = record
+                    .get(2)
+                    .unwrap()
+                    .lines()
+                    .map(|line| line.trim().to_string())
+                    .filter(|line| !line.is_empty())
+                    .collect();
                 let question = record.get(3).unwrap();
-                let answer = record.get(4).unwrap();
+                let (question, single_answer) = match check_if_single_answer(question) {
+                    Some(question) => (question, true),
+                    None => (question.to_string(), single_answer_tour),
+                };
+                let (question, read_aloud) = match check_if_read_aloud(question.to_string()) {
+                    Some(question) => (question, true),
+                    None => (question.to_string(), false),
+                };
+                let question = question.as_str();
+                let (answer, alt_answers) = parse_answers(record.get(4).unwrap());
+                let answer = answer.as_str();
                 let comment = record.get(5);
                 let comment = if comment == Some(&"".to_string()) {
                     None
@@ -119,14 +252,25 @@ impl CsvQuestionsStorage {
                     });
                     current_topic = Some(topic.clone());
                     current_difficulty = 1;
+                    current_costs.clear();
                 }
                 match current_topic {
                     Some(ref current_topic) => {
+                        let cost = custom_cost.unwrap_or(current_difficulty * multiplier);
+                        if !current_costs.insert(cost) {
+                            return Err(err_msg(format!(
+                                "topic '{}' has several questions for {}",
+                                current_topic, cost
+                            )));
+                        }
+                        if let Some(custom_cost) = custom_cost {
+                            custom_costs.insert((current_topic.clone(), current_difficulty), custom_cost);
+                        }
 
                         let mut question = if let Some((cat_in_bag_topic, question)) = check_if_cat_in_bag(question.to_string())? {
                             let cat_in_bag = CatInBag {
                                 old_topic: current_topic.clone(),
-                                cost: current_difficulty * multiplier,
+                                cost,
                                 new_topic: cat_in_bag_topic,
                                 question: question.clone(),
                                 answer: answer.to_string(),
@@ -134,21 +278,41 @@ impl CsvQuestionsStorage {
                             cats_in_bags.push(cat_in_bag);
                             Question::new(question, answer.to_string(), comment.map(|c| c.to_string()))
                         } else if let Some(question) = check_if_manual(question.to_string())? {
-                            manual_questions.push((current_topic.clone(), current_difficulty * multiplier));
+                            manual_questions.push((current_topic.clone(), cost));
                             Question::new(question, answer.to_string(), comment.map(|c| c.to_string()))
                         } else if let Some(question) = check_if_auction(question.to_string())? {
-                            auctions.push((current_topic.clone(), current_difficulty * multiplier));
+                            auctions.push((current_topic.clone(), cost));
+                            Question::new(question, answer.to_string(), comment.map(|c| c.to_string()))
+                        } else if let Some(question) = check_if_stake(question.to_string()) {
+                            stakes.push((current_topic.clone(), cost));
                             Question::new(question, answer.to_string(), comment.map(|c| c.to_string()))
                         } else {
+                            if single_answer {
+                                single_answer_questions.push((current_topic.clone(), cost));
+                            }
                             Question::new(question, &answer, comment)
                         };
-                        if let Some(image) = image {
-                            question.set_image(image);
+                        question.set_alt_answers(alt_answers);
+                        let key = (current_topic.clone(), current_difficulty);
+                        check_attachments(&attachments)?;
+                        if attachment_settings.prefetch {
+                            add_attachments(&mut question, &attachments, &attachment_settings, cache).await?;
+                        } else if !attachments.is_empty() {
+                            pending_attachments.insert(key.clone(), attachments);
                         }
-                        if let Some(audio) = audio {
-                            question.set_audio(audio);
+                        // Audio attachment, if there is one, replaces the synthesized speech
+                        if read_aloud && question.audio().is_none() {
+                            match tts {
+                                Some(ref tts) => {
+                                    let speech = synthesize_speech(question.question(), tts, cache).await?;
+                                    set_audio(&mut question, Media::File(speech));
+                                }
+                                None => {
+                                    eprintln!("question should be read aloud, but tts is not configured");
+                                }
+                            }
                         }
-                        questions_storage.insert((current_topic.clone(), current_difficulty), question);
+                        questions_storage.insert(key, question);
                     }
                     None => {
                         return Err(err_msg("current topic is empty"));
@@ -156,30 +320,90 @@ impl CsvQuestionsStorage {
                 }
             }
 
+            if topics.is_empty() {
+                eprintln!("warning: tour with multiplier {} has no topics", multiplier);
+            }
             tours.push(TourDescription {
                 multiplier,
+                title,
+                blitz,
                 topics,
             });
-            i += 1;
         }
 
         eprintln!("Found {} cats in bags", cats_in_bags.len());
         eprintln!("Found {} manual questions", manual_questions.len());
         eprintln!("Found {} auctions", auctions.len());
+        eprintln!("Found {} stake questions", stakes.len());
+        eprintln!("Found {} single answer questions", single_answer_questions.len());
+        eprintln!("Found {} custom costs", custom_costs.len());
+        eprintln!("{} questions have attachments to download later", pending_attachments.len());
 
         Ok(Self {
-            questions: questions_storage,
+            questions: Mutex::new(questions_storage),
+            pending_attachments: Mutex::new(pending_attachments),
+            loading: Mutex::new(HashMap::new()),
+            attachment_errors: Mutex::new(vec![]),
+            attachment_settings,
+            cache: cache.clone(),
             tours,
             cats_in_bags,
             manual_questions,
             auctions,
+            stakes,
+            single_answer_questions,
+            custom_costs,
         })
     }
 }
 
-async fn downloading_questions_from_gdrive(url: String, use_cached_questions: bool) -> Result<PathBuf, Error> {
+const TOURS_MANIFEST: &str = "tours.json";
+
+// Entry of the manifest, tours are played in the order of the manifest
+#[derive(Deserialize)]
+struct ManifestTour {
+    file: String,
+    // By default it's 100 for the first tour, 200 for the second etc
+    multiplier: Option<usize>,
+}
+
+// Files of the tours and their multipliers. They are listed in the manifest
+// if it exists, otherwise these are tour1.csv, tour2.csv, ...
+fn tour_files(dir: &Path) -> Result<Vec<(PathBuf, usize)>, Error> {
+    let manifest = dir.join(TOURS_MANIFEST);
+    if !manifest.exists() {
+        let mut res = vec![];
+        for i in 1.. {
+            let file = dir.join(format!("tour{}.csv", i));
+            if !file.exists() {
+                break;
+            }
+            res.push((file, 100 * i));
+        }
+        return Ok(res);
+    }
+
+    eprintln!("reading tours from {:?}", manifest);
+    let tours: Vec<ManifestTour> = serde_json::from_reader(File::open(&manifest)?)
+        .map_err(|err| err_msg(format!("invalid tours manifest {:?}: {}", manifest, err)))?;
+    let mut res = vec![];
+    for (i, tour) in tours.into_iter().enumerate() {
+        let file = dir.join(&tour.file);
+        if !file.exists() {
+            return Err(err_msg(format!("{:?} from the tours manifest does not exist", file)));
+        }
+        res.push((file, tour.multiplier.unwrap_or(100 * (i + 1))));
+    }
+    Ok(res)
+}
+
+async fn downloading_questions_from_gdrive(
+    url: String,
+    use_cached_questions: bool,
+    cache: &Cache,
+) -> Result<PathBuf, Error> {
     
-    let p = PathBuf::from("downloaded_questions");
+    let p = cache.path("downloaded_questions");
     if use_cached_questions {
         eprintln!("using cached questions");
         for i in 1..4 {
@@ -215,19 +439,143 @@ async fn downloading_questions_from_gdrive(url: String, use_cached_questions: bo
     Ok(p)
 }
 
-async fn parse_attachment(attachment: &str, google_api_key: Option<String>) -> Result<(Option<PathBuf>, Option<PathBuf>), Error> {
+enum Attachment {
+    Image(Media),
+    Audio(Media),
+    // Gif or a short video clip
+    Animation(Media),
+    // Pdf or another file which is sent as is
+    Document(Media),
+}
+
+async fn add_attachments(
+    question: &mut Question,
+    attachments: &[String],
+    settings: &AttachmentSettings,
+    cache: &Cache,
+) -> Result<(), Error> {
+    let mut images = vec![];
+    let mut audio = None;
+    let mut animation = None;
+    let mut document = None;
+    for attachment in attachments {
+        match parse_attachment(attachment, settings, cache).await? {
+            Attachment::Image(image) => images.push(image),
+            Attachment::Audio(path) => {
+                if audio.is_some() {
+                    return Err(err_msg("only one audio attachment per question is supported"));
+                }
+                audio = Some(path);
+            }
+            Attachment::Animation(path) => {
+                if animation.is_some() {
+                    return Err(err_msg("only one animation per question is supported"));
+                }
+                animation = Some(path);
+            }
+            Attachment::Document(path) => {
+                if document.is_some() {
+                    return Err(err_msg("only one document per question is supported"));
+                }
+                document = Some(path);
+            }
+        }
+    }
+
+    for image in images {
+        question.add_image(image);
+    }
+    if let Some(audio) = audio {
+        set_audio(question, audio);
+    }
+    if let Some(animation) = animation {
+        question.set_animation(animation);
+    }
+    if let Some(document) = document {
+        question.set_document(document);
+    }
+    Ok(())
+}
+
+// Type of the attachment is known before downloading only if it's set
+// explicitly or the url has an extension, other attachments are checked
+// when they are downloaded
+fn check_attachments(attachments: &[String]) -> Result<(), Error> {
+    let mut kinds = HashSet::new();
+    for attachment in attachments {
+        let (kind, uri) = split_attachment(attachment);
+        let kind = match remote_attachment(kind, uri) {
+            Some(Attachment::Audio(_)) => "audio",
+            Some(Attachment::Animation(_)) => "animation",
+            Some(Attachment::Document(_)) => "document",
+            Some(Attachment::Image(_)) | None => continue,
+        };
+        if !kinds.insert(kind) {
+            return Err(err_msg(format!(
+                "only one {} attachment per question is supported: {}",
+                kind, attachment
+            )));
+        }
+    }
+    Ok(())
+}
+
+type AttachmentsLoader = JoinHandle<Result<Question, Error>>;
+
+// Game state asks for questions synchronously, so attachments are downloaded
+// on a separate runtime
+fn load_attachments(
+    mut question: Question,
+    attachments: Vec<String>,
+    settings: &AttachmentSettings,
+    cache: &Cache,
+) -> AttachmentsLoader {
+    let settings = settings.clone();
+    let cache = cache.clone();
+    std::thread::spawn(move || {
+        let mut runtime = tokio_compat::runtime::Runtime::new()?;
+        runtime.block_on_std(add_attachments(&mut question, &attachments, &settings, &cache))?;
+        Ok(question)
+    })
+}
+
+fn split_attachment(attachment: &str) -> (Option<&str>, &str) {
     let split = attachment.splitn(2, " ").collect::<Vec<_>>();
-    let uri = if split.len() == 2 {
-        split[1]
+    if split.len() == 2 {
+        (Some(split[0]), split[1])
     } else {
-        split[0]
-    };
+        (None, split[0])
+    }
+}
+
+fn set_audio(question: &mut Question, audio: Media) {
+    if let Media::File(ref path) = audio {
+        if let Some(duration) = probe_audio_duration(path) {
+            question.set_audio_duration(duration);
+        }
+    }
+    question.set_audio(audio);
+}
 
-    let uri = convert_url(uri.to_string(), google_api_key);
+async fn parse_attachment(
+    attachment: &str,
+    settings: &AttachmentSettings,
+    cache: &Cache,
+) -> Result<Attachment, Error> {
+    let (kind, uri) = split_attachment(attachment);
+
+    if settings.direct_urls && !needs_download(uri) {
+        if let Some(attachment) = remote_attachment(kind, uri) {
+            eprintln!("telegram will fetch {} by itself", uri);
+            return Ok(attachment);
+        }
+    }
+
+    let uri = convert_url(uri.to_string(), settings.google_api_key.clone());
     eprintln!("converted url to {}", uri);
     let mut s = DefaultHasher::new();
     uri.hash(&mut s);
-    let filename = format!("{}", s.finish());
+    let filename = cache.path(s.finish().to_string()).to_string_lossy().to_string();
     
     if !Path::new(&filename).exists() {
         let bytes = download_url(&uri).await?;
@@ -241,8 +589,13 @@ async fn parse_attachment(attachment: &str, google_api_key: Option<String>) -> R
     let maybe_type = infer::get_from_path(filename.clone())?;
     let ty = maybe_type.ok_or_else(|| err_msg(format!("cannot get type of {}", filename)))?;
 
-    if  ty.matcher_type() == infer::MatcherType::Image {
-        Ok((Some(filename.into()), None))
+    if ty.mime_type() == "image/gif" || ty.matcher_type() == infer::MatcherType::Video {
+        Ok(Attachment::Animation(Media::File(filename.into())))
+    } else if ty.matcher_type() == infer::MatcherType::Image {
+        if let Err(err) = compress_image(&filename, &settings.image_compression) {
+            eprintln!("failed to compress {}, using the original: {}", filename, err);
+        }
+        Ok(Attachment::Image(Media::File(filename.into())))
     } else if ty.matcher_type() == infer::MatcherType::Audio {
         // Removes mp3 if they exists
         match id3::Tag::remove_from_path(filename.clone()) {
@@ -257,12 +610,133 @@ async fn parse_attachment(attachment: &str, google_api_key: Option<String>) -> R
             }
         };
 
-        Ok((None, Some(filename.into())))
+        Ok(Attachment::Audio(Media::File(filename.into())))
+    } else if ty.mime_type() == "application/pdf" || ty.matcher_type() == infer::MatcherType::Doc {
+        // Cached files have no extension, but players should see the type of the document
+        let document = format!("{}.{}", filename, ty.extension());
+        if !Path::new(&document).exists() {
+            std::fs::copy(&filename, &document)?;
+        }
+        Ok(Attachment::Document(Media::File(document.into())))
     } else {
         Err(err_msg(format!("invalid attachment type {}", ty)))
     }
 }
 
+fn ffprobe_installed() -> bool {
+    Command::new("ffprobe").arg("-version").output().is_ok()
+}
+
+// Uses ffprobe, None if it's not installed or can't read the file
+fn probe_audio_duration(path: &Path) -> Option<Duration> {
+    let output = Command::new("ffprobe")
+        .args(["-v", "error", "-show_entries", "format=duration", "-of", "csv=p=0"])
+        .arg(path)
+        .output();
+    match output {
+        Ok(output) if output.status.success() => {
+            parse_audio_duration(&String::from_utf8_lossy(&output.stdout))
+        }
+        Ok(output) => {
+            eprintln!("ffprobe failed for {}: {}", path.display(), String::from_utf8_lossy(&output.stderr));
+            None
+        }
+        // Missing ffprobe is reported once when the questions are loaded
+        Err(ref err) if err.kind() == std::io::ErrorKind::NotFound => None,
+        Err(err) => {
+            eprintln!("can't get duration of {}: {}", path.display(), err);
+            None
+        }
+    }
+}
+
+fn parse_audio_duration(s: &str) -> Option<Duration> {
+    let secs: f64 = s.trim().parse().ok()?;
+    if secs.is_finite() && secs > 0.0 {
+        Some(Duration::from_secs_f64(secs))
+    } else {
+        None
+    }
+}
+
+// Replaces the file with a downscaled jpeg if it exceeds the limits.
+// Jpeg may still be larger than max_bytes, so the size of the compressed file
+// is kept in a marker file and it isn't compressed again on the next start
+fn compress_image(filename: &str, config: &ImageCompressionConfig) -> Result<(), Error> {
+    let size = std::fs::metadata(filename)?.len();
+    let marker = format!("{}.compressed", filename);
+    if std::fs::read_to_string(&marker).ok() == Some(size.to_string()) {
+        return Ok(());
+    }
+    // Cached files have no extension, so the format is guessed from the content
+    let (width, height) = image::io::Reader::open(filename)?
+        .with_guessed_format()?
+        .into_dimensions()?;
+    if size <= config.max_bytes && width <= config.max_dimension && height <= config.max_dimension {
+        return Ok(());
+    }
+
+    let mut img = image::io::Reader::open(filename)?.with_guessed_format()?.decode()?;
+    if width > config.max_dimension || height > config.max_dimension {
+        img = img.resize(
+            config.max_dimension,
+            config.max_dimension,
+            image::imageops::FilterType::Lanczos3,
+        );
+    }
+    let mut bytes = vec![];
+    image::codecs::jpeg::JpegEncoder::new_with_quality(&mut bytes, 85).encode_image(&img.to_rgb8())?;
+    eprintln!(
+        "compressed {} from {}x{} {} bytes to {}x{} {} bytes",
+        filename,
+        width,
+        height,
+        size,
+        img.width(),
+        img.height(),
+        bytes.len()
+    );
+    std::fs::write(filename, &bytes)?;
+    std::fs::write(marker, bytes.len().to_string())?;
+    Ok(())
+}
+
+async fn synthesize_speech(text: &str, tts: &TtsConfig, cache: &Cache) -> Result<PathBuf, Error> {
+    let mut s = DefaultHasher::new();
+    tts.url.hash(&mut s);
+    text.hash(&mut s);
+    let filename = cache.path(s.finish().to_string()).to_string_lossy().to_string();
+
+    if !Path::new(&filename).exists() {
+        let https = HttpsConnector::new();
+        let client = Client::builder().build::<_, hyper::Body>(https);
+        let body = serde_json::json!({ "text": text }).to_string();
+        let mut request = hyper::Request::post(tts.url.as_str())
+            .header("Content-Type", "application/json");
+        if let Some(ref api_key) = tts.api_key {
+            request = request.header("Authorization", format!("Bearer {}", api_key));
+        }
+        let request = request.body(hyper::Body::from(body))?;
+
+        let resp = client.request(request).await?;
+        let status = resp.status();
+        if status != hyper::StatusCode::OK {
+            return Err(err_msg(format!("tts failed with error code {}", status)));
+        }
+        let bytes = hyper::body::to_bytes(resp.into_body()).await?;
+        eprintln!("synthesized {}", bytes.len());
+        std::fs::write(filename.clone(), bytes)?;
+    } else {
+        eprintln!("skiping speech synthesis because already synthesized");
+    }
+
+    let maybe_type = infer::get_from_path(filename.clone())?;
+    match maybe_type {
+        Some(ty) if ty.matcher_type() == infer::MatcherType::Audio => Ok(filename.into()),
+        _ => Err(err_msg(format!("tts returned not an audio in {}", filename))),
+    }
+}
+
 async fn download_url(uri: &str) -> Result<hyper::body::Bytes, Error> {
     let https = HttpsConnector::new();
     let client = Client::builder().build::<_, hyper::Body>(https);
@@ -288,6 +762,33 @@ async fn download_url(uri: &str) -> Result<hyper::body::Bytes, Error> {
     Ok(bytes)
 }
 
+// Google drive links need conversion or an api key, so telegram can't
+// fetch them by itself
+fn needs_download(uri: &str) -> bool {
+    let is_http = uri.starts_with("http://") || uri.starts_with("https://");
+    !is_http || uri.contains("drive.google.com") || uri.contains("docs.google.com")
+}
+
+// Attachment type has to be known without downloading the file, so it's taken
+// either from the attachment kind or from the url extension
+fn remote_attachment(kind: Option<&str>, uri: &str) -> Option<Attachment> {
+    let kind = match kind {
+        Some(kind) => kind.to_lowercase(),
+        None => {
+            let path = uri.split(['?', '#']).next().unwrap_or(uri);
+            path.rsplit('.').next().unwrap_or_default().to_lowercase()
+        }
+    };
+    let media = Media::Url(uri.to_string());
+    match kind.as_str() {
+        "image" | "photo" | "jpg" | "jpeg" | "png" | "webp" => Some(Attachment::Image(media)),
+        "audio" | "mp3" | "ogg" | "m4a" => Some(Attachment::Audio(media)),
+        "animation" | "gif" | "video" | "mp4" => Some(Attachment::Animation(media)),
+        "document" | "pdf" => Some(Attachment::Document(media)),
+        _ => None,
+    }
+}
+
 fn convert_url(s: String, google_api_key: Option<String>) -> String {
     let regexes = &[
         "^https://drive.google.com/file/d/([^/])/view",
@@ -330,6 +831,37 @@ fn check_if_cat_in_bag(question: String) -> Result<Option<(String, String)>, Err
     Ok(None)
 }
 
+fn check_if_tour_title(topic: &str) -> Option<String> {
+    let topic = topic.trim();
+    let tour_title = "НАЗВАНИЕ ТУРА:";
+
+    if topic.starts_with(tour_title) {
+        let title = topic.trim_start_matches(tour_title).trim();
+        if !title.is_empty() {
+            return Some(title.to_string());
+        }
+    }
+
+    None
+}
+
+fn check_if_blitz(topic: &str) -> bool {
+    topic.trim() == "БЛИЦ"
+}
+
+const SINGLE_ANSWER: &str = "ОДИН ОТВЕТ";
+
+fn check_if_single_answer_tour(topic: &str) -> bool {
+    topic.trim() == SINGLE_ANSWER
+}
+
+fn check_if_single_answer(question: &str) -> Option<String> {
+    question
+        .trim()
+        .strip_prefix(SINGLE_ANSWER)
+        .map(|question| question.trim().to_string())
+}
+
 fn check_if_manual(question: String) -> Result<Option<String>, Error> {
     let question = question.trim();
     let manual = "РУЧНОЙ";
@@ -342,6 +874,27 @@ fn check_if_manual(question: String) -> Result<Option<String>, Error> {
     return Ok(None);
 }
 
+// Answer cell may contain several acceptable answers separated by '|',
+// the first one is the main answer
+fn parse_answers(answers: &str) -> (String, Vec<String>) {
+    let mut answers = answers.split('|').map(|answer| answer.trim().to_string());
+    let answer = answers.next().unwrap_or_default();
+    let alt_answers = answers.filter(|answer| !answer.is_empty()).collect();
+    (answer, alt_answers)
+}
+
+fn check_if_read_aloud(question: String) -> Option<String> {
+    let question = question.trim();
+    let read_aloud = "ОЗВУЧИТЬ";
+
+    if question.starts_with(read_aloud) {
+        let question = question.trim_start_matches(read_aloud).trim();
+        return Some(question.to_string());
+    }
+
+    None
+}
+
 fn check_if_auction(question: String) -> Result<Option<String>, Error> {
     let question = question.trim();
     let auction = "АУКЦИОН";
@@ -354,9 +907,71 @@ fn check_if_auction(question: String) -> Result<Option<String>, Error> {
     return Ok(None);
 }
 
+fn check_if_stake(question: String) -> Option<String> {
+    let question = question.trim();
+    question
+        .strip_prefix("СТАВКА")
+        .map(|question| question.trim().to_string())
+}
+
+// Empty cell means the default cost
+fn parse_cost(cost: &str) -> Result<Option<usize>, Error> {
+    let cost = cost.trim();
+    if cost.is_empty() {
+        return Ok(None);
+    }
+    match cost.parse::<usize>() {
+        Ok(cost) if cost > 0 => Ok(Some(cost)),
+        _ => Err(err_msg(format!("invalid cost '{}'", cost))),
+    }
+}
+
+impl CsvQuestionsStorage {
+    fn start_loading(&self, key: &(String, usize)) {
+        let attachments = match self.pending_attachments.lock().unwrap().remove(key) {
+            Some(attachments) => attachments,
+            None => return,
+        };
+        if let Some(question) = self.questions.lock().unwrap().get(key).cloned() {
+            eprintln!("downloading attachments of {} {}", key.0, key.1);
+            let loading = load_attachments(question, attachments, &self.attachment_settings, &self.cache);
+            self.loading.lock().unwrap().insert(key.clone(), loading);
+        }
+    }
+}
+
 impl QuestionsStorage for CsvQuestionsStorage {
     fn get(&self, topic_name: String, difficulty: usize) -> Option<Question> {
-        self.questions.get(&(topic_name, difficulty)).cloned()
+        let key = (topic_name, difficulty);
+        self.start_loading(&key);
+        let loading = self.loading.lock().unwrap().remove(&key);
+        let mut questions = self.questions.lock().unwrap();
+        if let Some(loading) = loading {
+            // Usually attachments are downloaded while the cost is chosen,
+            // otherwise the game waits for them
+            let loaded = loading
+                .join()
+                .map_err(|_| err_msg("attachments loader panicked"))
+                .and_then(|loaded| loaded);
+            match loaded {
+                Ok(question) => {
+                    questions.insert(key.clone(), question);
+                }
+                Err(err) => {
+                    // The question is still asked, just without attachments
+                    eprintln!("failed to load attachments of {} {}: {}", key.0, key.1, err);
+                    self.attachment_errors.lock().unwrap().push(format!(
+                        "Attachments of {} {} failed to download, the question is asked without them: {}",
+                        key.0, key.1, err
+                    ));
+                }
+            }
+        }
+        questions.get(&key).cloned()
+    }
+
+    fn contains(&self, topic_name: String, difficulty: usize) -> bool {
+        self.questions.lock().unwrap().contains_key(&(topic_name, difficulty))
     }
 
     fn get_tours(&self) -> Vec<TourDescription> {
@@ -374,4 +989,335 @@ impl QuestionsStorage for CsvQuestionsStorage {
     fn get_auctions(&self) -> Vec<(String, usize)> {
         self.auctions.clone()
     }
+
+    fn get_stakes(&self) -> Vec<(String, usize)> {
+        self.stakes.clone()
+    }
+
+    fn get_single_answer_questions(&self) -> Vec<(String, usize)> {
+        self.single_answer_questions.clone()
+    }
+
+    fn get_custom_costs(&self) -> HashMap<(String, usize), usize> {
+        self.custom_costs.clone()
+    }
+
+    fn prefetch(&self, questions: Vec<(String, usize)>) {
+        for key in questions {
+            self.start_loading(&key);
+        }
+    }
+
+    fn take_attachment_errors(&self) -> Vec<String> {
+        std::mem::take(&mut *self.attachment_errors.lock().unwrap())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn dimensions(filename: &str) -> (u32, u32) {
+        image::io::Reader::open(filename)
+            .unwrap()
+            .with_guessed_format()
+            .unwrap()
+            .into_dimensions()
+            .unwrap()
+    }
+
+    fn remote_media(attachment: Option<Attachment>) -> Option<(&'static str, String)> {
+        match attachment? {
+            Attachment::Image(Media::Url(url)) => Some(("image", url)),
+            Attachment::Audio(Media::Url(url)) => Some(("audio", url)),
+            Attachment::Animation(Media::Url(url)) => Some(("animation", url)),
+            Attachment::Document(Media::Url(url)) => Some(("document", url)),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_tour_files() {
+        let dir = std::env::temp_dir().join("svoyak_bot_test_tour_files");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        for name in &["tour1.csv", "tour2.csv", "history.csv", "finals.csv"] {
+            std::fs::write(dir.join(name), "").unwrap();
+        }
+        assert_eq!(
+            tour_files(&dir).unwrap(),
+            vec![(dir.join("tour1.csv"), 100), (dir.join("tour2.csv"), 200)]
+        );
+
+        std::fs::write(
+            dir.join(TOURS_MANIFEST),
+            r#"[{"file": "history.csv"}, {"file": "finals.csv", "multiplier": 500}]"#,
+        )
+        .unwrap();
+        assert_eq!(
+            tour_files(&dir).unwrap(),
+            vec![(dir.join("history.csv"), 100), (dir.join("finals.csv"), 500)]
+        );
+
+        std::fs::write(dir.join(TOURS_MANIFEST), r#"[{"file": "missing.csv"}]"#).unwrap();
+        assert!(tour_files(&dir).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_tour_without_topics() {
+        let dir = std::env::temp_dir().join("svoyak_bot_test_tour_without_topics");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("tour1.csv"), "Sport,100,,2 * 2 = ?,4,\n,200,,3 * 2 = ?,6,\n").unwrap();
+        // Only the title, topic cells are missing
+        std::fs::write(dir.join("tour2.csv"), "НАЗВАНИЕ ТУРА: Пустой\n").unwrap();
+        std::fs::write(dir.join("tour3.csv"), "Movies,300,,4 * 2 = ?,8,\n").unwrap();
+        let cache = Cache::new(dir.join("cache")).unwrap();
+
+        let storage = futures_03::executor::block_on(CsvQuestionsStorage::new(
+            dir.to_str().unwrap().to_string(),
+            false,
+            None,
+            AttachmentSettings::default(),
+            false,
+            &cache,
+        ))
+        .unwrap();
+        let tours = storage.get_tours();
+        assert_eq!(tours.len(), 3);
+        assert_eq!(tours[0].topics.len(), 1);
+        assert!(tours[1].topics.is_empty());
+        assert_eq!(tours[1].title, Some("Пустой".to_string()));
+        assert_eq!(tours[2].topics[0].name, "Movies");
+        assert!(storage.get("Sport".to_string(), 2).is_some());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_single_answer_questions() {
+        let dir = std::env::temp_dir().join("svoyak_bot_test_single_answer_questions");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("tour1.csv"),
+            "Sport,100,,ОДИН ОТВЕТ 2 * 2 = ?,4,\n,200,,3 * 2 = ?,6,\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("tour2.csv"),
+            "ОДИН ОТВЕТ,,,,,\nMovies,200,,4 * 2 = ?,8,\n,400,,АУКЦИОН 5 * 2 = ?,10,\n",
+        )
+        .unwrap();
+        let cache = Cache::new(dir.join("cache")).unwrap();
+
+        let storage = futures_03::executor::block_on(CsvQuestionsStorage::new(
+            dir.to_str().unwrap().to_string(),
+            false,
+            None,
+            AttachmentSettings::default(),
+            false,
+            &cache,
+        ))
+        .unwrap();
+        assert_eq!(
+            storage.get_single_answer_questions(),
+            vec![("Sport".to_string(), 100), ("Movies".to_string(), 200)]
+        );
+        assert_eq!(storage.get("Sport".to_string(), 1).unwrap().question(), "2 * 2 = ?");
+        assert_eq!(storage.get_tours()[1].topics.len(), 1);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_custom_costs() {
+        let dir = std::env::temp_dir().join("svoyak_bot_test_custom_costs");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("tour1.csv"),
+            "Sport,50,,2 * 2 = ?,4,\n,150,,АУКЦИОН 3 * 2 = ?,6,\n,,,4 * 2 = ?,8,\n",
+        )
+        .unwrap();
+        let cache = Cache::new(dir.join("cache")).unwrap();
+        let load = |use_custom_costs| {
+            futures_03::executor::block_on(CsvQuestionsStorage::new(
+                dir.to_str().unwrap().to_string(),
+                false,
+                None,
+                AttachmentSettings::default(),
+                use_custom_costs,
+                &cache,
+            ))
+        };
+
+        let storage = load(true).unwrap();
+        let custom_costs = storage.get_custom_costs();
+        assert_eq!(custom_costs.len(), 2);
+        assert_eq!(custom_costs.get(&("Sport".to_string(), 1)), Some(&50));
+        assert_eq!(custom_costs.get(&("Sport".to_string(), 2)), Some(&150));
+        assert_eq!(storage.get_auctions(), vec![("Sport".to_string(), 150)]);
+
+        // The column is ignored unless custom costs are enabled
+        let storage = load(false).unwrap();
+        assert!(storage.get_custom_costs().is_empty());
+        assert_eq!(storage.get_auctions(), vec![("Sport".to_string(), 200)]);
+
+        std::fs::write(dir.join("tour1.csv"), "Sport,много,,2 * 2 = ?,4,\n").unwrap();
+        assert!(load(true).is_err());
+
+        // Question for 200 would clash with the default cost of the second one
+        std::fs::write(dir.join("tour1.csv"), "Sport,200,,2 * 2 = ?,4,\n,,,3 * 2 = ?,6,\n").unwrap();
+        assert!(load(true).is_err());
+        std::fs::write(dir.join("tour1.csv"), "Sport,300,,2 * 2 = ?,4,\n,300,,3 * 2 = ?,6,\n").unwrap();
+        assert!(load(true).is_err());
+        // Same cost in different topics is fine
+        std::fs::write(dir.join("tour1.csv"), "Sport,300,,2 * 2 = ?,4,\nMovies,300,,3 * 2 = ?,6,\n").unwrap();
+        assert!(load(true).is_ok());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_lazy_attachments() {
+        let dir = std::env::temp_dir().join("svoyak_bot_test_lazy_attachments");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("tour1.csv"),
+            "Sport,100,https://example.com/cat.png,2 * 2 = ?,4,\n,200,,3 * 2 = ?,6,\n",
+        )
+        .unwrap();
+        let cache = Cache::new(dir.join("cache")).unwrap();
+
+        let settings = AttachmentSettings {
+            direct_urls: true,
+            ..AttachmentSettings::default()
+        };
+        let storage = futures_03::executor::block_on(CsvQuestionsStorage::new(
+            dir.to_str().unwrap().to_string(),
+            false,
+            None,
+            settings,
+            false,
+            &cache,
+        ))
+        .unwrap();
+        assert!(storage.contains("Sport".to_string(), 1));
+        assert_eq!(storage.pending_attachments.lock().unwrap().len(), 1);
+
+        storage.prefetch(vec![("Sport".to_string(), 1), ("Sport".to_string(), 2)]);
+        assert!(storage.pending_attachments.lock().unwrap().is_empty());
+        let question = storage.get("Sport".to_string(), 1).unwrap();
+        assert_eq!(question.images(), &vec![Media::Url("https://example.com/cat.png".to_string())]);
+        assert!(storage.pending_attachments.lock().unwrap().is_empty());
+        // Attachments are loaded only once
+        assert_eq!(storage.get("Sport".to_string(), 1), Some(question));
+        assert!(storage.get("Sport".to_string(), 2).unwrap().images().is_empty());
+        assert!(storage.take_attachment_errors().is_empty());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_failed_attachments() {
+        let dir = std::env::temp_dir().join("svoyak_bot_test_failed_attachments");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let cache = Cache::new(dir.join("cache")).unwrap();
+        let load = |attachments: &str| {
+            std::fs::write(dir.join("tour1.csv"), format!("Sport,100,\"{}\",2 * 2 = ?,4,\n", attachments)).unwrap();
+            futures_03::executor::block_on(CsvQuestionsStorage::new(
+                dir.to_str().unwrap().to_string(),
+                false,
+                None,
+                AttachmentSettings::default(),
+                false,
+                &cache,
+            ))
+        };
+
+        assert!(load("https://example.com/a.mp3\naudio https://example.com/b").is_err());
+        assert!(load("https://example.com/a.gif\nhttps://example.com/b.mp4").is_err());
+        assert!(load("https://example.com/a.png\nhttps://example.com/b.png\nhttps://example.com/c.mp3").is_ok());
+
+        // The question is asked without attachments, and the error is reported once
+        let storage = load("missing.png").unwrap();
+        assert!(storage.get("Sport".to_string(), 1).unwrap().images().is_empty());
+        assert_eq!(storage.take_attachment_errors().len(), 1);
+        assert!(storage.take_attachment_errors().is_empty());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_remote_attachment() {
+        assert!(needs_download("https://drive.google.com/file/d/1MAbfX6ar3sXx3oCkXRylAwA/view"));
+        assert!(needs_download("https://docs.google.com/uc?export=download&id=1pZpkki36fMKr"));
+        assert!(needs_download("local.png"));
+        assert!(!needs_download("https://example.com/cat.png"));
+
+        assert_eq!(
+            remote_media(remote_attachment(Some("audio"), "https://example.com/song")),
+            Some(("audio", "https://example.com/song".to_string()))
+        );
+        assert_eq!(
+            remote_media(remote_attachment(None, "https://example.com/cat.JPG?size=big")),
+            Some(("image", "https://example.com/cat.JPG?size=big".to_string()))
+        );
+        assert_eq!(
+            remote_media(remote_attachment(None, "https://example.com/funny.gif")),
+            Some(("animation", "https://example.com/funny.gif".to_string()))
+        );
+        assert_eq!(
+            remote_media(remote_attachment(None, "https://example.com/notes.pdf")),
+            Some(("document", "https://example.com/notes.pdf".to_string()))
+        );
+        assert!(remote_attachment(None, "https://example.com/download").is_none());
+    }
+
+    #[test]
+    fn test_parse_audio_duration() {
+        assert_eq!(parse_audio_duration("12.500000\n"), Some(Duration::from_millis(12500)));
+        assert_eq!(parse_audio_duration("N/A"), None);
+        assert_eq!(parse_audio_duration("0.000000"), None);
+    }
+
+    #[test]
+    fn test_compress_image() {
+        let filename = std::env::temp_dir().join("svoyak_bot_test_compress_image");
+        let filename = filename.to_str().unwrap();
+        let config = ImageCompressionConfig {
+            max_dimension: 100,
+            max_bytes: 1024 * 1024,
+        };
+
+        image::RgbImage::new(50, 40)
+            .save_with_format(filename, image::ImageFormat::Png)
+            .unwrap();
+        compress_image(filename, &config).unwrap();
+        assert_eq!(dimensions(filename), (50, 40));
+        assert_eq!(infer::get_from_path(filename).unwrap().unwrap().mime_type(), "image/png");
+
+        image::RgbImage::new(400, 200)
+            .save_with_format(filename, image::ImageFormat::Png)
+            .unwrap();
+        compress_image(filename, &config).unwrap();
+        assert_eq!(dimensions(filename), (100, 50));
+        assert_eq!(infer::get_from_path(filename).unwrap().unwrap().mime_type(), "image/jpeg");
+
+        // Jpeg is still above the byte limit, but it's not compressed again
+        let compressed = std::fs::read(filename).unwrap();
+        let config = ImageCompressionConfig {
+            max_dimension: 100,
+            max_bytes: 1,
+        };
+        compress_image(filename, &config).unwrap();
+        assert_eq!(std::fs::read(filename).unwrap(), compressed);
+
+        std::fs::remove_file(filename).unwrap();
+        std::fs::remove_file(format!("{}.compressed", filename)).unwrap();
+    }
 }
@@ -0,0 +1,276 @@
+use failure::Error;
+use rusqlite::{params, Connection, OptionalExtension};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::player::Player;
+
+pub struct LeaderboardEntry {
+    pub name: String,
+    pub total_score: i64,
+    pub games: i64,
+    pub wins: i64,
+}
+
+// Stores final scores of finished games, so that results survive bot restarts
+pub struct ResultsDb {
+    conn: Connection,
+}
+
+impl ResultsDb {
+    pub fn open(path: &str) -> Result<Self, Error> {
+        let conn = Connection::open(path)?;
+        Self::init(conn)
+    }
+
+    #[cfg(test)]
+    fn open_in_memory() -> Result<Self, Error> {
+        Self::init(Connection::open_in_memory()?)
+    }
+
+    fn init(conn: Connection) -> Result<Self, Error> {
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS games (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                finished_at INTEGER NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS results (
+                game_id INTEGER NOT NULL REFERENCES games(id),
+                user_id INTEGER NOT NULL,
+                name TEXT NOT NULL,
+                score INTEGER NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS ratings (
+                user_id INTEGER PRIMARY KEY,
+                name TEXT NOT NULL,
+                rating REAL NOT NULL
+            );",
+        )?;
+        Ok(Self { conn })
+    }
+
+    pub fn record_game(&mut self, scores: &[(Player, i64)]) -> Result<(), Error> {
+        let finished_at = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
+
+        let tx = self.conn.transaction()?;
+        tx.execute("INSERT INTO games (finished_at) VALUES (?1)", params![finished_at])?;
+        let game_id = tx.last_insert_rowid();
+        for (player, score) in scores {
+            let user_id: i64 = player.id().into();
+            tx.execute(
+                "INSERT INTO results (game_id, user_id, name, score) VALUES (?1, ?2, ?3, ?4)",
+                params![game_id, user_id, player.name(), score],
+            )?;
+        }
+
+        let mut prior_ratings = vec![];
+        for (player, _) in scores {
+            let user_id: i64 = player.id().into();
+            let rating = tx
+                .query_row(
+                    "SELECT rating FROM ratings WHERE user_id = ?1",
+                    params![user_id],
+                    |row| row.get(0),
+                )
+                .optional()?
+                .unwrap_or(INITIAL_RATING);
+            prior_ratings.push(rating);
+        }
+        let placements: Vec<_> = scores
+            .iter()
+            .map(|(_, score)| scores.iter().filter(|(_, other)| other > score).count())
+            .collect();
+        let new_ratings = update_ratings(&placements, &prior_ratings);
+        for ((player, _), rating) in scores.iter().zip(new_ratings) {
+            let user_id: i64 = player.id().into();
+            tx.execute(
+                "INSERT INTO ratings (user_id, name, rating) VALUES (?1, ?2, ?3)
+                ON CONFLICT(user_id) DO UPDATE SET name = ?2, rating = ?3",
+                params![user_id, player.name(), rating],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    // Aggregated results of all games. Players are matched by user id, the
+    // latest name is displayed
+    pub fn leaderboard(&self) -> Result<Vec<LeaderboardEntry>, Error> {
+        let mut stmt = self.conn.prepare(
+            "SELECT
+                (SELECT name FROM results last WHERE last.user_id = r.user_id
+                    ORDER BY last.game_id DESC LIMIT 1),
+                SUM(r.score),
+                COUNT(*),
+                SUM(CASE WHEN r.score = (SELECT MAX(score) FROM results best
+                    WHERE best.game_id = r.game_id) THEN 1 ELSE 0 END) AS wins
+            FROM results r
+            GROUP BY r.user_id
+            ORDER BY SUM(r.score) DESC, wins DESC, 1",
+        )?;
+        let entries = stmt
+            .query_map(params![], |row| {
+                Ok(LeaderboardEntry {
+                    name: row.get(0)?,
+                    total_score: row.get(1)?,
+                    games: row.get(2)?,
+                    wins: row.get(3)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(entries)
+    }
+
+    pub fn ratings(&self) -> Result<Vec<(String, f64)>, Error> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT name, rating FROM ratings ORDER BY rating DESC, name")?;
+        let ratings = stmt
+            .query_map(params![], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(ratings)
+    }
+}
+
+const INITIAL_RATING: f64 = 1500.0;
+const RATING_K_FACTOR: f64 = 32.0;
+
+// Multiplayer ELO: every game is treated as a set of pairwise matches between
+// all players. `placements` are places of the players in the game (0 is the
+// best, equal placements mean a draw), `ratings` are their ratings before the game.
+pub fn update_ratings(placements: &[usize], ratings: &[f64]) -> Vec<f64> {
+    let n = ratings.len();
+    if n < 2 {
+        return ratings.to_vec();
+    }
+
+    let mut new_ratings = vec![];
+    for i in 0..n {
+        let mut delta = 0.0;
+        for j in 0..n {
+            if i == j {
+                continue;
+            }
+            let expected = 1.0 / (1.0 + 10f64.powf((ratings[j] - ratings[i]) / 400.0));
+            let actual = if placements[i] < placements[j] {
+                1.0
+            } else if placements[i] == placements[j] {
+                0.5
+            } else {
+                0.0
+            };
+            delta += actual - expected;
+        }
+        new_ratings.push(ratings[i] + RATING_K_FACTOR * delta / (n - 1) as f64);
+    }
+    new_ratings
+}
+
+pub fn format_ratings(ratings: &[(String, f64)]) -> String {
+    if ratings.is_empty() {
+        return "Ещё не сыграно ни одной игры".to_string();
+    }
+
+    let mut res = "Рейтинг игроков:".to_string();
+    for (i, (name, rating)) in ratings.iter().enumerate() {
+        res += &format!("\n{}. {}: {:.0}", i + 1, name, rating);
+    }
+    res
+}
+
+pub fn format_leaderboard(entries: &[LeaderboardEntry]) -> String {
+    if entries.is_empty() {
+        return "Ещё не сыграно ни одной игры".to_string();
+    }
+
+    let mut res = "Таблица лидеров:".to_string();
+    let mut rank = 0;
+    for (i, entry) in entries.iter().enumerate() {
+        // Players with the same score and number of wins share the rank
+        let tie = i > 0
+            && entries[i - 1].total_score == entry.total_score
+            && entries[i - 1].wins == entry.wins;
+        if !tie {
+            rank = i + 1;
+        }
+        res += &format!(
+            "\n{}. {}: {} (игр: {}, побед: {})",
+            rank,
+            entry.name,
+            entry.total_score,
+            entry.games,
+            entry.wins
+        );
+    }
+    res
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use telegram_bot::UserId;
+
+    #[test]
+    fn test_update_ratings() {
+        // Single player doesn't change the rating
+        assert_eq!(update_ratings(&[0], &[1500.0]), vec![1500.0]);
+
+        // Equal ratings, winner gets half of K factor
+        assert_eq!(update_ratings(&[0, 1], &[1500.0, 1500.0]), vec![1516.0, 1484.0]);
+
+        // Draw between equal players changes nothing
+        assert_eq!(update_ratings(&[0, 0], &[1500.0, 1500.0]), vec![1500.0, 1500.0]);
+
+        // Favourite wins less than an underdog would
+        let favourite_won = update_ratings(&[0, 1], &[1700.0, 1500.0]);
+        let underdog_won = update_ratings(&[1, 0], &[1700.0, 1500.0]);
+        assert!(favourite_won[0] - 1700.0 < underdog_won[1] - 1500.0);
+
+        // Ratings are only redistributed
+        let new_ratings = update_ratings(&[0, 1, 1, 3], &[1600.0, 1500.0, 1450.0, 1400.0]);
+        let total: f64 = new_ratings.iter().sum();
+        assert!((total - 5950.0).abs() < 1e-9);
+        assert!(new_ratings[0] > 1600.0);
+        assert!(new_ratings[3] < 1400.0);
+        assert!(new_ratings[1] - 1500.0 < new_ratings[2] - 1450.0);
+    }
+
+    #[test]
+    fn test_ratings() {
+        let mut db = ResultsDb::open_in_memory().unwrap();
+        let p1 = Player::new("first".to_string(), UserId::from(1), None);
+        let p2 = Player::new("second".to_string(), UserId::from(2), None);
+        db.record_game(&[(p1, 300), (p2, 100)]).unwrap();
+
+        assert_eq!(
+            format_ratings(&db.ratings().unwrap()),
+            "Рейтинг игроков:\n1. first: 1516\n2. second: 1484"
+        );
+    }
+
+    #[test]
+    fn test_leaderboard() {
+        let mut db = ResultsDb::open_in_memory().unwrap();
+        assert_eq!(format_leaderboard(&db.leaderboard().unwrap()), "Ещё не сыграно ни одной игры");
+
+        let p1 = Player::new("first".to_string(), UserId::from(1), None);
+        let p2 = Player::new("second".to_string(), UserId::from(2), None);
+        db.record_game(&[(p1.clone(), 300), (p2.clone(), 100)]).unwrap();
+
+        let p1_renamed = Player::new("first renamed".to_string(), UserId::from(1), None);
+        db.record_game(&[(p1_renamed, 100), (p2.clone(), 500)]).unwrap();
+        db.record_game(&[(p2, -100)]).unwrap();
+
+        assert_eq!(
+            format_leaderboard(&db.leaderboard().unwrap()),
+            "Таблица лидеров:\n1. second: 500 (игр: 3, побед: 2)\n2. first renamed: 400 (игр: 2, побед: 1)"
+        );
+
+        let p3 = Player::new("third".to_string(), UserId::from(3), None);
+        db.record_game(&[(p3, 400)]).unwrap();
+        assert_eq!(
+            format_leaderboard(&db.leaderboard().unwrap()),
+            "Таблица лидеров:\n1. second: 500 (игр: 3, побед: 2)\n\
+             2. first renamed: 400 (игр: 2, побед: 1)\n2. third: 400 (игр: 1, побед: 1)"
+        );
+    }
+}
@@ -0,0 +1,512 @@
+use ab_glyph::{point, Font, FontRef, PxScale, ScaleFont};
+use failure::{err_msg, Error};
+use image::{Rgb, RgbImage};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::gamestate::{GameSummary, ScoreTable};
+
+// Native renderer of the score table, it draws the same picture as
+// external/draw_table.py but doesn't need python. The font is bundled
+// because system fonts often have no cyrillic glyphs
+const FONT: &[u8] = include_bytes!("../external/fonts/DejaVuSans.ttf");
+
+const TOPIC_WIDTH: u32 = 800;
+const SCORE_WIDTH: u32 = 100;
+const ROW_HEIGHT: u32 = 70;
+const LINE_WIDTH: u32 = 3;
+const CELL_OFFSET: u32 = 7;
+const MIN_FONT_SIZE: f32 = 10.0;
+// Columns of the game summary
+const NAME_WIDTH: u32 = 700;
+const NUMBER_WIDTH: u32 = 200;
+
+// Colors as "#rrggbb" strings, missing ones are taken from the default theme
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ColorsConfig {
+    pub background: Option<String>,
+    pub grid: Option<String>,
+    // Cells with questions which are still available
+    pub marked: Option<String>,
+    pub text: Option<String>,
+    // Player who chooses the next question
+    pub highlight: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Colors {
+    pub background: Rgb<u8>,
+    pub grid: Rgb<u8>,
+    pub marked: Rgb<u8>,
+    pub text: Rgb<u8>,
+    pub highlight: Rgb<u8>,
+}
+
+impl Default for Colors {
+    fn default() -> Self {
+        Self {
+            background: Rgb([30, 30, 100]),
+            grid: Rgb([100, 80, 20]),
+            marked: Rgb([30, 30, 100]),
+            text: Rgb([255, 238, 173]),
+            highlight: Rgb([255, 200, 60]),
+        }
+    }
+}
+
+impl Colors {
+    // Invalid colors are reported and replaced by the default ones, so that
+    // a typo in the config doesn't break the board
+    pub fn from_config(config: &ColorsConfig) -> Self {
+        let default = Self::default();
+        let color = |name: &str, value: &Option<String>, default: Rgb<u8>| match value {
+            Some(value) => parse_color(value).unwrap_or_else(|| {
+                eprintln!("invalid scoreboard color {} '{}', default is used", name, value);
+                default
+            }),
+            None => default,
+        };
+        Self {
+            background: color("background", &config.background, default.background),
+            grid: color("grid", &config.grid, default.grid),
+            marked: color("marked", &config.marked, default.marked),
+            text: color("text", &config.text, default.text),
+            highlight: color("highlight", &config.highlight, default.highlight),
+        }
+    }
+}
+
+fn parse_color(value: &str) -> Option<Rgb<u8>> {
+    let hex = value.strip_prefix('#')?;
+    if hex.len() != 6 || !hex.is_ascii() {
+        return None;
+    }
+    let component = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+    Some(Rgb([component(0)?, component(2)?, component(4)?]))
+}
+
+pub fn save_score_table(table: &ScoreTable, colors: &Colors, filename: &str) -> Result<(), Error> {
+    draw_score_table(table, colors)?
+        .save_with_format(filename, image::ImageFormat::Png)
+        .map_err(|err| err_msg(format!("Can't save score table image {}: {}", filename, err)))
+}
+
+pub fn draw_score_table(table: &ScoreTable, colors: &Colors) -> Result<RgbImage, Error> {
+    let font = FontRef::try_from_slice(FONT).map_err(|_| err_msg("bundled font is invalid"))?;
+
+    let costs: Vec<String> = table.scores.iter().map(|cost| cost.to_string()).collect();
+    // Optional trailing column with number of played questions
+    let has_played = table.data.iter().any(|item| item.played.is_some());
+    let columns = costs.len() as u32 + if has_played { 1 } else { 0 };
+    let rows: Vec<Vec<String>> = table
+        .data
+        .iter()
+        .map(|item| {
+            let mut row = vec![item.name.clone()];
+            for cost in &table.scores {
+                if item.questions.contains(cost) {
+                    row.push(cost.to_string());
+                } else {
+                    row.push(String::new());
+                }
+            }
+            if has_played {
+                row.push(item.played.map(|played| played.to_string()).unwrap_or_default());
+            }
+            row
+        })
+        .collect();
+
+    let width = TOPIC_WIDTH + SCORE_WIDTH * columns + LINE_WIDTH;
+    let table_height = ROW_HEIGHT * rows.len() as u32 + LINE_WIDTH;
+    let mut height = table_height;
+    if table.current_player.is_some() {
+        height += ROW_HEIGHT;
+    }
+    // Players' totals are drawn under the table, names in the topics column
+    // and scores across the costs columns
+    let standings_top = height;
+    let totals: Vec<String> = table.standings.iter().map(|standing| standing.score.to_string()).collect();
+    if !table.standings.is_empty() {
+        height += ROW_HEIGHT * table.standings.len() as u32 + LINE_WIDTH;
+    }
+    let total_width = SCORE_WIDTH * columns;
+
+    // Single font size for the whole table, the biggest one which fits
+    // every topic and cost into its cell
+    let mut size = ROW_HEIGHT as f32;
+    for row in &rows {
+        size = size.min(fit_font_size(&font, &row[0], TOPIC_WIDTH - 2 * CELL_OFFSET));
+    }
+    for cost in &costs {
+        size = size.min(fit_font_size(&font, cost, SCORE_WIDTH - 2 * CELL_OFFSET));
+    }
+    for standing in &table.standings {
+        size = size.min(fit_font_size(&font, &standing.name, TOPIC_WIDTH - 2 * CELL_OFFSET));
+    }
+    for total in &totals {
+        size = size.min(fit_font_size(&font, total, total_width.saturating_sub(2 * CELL_OFFSET)));
+    }
+    let scale = PxScale::from(size);
+
+    let mut xs = vec![0, TOPIC_WIDTH];
+    for _ in 0..columns {
+        xs.push(xs[xs.len() - 1] + SCORE_WIDTH);
+    }
+    let ys: Vec<u32> = (0..=rows.len() as u32).map(|row| row * ROW_HEIGHT).collect();
+
+    let offset = (width.min(height) as f32 * 0.05) as u32;
+    let mut image = RgbImage::from_pixel(width + offset * 2, height + offset * 2, colors.background);
+
+    for (y, row) in rows.iter().enumerate() {
+        for (x, text) in row.iter().enumerate().take(costs.len() + 1).skip(1) {
+            if !text.is_empty() {
+                fill_rect(
+                    &mut image,
+                    offset + xs[x],
+                    offset + ys[y],
+                    xs[x + 1] - xs[x],
+                    ROW_HEIGHT,
+                    colors.marked,
+                );
+            }
+        }
+    }
+    for x in &xs {
+        fill_rect(&mut image, offset + x, offset, LINE_WIDTH, table_height, colors.grid);
+    }
+    for y in &ys {
+        fill_rect(&mut image, offset, offset + y, width, LINE_WIDTH, colors.grid);
+    }
+
+    for (y, row) in rows.iter().enumerate() {
+        for (x, text) in row.iter().enumerate() {
+            if text.is_empty() {
+                continue;
+            }
+            let (text_width, text_height) = text_size(&font, scale, text);
+            let offx = if x == 0 {
+                CELL_OFFSET
+            } else {
+                xs[x] + (xs[x + 1] - xs[x]).saturating_sub(text_width) / 2
+            };
+            let offy = ys[y] + ROW_HEIGHT.saturating_sub(text_height) / 2;
+            draw_text(&mut image, &font, scale, offset + offx, offset + offy, text, colors.text);
+        }
+    }
+
+    if let Some(ref current_player) = table.current_player {
+        let text = format!("Выбирает: {}", current_player);
+        let (_, text_height) = text_size(&font, scale, &text);
+        let offy = table_height + ROW_HEIGHT.saturating_sub(text_height) / 2;
+        draw_text(
+            &mut image,
+            &font,
+            scale,
+            offset + CELL_OFFSET,
+            offset + offy,
+            &text,
+            colors.highlight,
+        );
+    }
+
+    if !table.standings.is_empty() {
+        let standings_height = ROW_HEIGHT * table.standings.len() as u32 + LINE_WIDTH;
+        for x in &[0, TOPIC_WIDTH, TOPIC_WIDTH + total_width] {
+            fill_rect(
+                &mut image,
+                offset + x,
+                offset + standings_top,
+                LINE_WIDTH,
+                standings_height,
+                colors.grid,
+            );
+        }
+        for (i, (standing, total)) in table.standings.iter().zip(totals.iter()).enumerate() {
+            let top = standings_top + ROW_HEIGHT * i as u32;
+            fill_rect(&mut image, offset, offset + top, width, LINE_WIDTH, colors.grid);
+
+            let (_, text_height) = text_size(&font, scale, &standing.name);
+            let offy = top + ROW_HEIGHT.saturating_sub(text_height) / 2;
+            draw_text(
+                &mut image,
+                &font,
+                scale,
+                offset + CELL_OFFSET,
+                offset + offy,
+                &standing.name,
+                colors.text,
+            );
+            let (total_text_width, _) = text_size(&font, scale, total);
+            let offx = TOPIC_WIDTH + total_width.saturating_sub(total_text_width) / 2;
+            draw_text(&mut image, &font, scale, offset + offx, offset + offy, total, colors.text);
+        }
+        let bottom = standings_top + standings_height - LINE_WIDTH;
+        fill_rect(&mut image, offset, offset + bottom, width, LINE_WIDTH, colors.grid);
+    }
+
+    Ok(image)
+}
+
+pub fn save_game_summary(summary: &GameSummary, colors: &Colors, filename: &str) -> Result<(), Error> {
+    draw_game_summary(summary, colors)?
+        .save_with_format(filename, image::ImageFormat::Png)
+        .map_err(|err| err_msg(format!("Can't save game summary image {}: {}", filename, err)))
+}
+
+// Final ranking of the players with the winners highlighted, the same
+// picture as the summary of external/draw_table.py
+pub fn draw_game_summary(summary: &GameSummary, colors: &Colors) -> Result<RgbImage, Error> {
+    let font = FontRef::try_from_slice(FONT).map_err(|_| err_msg("bundled font is invalid"))?;
+
+    let mut rows = vec![(String::new(), "Очки".to_string(), "Ответы".to_string(), false)];
+    for player in &summary.players {
+        rows.push((
+            format!("{}. {}", player.rank, player.name),
+            player.score.to_string(),
+            player.answered.to_string(),
+            player.winner,
+        ));
+    }
+
+    let width = NAME_WIDTH + 2 * NUMBER_WIDTH;
+    let height = ROW_HEIGHT * rows.len() as u32;
+
+    let mut size = ROW_HEIGHT as f32;
+    for (name, score, answered, _) in &rows {
+        size = size.min(fit_font_size(&font, name, NAME_WIDTH - 2 * CELL_OFFSET));
+        size = size.min(fit_font_size(&font, score, NUMBER_WIDTH - 2 * CELL_OFFSET));
+        size = size.min(fit_font_size(&font, answered, NUMBER_WIDTH - 2 * CELL_OFFSET));
+    }
+    let scale = PxScale::from(size);
+
+    let offset = (width.min(height) as f32 * 0.05) as u32;
+    let mut image = RgbImage::from_pixel(width + offset * 2, height + offset * 2, colors.background);
+
+    for (y, (name, score, answered, winner)) in rows.iter().enumerate() {
+        let color = if *winner { colors.highlight } else { colors.text };
+        let cells = [
+            (name, 0, NAME_WIDTH),
+            (score, NAME_WIDTH, NUMBER_WIDTH),
+            (answered, NAME_WIDTH + NUMBER_WIDTH, NUMBER_WIDTH),
+        ];
+        for (x, (text, left, cell_width)) in cells.iter().enumerate() {
+            if text.is_empty() {
+                continue;
+            }
+            let (text_width, text_height) = text_size(&font, scale, text);
+            let offx = if x == 0 {
+                left + CELL_OFFSET
+            } else {
+                left + cell_width.saturating_sub(text_width) / 2
+            };
+            let offy = y as u32 * ROW_HEIGHT + ROW_HEIGHT.saturating_sub(text_height) / 2;
+            draw_text(&mut image, &font, scale, offset + offx, offset + offy, text, color);
+        }
+    }
+
+    Ok(image)
+}
+
+fn fit_font_size(font: &FontRef, text: &str, width: u32) -> f32 {
+    let mut size = ROW_HEIGHT as f32;
+    while size > MIN_FONT_SIZE {
+        let (text_width, text_height) = text_size(font, PxScale::from(size), text);
+        if text_width <= width && text_height <= ROW_HEIGHT {
+            break;
+        }
+        size -= 1.0;
+    }
+    size
+}
+
+fn text_size(font: &FontRef, scale: PxScale, text: &str) -> (u32, u32) {
+    let font = font.as_scaled(scale);
+    let mut width = 0.0;
+    let mut previous = None;
+    for c in text.chars() {
+        let glyph = font.glyph_id(c);
+        if let Some(previous) = previous {
+            width += font.kern(previous, glyph);
+        }
+        width += font.h_advance(glyph);
+        previous = Some(glyph);
+    }
+    (width.ceil() as u32, font.height().ceil() as u32)
+}
+
+fn draw_text(
+    image: &mut RgbImage,
+    font: &FontRef,
+    scale: PxScale,
+    x: u32,
+    y: u32,
+    text: &str,
+    color: Rgb<u8>,
+) {
+    let scaled = font.as_scaled(scale);
+    let mut caret = point(x as f32, y as f32 + scaled.ascent());
+    let mut previous = None;
+    for c in text.chars() {
+        let glyph_id = scaled.glyph_id(c);
+        if let Some(previous) = previous {
+            caret.x += scaled.kern(previous, glyph_id);
+        }
+        let glyph = glyph_id.with_scale_and_position(scale, caret);
+        caret.x += scaled.h_advance(glyph_id);
+        previous = Some(glyph_id);
+
+        let outlined = match font.outline_glyph(glyph) {
+            Some(outlined) => outlined,
+            // Spaces have no outline
+            None => continue,
+        };
+        let bounds = outlined.px_bounds();
+        outlined.draw(|gx, gy, coverage| {
+            let px = bounds.min.x as i64 + gx as i64;
+            let py = bounds.min.y as i64 + gy as i64;
+            if px < 0 || py < 0 || px >= image.width() as i64 || py >= image.height() as i64 {
+                return;
+            }
+            let pixel = image.get_pixel_mut(px as u32, py as u32);
+            for i in 0..3 {
+                let blended = pixel[i] as f32 * (1.0 - coverage) + color[i] as f32 * coverage;
+                pixel[i] = blended.round() as u8;
+            }
+        });
+    }
+}
+
+fn fill_rect(image: &mut RgbImage, x: u32, y: u32, width: u32, height: u32, color: Rgb<u8>) {
+    for py in y..(y + height).min(image.height()) {
+        for px in x..(x + width).min(image.width()) {
+            image.put_pixel(px, py, color);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::gamestate::{GameSummaryRow, PublicScore, ScoreTableItem};
+
+    #[test]
+    fn test_font_has_cyrillic() {
+        let font = FontRef::try_from_slice(FONT).unwrap();
+        for c in "АБВГДЕЁЖЗИЙКЛМНОПРСТУФХЦЧШЩЪЫЬЭЮЯабвгдеёжзийклмнопрстуфхцчшщъыьэюя".chars() {
+            assert_ne!(font.glyph_id(c).0, 0, "no glyph for {}", c);
+        }
+    }
+
+    #[test]
+    fn test_draw_score_table() {
+        let table = ScoreTable {
+            scores: vec![100, 200],
+            data: vec![
+                ScoreTableItem {
+                    name: "Кино и театр".to_string(),
+                    questions: vec![200],
+                    played: None,
+                },
+                ScoreTableItem {
+                    name: "Спорт".to_string(),
+                    questions: vec![100, 200],
+                    played: None,
+                },
+            ],
+            current_player: Some("Вася".to_string()),
+            standings: vec![],
+        };
+        let colors = Colors::default();
+        let image = draw_score_table(&table, &colors).unwrap();
+        // Table and the current player row plus the frame
+        assert_eq!(image.width(), 1003 + 2 * 10);
+        assert_eq!(image.height(), 213 + 2 * 10);
+        assert!(image.pixels().any(|pixel| *pixel == colors.text));
+        assert!(image.pixels().any(|pixel| *pixel == colors.highlight));
+
+        let colors = Colors {
+            marked: Rgb([0, 255, 0]),
+            ..Colors::default()
+        };
+        let image = draw_score_table(&table, &colors).unwrap();
+        // Available 100 of the second topic, the first topic has no 100
+        assert_eq!(*image.get_pixel(10 + 805, 10 + 75), colors.marked);
+        assert_eq!(*image.get_pixel(10 + 805, 10 + 5), colors.background);
+    }
+
+    #[test]
+    fn test_draw_standings() {
+        let table = ScoreTable {
+            scores: vec![100, 200],
+            data: vec![ScoreTableItem {
+                name: "Спорт".to_string(),
+                questions: vec![100, 200],
+                played: None,
+            }],
+            current_player: None,
+            standings: vec![
+                PublicScore {
+                    name: "Петя".to_string(),
+                    score: 300,
+                },
+                PublicScore {
+                    name: "Вася".to_string(),
+                    score: -100,
+                },
+            ],
+        };
+        let colors = Colors::default();
+        let image = draw_score_table(&table, &colors).unwrap();
+        // One topic row and two players' rows plus the frame
+        assert_eq!(image.height(), 73 + 143 + 2 * 10);
+        assert_eq!(*image.get_pixel(10 + 801, 10 + 73 + 35), colors.grid);
+        assert_eq!(*image.get_pixel(10 + 901, 10 + 73 + 35), colors.background);
+    }
+
+    #[test]
+    fn test_draw_game_summary() {
+        let summary = GameSummary {
+            players: vec![
+                GameSummaryRow {
+                    rank: 1,
+                    name: "Петя".to_string(),
+                    score: 300,
+                    answered: 2,
+                    winner: true,
+                },
+                GameSummaryRow {
+                    rank: 2,
+                    name: "Вася".to_string(),
+                    score: -100,
+                    answered: 0,
+                    winner: false,
+                },
+            ],
+        };
+        let colors = Colors::default();
+        let image = draw_game_summary(&summary, &colors).unwrap();
+        // Header and two players plus the frame
+        assert_eq!(image.width(), 1100 + 2 * 10);
+        assert_eq!(image.height(), 210 + 2 * 10);
+        assert!(image.pixels().any(|pixel| *pixel == colors.text));
+        assert!(image.pixels().any(|pixel| *pixel == colors.highlight));
+    }
+
+    #[test]
+    fn test_colors_from_config() {
+        let config = ColorsConfig {
+            background: Some("#000000".to_string()),
+            grid: Some("#FFfF00".to_string()),
+            marked: Some("green".to_string()),
+            text: Some("#12345".to_string()),
+            highlight: None,
+        };
+        let colors = Colors::from_config(&config);
+        let default = Colors::default();
+        assert_eq!(colors.background, Rgb([0, 0, 0]));
+        assert_eq!(colors.grid, Rgb([255, 255, 0]));
+        assert_eq!(colors.marked, default.marked);
+        assert_eq!(colors.text, default.text);
+        assert_eq!(colors.highlight, default.highlight);
+    }
+}
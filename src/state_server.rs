@@ -0,0 +1,96 @@
+use failure::Error;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{header, Body, Method, Request, Response, Server, StatusCode};
+use serde::Serialize;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+// Latest state of the game serialized to json. It's updated by the bot after
+// every event and only read by the http server
+#[derive(Clone)]
+pub struct StateSnapshot {
+    json: Arc<Mutex<String>>,
+}
+
+impl Default for StateSnapshot {
+    fn default() -> Self {
+        Self {
+            json: Arc::new(Mutex::new("{}".to_string())),
+        }
+    }
+}
+
+impl StateSnapshot {
+    pub fn update<T: Serialize>(&self, state: &T) {
+        match serde_json::to_string(state) {
+            Ok(json) => {
+                *self.json.lock().unwrap() = json;
+            }
+            Err(err) => {
+                eprintln!("failed to serialize game state: {}", err);
+            }
+        }
+    }
+
+    fn get(&self) -> String {
+        self.json.lock().unwrap().clone()
+    }
+}
+
+fn respond(snapshot: &StateSnapshot, request: &Request<Body>) -> Response<Body> {
+    if request.method() != Method::GET || request.uri().path() != "/state" {
+        let mut response = Response::new(Body::empty());
+        *response.status_mut() = StatusCode::NOT_FOUND;
+        return response;
+    }
+
+    let mut response = Response::new(Body::from(snapshot.get()));
+    let headers = response.headers_mut();
+    headers.insert(header::CONTENT_TYPE, header::HeaderValue::from_static("application/json"));
+    // Overlays are usually web pages served from another origin
+    headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, header::HeaderValue::from_static("*"));
+    response
+}
+
+// Serves GET /state until the bot is stopped
+pub async fn serve(addr: SocketAddr, snapshot: StateSnapshot) -> Result<(), Error> {
+    let make_service = make_service_fn(move |_| {
+        let snapshot = snapshot.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                let response = respond(&snapshot, &request);
+                async move { Ok::<_, Infallible>(response) }
+            }))
+        }
+    });
+    eprintln!("serving game state on http://{}/state", addr);
+    Server::bind(&addr).serve(make_service).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn request(method: Method, path: &str) -> Request<Body> {
+        Request::builder().method(method).uri(path).body(Body::empty()).unwrap()
+    }
+
+    #[test]
+    fn test_respond() {
+        let snapshot = StateSnapshot::default();
+        let response = respond(&snapshot, &request(Method::GET, "/state"));
+        assert_eq!(response.status(), StatusCode::OK);
+
+        snapshot.update(&vec![("Вася", 100)]);
+        assert_eq!(snapshot.get(), r#"[["Вася",100]]"#);
+        let response = respond(&snapshot, &request(Method::GET, "/state"));
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/json");
+
+        let response = respond(&snapshot, &request(Method::POST, "/state"));
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let response = respond(&snapshot, &request(Method::GET, "/"));
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+}
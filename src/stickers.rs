@@ -1,16 +1,107 @@
+use failure::{err_msg, Error};
 use rand::{seq::SliceRandom, thread_rng};
+use serde_derive::Deserialize;
+use std::fs::File;
 
-pub fn get_rand_sticker() -> Option<String> {
-   let stickers = vec![
-       "CAACAgIAAxkBAAJC8mHu7iSGjSCrqcX_6idsLAHqm181AAIVAAPANk8TzVamO2GeZOcjBA".to_string(),
-       "CAACAgIAAxkBAAJC82Hu7nhptVATZC7GLnGz00Q6nqCMAAJxFAAC6Cy5SjtLqwG1uMNJIwQ".to_string(),
-       "CAACAgIAAxkBAAJC9GHu7oWfAsm3m31zx06tvFjUK6DHAAJJFgACJl6gSN8LumhksQqgIwQ".to_string(),
-       "CAACAgIAAxkBAAJLWWH2fgX2KK1dnrruyvIKTGGFYv7yAALSEgACCzsRShf2atm48POfIwQ".to_string(),
-       "CAACAgIAAxkBAAJLWmH2fiNXRWY4cXNQEHECeNepDXyBAAJTFQACl6NASUkdCbRrtLunIwQ".to_string(),
-       "CAACAgIAAxkBAAJLW2H2fkSnL9rzDECwodrfKTgxvTgEAALUFAACb7nISPsOb82nfnIQIwQ".to_string(),
-       "CAACAgEAAxkBAAJLXGH2fmQMzV62jolwSQ3YgpfhulsaAAJKAQACoAQpR4ZbZ4pD98oxIwQ".to_string(),
-       "CAACAgIAAxkBAAJLXWH2fo1TB4qUewwEBZhLBbjf-K5JAALdDwACzkP4SjmdKcNmQDlrIwQ".to_string(),
-   ];
+fn default_correct_stickers() -> Vec<String> {
+    vec![
+        "CAACAgIAAxkBAAJC8mHu7iSGjSCrqcX_6idsLAHqm181AAIVAAPANk8TzVamO2GeZOcjBA".to_string(),
+        "CAACAgIAAxkBAAJC82Hu7nhptVATZC7GLnGz00Q6nqCMAAJxFAAC6Cy5SjtLqwG1uMNJIwQ".to_string(),
+        "CAACAgIAAxkBAAJC9GHu7oWfAsm3m31zx06tvFjUK6DHAAJJFgACJl6gSN8LumhksQqgIwQ".to_string(),
+        "CAACAgIAAxkBAAJLWWH2fgX2KK1dnrruyvIKTGGFYv7yAALSEgACCzsRShf2atm48POfIwQ".to_string(),
+        "CAACAgIAAxkBAAJLWmH2fiNXRWY4cXNQEHECeNepDXyBAAJTFQACl6NASUkdCbRrtLunIwQ".to_string(),
+        "CAACAgIAAxkBAAJLW2H2fkSnL9rzDECwodrfKTgxvTgEAALUFAACb7nISPsOb82nfnIQIwQ".to_string(),
+        "CAACAgEAAxkBAAJLXGH2fmQMzV62jolwSQ3YgpfhulsaAAJKAQACoAQpR4ZbZ4pD98oxIwQ".to_string(),
+        "CAACAgIAAxkBAAJLXWH2fo1TB4qUewwEBZhLBbjf-K5JAALdDwACzkP4SjmdKcNmQDlrIwQ".to_string(),
+    ]
+}
+
+// Sticker file ids, a random one from the pool is sent at the corresponding
+// moment of the game. Empty pool means no sticker
+#[derive(Clone, Debug, Deserialize)]
+pub struct Stickers {
+    // Correct answer to the most expensive question
+    #[serde(default = "default_correct_stickers")]
+    correct: Vec<String>,
+    #[serde(default)]
+    wrong: Vec<String>,
+    #[serde(default)]
+    start: Vec<String>,
+}
+
+impl Default for Stickers {
+    fn default() -> Self {
+        Self {
+            correct: default_correct_stickers(),
+            wrong: vec![],
+            start: vec![],
+        }
+    }
+}
+
+impl Stickers {
+    // Built-in stickers are used if there is no file, a malformed file is an error
+    pub fn load(path: Option<&str>) -> Result<Self, Error> {
+        let path = match path {
+            Some(path) => path,
+            None => {
+                return Ok(Self::default());
+            }
+        };
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(err) => {
+                eprintln!("can't open stickers file '{}', using the default stickers: {}", path, err);
+                return Ok(Self::default());
+            }
+        };
+        let stickers = serde_json::from_reader(file)
+            .map_err(|err| err_msg(format!("invalid stickers file '{}': {}", path, err)))?;
+        Ok(stickers)
+    }
+
+    pub fn correct(&self) -> Option<String> {
+        choose(&self.correct)
+    }
+
+    pub fn wrong(&self) -> Option<String> {
+        choose(&self.wrong)
+    }
+
+    pub fn start(&self) -> Option<String> {
+        choose(&self.start)
+    }
+}
+
+fn choose(stickers: &[String]) -> Option<String> {
     let mut rng = thread_rng();
     stickers.choose(&mut rng).cloned()
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_load_stickers() {
+        let stickers = Stickers::load(None).unwrap();
+        assert!(stickers.correct().is_some());
+        assert!(stickers.wrong().is_none());
+
+        let path = std::env::temp_dir().join("svoyak_bot_test_stickers.json");
+        let path = path.to_str().unwrap();
+        std::fs::write(path, r#"{"wrong": ["wrong_id"], "start": []}"#).unwrap();
+        let stickers = Stickers::load(Some(path)).unwrap();
+        assert!(stickers.correct().is_some());
+        assert_eq!(stickers.wrong(), Some("wrong_id".to_string()));
+        assert!(stickers.start().is_none());
+
+        std::fs::write(path, "not json").unwrap();
+        assert!(Stickers::load(Some(path)).is_err());
+        std::fs::remove_file(path).unwrap();
+
+        let stickers = Stickers::load(Some(path)).unwrap();
+        assert!(stickers.correct().is_some());
+        assert!(stickers.wrong().is_none());
+    }
+}
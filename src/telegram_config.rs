@@ -1,19 +1,139 @@
+use serde::{de, Deserialize as _, Deserializer};
 use serde_derive::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::fs::File;
+use std::time::Duration;
 use telegram_bot;
 
+use crate::gamestate::{Delay, Delays, Intro};
+use crate::questionsstorage::{ImageCompressionConfig, TtsConfig};
+use crate::score_image::{Colors, ColorsConfig};
+
 #[derive(Clone, Serialize, Deserialize)]
 pub struct Question {
     topic: String,
     cost: usize,
 }
 
+// How the score table is shown to the players
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ScoreboardMode {
+    // Image drawn by the external script, text if drawing fails
+    #[default]
+    Image,
+    // Image drawn without the external script, text if drawing fails
+    Native,
+    Text,
+    Off,
+}
+
 #[derive(Serialize, Deserialize)]
 struct RawConfig {
     pub admin_id: i64,
     pub game_chat_id: Option<i64>,
     pub questions_storage_path: String,
-    pub questions_per_topic: usize,
+    // If it is not set, then it is detected from the questions
+    #[serde(default)]
+    pub questions_per_topic: Option<usize>,
+    #[serde(default = "default_manual_question_answerers")]
+    pub manual_question_answerers: usize,
+    #[serde(default)]
+    pub tts: Option<TtsConfig>,
+    #[serde(default)]
+    pub auto_judge: bool,
+    #[serde(default)]
+    pub game_duration_minutes: Option<u64>,
+    #[serde(default = "default_command_prefix")]
+    pub command_prefix: String,
+    #[serde(default)]
+    pub results_db_path: Option<String>,
+    #[serde(default = "default_min_players")]
+    pub min_players: usize,
+    #[serde(default)]
+    pub tour_recap: bool,
+    #[serde(default)]
+    pub image_compression: ImageCompressionConfig,
+    #[serde(default)]
+    pub direct_attachment_urls: bool,
+    #[serde(default = "default_cache_dir")]
+    pub cache_dir: String,
+    #[serde(default)]
+    pub clean_cache_on_start: bool,
+    #[serde(default)]
+    pub cache_max_age_days: Option<u64>,
+    #[serde(default = "default_scoreboard_python")]
+    pub scoreboard_python: String,
+    #[serde(default = "default_scoreboard_script")]
+    pub scoreboard_script: String,
+    #[serde(default)]
+    pub scoreboard_mode: ScoreboardMode,
+    #[serde(default = "default_auction_bid_timeout_secs")]
+    pub auction_bid_timeout_secs: u64,
+    #[serde(default)]
+    pub cat_in_bag_dm: bool,
+    #[serde(default)]
+    pub highlight_current_player: bool,
+    #[serde(default)]
+    pub audit_log_path: Option<String>,
+    #[serde(default = "default_audio_answer_buffer_secs")]
+    pub audio_answer_buffer_secs: u64,
+    #[serde(default)]
+    pub stickers_path: Option<String>,
+    #[serde(default)]
+    pub registration_window_secs: u64,
+    #[serde(default)]
+    pub join_cooldown_secs: u64,
+    #[serde(default)]
+    pub turn_dm: bool,
+    #[serde(default = "default_length_thresholds", deserialize_with = "deserialize_length_thresholds")]
+    pub length_thresholds: (usize, usize),
+    #[serde(default)]
+    pub phrases_path: Option<String>,
+    #[serde(default)]
+    pub state_server_addr: Option<String>,
+    #[serde(default = "default_admin_can_play")]
+    pub admin_can_play: bool,
+    #[serde(default)]
+    pub max_players: Option<usize>,
+    #[serde(default)]
+    pub question_attribution: bool,
+    #[serde(default)]
+    pub intro: Intro,
+    #[serde(default)]
+    pub scoreboard_colors: ColorsConfig,
+    #[serde(default)]
+    pub answer_countdown: bool,
+    // Overrides default durations of the question steps, e.g.
+    // {"answer_window": 20, "pre_question": 3, "post_answer": 2}
+    #[serde(default)]
+    pub delays_secs: HashMap<Delay, u64>,
+    #[serde(default)]
+    pub heartbeat_interval_secs: Option<u64>,
+    #[serde(default)]
+    pub heartbeat_to_admin: bool,
+    // Download all attachments on startup instead of when the question is asked
+    #[serde(default)]
+    pub prefetch_attachments: bool,
+    // Repeated yes/no taps on the same prompt within this interval are ignored
+    #[serde(default = "default_judgment_debounce_ms")]
+    pub judgment_debounce_ms: u64,
+    // How many played questions can be shown again with /last
+    #[serde(default = "default_last_questions_count")]
+    pub last_questions_count: usize,
+    // Costs are taken from the second column of the questions pack
+    #[serde(default)]
+    pub custom_costs: bool,
+    // While the game chat isn't selected, the admin is reminded to send
+    // /thischat, and the bot exits after the timeout. 0 means no timeout
+    #[serde(default = "default_game_chat_reminder_secs")]
+    pub game_chat_reminder_secs: u64,
+    #[serde(default = "default_game_chat_timeout_secs")]
+    pub game_chat_timeout_secs: u64,
+    // Test message is sent to the configured game chat on startup, so that
+    // a wrong chat id is reported right away
+    #[serde(default = "default_probe_game_chat")]
+    pub probe_game_chat: bool,
 }
 
 pub struct Config {
@@ -22,11 +142,127 @@ pub struct Config {
     pub admin_chat: telegram_bot::ChatId,
     pub game_chat: Option<telegram_bot::ChatId>,
     pub questions_storage_path: String,
-    pub questions_per_topic: usize,
+    pub questions_per_topic: Option<usize>,
+    pub manual_question_answerers: usize,
+    pub tts: Option<TtsConfig>,
+    pub auto_judge: bool,
+    pub game_duration_minutes: Option<u64>,
+    pub command_prefix: String,
+    pub results_db_path: Option<String>,
+    pub min_players: usize,
+    pub tour_recap: bool,
+    pub image_compression: ImageCompressionConfig,
+    pub direct_attachment_urls: bool,
+    pub cache_dir: String,
+    pub clean_cache_on_start: bool,
+    pub cache_max_age_days: Option<u64>,
+    pub scoreboard_python: String,
+    pub scoreboard_script: String,
+    pub scoreboard_mode: ScoreboardMode,
+    pub auction_bid_timeout_secs: u64,
+    pub cat_in_bag_dm: bool,
+    pub highlight_current_player: bool,
+    pub audit_log_path: Option<String>,
+    pub audio_answer_buffer_secs: u64,
+    pub stickers_path: Option<String>,
+    pub registration_window_secs: u64,
+    pub join_cooldown_secs: u64,
+    pub turn_dm: bool,
+    pub length_thresholds: (usize, usize),
+    pub phrases_path: Option<String>,
+    pub state_server_addr: Option<String>,
+    pub admin_can_play: bool,
+    pub max_players: Option<usize>,
+    pub question_attribution: bool,
+    pub intro: Intro,
+    pub scoreboard_colors: Colors,
+    pub answer_countdown: bool,
+    pub delays: Delays,
+    pub heartbeat_interval_secs: Option<u64>,
+    pub heartbeat_to_admin: bool,
+    pub prefetch_attachments: bool,
+    pub judgment_debounce_ms: u64,
+    pub last_questions_count: usize,
+    pub custom_costs: bool,
+    pub game_chat_reminder_secs: u64,
+    pub game_chat_timeout_secs: u64,
+    pub probe_game_chat: bool,
 }
 
 const DEFAULT_ADMIN_ID: i64 = 125732128;
 
+fn default_manual_question_answerers() -> usize {
+    1
+}
+
+fn default_min_players() -> usize {
+    2
+}
+
+fn default_judgment_debounce_ms() -> u64 {
+    1000
+}
+
+fn default_game_chat_reminder_secs() -> u64 {
+    60
+}
+
+fn default_game_chat_timeout_secs() -> u64 {
+    1800
+}
+
+fn default_probe_game_chat() -> bool {
+    true
+}
+
+fn default_last_questions_count() -> usize {
+    5
+}
+
+fn default_auction_bid_timeout_secs() -> u64 {
+    15
+}
+
+fn default_audio_answer_buffer_secs() -> u64 {
+    3
+}
+
+fn default_admin_can_play() -> bool {
+    true
+}
+
+// Questions up to these lengths get short and medium time before answering
+fn default_length_thresholds() -> (usize, usize) {
+    (100, 230)
+}
+
+fn deserialize_length_thresholds<'de, D: Deserializer<'de>>(deserializer: D) -> Result<(usize, usize), D::Error> {
+    let (short, medium) = <(usize, usize)>::deserialize(deserializer)?;
+    if short > medium {
+        return Err(de::Error::custom(format!(
+            "short question length threshold {} is greater than the medium one {}",
+            short, medium
+        )));
+    }
+    Ok((short, medium))
+}
+
+fn default_cache_dir() -> String {
+    "cache".to_string()
+}
+
+fn default_scoreboard_python() -> String {
+    "python3".to_string()
+}
+
+fn default_scoreboard_script() -> String {
+    "external/draw_table.py".to_string()
+}
+
+fn default_command_prefix() -> String {
+    "/".to_string()
+}
+
 impl RawConfig {
     fn new(filename: Option<String>) -> Self {
         match filename {
@@ -34,10 +270,10 @@ impl RawConfig {
                 eprintln!("Loading configuration from '{}'", fname);
                 let file = File::open(fname)
                     .unwrap_or_else(|_| panic!("Can't open file '{}' with configuration", fname));
-                let config: Self = serde_json::from_reader(file).unwrap_or_else(|_| {
+                let config: Self = serde_json::from_reader(file).unwrap_or_else(|err| {
                     panic!(
-                        "Content of '{}' is not a valid InstanceConfig object",
-                        fname
+                        "Content of '{}' is not a valid InstanceConfig object: {}",
+                        fname, err
                     )
                 });
                 config
@@ -48,7 +284,51 @@ impl RawConfig {
                     admin_id: DEFAULT_ADMIN_ID,
                     game_chat_id: None,
                     questions_storage_path: "storage.csv".into(),
-                    questions_per_topic: 5,
+                    questions_per_topic: Some(5),
+                    manual_question_answerers: default_manual_question_answerers(),
+                    tts: None,
+                    auto_judge: false,
+                    game_duration_minutes: None,
+                    command_prefix: default_command_prefix(),
+                    results_db_path: None,
+                    min_players: default_min_players(),
+                    tour_recap: false,
+                    image_compression: ImageCompressionConfig::default(),
+                    direct_attachment_urls: false,
+                    cache_dir: default_cache_dir(),
+                    clean_cache_on_start: false,
+                    cache_max_age_days: None,
+                    scoreboard_python: default_scoreboard_python(),
+                    scoreboard_script: default_scoreboard_script(),
+                    scoreboard_mode: ScoreboardMode::default(),
+                    auction_bid_timeout_secs: default_auction_bid_timeout_secs(),
+                    cat_in_bag_dm: false,
+                    highlight_current_player: false,
+                    audit_log_path: None,
+                    audio_answer_buffer_secs: default_audio_answer_buffer_secs(),
+                    stickers_path: None,
+                    registration_window_secs: 0,
+                    join_cooldown_secs: 0,
+                    turn_dm: false,
+                    length_thresholds: default_length_thresholds(),
+                    phrases_path: None,
+                    state_server_addr: None,
+                    admin_can_play: true,
+                    max_players: None,
+                    question_attribution: false,
+                    intro: Intro::default(),
+                    scoreboard_colors: ColorsConfig::default(),
+                    answer_countdown: false,
+                    delays_secs: HashMap::new(),
+                    heartbeat_interval_secs: None,
+                    heartbeat_to_admin: false,
+                    prefetch_attachments: false,
+                    judgment_debounce_ms: default_judgment_debounce_ms(),
+                    last_questions_count: default_last_questions_count(),
+                    custom_costs: false,
+                    game_chat_reminder_secs: default_game_chat_reminder_secs(),
+                    game_chat_timeout_secs: default_game_chat_timeout_secs(),
+                    probe_game_chat: default_probe_game_chat(),
                 }
             }
         }
@@ -67,6 +347,69 @@ impl Config {
             game_chat: config.game_chat_id.map(telegram_bot::ChatId::from),
             questions_storage_path: config.questions_storage_path,
             questions_per_topic: config.questions_per_topic,
+            manual_question_answerers: config.manual_question_answerers,
+            tts: config.tts,
+            auto_judge: config.auto_judge,
+            game_duration_minutes: config.game_duration_minutes,
+            command_prefix: config.command_prefix,
+            results_db_path: config.results_db_path,
+            min_players: config.min_players,
+            tour_recap: config.tour_recap,
+            image_compression: config.image_compression,
+            direct_attachment_urls: config.direct_attachment_urls,
+            cache_dir: config.cache_dir,
+            clean_cache_on_start: config.clean_cache_on_start,
+            cache_max_age_days: config.cache_max_age_days,
+            scoreboard_python: config.scoreboard_python,
+            scoreboard_script: config.scoreboard_script,
+            scoreboard_mode: config.scoreboard_mode,
+            auction_bid_timeout_secs: config.auction_bid_timeout_secs,
+            cat_in_bag_dm: config.cat_in_bag_dm,
+            highlight_current_player: config.highlight_current_player,
+            audit_log_path: config.audit_log_path,
+            audio_answer_buffer_secs: config.audio_answer_buffer_secs,
+            stickers_path: config.stickers_path,
+            registration_window_secs: config.registration_window_secs,
+            join_cooldown_secs: config.join_cooldown_secs,
+            turn_dm: config.turn_dm,
+            length_thresholds: config.length_thresholds,
+            phrases_path: config.phrases_path,
+            state_server_addr: config.state_server_addr,
+            admin_can_play: config.admin_can_play,
+            max_players: config.max_players,
+            question_attribution: config.question_attribution,
+            intro: config.intro,
+            scoreboard_colors: Colors::from_config(&config.scoreboard_colors),
+            answer_countdown: config.answer_countdown,
+            delays: Delays::new(
+                config
+                    .delays_secs
+                    .into_iter()
+                    .map(|(delay, secs)| (delay, Duration::from_secs(secs)))
+                    .collect(),
+            ),
+            heartbeat_interval_secs: config.heartbeat_interval_secs,
+            heartbeat_to_admin: config.heartbeat_to_admin,
+            prefetch_attachments: config.prefetch_attachments,
+            judgment_debounce_ms: config.judgment_debounce_ms,
+            last_questions_count: config.last_questions_count,
+            custom_costs: config.custom_costs,
+            game_chat_reminder_secs: config.game_chat_reminder_secs,
+            game_chat_timeout_secs: config.game_chat_timeout_secs,
+            probe_game_chat: config.probe_game_chat,
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_length_thresholds() {
+        let parse = |json: &str| deserialize_length_thresholds(&mut serde_json::Deserializer::from_str(json));
+        assert_eq!(parse("[100, 230]").unwrap(), (100, 230));
+        assert_eq!(parse("[100, 100]").unwrap(), (100, 100));
+        assert!(parse("[230, 100]").is_err());
+    }
+}
@@ -1,27 +1,49 @@
-use std::mem;
+use std::collections::HashMap;
 
 use failure::{err_msg, Error};
 use futures::sync::mpsc::Receiver;
 use futures::{Async, Future, Poll, Stream};
 
+// Timers with different ids run independently of each other.
+// New timer replaces inflight timer with the same id.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum TimerId {
+    // Falsestart, answer window and other timers of the current question
+    Question,
+    // Overall duration of the game
+    GameClock,
+    // Auction bidding is closed when nobody raises the bid in time
+    Auction,
+    // Registration of players is closed
+    Registration,
+    // Next update of the answer countdown message
+    Countdown,
+    // Periodic "alive" log line
+    Heartbeat,
+    // Answer of the closed question is revealed after a pause
+    Reveal,
+    // Next question of a blitz tour is asked
+    Blitz,
+}
+
+pub type TimerRequest = (TimerId, Option<Box<dyn Future<Item = (), Error = Error>>>);
+
 pub struct TimeoutStream {
-    new_timers_stream: Receiver<Option<Box<dyn Future<Item = (), Error = Error>>>>,
-    inflight_timer: Option<Box<dyn Future<Item = (), Error = Error>>>,
+    new_timers_stream: Receiver<TimerRequest>,
+    inflight_timers: HashMap<TimerId, Box<dyn Future<Item = (), Error = Error>>>,
 }
 
 impl TimeoutStream {
-    pub fn new(
-        new_timers_stream: Receiver<Option<Box<dyn Future<Item = (), Error = Error>>>>,
-    ) -> Self {
+    pub fn new(new_timers_stream: Receiver<TimerRequest>) -> Self {
         Self {
             new_timers_stream,
-            inflight_timer: None,
+            inflight_timers: HashMap::new(),
         }
     }
 }
 
 impl Stream for TimeoutStream {
-    type Item = ();
+    type Item = TimerId;
     type Error = Error;
 
     fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
@@ -31,16 +53,16 @@ impl Stream for TimeoutStream {
                 .poll()
                 .map_err(|_| err_msg("sending timer failed"))?;
             match new_timer {
-                Async::Ready(Some(timer_or_cancel)) => match timer_or_cancel {
+                Async::Ready(Some((id, timer_or_cancel))) => match timer_or_cancel {
                     Some(timer) => {
                         let fut = Box::new(timer.map_err(|err| {
                             let msg = format!("timer failed: {}", err);
                             err_msg(msg)
                         }));
-                        let _ = mem::replace(&mut self.inflight_timer, Some(fut));
+                        self.inflight_timers.insert(id, fut);
                     }
                     None => {
-                        let _ = mem::replace(&mut self.inflight_timer, None);
+                        self.inflight_timers.remove(&id);
                     }
                 },
                 Async::NotReady | Async::Ready(None) => {
@@ -49,19 +71,20 @@ impl Stream for TimeoutStream {
             }
         }
 
-        let res = match self.inflight_timer {
-            Some(ref mut timer) => match timer.poll()? {
-                Async::Ready(_) => Async::Ready(Some(())),
-                Async::NotReady => {
-                    return Ok(Async::NotReady);
-                }
-            },
-            None => {
-                return Ok(Async::NotReady);
+        let mut fired = None;
+        for (id, timer) in self.inflight_timers.iter_mut() {
+            if let Async::Ready(_) = timer.poll()? {
+                fired = Some(*id);
+                break;
             }
-        };
+        }
 
-        let _ = mem::replace(&mut self.inflight_timer, None);
-        Ok(res)
+        match fired {
+            Some(id) => {
+                self.inflight_timers.remove(&id);
+                Ok(Async::Ready(Some(id)))
+            }
+            None => Ok(Async::NotReady),
+        }
     }
 }